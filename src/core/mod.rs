@@ -1 +1,2 @@
 pub mod qm;
+pub mod parallel;
@@ -0,0 +1,103 @@
+//! Support for spreading work across threads
+
+use core::qm;
+use std::panic;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+/// Applies the function to each of the items, using up to n_threads
+/// threads, and returns the results in the same order as the items. Each
+/// thread takes the next unstarted item whenever it finishes one, so uneven
+/// items are balanced across the threads.
+///
+/// The results do not depend on the number of threads, as long as the
+/// function gives the same result for an item whichever thread it runs on.
+/// If any items fail, the error is that of the first failing item in the
+/// order they were given. A panic in the function is propagated to the
+/// caller once all the threads have finished.
+pub fn map_parallel<T, R, F>(n_threads: usize, items: Vec<T>, function: F)
+    -> Result<Vec<R>, qm::Error>
+    where T: Send, R: Send, F: Fn(T) -> Result<R, qm::Error> + Sync {
+
+    let n_items = items.len();
+    let n_threads = n_threads.min(n_items);
+    if n_threads <= 1 {
+        return items.into_iter().map(function).collect()
+    }
+
+    let queue: Vec<Mutex<Option<T>>> = items.into_iter()
+        .map(|item| Mutex::new(Some(item))).collect();
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<Result<R, qm::Error>>> =
+        (0..n_items).map(|_| None).collect();
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..n_threads).map(|_| scope.spawn(|| {
+            let mut done = Vec::new();
+            loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= n_items {
+                    return done
+                }
+                // each item is taken exactly once, so the lock is never
+                // contended or poisoned
+                let item = queue[i].lock().unwrap().take().unwrap();
+                done.push((i, function(item)));
+            }
+        })).collect();
+
+        let mut panicked = None;
+        for worker in workers {
+            match worker.join() {
+                Ok(done) => for (i, result) in done {
+                    results[i] = Some(result);
+                },
+                Err(payload) => panicked = Some(payload)
+            }
+        }
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+    });
+
+    results.into_iter().map(|result| result.unwrap()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_are_in_order() {
+        let items: Vec<usize> = (0..100).collect();
+        for &n_threads in [1, 3, 8, 200].iter() {
+            let squares = map_parallel(n_threads, items.clone(),
+                |i| Ok(i * i)).unwrap();
+            assert_eq!(squares.len(), 100);
+            for (i, square) in squares.iter().enumerate() {
+                assert_eq!(*square, i * i);
+            }
+        }
+
+        let empty: Vec<usize> = map_parallel(4, Vec::new(),
+            |i: usize| Ok(i)).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn first_error_is_returned() {
+        let items: Vec<usize> = (0..50).collect();
+        for &n_threads in [1, 4].iter() {
+            let result = map_parallel(n_threads, items.clone(), |i|
+                if i % 7 == 3 {
+                    Err(qm::Error::new(&format!("failed on {}", i)))
+                } else {
+                    Ok(i)
+                });
+            let message = format!("{}", result.err().unwrap());
+            assert!(message.ends_with("failed on 3"), "{}", message);
+        }
+    }
+}
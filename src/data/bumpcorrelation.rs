@@ -0,0 +1,32 @@
+use std::sync::Arc;
+use data::correlation::LocalCorrelation;
+use data::bump::Bump;
+
+/// Bump that defines all the supported bumps to a correlation between two
+/// underliers. Bumped correlations are clamped to the range -1 to 1.
+pub enum BumpCorrelation {
+    FlatAdditive { size: f64 },
+    Replace { correlation: f64 }
+}
+
+impl BumpCorrelation {
+    pub fn new_flat_additive(size: f64) -> BumpCorrelation {
+        BumpCorrelation::FlatAdditive { size: size }
+    }
+
+    pub fn new_replace(correlation: f64) -> BumpCorrelation {
+        BumpCorrelation::Replace { correlation: correlation }
+    }
+}
+
+impl Bump<Arc<LocalCorrelation>> for BumpCorrelation {
+
+    fn apply(&self, old: Arc<LocalCorrelation>) -> Arc<LocalCorrelation> {
+        match self {
+            &BumpCorrelation::FlatAdditive { size }
+                => Arc::new(old.new_modified(&|c| c + size)),
+            &BumpCorrelation::Replace { correlation }
+                => Arc::new(old.new_modified(&|_| correlation))
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use data::divstream::DividendStream;
 use data::bump::Bump;
 
@@ -14,12 +14,12 @@ impl BumpDivs {
     }
 }
 
-impl Bump<Rc<DividendStream>> for BumpDivs {
+impl Bump<Arc<DividendStream>> for BumpDivs {
 
-    fn apply(&self, divs: Rc<DividendStream>) -> Rc<DividendStream> {
+    fn apply(&self, divs: Arc<DividendStream>) -> Arc<DividendStream> {
         match self {
             &BumpDivs::BumpAllRelative { size }
-                => Rc::new(DividendStream::new_bump_all(&*divs, size)),
+                => Arc::new(DividendStream::new_bump_all(&*divs, size)),
         }
     }
 }
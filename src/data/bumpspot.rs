@@ -3,6 +3,7 @@ use data::bump::Bump;
 /// Bump that defines all the supported bumps to a spot value
 pub enum BumpSpot {
     Relative { bump: f64 },
+    Absolute { bump: f64 },
     Replace { spot: f64 }
 }
 
@@ -11,6 +12,10 @@ impl BumpSpot {
         BumpSpot::Relative { bump: bump }
     }
 
+    pub fn new_absolute(bump: f64) -> BumpSpot {
+        BumpSpot::Absolute { bump: bump }
+    }
+
     pub fn new_replace(spot: f64) -> BumpSpot {
         BumpSpot::Replace { spot: spot }
     }
@@ -21,6 +26,7 @@ impl Bump<f64> for BumpSpot {
     fn apply(&self, old_spot: f64) -> f64 {
         match self {
             &BumpSpot::Relative { bump } => old_spot * (1.0 + bump),
+            &BumpSpot::Absolute { bump } => old_spot + bump,
             &BumpSpot::Replace { spot } => spot
         }
     }
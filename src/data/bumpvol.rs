@@ -1,14 +1,21 @@
-use std::rc::Rc;
+use std::sync::Arc;
+use core::qm;
 use data::volsurface::VolSurface;
 use data::voldecorators::TimeScaledBumpVol;
 use data::voldecorators::ParallelBumpVol;
+use data::voldecorators::PillarBumpVol;
+use data::voldecorators::RegionBumpVol;
 use data::bump::Bump;
+use dates::datetime::DateDayFraction;
 
 /// Bump that defines all the supported bumps and risk transformations of a
 /// vol surface.
 pub enum BumpVol {
     FlatAdditive { size: f64 },
-    TimeScaled { size: f64, floor: f64 }
+    TimeScaled { size: f64, floor: f64 },
+    ExpiryPillar { pillars: Vec<DateDayFraction>, pillar: usize, size: f64 },
+    Region { pillars: Vec<DateDayFraction>, pillar: usize,
+        strikes: Vec<f64>, strike: usize, size: f64 }
 }
 
 impl BumpVol {
@@ -19,17 +26,65 @@ impl BumpVol {
     pub fn new_time_scaled(size: f64, floor: f64) -> BumpVol {
         BumpVol::TimeScaled { size: size, floor: floor }
     }
+
+    /// Bump localised to one of a set of expiry pillars, which must be in
+    /// ascending order. See PillarBumpVol for details.
+    pub fn new_expiry_pillar(pillars: &[DateDayFraction], pillar: usize,
+        size: f64) -> Result<BumpVol, qm::Error> {
+        validate_pillars(pillars, pillar)?;
+        Ok(BumpVol::ExpiryPillar { pillars: pillars.to_vec(), pillar: pillar,
+            size: size })
+    }
+
+    /// Bump localised to the region around one expiry pillar and one strike
+    /// pillar. Both sets of pillars must be in ascending order. See
+    /// RegionBumpVol for details.
+    pub fn new_region(pillars: &[DateDayFraction], pillar: usize,
+        strikes: &[f64], strike: usize, size: f64)
+        -> Result<BumpVol, qm::Error> {
+        validate_pillars(pillars, pillar)?;
+        if strike >= strikes.len() {
+            return Err(qm::Error::new("Strike pillar out of range"))
+        }
+        if strikes.windows(2).any(|pair| !(pair[0] < pair[1])) {
+            return Err(qm::Error::new(
+                "Strike pillars must be strictly ascending"))
+        }
+        Ok(BumpVol::Region { pillars: pillars.to_vec(), pillar: pillar,
+            strikes: strikes.to_vec(), strike: strike, size: size })
+    }
 }
 
-impl Bump<Rc<VolSurface>> for BumpVol {
+fn validate_pillars(pillars: &[DateDayFraction], pillar: usize)
+    -> Result<(), qm::Error> {
+    if pillar >= pillars.len() {
+        return Err(qm::Error::new("Expiry pillar out of range"))
+    }
+    if pillars.windows(2).any(|pair| !(pair[0] < pair[1])) {
+        return Err(qm::Error::new(
+            "Expiry pillars must be strictly ascending"))
+    }
+    Ok(())
+}
 
-    fn apply(&self, surface: Rc<VolSurface>) -> Rc<VolSurface> {
+impl Bump<Arc<VolSurface>> for BumpVol {
+
+    fn apply(&self, surface: Arc<VolSurface>) -> Arc<VolSurface> {
         match self {
             &BumpVol::FlatAdditive { size }
-                => Rc::new(ParallelBumpVol::new(surface.clone(), size)),
+                => Arc::new(ParallelBumpVol::new(surface.clone(), size)),
 
             &BumpVol::TimeScaled { size, floor }
-                => Rc::new(TimeScaledBumpVol::new(surface.clone(), size, floor))
+                => Arc::new(TimeScaledBumpVol::new(surface.clone(), size,
+                    floor)),
+
+            &BumpVol::ExpiryPillar { ref pillars, pillar, size }
+                => Arc::new(PillarBumpVol::new(surface.clone(), pillars,
+                    pillar, size)),
+
+            &BumpVol::Region { ref pillars, pillar, ref strikes, strike, size }
+                => Arc::new(RegionBumpVol::new(surface.clone(), pillars,
+                    pillar, strikes, strike, size))
         }
     }
 }
@@ -1,14 +1,20 @@
-use std::rc::Rc;
+use std::sync::Arc;
+use core::qm;
 use data::curves::RateCurve;
 use data::curves::AnnualisedFlatBump;
 use data::curves::ContinuouslyCompoundedFlatBump;
+use data::curves::KeyRateBump;
+use data::curves::KeyRateShape;
 use data::bump::Bump;
+use dates::Date;
 
 /// Bump that defines all the supported bumps and risk transformations of a
 /// rate curve such as a borrow curve or a yield curve.
 pub enum BumpYield {
     FlatAnnualised { size: f64 },
-    FlatContinuouslyCompounded { size: f64 }
+    FlatContinuouslyCompounded { size: f64 },
+    KeyRate { pillars: Vec<Date>, pillar: usize, size: f64,
+        shape: KeyRateShape }
 }
 
 impl BumpYield {
@@ -19,22 +25,41 @@ impl BumpYield {
     pub fn new_flat_continuously_compounded(size: f64) -> BumpYield {
         BumpYield::FlatContinuouslyCompounded { size: size }
     }
+
+    /// Bump in continuously compounded yield, localised to one of a set of
+    /// pillar dates, which must be strictly ascending. See KeyRateBump.
+    pub fn new_key_rate(pillars: &[Date], pillar: usize, size: f64,
+        shape: KeyRateShape) -> Result<BumpYield, qm::Error> {
+        if pillar >= pillars.len() {
+            return Err(qm::Error::new("Key rate pillar out of range"))
+        }
+        if pillars.windows(2).any(|pair| !(pair[0] < pair[1])) {
+            return Err(qm::Error::new(
+                "Key rate pillars must be strictly ascending"))
+        }
+        Ok(BumpYield::KeyRate { pillars: pillars.to_vec(), pillar: pillar,
+            size: size, shape: shape })
+    }
 }
 
-impl Bump<Rc<RateCurve>> for BumpYield {
+impl Bump<Arc<RateCurve>> for BumpYield {
 
-    fn apply(&self, surface: Rc<RateCurve>) -> Rc<RateCurve> {
+    fn apply(&self, surface: Arc<RateCurve>) -> Arc<RateCurve> {
         match self {
             &BumpYield::FlatAnnualised { size }
-                => Rc::new(AnnualisedFlatBump::new(
+                => Arc::new(AnnualisedFlatBump::new(
                     surface.clone(), size)),
 
             // Note that an alternative methodology here would be to
             // bump the pillars. Consider this if profiling shows this
             // to be a bottleneck.
             &BumpYield::FlatContinuouslyCompounded { size }
-                => Rc::new(ContinuouslyCompoundedFlatBump::new(
-                    surface.clone(), size))
+                => Arc::new(ContinuouslyCompoundedFlatBump::new(
+                    surface.clone(), size)),
+
+            &BumpYield::KeyRate { ref pillars, pillar, size, shape }
+                => Arc::new(KeyRateBump::new(
+                    surface.clone(), pillars, pillar, size, shape))
         }
     }
 }
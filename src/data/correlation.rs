@@ -0,0 +1,151 @@
+use core::qm;
+use dates::Date;
+
+/// Local correlation between the log returns of two underliers. A local
+/// correlation applies to the increments of the underliers over any short
+/// period of time, as opposed to the terminal correlation of their values
+/// on some date. The two are only the same if the vols are flat.
+///
+/// The correlation is either constant, or a piecewise constant term
+/// structure. In a term structure, each correlation applies to all dates up
+/// to and including its pillar date, and the last correlation applies to
+/// all dates after that.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalCorrelation {
+    pillars: Vec<Date>,
+    correlations: Vec<f64>
+}
+
+impl LocalCorrelation {
+    /// Creates a correlation that is the same for all dates
+    pub fn new_constant(correlation: f64)
+        -> Result<LocalCorrelation, qm::Error> {
+        validate_correlation(correlation)?;
+        Ok(LocalCorrelation { pillars: Vec::new(),
+            correlations: vec!(correlation) })
+    }
+
+    /// Creates a term structure of local correlations, given pairs of pillar
+    /// date and correlation. The pillar dates must be strictly increasing.
+    pub fn new_term_structure(points: &[(Date, f64)])
+        -> Result<LocalCorrelation, qm::Error> {
+
+        if points.is_empty() {
+            return Err(qm::Error::new(
+                "Correlation term structure must have at least one point"))
+        }
+
+        for (i, &(date, correlation)) in points.iter().enumerate() {
+            validate_correlation(correlation)?;
+            if i > 0 && date <= points[i - 1].0 {
+                return Err(qm::Error::new(&format!("Correlation pillar \
+                    dates must be strictly increasing: {} follows {}",
+                    date, points[i - 1].0)))
+            }
+        }
+
+        Ok(LocalCorrelation {
+            pillars: points.iter().map(|p| p.0).collect(),
+            correlations: points.iter().map(|p| p.1).collect() })
+    }
+
+    /// Returns the local correlation that applies on the given date
+    pub fn correlation(&self, date: Date) -> f64 {
+        let index = self.pillars.iter().position(|pillar| date <= *pillar)
+            .unwrap_or(self.correlations.len() - 1);
+        self.correlations[index]
+    }
+
+    /// Returns true if the correlation is the same for all dates
+    pub fn is_constant(&self) -> bool {
+        self.pillars.is_empty()
+    }
+
+    /// The pillar dates of a term structure. Empty for a constant
+    /// correlation.
+    pub fn pillar_dates(&self) -> &[Date] {
+        &self.pillars
+    }
+
+    /// The correlations, one for each pillar date, or a single value for a
+    /// constant correlation.
+    pub fn correlations(&self) -> &[f64] {
+        &self.correlations
+    }
+
+    /// Creates a new correlation with the same pillars, where each
+    /// correlation has been modified by the given function, then clamped to
+    /// the range -1 to 1.
+    pub fn new_modified(&self, modify: &Fn(f64) -> f64) -> LocalCorrelation {
+        LocalCorrelation {
+            pillars: self.pillars.clone(),
+            correlations: self.correlations.iter()
+                .map(|c| modify(*c).max(-1.0).min(1.0)).collect() }
+    }
+}
+
+fn validate_correlation(correlation: f64) -> Result<(), qm::Error> {
+    if correlation >= -1.0 && correlation <= 1.0 {
+        Ok(())
+    } else {
+        Err(qm::Error::new(&format!(
+            "Correlation {} is outside the range -1 to 1", correlation)))
+    }
+}
+
+/// Correlations in market data are keyed by a combination of the ids of the
+/// two instruments. The key is the same whichever order the ids are given.
+pub fn correlation_key(first: &str, second: &str) -> String {
+    if first <= second {
+        format!("{}|{}", first, second)
+    } else {
+        format!("{}|{}", second, first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_and_term_structure() {
+        let d = Date::from_ymd(2017, 01, 02);
+
+        let constant = LocalCorrelation::new_constant(0.4).unwrap();
+        assert!(constant.is_constant());
+        assert_eq!(constant.correlation(d), 0.4);
+        assert_eq!(constant.correlation(d + 1000), 0.4);
+
+        let term = LocalCorrelation::new_term_structure(&[
+            (d + 30, 0.5), (d + 90, 0.25), (d + 365, -0.5)]).unwrap();
+        assert!(!term.is_constant());
+        assert_eq!(term.correlation(d), 0.5);
+        assert_eq!(term.correlation(d + 30), 0.5);
+        assert_eq!(term.correlation(d + 31), 0.25);
+        assert_eq!(term.correlation(d + 365), -0.5);
+        assert_eq!(term.correlation(d + 1000), -0.5);
+
+        // bumped correlations are clamped
+        let bumped = term.new_modified(&|c| c + 0.625);
+        assert_eq!(bumped.correlations(), &[1.0, 0.875, 0.125]);
+        assert_eq!(bumped.pillar_dates(), term.pillar_dates());
+    }
+
+    #[test]
+    fn invalid_correlations() {
+        let d = Date::from_ymd(2017, 01, 02);
+        assert!(LocalCorrelation::new_constant(1.01).is_err());
+        assert!(LocalCorrelation::new_constant(::std::f64::NAN).is_err());
+        assert!(LocalCorrelation::new_term_structure(&[]).is_err());
+        assert!(LocalCorrelation::new_term_structure(&[
+            (d + 30, 0.5), (d + 30, 0.3)]).is_err());
+        assert!(LocalCorrelation::new_term_structure(&[
+            (d + 30, 0.5), (d + 60, -1.5)]).is_err());
+    }
+
+    #[test]
+    fn correlation_key_is_symmetric() {
+        assert_eq!(correlation_key("BP.L", "GSK.L"), "BP.L|GSK.L");
+        assert_eq!(correlation_key("GSK.L", "BP.L"), "BP.L|GSK.L");
+    }
+}
@@ -2,8 +2,10 @@ use dates::Date;
 use math::interpolation::Interpolate;
 use math::interpolation::Linear;
 use math::interpolation::Extrap;
+use math::interpolation::hat_weight;
+use math::interpolation::bucket_weight;
 use core::qm;
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// Curves representing rate multipled by time are used in various ways in
 /// finance. For example, yield curves, hazard rate curves, repo rate curves.
@@ -13,7 +15,7 @@ use std::rc::Rc;
 /// a function of time such that the discount factor between time t1 and t2
 /// (fractions of a year) is exp(-r(t2) * t2) / exp(-r(t1) * t1).
 
-pub trait RateCurve {
+pub trait RateCurve : Send + Sync {
 
     /// Returns the base date
     fn base_date(&self) -> Date;
@@ -21,6 +23,11 @@ pub trait RateCurve {
     /// Returns true if the curve is zero for all dates
     fn is_zero(&self) -> bool { false }
 
+    /// For curves built by interpolating between pillars, returns the pillar
+    /// dates in ascending order. These are what key-rate risks are reported
+    /// against. Decorators should delegate to the curve they wrap.
+    fn pillar_dates(&self) -> Option<Vec<Date>> { None }
+
     /// This is the function to implement internally. However, in general
     /// users should call rt instead. r and t are really just internal to this
     /// class.
@@ -101,6 +108,10 @@ impl RateCurve for RateCurveAct365 {
     fn base_date(&self) -> Date {
         self.base
     }
+
+    fn pillar_dates(&self) -> Option<Vec<Date>> {
+        Some(self.interp.points().iter().map(|point| point.0).collect())
+    }
 }
 
 impl RateCurveAct365 {
@@ -116,7 +127,7 @@ impl RateCurveAct365 {
 
 /// Decorator that applies a flat bump in annualised yield to a rate curve
 pub struct AnnualisedFlatBump {
-    curve: Rc<RateCurve>,
+    curve: Arc<RateCurve>,
     bump: f64
 }
 
@@ -142,17 +153,21 @@ impl RateCurve for AnnualisedFlatBump {
     fn base_date(&self) -> Date {
         self.curve.base_date()
     }
+
+    fn pillar_dates(&self) -> Option<Vec<Date>> {
+        self.curve.pillar_dates()
+    }
 } 
 
 impl AnnualisedFlatBump {
-    pub fn new(curve: Rc<RateCurve>, bump: f64) -> AnnualisedFlatBump {
+    pub fn new(curve: Arc<RateCurve>, bump: f64) -> AnnualisedFlatBump {
         AnnualisedFlatBump { curve: curve, bump: bump }
     }
 }
 
 /// Decorator that applies a flat bump in contnuously compounded yield
 pub struct ContinuouslyCompoundedFlatBump {
-    curve: Rc<RateCurve>,
+    curve: Arc<RateCurve>,
     bump: f64
 }
 
@@ -165,10 +180,14 @@ impl RateCurve for ContinuouslyCompoundedFlatBump {
     fn base_date(&self) -> Date {
         self.curve.base_date()
     }
+
+    fn pillar_dates(&self) -> Option<Vec<Date>> {
+        self.curve.pillar_dates()
+    }
 } 
 
 impl ContinuouslyCompoundedFlatBump {
-    pub fn new(curve: Rc<RateCurve>, bump: f64)
+    pub fn new(curve: Arc<RateCurve>, bump: f64)
         -> ContinuouslyCompoundedFlatBump {
         ContinuouslyCompoundedFlatBump { curve: curve, bump: bump }
     }
@@ -177,7 +196,7 @@ impl ContinuouslyCompoundedFlatBump {
 /// Decorator that applies a relative bump. This is necessarily in continuously
 /// compounded yield, as that is what the original rate is.
 pub struct RelativeBump {
-    curve: Rc<RateCurve>,
+    curve: Arc<RateCurve>,
     one_plus_bump: f64
 }
 
@@ -190,14 +209,69 @@ impl RateCurve for RelativeBump {
     fn base_date(&self) -> Date {
         self.curve.base_date()
     }
+
+    fn pillar_dates(&self) -> Option<Vec<Date>> {
+        self.curve.pillar_dates()
+    }
 }
 
 impl RelativeBump {
-    pub fn new(curve: Rc<RateCurve>, bump: f64) -> RelativeBump {
+    pub fn new(curve: Arc<RateCurve>, bump: f64) -> RelativeBump {
         RelativeBump { curve: curve, one_plus_bump: bump }
     }
 }
 
+/// The shape of a key-rate bump. A triangular bump has its full size at the
+/// pillar, and falls linearly to zero at the neighbouring pillars. A pillar
+/// bump has its full size over the region closer to this pillar than to any
+/// other, and is zero elsewhere. In both cases, the end pillars extend flat
+/// beyond the first and last pillar, so bumping every pillar in turn adds up
+/// to a flat bump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyRateShape {
+    Triangular,
+    Pillar
+}
+
+/// Decorator that applies a bump in continuously compounded yield, localised
+/// around one of a set of pillar dates. The pillars would normally be those
+/// of the curve itself, but need not be.
+pub struct KeyRateBump {
+    curve: Arc<RateCurve>,
+    pillars: Vec<Date>,
+    pillar: usize,
+    bump: f64,
+    shape: KeyRateShape
+}
+
+impl RateCurve for KeyRateBump {
+    fn r_and_t(&self, date: Date) -> Result<(f64, f64), qm::Error> {
+        let (r, t) = self.curve.r_and_t(date)?;
+        let weight = match self.shape {
+            KeyRateShape::Triangular
+                => hat_weight(&self.pillars, self.pillar, date),
+            KeyRateShape::Pillar
+                => bucket_weight(&self.pillars, self.pillar, date)
+        };
+        Ok((r + self.bump * weight, t))
+    }
+
+    fn base_date(&self) -> Date {
+        self.curve.base_date()
+    }
+
+    fn pillar_dates(&self) -> Option<Vec<Date>> {
+        self.curve.pillar_dates()
+    }
+}
+
+impl KeyRateBump {
+    pub fn new(curve: Arc<RateCurve>, pillars: &[Date], pillar: usize,
+        bump: f64, shape: KeyRateShape) -> KeyRateBump {
+        KeyRateBump { curve: curve, pillars: pillars.to_vec(),
+            pillar: pillar, bump: bump, shape: shape }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -230,6 +304,49 @@ mod tests {
         assert_rt(c.rt(d + 365), 0.082 * 365.0 / 365.0);
     }
 
+    #[test]
+    fn key_rate_bumps() {
+
+        let base = Date::from_ymd(2017, 01, 01);
+        let d = base;
+        let points = [(d, 0.05), (d + 14, 0.08), (d + 56, 0.09),
+            (d + 112, 0.085), (d + 224, 0.082)];
+        let c: Arc<RateCurve> = Arc::new(RateCurveAct365::new(base, &points,
+            Extrap::Flat, Extrap::Flat).unwrap());
+        let pillars = c.pillar_dates().unwrap();
+        assert_eq!(pillars.len(), 5);
+
+        let bump = 0.001;
+        let triangular = KeyRateBump::new(c.clone(), &pillars, 2, bump,
+            KeyRateShape::Triangular);
+        assert_rt(triangular.rt(d + 56), 0.091 * 56.0 / 365.0);
+        assert_rt(triangular.rt(d + 35), 0.0855 * 35.0 / 365.0);
+        assert_rt(triangular.rt(d + 84), 0.0880 * 84.0 / 365.0);
+        assert_rt(triangular.rt(d + 14), 0.08 * 14.0 / 365.0);
+
+        let pillar = KeyRateBump::new(c.clone(), &pillars, 2, bump,
+            KeyRateShape::Pillar);
+        for &(days, weight) in [(34, 0.0), (35, 1.0), (83, 1.0), (84, 0.0)]
+            .iter() {
+            let shift = pillar.rt(d + days).unwrap() - c.rt(d + days).unwrap();
+            assert_rt(Ok(shift), bump * weight * days as f64 / 365.0);
+        }
+
+        // bumping every pillar adds up to a flat bump, even beyond the ends
+        for shape in [KeyRateShape::Triangular, KeyRateShape::Pillar].iter() {
+            for &days in [7, 40, 100, 300].iter() {
+                let unbumped = c.rt(d + days).unwrap();
+                let mut total = 0.0;
+                for i in 0..pillars.len() {
+                    let bumped = KeyRateBump::new(c.clone(), &pillars, i,
+                        bump, *shape);
+                    total += bumped.rt(d + days).unwrap() - unbumped;
+                }
+                assert_rt(Ok(total), bump * days as f64 / 365.0);
+            }
+        }
+    }
+
     fn assert_rt(rt: Result<f64, qm::Error>, v: f64) {
 
         let interpolated = rt.unwrap();
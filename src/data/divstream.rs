@@ -4,7 +4,7 @@ use data::curves::RelativeBump;
 use data::forward::log_discount_with_borrow;
 use data::forward::discount_with_borrow;
 use core::qm;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::f64::NAN;
 
 /// A dividend is a corporate action that pays shareholders an amount of cash.
@@ -74,12 +74,12 @@ impl Dividend {
 /// to ensure that Futures and equity swaps are matched correctly.
 pub struct DividendStream {
     dividends: Vec<Dividend>,
-    div_yield: Rc<RateCurve>,
+    div_yield: Arc<RateCurve>,
     last_cash_ex_date: Date
 }
 
 impl DividendStream {
-    pub fn new(dividends: &[Dividend], div_yield: Rc<RateCurve>) 
+    pub fn new(dividends: &[Dividend], div_yield: Arc<RateCurve>) 
         -> DividendStream {
 
         // the last cash ex date is important for volatility models such as
@@ -108,7 +108,7 @@ impl DividendStream {
            div.bump_all_relative(one_plus_bump);
         }
 
-        let bumped_yield = Rc::new(RelativeBump::new(divs.div_yield(), bump)); 
+        let bumped_yield = Arc::new(RelativeBump::new(divs.div_yield(), bump)); 
 
         DividendStream {
             dividends: bumped_divs,
@@ -117,7 +117,7 @@ impl DividendStream {
     }
 
     pub fn dividends(&self) -> &[Dividend] { &self.dividends }
-    pub fn div_yield(&self) -> Rc<RateCurve> { Rc::clone(&self.div_yield) }
+    pub fn div_yield(&self) -> Arc<RateCurve> { Arc::clone(&self.div_yield) }
     pub fn last_cash_ex_date(&self) -> Date { self.last_cash_ex_date }
 }
 
@@ -447,7 +447,7 @@ mod tests {
             (d + 365 * 5, 0.01), (d + 365 * 10, 0.015)];
         let curve = RateCurveAct365::new(d + 365 * 2, &points,
             Extrap::Zero, Extrap::Flat).unwrap();
-        let div_yield = Rc::new(curve);
+        let div_yield = Arc::new(curve);
 
         DividendStream::new(&divs, div_yield) 
     }
@@ -3,14 +3,15 @@ use dates::rules::DateRule;
 use math::interpolation::Interpolate;
 use data::divstream::DividendBootstrap;
 use data::divstream::DividendStream;
+use data::divstream::Dividend;
 use data::curves::RateCurve;
 use core::qm;
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// Forward curve. This represents the expectation value of some asset over
 /// time. It is implemented in different ways for futures (generally driftless)
 /// equities and other assets.
-pub trait Forward {
+pub trait Forward : Send + Sync {
 
     /// Returns the forward on the given date. For example, this may be
     /// the equity forward. In almost all cases, forwards can be considered
@@ -23,6 +24,14 @@ pub trait Forward {
     fn fixed_divs_after(&self, _date: Date) -> Result<f64, qm::Error> {
         Ok(0.0)
     }
+
+    /// Returns the discrete dividends, in ex date order, that go ex after
+    /// the base date of the forward. These are needed by backward-induction
+    /// pricers, which apply them as jumps in the underlying. Defaults to
+    /// returning none.
+    fn dividends(&self) -> &[Dividend] {
+        &[]
+    }
 }
 
 /// Driftless forward, for example for a future, where the expectation on any
@@ -66,11 +75,12 @@ impl InterpolatedForward {
 /// An equity forward has a spot, a discount rate which, together with a
 /// borrow, defines the rate of growth, plus a dividend stream.
 pub struct EquityForward {
-    settlement: Rc<DateRule>,
-    rate: Rc<RateCurve>,
-    borrow: Rc<RateCurve>,
-    div_yield: Rc<RateCurve>,
+    settlement: Arc<DateRule>,
+    rate: Arc<RateCurve>,
+    borrow: Arc<RateCurve>,
+    div_yield: Arc<RateCurve>,
     bootstrap: DividendBootstrap,
+    dividends: Vec<Dividend>,
     reference_spot: f64,
     base_log_discount: f64
 }
@@ -97,15 +107,19 @@ impl Forward for EquityForward {
     fn fixed_divs_after(&self, date: Date) -> Result<f64, qm::Error> {
         self.bootstrap.discounted_cash_divs_after(date)
     }
+
+    fn dividends(&self) -> &[Dividend] {
+        &self.dividends
+    }
 }
 
 impl EquityForward {
     pub fn new(
         base_date: Date,
         spot: f64,
-        settlement: Rc<DateRule>,
-        rate: Rc<RateCurve>,
-        borrow: Rc<RateCurve>,
+        settlement: Arc<DateRule>,
+        rate: Arc<RateCurve>,
+        borrow: Arc<RateCurve>,
         divs: &DividendStream,
         high_water_mark: Date) -> Result<EquityForward, qm::Error> {
 
@@ -133,6 +147,8 @@ impl EquityForward {
             borrow: borrow,
             div_yield: divs.div_yield(),
             bootstrap: bootstrap,
+            dividends: divs.dividends().iter()
+                .filter(|d| d.ex_date() > base_date).cloned().collect(),
             reference_spot: reference_spot,
             base_log_discount: base_log_discount })
     }
@@ -169,7 +185,7 @@ pub fn log_discount_with_borrow(rate: &RateCurve, borrow: &RateCurve,
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::rc::Rc;
+    use std::sync::Arc;
     use math::numerics::approx_eq;
     use math::interpolation::Extrap;
     use math::interpolation::CubicSpline;
@@ -211,8 +227,8 @@ mod tests {
         let divs = create_sample_divstream();
         let rate = create_sample_rate();
         let borrow = create_sample_borrow();
-        let calendar = Rc::new(WeekdayCalendar{});
-        let settlement = Rc::new(BusinessDays::new_step(calendar, 2));
+        let calendar = Arc::new(WeekdayCalendar{});
+        let settlement = Arc::new(BusinessDays::new_step(calendar, 2));
 
         let fwd = EquityForward::new(d, spot, settlement, rate, borrow, &divs,
             d + 1500).unwrap();
@@ -252,24 +268,24 @@ mod tests {
             (d + 365 * 5, 0.01), (d + 365 * 10, 0.015)];
         let curve = RateCurveAct365::new(d + 365 * 2, &points,
             Extrap::Zero, Extrap::Flat).unwrap();
-        let div_yield = Rc::new(curve);
+        let div_yield = Arc::new(curve);
 
         DividendStream::new(&divs, div_yield)
     }
 
-    fn create_sample_rate() -> Rc<RateCurve> {
+    fn create_sample_rate() -> Arc<RateCurve> {
         let d = Date::from_ymd(2016, 12, 30);
         let rate_points = [(d, 0.05), (d + 14, 0.08), (d + 182, 0.09),
             (d + 364, 0.085), (d + 728, 0.082)];
-        Rc::new(RateCurveAct365::new(d, &rate_points,
+        Arc::new(RateCurveAct365::new(d, &rate_points,
             Extrap::Flat, Extrap::Flat).unwrap())
     }
 
-    fn create_sample_borrow() -> Rc<RateCurve> {
+    fn create_sample_borrow() -> Arc<RateCurve> {
         let d = Date::from_ymd(2016, 12, 30);
         let borrow_points = [(d, 0.01), (d + 196, 0.012),
             (d + 364, 0.0125), (d + 728, 0.012)];
-        Rc::new(RateCurveAct365::new(d, &borrow_points,
+        Arc::new(RateCurveAct365::new(d, &borrow_points,
             Extrap::Flat, Extrap::Flat).unwrap())
     }
 
@@ -1,13 +1,16 @@
 pub mod bump;
+pub mod bumpcorrelation;
 pub mod bumpdivs;
 pub mod bumpspot;
 pub mod bumptime;
 pub mod bumpvol;
 pub mod bumpyield;
+pub mod correlation;
 pub mod curves;
 pub mod divstream;
 pub mod fixings;
 pub mod forward;
+pub mod volarbitrage;
 pub mod voldecorators;
 pub mod volsmile;
 pub mod volsurface;
@@ -0,0 +1,439 @@
+use std::fmt;
+use data::volsurface::VolSurface;
+use data::volsurface::VolQuoting;
+use data::forward::Forward;
+use dates::datetime::DateDayFraction;
+use math::optionpricing::Black76;
+use math::optionpricing::Bachelier;
+use math::optionpricing::ShiftedBlack76;
+use core::qm;
+
+/// Checks a vol surface for static arbitrage, by scanning a grid of strikes
+/// and expiries. The expiries are specified as offsets in calendar days from
+/// the base date of the surface, and the strikes as normalised strikes,
+/// i.e. numbers of atm standard deviations from the forward, so the same
+/// checker can be used for any surface, regardless of its date or the level
+/// of its underlying.
+///
+/// We check for three sorts of arbitrage:
+///
+/// * Calendar spread arbitrage, where the total variance decreases in time
+///   at a fixed forward moneyness
+/// * Call prices that increase with strike
+/// * Butterfly arbitrage, where call prices are not convex in strike, which
+///   implies a negative probability density
+pub struct VolArbitrageChecker {
+    expiry_days: Vec<i32>,
+    normalised_strikes: Vec<f64>,
+    tolerance: f64
+}
+
+impl VolArbitrageChecker {
+    /// Creates a checker. The expiry days must be positive and ascending,
+    /// and the normalised strikes ascending. The tolerance is relative:
+    /// variances are compared to within tolerance times the atm variance,
+    /// and prices within tolerance times the atm price, so that rounding
+    /// errors do not show up as arbitrage.
+    pub fn new(expiry_days: &[i32], normalised_strikes: &[f64],
+        tolerance: f64) -> Result<VolArbitrageChecker, qm::Error> {
+
+        if expiry_days.is_empty() || normalised_strikes.len() < 3 {
+            return Err(qm::Error::new("Arbitrage checker needs at least one \
+                expiry and three strikes"))
+        }
+        if expiry_days[0] <= 0 || expiry_days.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(qm::Error::new("Arbitrage checker expiries must be \
+                positive and strictly ascending"))
+        }
+        if normalised_strikes.windows(2).any(|w| !(w[0] < w[1])) {
+            return Err(qm::Error::new("Arbitrage checker strikes must be \
+                strictly ascending"))
+        }
+        if !(tolerance >= 0.0) {
+            return Err(qm::Error::new("Arbitrage checker tolerance must be \
+                non-negative"))
+        }
+
+        Ok(VolArbitrageChecker {
+            expiry_days: expiry_days.to_vec(),
+            normalised_strikes: normalised_strikes.to_vec(),
+            tolerance: tolerance
+        })
+    }
+
+    /// Checks the surface, using the forward embedded in the surface. This
+    /// errors if the surface has no forward, in which case you should use
+    /// check_with_forward.
+    pub fn check(&self, surface: &VolSurface)
+        -> Result<VolArbitrageReport, qm::Error> {
+
+        match surface.forward() {
+            Some(forward) => self.check_with_forward(surface, forward),
+            None => Err(qm::Error::new("Vol surface has no forward. Use \
+                check_with_forward to supply one."))
+        }
+    }
+
+    /// Checks the surface, given the forward to use for converting strikes
+    /// and pricing. Errors are only returned if the checker cannot run at
+    /// all. Failures to evaluate the surface at any given expiry are
+    /// reported as violations, as they are normally caused by arbitrage in
+    /// the underlying data.
+    pub fn check_with_forward(&self, surface: &VolSurface, forward: &Forward)
+        -> Result<VolArbitrageReport, qm::Error> {
+
+        let quoting = surface.vol_quoting();
+        let base = surface.base_date();
+        let n = self.normalised_strikes.len();
+        let mut violations = Vec::new();
+
+        // the date and forward of the previous expiry we could evaluate
+        let mut previous: Option<(DateDayFraction, f64)> = None;
+
+        for days in self.expiry_days.iter() {
+            let expiry = base + *days;
+            let slice = match self.slice(surface, forward, quoting, expiry) {
+                Ok(slice) => slice,
+                Err(e) => {
+                    violations.push(VolArbitrage::Unevaluable {
+                        expiry: expiry, message: e.to_string() });
+                    previous = None;
+                    continue
+                }
+            };
+
+            // Calendar spreads. Compare with the previous expiry at the same
+            // forward moneyness.
+            if let Some((prev_expiry, prev_forward)) = previous {
+                let prev_strikes: Vec<f64> = slice.strikes.iter().map(|k|
+                    quoting.move_strike(*k, slice.forward, prev_forward))
+                    .collect();
+                let mut prev_variances = vec![0.0; n];
+                match surface.variances(prev_expiry, &prev_strikes,
+                    &mut prev_variances) {
+                    Ok(()) => for i in 0..n {
+                        if slice.variances[i] < prev_variances[i]
+                            - self.tolerance * slice.atm_variance {
+                            violations.push(VolArbitrage::CalendarSpread {
+                                expiry: expiry,
+                                previous_expiry: prev_expiry,
+                                strike: slice.strikes[i],
+                                variance: slice.variances[i],
+                                previous_variance: prev_variances[i] });
+                        }
+                    },
+                    Err(e) => violations.push(VolArbitrage::Unevaluable {
+                        expiry: prev_expiry, message: e.to_string() })
+                }
+            }
+
+            // Monotonicity and convexity of undiscounted call prices
+            let price_tolerance = self.tolerance * slice.atm_price;
+            let strikes = &slice.strikes;
+            let prices = &slice.prices;
+            for i in 1..n {
+                if prices[i] > prices[i - 1] + price_tolerance {
+                    violations.push(VolArbitrage::NonMonotoneCallPrice {
+                        expiry: expiry,
+                        strike: strikes[i],
+                        slope: (prices[i] - prices[i - 1])
+                            / (strikes[i] - strikes[i - 1]) });
+                }
+            }
+            for i in 1..n - 1 {
+                let left = (prices[i] - prices[i - 1])
+                    / (strikes[i] - strikes[i - 1]);
+                let right = (prices[i + 1] - prices[i])
+                    / (strikes[i + 1] - strikes[i]);
+                let width = 0.5 * (strikes[i + 1] - strikes[i - 1]);
+                if (right - left) * width < -price_tolerance {
+                    violations.push(VolArbitrage::NegativeDensity {
+                        expiry: expiry,
+                        strike: strikes[i],
+                        density: (right - left) / width });
+                }
+            }
+
+            previous = Some((expiry, slice.forward));
+        }
+
+        Ok(VolArbitrageReport { violations: violations })
+    }
+
+    /// Evaluates the strikes, variances and call prices at one expiry
+    fn slice(&self, surface: &VolSurface, forward: &Forward,
+        quoting: VolQuoting, expiry: DateDayFraction)
+        -> Result<Slice, qm::Error> {
+
+        let f = forward.forward(expiry.date())?;
+        let atm_variance = surface.variance(expiry, f)?;
+        if !(atm_variance > 0.0) {
+            return Err(qm::Error::new(&format!("Non-positive atm variance {} \
+                at {:?}", atm_variance, expiry)))
+        }
+        let strikes = quoting.to_strikes(&self.normalised_strikes, f,
+            atm_variance.sqrt());
+        let mut variances = vec![0.0; strikes.len()];
+        surface.variances(expiry, &strikes, &mut variances)?;
+
+        // Cash dividend displacement is only relevant to log-normals. We
+        // check JumpDivs surfaces as if they were plain log-normals, which
+        // is an approximation.
+        let displacement = match quoting {
+            VolQuoting::Normal => 0.0,
+            _ => surface.displacement(expiry.date()).unwrap_or(0.0)
+        };
+        let fd = f - displacement;
+        let mut prices = Vec::with_capacity(strikes.len());
+        for (k, variance) in strikes.iter().zip(variances.iter()) {
+            if *variance < 0.0 {
+                return Err(qm::Error::new(&format!("Negative variance {} at \
+                    strike {}", variance, k)))
+            }
+            prices.push(call_price(quoting, fd, k + displacement,
+                variance.sqrt())?);
+        }
+        let atm_price = call_price(quoting, fd, f + displacement,
+            atm_variance.sqrt())?;
+
+        Ok(Slice { forward: f, strikes: strikes, variances: variances,
+            prices: prices, atm_variance: atm_variance, atm_price: atm_price })
+    }
+}
+
+struct Slice {
+    forward: f64,
+    strikes: Vec<f64>,
+    variances: Vec<f64>,
+    prices: Vec<f64>,
+    atm_variance: f64,
+    atm_price: f64
+}
+
+/// Undiscounted call price using the formula matching the vol quoting
+fn call_price(quoting: VolQuoting, forward: f64, strike: f64, sqrt_var: f64)
+    -> Result<f64, qm::Error> {
+    Ok(match quoting {
+        VolQuoting::LogNormal => {
+            if !(forward > 0.0) || !(strike > 0.0) {
+                return Err(qm::Error::new(&format!("Log-normal vols need \
+                    positive forward {} and strike {}", forward, strike)))
+            }
+            Black76::new()?.call_price(1.0, forward, strike, sqrt_var)
+        },
+        VolQuoting::Normal =>
+            Bachelier::new()?.call_price(1.0, forward, strike, sqrt_var),
+        VolQuoting::ShiftedLogNormal { shift } => {
+            if !(forward + shift > 0.0) || !(strike + shift > 0.0) {
+                return Err(qm::Error::new(&format!("Shifted log-normal vols \
+                    need forward {} and strike {} above -{}", forward, strike,
+                    shift)))
+            }
+            ShiftedBlack76::new(shift)?.call_price(1.0, forward, strike,
+                sqrt_var)
+        }
+    })
+}
+
+/// A single instance of static arbitrage found by the VolArbitrageChecker.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VolArbitrage {
+    /// The total variance at this expiry and strike is less than the
+    /// variance at the previous expiry, at the same forward moneyness
+    CalendarSpread {
+        expiry: DateDayFraction,
+        previous_expiry: DateDayFraction,
+        strike: f64,
+        variance: f64,
+        previous_variance: f64
+    },
+
+    /// The undiscounted call price increases from the previous strike to
+    /// this one. The slope is the rate of increase.
+    NonMonotoneCallPrice {
+        expiry: DateDayFraction,
+        strike: f64,
+        slope: f64
+    },
+
+    /// Call prices are concave around this strike, which implies a negative
+    /// density, estimated by finite differences.
+    NegativeDensity {
+        expiry: DateDayFraction,
+        strike: f64,
+        density: f64
+    },
+
+    /// The surface could not be evaluated at this expiry
+    Unevaluable {
+        expiry: DateDayFraction,
+        message: String
+    }
+}
+
+impl fmt::Display for VolArbitrage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VolArbitrage::CalendarSpread { expiry, previous_expiry, strike,
+                variance, previous_variance } =>
+                write!(f, "calendar spread at {:?} strike {}: variance {} is \
+                    below {} at {:?}", expiry, strike, variance,
+                    previous_variance, previous_expiry),
+            VolArbitrage::NonMonotoneCallPrice { expiry, strike, slope } =>
+                write!(f, "call price increasing at {:?} strike {}: \
+                    slope {}", expiry, strike, slope),
+            VolArbitrage::NegativeDensity { expiry, strike, density } =>
+                write!(f, "negative density at {:?} strike {}: {}",
+                    expiry, strike, density),
+            VolArbitrage::Unevaluable { expiry, ref message } =>
+                write!(f, "cannot evaluate at {:?}: {}", expiry, message)
+        }
+    }
+}
+
+/// The result of running the VolArbitrageChecker over a surface
+#[derive(Debug, Clone)]
+pub struct VolArbitrageReport {
+    violations: Vec<VolArbitrage>
+}
+
+impl VolArbitrageReport {
+    /// Returns true if no arbitrage was found
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// All the arbitrage found, in order of expiry
+    pub fn violations(&self) -> &[VolArbitrage] {
+        &self.violations
+    }
+}
+
+impl fmt::Display for VolArbitrageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "no arbitrage")
+        }
+        for (i, violation) in self.violations.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dates::Date;
+    use dates::calendar::WeekdayCalendar;
+    use data::volsurface::FlatVolSurface;
+    use data::volsurface::VolByProbability;
+    use data::volsurface::DivAssumptions;
+    use data::volsmile::CubicSplineSmile;
+    use data::forward::DriftlessForward;
+
+    fn sample_checker() -> VolArbitrageChecker {
+        VolArbitrageChecker::new(&[7, 30, 91, 182, 365, 730],
+            &[-3.0, -2.0, -1.5, -1.0, -0.5, 0.0, 0.5, 1.0, 1.5, 2.0, 3.0],
+            1e-10).unwrap()
+    }
+
+    #[test]
+    fn flat_surface_is_clean() {
+        let calendar = Box::new(WeekdayCalendar());
+        let base = DateDayFraction::new(Date::from_ymd(2018, 05, 30), 0.2);
+        let surface = FlatVolSurface::new(0.3, calendar, base);
+        let forward = DriftlessForward::new(100.0);
+
+        let report = sample_checker().check_with_forward(&surface, &forward)
+            .unwrap();
+        assert!(report.is_clean(), "{}", report);
+
+        // there is no forward on a flat surface
+        assert!(sample_checker().check(&surface).is_err());
+    }
+
+    #[test]
+    fn butterfly_arbitrage_is_reported() {
+        let calendar = Box::new(WeekdayCalendar());
+        let base_date = Date::from_ymd(2018, 05, 30);
+        let base = DateDayFraction::new(base_date, 0.2);
+        let forward = Box::new(DriftlessForward::new(100.0));
+
+        // A smile with a sharp spike in vol just below the money. This
+        // makes the call price concave, or even increasing, there.
+        let points = [(50.0, 0.3), (90.0, 0.3), (95.0, 0.9), (100.0, 0.3),
+            (150.0, 0.3)];
+        let expiry = DateDayFraction::new(base_date + 91, 0.2);
+        let smiles = vec![(expiry, CubicSplineSmile::new(&points).unwrap())];
+        let surface = VolByProbability::new(&smiles, calendar, base, forward,
+            DivAssumptions::NoCashDivs).unwrap();
+
+        let checker = VolArbitrageChecker::new(&[91],
+            &[-1.0, -0.6, -0.5, -0.4, -0.3, -0.2, -0.1, 0.0, 0.5, 1.0],
+            1e-10).unwrap();
+        let report = checker.check(&surface).unwrap();
+        assert!(!report.is_clean());
+
+        // all the violations are at the expiry and near the spike, though
+        // the spline overshoots a little either side of it
+        let mut found_density = false;
+        for violation in report.violations() {
+            match *violation {
+                VolArbitrage::NegativeDensity { expiry: e, strike, .. } => {
+                    assert_eq!(e, expiry);
+                    assert!(strike > 80.0 && strike < 120.0, "{}", strike);
+                    found_density |= strike > 90.0 && strike < 100.0;
+                },
+                VolArbitrage::NonMonotoneCallPrice { expiry: e, strike, .. }
+                    => {
+                    assert_eq!(e, expiry);
+                    assert!(strike > 80.0 && strike < 120.0, "{}", strike);
+                },
+                _ => assert!(false, "unexpected violation {}", violation)
+            }
+        }
+        assert!(found_density, "{}", report);
+    }
+
+    #[test]
+    fn decreasing_variance_between_pillars_is_reported() {
+        let calendar = Box::new(WeekdayCalendar());
+        let base_date = Date::from_ymd(2018, 05, 30);
+        let base = DateDayFraction::new(base_date, 0.2);
+        let forward = Box::new(DriftlessForward::new(100.0));
+
+        // The atm variances increase, so the surface can be constructed,
+        // but the downside variance at the second pillar is lower than at
+        // the first.
+        let first = DateDayFraction::new(base_date + 91, 0.2);
+        let second = DateDayFraction::new(base_date + 182, 0.2);
+        let smiles = vec![
+            (first, CubicSplineSmile::new(&[(60.0, 0.6), (100.0, 0.2),
+                (140.0, 0.2)]).unwrap()),
+            (second, CubicSplineSmile::new(&[(60.0, 0.2), (100.0, 0.2),
+                (140.0, 0.2)]).unwrap())];
+        let surface = VolByProbability::new(&smiles, calendar, base, forward,
+            DivAssumptions::NoCashDivs).unwrap();
+
+        let checker = VolArbitrageChecker::new(&[91, 182],
+            &[-2.0, -1.0, 0.0, 1.0, 2.0], 1e-10).unwrap();
+        let report = checker.check(&surface).unwrap();
+        let calendar_spreads: Vec<&VolArbitrage> = report.violations().iter()
+            .filter(|v| match **v {
+                VolArbitrage::CalendarSpread { .. } => true,
+                _ => false }).collect();
+        assert!(!calendar_spreads.is_empty(), "{}", report);
+        for violation in calendar_spreads {
+            if let VolArbitrage::CalendarSpread { expiry, previous_expiry,
+                strike, .. } = *violation {
+                assert_eq!(expiry, second);
+                assert_eq!(previous_expiry, first);
+                assert!(strike < 100.0);
+            }
+        }
+    }
+}
@@ -1,24 +1,26 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use data::volsurface::VolSurface;
 use data::forward::Forward;
 use data::volsurface::DivAssumptions;
+use data::volsurface::VolQuoting;
 use dates::datetime::DateDayFraction;
 use dates::calendar::Calendar;
 use dates::Date;
 use core::qm;
+use math::interpolation::hat_weight;
 
 /// Time evolve a vol surface such that volatilities at all expiries
 /// remain constant, even between pillars. This is the evolution to use if
 /// you believe that term-structure changes to vol are caused by anticipated
 /// events, such as payroll figures and profit announcements.
 pub struct ConstantExpiryTimeEvolution {
-    base_vol: Rc<VolSurface>,
+    base_vol: Arc<VolSurface>,
     vol_time_offset: f64,
     base_date: DateDayFraction
 }
 
 impl ConstantExpiryTimeEvolution {
-    pub fn new(base_vol: Rc<VolSurface>, vol_time_offset: f64,
+    pub fn new(base_vol: Arc<VolSurface>, vol_time_offset: f64,
         base_date: DateDayFraction) -> ConstantExpiryTimeEvolution {
 
         ConstantExpiryTimeEvolution { 
@@ -54,6 +56,10 @@ impl VolSurface for ConstantExpiryTimeEvolution {
         self.base_vol.forward()
     }
 
+    fn pillar_dates(&self) -> Option<Vec<DateDayFraction>> {
+        self.base_vol.pillar_dates()
+    }
+
     fn base_date(&self) -> DateDayFraction {
         self.base_date
     }
@@ -65,6 +71,10 @@ impl VolSurface for ConstantExpiryTimeEvolution {
     fn displacement(&self, date: Date) -> Result<f64, qm::Error> {
         self.base_vol.displacement(date)
     }
+
+    fn vol_quoting(&self) -> VolQuoting {
+        self.base_vol.vol_quoting()
+    }
 }
 
 /// Time evolve a vol surface such that volatilities at all expiries roll
@@ -72,13 +82,13 @@ impl VolSurface for ConstantExpiryTimeEvolution {
 /// if there is fractional vol time at weekends, it may not be possible to
 /// roll expiries to exact date times that match the unrolled vol time.
 pub struct RollingExpiryTimeEvolution {
-    base_vol: Rc<VolSurface>,
+    base_vol: Arc<VolSurface>,
     vol_time_offset: f64,
     base_date: DateDayFraction
 }
 
 impl RollingExpiryTimeEvolution {
-    pub fn new(base_vol: Rc<VolSurface>, vol_time_offset: f64,
+    pub fn new(base_vol: Arc<VolSurface>, vol_time_offset: f64,
         base_date: DateDayFraction) -> RollingExpiryTimeEvolution {
         RollingExpiryTimeEvolution { 
             base_vol: base_vol, vol_time_offset: vol_time_offset,
@@ -118,6 +128,10 @@ impl VolSurface for RollingExpiryTimeEvolution {
         self.base_vol.forward()
     }
 
+    fn pillar_dates(&self) -> Option<Vec<DateDayFraction>> {
+        self.base_vol.pillar_dates()
+    }
+
     fn base_date(&self) -> DateDayFraction {
         self.base_date
     }
@@ -129,6 +143,10 @@ impl VolSurface for RollingExpiryTimeEvolution {
     fn displacement(&self, date: Date) -> Result<f64, qm::Error> {
         self.base_vol.displacement(date)
     }
+
+    fn vol_quoting(&self) -> VolQuoting {
+        self.base_vol.vol_quoting()
+    }
 }
 
 /// Apply a flat vol bump to a vol surface, the same relative bump size for
@@ -136,12 +154,12 @@ impl VolSurface for RollingExpiryTimeEvolution {
 /// zero. The vols that are bumped are those natural to the vol surface --
 /// business day if the vol surface has a business day calendar.
 pub struct ParallelBumpVol {
-    base_vol: Rc<VolSurface>,
+    base_vol: Arc<VolSurface>,
     bump: f64
 }
 
 impl ParallelBumpVol {
-    pub fn new(base_vol: Rc<VolSurface>, bump: f64) -> ParallelBumpVol {
+    pub fn new(base_vol: Arc<VolSurface>, bump: f64) -> ParallelBumpVol {
         ParallelBumpVol { base_vol: base_vol, bump: bump }
     }
 }
@@ -171,6 +189,10 @@ impl VolSurface for ParallelBumpVol {
         self.base_vol.forward()
     }
 
+    fn pillar_dates(&self) -> Option<Vec<DateDayFraction>> {
+        self.base_vol.pillar_dates()
+    }
+
     fn base_date(&self) -> DateDayFraction {
         self.base_vol.base_date()
     }
@@ -182,6 +204,10 @@ impl VolSurface for ParallelBumpVol {
     fn displacement(&self, date: Date) -> Result<f64, qm::Error> {
         self.base_vol.displacement(date)
     }
+
+    fn vol_quoting(&self) -> VolQuoting {
+        self.base_vol.vol_quoting()
+    }
 }
 
 /// Apply a vol bump that is scaled with sqrt T, to give a flat bump
@@ -190,13 +216,13 @@ impl VolSurface for ParallelBumpVol {
 /// some vol time, to avoid infinite vols at low T. Before this vol time,
 /// the vols are flat bumped. Typically use one month for the cutoff.
 pub struct TimeScaledBumpVol {
-    base_vol: Rc<VolSurface>,
+    base_vol: Arc<VolSurface>,
     bump: f64,
     vol_time_floor: f64
 }
 
 impl TimeScaledBumpVol {
-    pub fn new(base_vol: Rc<VolSurface>, bump: f64, vol_time_floor: f64)
+    pub fn new(base_vol: Arc<VolSurface>, bump: f64, vol_time_floor: f64)
         -> TimeScaledBumpVol {
 
         TimeScaledBumpVol { base_vol: base_vol, bump: bump,
@@ -230,6 +256,10 @@ impl VolSurface for TimeScaledBumpVol {
         self.base_vol.forward()
     }
 
+    fn pillar_dates(&self) -> Option<Vec<DateDayFraction>> {
+        self.base_vol.pillar_dates()
+    }
+
     fn base_date(&self) -> DateDayFraction {
         self.base_vol.base_date()
     }
@@ -241,18 +271,22 @@ impl VolSurface for TimeScaledBumpVol {
     fn displacement(&self, date: Date) -> Result<f64, qm::Error> {
         self.base_vol.displacement(date)
     }
+
+    fn vol_quoting(&self) -> VolQuoting {
+        self.base_vol.vol_quoting()
+    }
 }
 
 /// Apply a shift in the strike direction between two forwards to a vol
 /// surface. This may be done for sticky delta risk calculation or evolution,
 /// or it may be done for benchmarking one vol surface from another.
 pub struct StickyDeltaBumpVol {
-    base_vol: Rc<VolSurface>,
-    bumped_forward: Rc<Forward>
+    base_vol: Arc<VolSurface>,
+    bumped_forward: Arc<Forward>
 }
 
 impl StickyDeltaBumpVol {
-    pub fn new(base_vol: Rc<VolSurface>, bumped_forward: Rc<Forward>)
+    pub fn new(base_vol: Arc<VolSurface>, bumped_forward: Arc<Forward>)
         -> StickyDeltaBumpVol {
         StickyDeltaBumpVol { base_vol: base_vol, 
             bumped_forward: bumped_forward }
@@ -282,9 +316,10 @@ impl VolSurface for StickyDeltaBumpVol {
                 let date = date_time.date();
                 let new_forward = self.bumped_forward.forward(date)?;
                 let old_forward = fwd.forward(date)?;
-                let adjustment = old_forward / new_forward;
+                let quoting = self.base_vol.vol_quoting();
                 for i in 0..n {
-                    adj_strikes[i] *= adjustment;
+                    adj_strikes[i] = quoting.move_strike(adj_strikes[i],
+                        new_forward, old_forward);
                 }
 
                 self.base_vol.volatilities(date_time, &adj_strikes, out)
@@ -301,6 +336,10 @@ impl VolSurface for StickyDeltaBumpVol {
         Some(&*self.bumped_forward)
     }
 
+    fn pillar_dates(&self) -> Option<Vec<DateDayFraction>> {
+        self.base_vol.pillar_dates()
+    }
+
     fn base_date(&self) -> DateDayFraction {
         self.base_vol.base_date()
     }
@@ -321,7 +360,174 @@ impl VolSurface for StickyDeltaBumpVol {
                 surface. This needs more careful handling."))
         }
     }
+
+    fn vol_quoting(&self) -> VolQuoting {
+        self.base_vol.vol_quoting()
+    }
 }
+/// Apply a vol bump localised to a single expiry pillar. The bump has its
+/// full size at the pillar and tails off linearly in vol time, reaching zero
+/// at the neighbouring pillars. Before the first pillar or after the last,
+/// the end pillar takes the full bump. The weights of all the pillars sum to
+/// one, so bumping each pillar in turn decomposes a flat additive bump.
+///
+/// The pillars are normally those of the surface being bumped, but any
+/// ascending set of dates may be used. Negative bumps are floored at zero vol.
+pub struct PillarBumpVol {
+    base_vol: Arc<VolSurface>,
+    pillar_times: Vec<f64>,
+    pillar: usize,
+    bump: f64
+}
+
+impl PillarBumpVol {
+    pub fn new(base_vol: Arc<VolSurface>, pillars: &[DateDayFraction],
+        pillar: usize, bump: f64) -> PillarBumpVol {
+
+        let pillar_times = pillar_vol_times(&*base_vol, pillars);
+        PillarBumpVol { base_vol: base_vol, pillar_times: pillar_times,
+            pillar: pillar, bump: bump }
+    }
+}
+
+impl VolSurface for PillarBumpVol {
+
+    fn volatilities(&self,
+        date_time: DateDayFraction,
+        strikes: &[f64],
+        out: &mut[f64]) -> Result<(f64), qm::Error> {
+
+        let vol_time = self.base_vol.volatilities(date_time, strikes, out)?;
+        let weight = hat_weight(&self.pillar_times, self.pillar, vol_time);
+        if weight == 0.0 {
+            return Ok(vol_time)
+        }
+
+        let bump = self.bump * weight;
+        for i in 0..out.len() {
+            let vol = out[i] + bump;
+            out[i] = vol.max(0.0);
+        }
+
+        Ok(vol_time)
+    }
+
+    fn calendar(&self) -> &Calendar {
+        self.base_vol.calendar()
+    }
+
+    fn forward(&self) -> Option<&Forward> {
+        self.base_vol.forward()
+    }
+
+    fn pillar_dates(&self) -> Option<Vec<DateDayFraction>> {
+        self.base_vol.pillar_dates()
+    }
+
+    fn base_date(&self) -> DateDayFraction {
+        self.base_vol.base_date()
+    }
+
+    fn div_assumptions(&self) -> DivAssumptions {
+        self.base_vol.div_assumptions()
+    }
+
+    fn displacement(&self, date: Date) -> Result<f64, qm::Error> {
+        self.base_vol.displacement(date)
+    }
+
+    fn vol_quoting(&self) -> VolQuoting {
+        self.base_vol.vol_quoting()
+    }
+}
+
+/// Apply a vol bump localised to a region around one expiry pillar and one
+/// strike pillar. The weight is the product of the expiry weight, as in
+/// PillarBumpVol, and a similar weight in the strike direction, which is one
+/// at the strike pillar, zero at its neighbours and flat beyond the end
+/// strikes. As with PillarBumpVol, the weights over all regions sum to one.
+pub struct RegionBumpVol {
+    base_vol: Arc<VolSurface>,
+    pillar_times: Vec<f64>,
+    pillar: usize,
+    strikes: Vec<f64>,
+    strike: usize,
+    bump: f64
+}
+
+impl RegionBumpVol {
+    pub fn new(base_vol: Arc<VolSurface>, pillars: &[DateDayFraction],
+        pillar: usize, strikes: &[f64], strike: usize, bump: f64)
+        -> RegionBumpVol {
+
+        let pillar_times = pillar_vol_times(&*base_vol, pillars);
+        RegionBumpVol { base_vol: base_vol, pillar_times: pillar_times,
+            pillar: pillar, strikes: strikes.to_vec(), strike: strike,
+            bump: bump }
+    }
+}
+
+impl VolSurface for RegionBumpVol {
+
+    fn volatilities(&self,
+        date_time: DateDayFraction,
+        strikes: &[f64],
+        out: &mut[f64]) -> Result<(f64), qm::Error> {
+
+        let vol_time = self.base_vol.volatilities(date_time, strikes, out)?;
+        let weight = hat_weight(&self.pillar_times, self.pillar, vol_time);
+        if weight == 0.0 {
+            return Ok(vol_time)
+        }
+
+        for i in 0..out.len() {
+            let strike_weight = hat_weight(&self.strikes, self.strike,
+                strikes[i]);
+            let vol = out[i] + self.bump * weight * strike_weight;
+            out[i] = vol.max(0.0);
+        }
+
+        Ok(vol_time)
+    }
+
+    fn calendar(&self) -> &Calendar {
+        self.base_vol.calendar()
+    }
+
+    fn forward(&self) -> Option<&Forward> {
+        self.base_vol.forward()
+    }
+
+    fn pillar_dates(&self) -> Option<Vec<DateDayFraction>> {
+        self.base_vol.pillar_dates()
+    }
+
+    fn base_date(&self) -> DateDayFraction {
+        self.base_vol.base_date()
+    }
+
+    fn div_assumptions(&self) -> DivAssumptions {
+        self.base_vol.div_assumptions()
+    }
+
+    fn displacement(&self, date: Date) -> Result<f64, qm::Error> {
+        self.base_vol.displacement(date)
+    }
+
+    fn vol_quoting(&self) -> VolQuoting {
+        self.base_vol.vol_quoting()
+    }
+}
+
+/// Converts pillar dates to vol times, measured the same way as the vol
+/// times returned by the surface itself.
+fn pillar_vol_times(surface: &VolSurface, pillars: &[DateDayFraction])
+    -> Vec<f64> {
+    let base_date = surface.base_date();
+    pillars.iter().map(|pillar|
+        surface.calendar().year_fraction(base_date, *pillar)).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -336,7 +542,7 @@ mod tests {
     use math::interpolation::CubicSpline;
 
     fn sample_vol_surface(base: DateDayFraction)
-        -> Rc<VolByProbability<CubicSplineSmile>> {
+        -> Arc<VolByProbability<CubicSplineSmile>> {
 
         let calendar = Box::new(WeekdayCalendar());
         let d = base.date();
@@ -365,7 +571,7 @@ mod tests {
         smiles.push((DateDayFraction::new(d + 364, 0.7),
             CubicSplineSmile::new(&points).unwrap()));
 
-        Rc::new(VolByProbability::new(&smiles, calendar, base, fwd,
+        Arc::new(VolByProbability::new(&smiles, calendar, base, fwd,
             DivAssumptions::NoCashDivs).unwrap())
     }
 
@@ -401,7 +607,7 @@ mod tests {
         // to do the modification. Note that we add four days because 
         // 2012-05-25 is a Friday and we want to add two business days.
         let base_date = DateDayFraction::new(Date::from_ymd(2012, 05, 25), 0.2);
-        let unbumped: Rc<VolSurface> = sample_vol_surface(base_date);
+        let unbumped: Arc<VolSurface> = sample_vol_surface(base_date);
         let dynamics = VolTimeDynamics::ConstantExpiry;
         let mut bumped = unbumped.clone();
         dynamics.modify(&mut bumped, base_date.date() + 4).unwrap();
@@ -463,7 +669,7 @@ mod tests {
         // same as the previous test, but this time we use the dynamics enum
         // to do the modification
         let base_date = DateDayFraction::new(Date::from_ymd(2012, 05, 25), 0.2);
-        let unbumped: Rc<VolSurface> = sample_vol_surface(base_date);
+        let unbumped: Arc<VolSurface> = sample_vol_surface(base_date);
         let dynamics = VolTimeDynamics::RollingExpiry;
         let mut bumped = unbumped.clone();
         dynamics.modify(&mut bumped, base_date.date() + 4).unwrap();
@@ -547,7 +753,7 @@ mod tests {
             (d+120, 99.0), (d+240, 98.89), (d+480, 98.78), (d+960, 98.78)];
         let cs = Box::new(CubicSpline::new(&points,
             Extrap::Natural, Extrap::Natural).unwrap());
-        let fwd = Rc::new(InterpolatedForward::new(cs));
+        let fwd = Arc::new(InterpolatedForward::new(cs));
 
         let bumped = StickyDeltaBumpVol::new(unbumped.clone(), fwd);
 
@@ -567,6 +773,86 @@ mod tests {
         }
     }
 
+    #[test]
+    fn pillar_bumps_sum_to_parallel_bump() {
+
+        let base_date = DateDayFraction::new(Date::from_ymd(2012, 05, 25), 0.2);
+        let unbumped = sample_vol_surface(base_date);
+        let pillars = unbumped.pillar_dates().unwrap();
+        assert_eq!(pillars.len(), 4);
+        let bump = 0.01;
+
+        let strikes = vec![85.0, 88.0, 91.0, 94.0];
+        let mut unbumped_vols = vec![0.0; strikes.len()];
+        let mut bumped_vols = vec![0.0; strikes.len()];
+        let d = base_date.date();
+
+        // on a pillar, only that pillar's bump has any effect. Between
+        // pillars, the bump is shared between the two neighbours. Outside
+        // the pillars, the end pillar takes it all.
+        for &(days, pillar, expected) in [(28, 1, 1.0), (28, 2, 0.0),
+            (3, 0, 1.0), (500, 3, 1.0), (500, 2, 0.0)].iter() {
+            let expiry = DateDayFraction::new(d + days, 0.7);
+            let bumped = PillarBumpVol::new(unbumped.clone(), &pillars,
+                pillar, bump);
+            unbumped.volatilities(expiry, &strikes, &mut unbumped_vols)
+                .unwrap();
+            bumped.volatilities(expiry, &strikes, &mut bumped_vols).unwrap();
+            for i in 0..strikes.len() {
+                assert_approx(bumped_vols[i],
+                    unbumped_vols[i] + bump * expected, 1e-12);
+            }
+        }
+
+        let expiry = DateDayFraction::new(d + 60, 0.7);
+        unbumped.volatilities(expiry, &strikes, &mut unbumped_vols).unwrap();
+        let mut total = vec![0.0; strikes.len()];
+        for pillar in 0..pillars.len() {
+            let bumped = PillarBumpVol::new(unbumped.clone(), &pillars,
+                pillar, bump);
+            bumped.volatilities(expiry, &strikes, &mut bumped_vols).unwrap();
+            for i in 0..strikes.len() {
+                total[i] += bumped_vols[i] - unbumped_vols[i];
+            }
+        }
+        for i in 0..strikes.len() {
+            assert_approx(total[i], bump, 1e-12);
+        }
+    }
+
+    #[test]
+    fn region_bump_localised_in_strike() {
+
+        let base_date = DateDayFraction::new(Date::from_ymd(2012, 05, 25), 0.2);
+        let unbumped = sample_vol_surface(base_date);
+        let pillars = unbumped.pillar_dates().unwrap();
+        let strike_pillars = [60.0, 80.0, 100.0];
+        let bump = 0.01;
+
+        let strikes = vec![50.0, 60.0, 70.0, 80.0, 90.0, 100.0, 110.0];
+        let expected = [0.0, 0.0, 0.5, 1.0, 0.5, 0.0, 0.0];
+        let mut unbumped_vols = vec![0.0; strikes.len()];
+        let mut bumped_vols = vec![0.0; strikes.len()];
+
+        let expiry = pillars[2];
+        let bumped = RegionBumpVol::new(unbumped.clone(), &pillars, 2,
+            &strike_pillars, 1, bump);
+        unbumped.volatilities(expiry, &strikes, &mut unbumped_vols).unwrap();
+        bumped.volatilities(expiry, &strikes, &mut bumped_vols).unwrap();
+        for i in 0..strikes.len() {
+            assert_approx(bumped_vols[i],
+                unbumped_vols[i] + bump * expected[i], 1e-12);
+        }
+
+        // a different expiry pillar leaves this expiry alone
+        let bumped = RegionBumpVol::new(unbumped.clone(), &pillars, 0,
+            &strike_pillars, 1, bump);
+        bumped.volatilities(expiry, &strikes, &mut bumped_vols).unwrap();
+        for i in 0..strikes.len() {
+            assert_approx(bumped_vols[i], unbumped_vols[i], 1e-12);
+        }
+    }
+
     fn assert_approx(value: f64, expected: f64, tolerance: f64) {
         assert!(approx_eq(value, expected, tolerance),
             "value={} expected={} tolerance={}", value, expected, tolerance);
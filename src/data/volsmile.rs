@@ -1,12 +1,14 @@
 use math::interpolation::CubicSpline;
 use math::interpolation::Interpolate;
 use math::interpolation::Extrap;
+use math::optimization::LevenbergMarquardt;
+use data::volsurface::VolQuoting;
 use core::qm;
 use std::f64::NAN;
 
 /// A VolSmile is a curve of volatilities by strike, all for a specific date.
 
-pub trait VolSmile {
+pub trait VolSmile : Send + Sync {
 
     /// These volatilities must be converted to variances by squaring and
     /// multiplying by some t. The t to use depends on the vol surface. We
@@ -94,6 +96,444 @@ impl CubicSplineSmile {
     }
 }
 
+/// An SVI (stochastic volatility inspired) smile, as described by Gatheral.
+/// The total implied variance is a function of log strike k = ln(K/F):
+///
+/// w(k) = a + b (rho (k - m) + sqrt((k - m)^2 + sigma^2))
+///
+/// which is the raw parameterisation. The smile is linear in total
+/// variance in the wings, which is consistent with Roger Lee's moment
+/// formula, so it extrapolates sensibly provided the wing slopes
+/// b (1 - rho) and b (1 + rho) do not exceed two. Calibrated smiles always
+/// satisfy this. Because the parameterisation is in log strike and total
+/// variance, the smile needs to know its forward and the vol time to its
+/// expiry.
+#[derive(Debug, Clone)]
+pub struct SviSmile {
+    forward: f64,
+    time: f64,
+    a: f64,
+    b: f64,
+    rho: f64,
+    m: f64,
+    sigma: f64
+}
+
+impl VolSmile for SviSmile {
+
+    fn volatilities(
+        &self,
+        strikes: &[f64],
+        volatilities: &mut[f64]) -> Result<(), qm::Error> {
+
+        let n = strikes.len();
+        assert!(n == volatilities.len());
+
+        for i in 0..n {
+            if !(strikes[i] > 0.0) {
+                return Err(qm::Error::new(&format!("SVI smile requires \
+                    positive strikes: {}", strikes[i])))
+            }
+            let log_moneyness = (strikes[i] / self.forward).ln();
+            let variance = self.total_variance(log_moneyness);
+            volatilities[i] = (variance / self.time).sqrt();
+        }
+        Ok(())
+    }
+}
+
+impl SviSmile {
+
+    /// Creates an SVI smile from its raw parameters. The forward and time
+    /// must be positive, and the parameters must satisfy b >= 0, |rho| < 1,
+    /// sigma > 0 and a + b sigma sqrt(1 - rho^2) >= 0, which ensures that the
+    /// variance is non-negative everywhere.
+    pub fn new_raw(forward: f64, time: f64, a: f64, b: f64, rho: f64, m: f64,
+        sigma: f64) -> Result<SviSmile, qm::Error> {
+
+        if !(forward > 0.0) || !(time > 0.0) {
+            return Err(qm::Error::new(&format!("SVI smile requires positive \
+                forward {} and time {}", forward, time)))
+        }
+        if !(b >= 0.0) || !(rho.abs() < 1.0) || !(sigma > 0.0)
+            || !m.is_finite() {
+            return Err(qm::Error::new(&format!("Invalid SVI parameters: \
+                b={} rho={} m={} sigma={}", b, rho, m, sigma)))
+        }
+        let min_variance = a + b * sigma * (1.0 - rho * rho).sqrt();
+        if !(min_variance >= 0.0) {
+            return Err(qm::Error::new(&format!("SVI parameters give negative \
+                variance: a={} b={} rho={} sigma={}", a, b, rho, sigma)))
+        }
+
+        Ok(SviSmile { forward: forward, time: time, a: a, b: b, rho: rho,
+            m: m, sigma: sigma })
+    }
+
+    /// Creates an SVI smile from its natural parameters, as described by
+    /// Gatheral and Jacquier:
+    ///
+    /// w(k) = delta + omega / 2 (1 + zeta rho (k - mu)
+    ///     + sqrt((zeta (k - mu) + rho)^2 + 1 - rho^2))
+    ///
+    /// This is an alternative way of expressing the same smile as the raw
+    /// parameterisation, and requires omega >= 0, zeta > 0 and |rho| < 1.
+    pub fn new_natural(forward: f64, time: f64, delta: f64, mu: f64, rho: f64,
+        omega: f64, zeta: f64) -> Result<SviSmile, qm::Error> {
+
+        if !(zeta > 0.0) || !(rho.abs() < 1.0) {
+            return Err(qm::Error::new(&format!("Invalid natural SVI \
+                parameters: rho={} zeta={}", rho, zeta)))
+        }
+        let root = (1.0 - rho * rho).sqrt();
+        SviSmile::new_raw(forward, time,
+            delta + 0.5 * omega * (1.0 - rho * rho),
+            0.5 * omega * zeta,
+            rho,
+            mu - rho / zeta,
+            root / zeta)
+    }
+
+    /// Fits an SVI smile to quoted (strike, volatility) pairs, minimising the
+    /// weighted sum of squared differences in volatility. There must be at
+    /// least five quotes with non-zero weight, as SVI has five parameters.
+    /// The steeper wing slope, b (1 + |rho|), is kept within Lee's bound of
+    /// two.
+    pub fn calibrate(forward: f64, time: f64, quotes: &[(f64, f64)],
+        weights: &[f64]) -> Result<SviSmile, qm::Error> {
+
+        let sqrt_weights = calibration_weights(quotes, weights, 5)?;
+        if !(forward > 0.0) || !(time > 0.0) {
+            return Err(qm::Error::new(&format!("SVI calibration requires \
+                positive forward {} and time {}", forward, time)))
+        }
+        if quotes.iter().any(|q| !(q.0 > 0.0)) {
+            return Err(qm::Error::new("SVI calibration requires positive \
+                strikes"))
+        }
+        let log_strikes: Vec<f64> = quotes.iter()
+            .map(|q| (q.0 / forward).ln()).collect();
+
+        // We fit the minimum variance rather than a, and the steeper wing
+        // slope rather than b, as this lets us impose the constraints of
+        // non-negative variance and Lee's bound as simple bounds.
+        // Parameters are [min_variance, max_slope, rho, m, sigma].
+        let n = quotes.len();
+        let variances: Vec<f64> = quotes.iter()
+            .map(|q| q.1 * q.1 * time).collect();
+        let max_variance = variances.iter().fold(0.0, |a: f64, v| a.max(*v));
+        let min_variance = variances.iter().fold(max_variance,
+            |a: f64, v| a.min(*v));
+        let k_min = log_strikes.iter().fold(0.0, |a: f64, k| a.min(*k));
+        let k_max = log_strikes.iter().fold(0.0, |a: f64, k| a.max(*k));
+        let k_range = (k_max - k_min).max(0.1);
+
+        let initial = [0.9 * min_variance,
+            (max_variance - min_variance).max(1e-4) / k_range, 0.0, 0.0,
+            0.1 * k_range];
+        let lower = [0.0, 0.0, -0.999, k_min - k_range, 1e-4];
+        let upper = [max_variance, 2.0, 0.999, k_max + k_range, 2.0 * k_range];
+
+        let to_raw = |p: &[f64]| -> (f64, f64, f64, f64, f64) {
+            let b = p[1] / (1.0 + p[2].abs());
+            let a = p[0] - b * p[4] * (1.0 - p[2] * p[2]).sqrt();
+            (a, b, p[2], p[3], p[4])
+        };
+
+        let lm = LevenbergMarquardt::new(200, 1e-14);
+        let params = lm.minimise(|p, r| {
+            let (a, b, rho, m, sigma) = to_raw(p);
+            let smile = SviSmile { forward: forward, time: time, a: a, b: b,
+                rho: rho, m: m, sigma: sigma };
+            for i in 0..n {
+                let vol = (smile.total_variance(log_strikes[i]) / time).sqrt();
+                r[i] = sqrt_weights[i] * (vol - quotes[i].1);
+            }
+            Ok(())
+        }, &initial, &lower, &upper, n)?;
+
+        let (a, b, rho, m, sigma) = to_raw(&params);
+        SviSmile::new_raw(forward, time, a, b, rho, m, sigma)
+    }
+
+    /// The total implied variance, vol^2 t, at the given log strike ln(K/F)
+    pub fn total_variance(&self, log_strike: f64) -> f64 {
+        let x = log_strike - self.m;
+        self.a + self.b * (self.rho * x + (x * x + self.sigma * self.sigma)
+            .sqrt())
+    }
+
+    pub fn forward(&self) -> f64 { self.forward }
+    pub fn time(&self) -> f64 { self.time }
+
+    /// Returns the raw parameters (a, b, rho, m, sigma)
+    pub fn raw_parameters(&self) -> (f64, f64, f64, f64, f64) {
+        (self.a, self.b, self.rho, self.m, self.sigma)
+    }
+
+    /// Returns the natural parameters (delta, mu, rho, omega, zeta)
+    pub fn natural_parameters(&self) -> (f64, f64, f64, f64, f64) {
+        let zeta = (1.0 - self.rho * self.rho).sqrt() / self.sigma;
+        let omega = 2.0 * self.b / zeta;
+        let mu = self.m + self.rho / zeta;
+        let delta = self.a - 0.5 * omega * (1.0 - self.rho * self.rho);
+        (delta, mu, self.rho, omega, zeta)
+    }
+}
+
+/// A SABR smile, using the asymptotic expansion of Hagan et al. The
+/// underlying follows dF = alpha F^beta dW, with stochastic alpha following
+/// d alpha = nu alpha dZ, and the correlation between dW and dZ is rho.
+///
+/// The smile can return either log-normal or normal vols, as specified by
+/// its quoting. Log-normal and shifted log-normal quoting requires positive
+/// (shifted) forward and strikes. Normal quoting with beta of zero allows
+/// any forward and strike, which makes it suitable for negative rates.
+#[derive(Debug, Clone)]
+pub struct SabrSmile {
+    forward: f64,
+    time: f64,
+    alpha: f64,
+    beta: f64,
+    rho: f64,
+    nu: f64,
+    quoting: VolQuoting
+}
+
+impl VolSmile for SabrSmile {
+
+    fn volatilities(
+        &self,
+        strikes: &[f64],
+        volatilities: &mut[f64]) -> Result<(), qm::Error> {
+
+        let n = strikes.len();
+        assert!(n == volatilities.len());
+
+        for i in 0..n {
+            volatilities[i] = match self.quoting {
+                VolQuoting::LogNormal => 
+                    self.lognormal_vol(self.forward, strikes[i])?,
+                VolQuoting::ShiftedLogNormal { shift } =>
+                    self.lognormal_vol(self.forward + shift,
+                        strikes[i] + shift)?,
+                VolQuoting::Normal => self.normal_vol(strikes[i])?
+            };
+        }
+        Ok(())
+    }
+}
+
+impl SabrSmile {
+
+    /// Creates a SABR smile. The time must be positive, and the parameters
+    /// must satisfy alpha > 0, 0 <= beta <= 1, |rho| < 1 and nu >= 0.
+    pub fn new(forward: f64, time: f64, alpha: f64, beta: f64, rho: f64,
+        nu: f64, quoting: VolQuoting) -> Result<SabrSmile, qm::Error> {
+
+        if !(time > 0.0) || !forward.is_finite() {
+            return Err(qm::Error::new(&format!("SABR smile requires positive \
+                time {} and finite forward {}", time, forward)))
+        }
+        if !(alpha > 0.0) || !(beta >= 0.0 && beta <= 1.0)
+            || !(rho.abs() < 1.0) || !(nu >= 0.0) {
+            return Err(qm::Error::new(&format!("Invalid SABR parameters: \
+                alpha={} beta={} rho={} nu={}", alpha, beta, rho, nu)))
+        }
+        let smile = SabrSmile { forward: forward, time: time, alpha: alpha,
+            beta: beta, rho: rho, nu: nu, quoting: quoting };
+        smile.validate_forward()?;
+        Ok(smile)
+    }
+
+    /// Fits the alpha, rho and nu parameters of a SABR smile to quoted
+    /// (strike, volatility) pairs, minimising the weighted sum of squared
+    /// differences in volatility. The vols must be quoted in the given
+    /// convention. As is normal market practice, beta is not calibrated, as
+    /// it is hard to distinguish from rho. There must be at least three
+    /// quotes with non-zero weight.
+    pub fn calibrate(forward: f64, time: f64, beta: f64, quoting: VolQuoting,
+        quotes: &[(f64, f64)], weights: &[f64])
+        -> Result<SabrSmile, qm::Error> {
+
+        // validate the inputs by constructing a trial smile
+        let trial = SabrSmile::new(forward, time, 1.0, beta, 0.0, 0.0,
+            quoting)?;
+        let sqrt_weights = calibration_weights(quotes, weights, 3)?;
+
+        // Estimate alpha from the vol nearest the money, ignoring the
+        // correction terms
+        let atm = quotes.iter().fold(quotes[0], |best, q|
+            if (q.0 - forward).abs() < (best.0 - forward).abs() { *q }
+            else { best });
+        let alpha_guess = match quoting {
+            VolQuoting::LogNormal =>
+                atm.1 * forward.powf(1.0 - beta),
+            VolQuoting::ShiftedLogNormal { shift } =>
+                atm.1 * (forward + shift).powf(1.0 - beta),
+            VolQuoting::Normal => atm.1 / forward.abs().powf(beta)
+        };
+        if !(alpha_guess > 0.0) || !alpha_guess.is_finite() {
+            return Err(qm::Error::new(&format!("Cannot estimate SABR alpha \
+                from atm vol {}", atm.1)))
+        }
+
+        // Parameters are [alpha, rho, nu]
+        let n = quotes.len();
+        let initial = [alpha_guess, 0.0, 0.3];
+        let lower = [alpha_guess * 1e-3, -0.999, 1e-6];
+        let upper = [alpha_guess * 1e3, 0.999, 10.0];
+        let lm = LevenbergMarquardt::new(200, 1e-14);
+        let params = lm.minimise(|p, r| {
+            let smile = SabrSmile { alpha: p[0], rho: p[1], nu: p[2],
+                .. trial.clone() };
+            for i in 0..n {
+                r[i] = sqrt_weights[i]
+                    * (smile.volatility(quotes[i].0)? - quotes[i].1);
+            }
+            Ok(())
+        }, &initial, &lower, &upper, n)?;
+
+        SabrSmile::new(forward, time, params[0], beta, params[1], params[2],
+            quoting)
+    }
+
+    pub fn forward(&self) -> f64 { self.forward }
+    pub fn time(&self) -> f64 { self.time }
+    pub fn alpha(&self) -> f64 { self.alpha }
+    pub fn beta(&self) -> f64 { self.beta }
+    pub fn rho(&self) -> f64 { self.rho }
+    pub fn nu(&self) -> f64 { self.nu }
+    pub fn quoting(&self) -> VolQuoting { self.quoting }
+
+    fn validate_forward(&self) -> Result<(), qm::Error> {
+        let valid = match self.quoting {
+            VolQuoting::LogNormal => self.forward > 0.0,
+            VolQuoting::ShiftedLogNormal { shift } =>
+                self.forward + shift > 0.0,
+            VolQuoting::Normal => self.beta == 0.0 || self.forward > 0.0
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(qm::Error::new(&format!("SABR forward {} is not valid for \
+                {:?} with beta {}", self.forward, self.quoting, self.beta)))
+        }
+    }
+
+    /// Hagan's formula for the Black vol, equation (2.17a) in the 2002 paper
+    fn lognormal_vol(&self, forward: f64, strike: f64)
+        -> Result<f64, qm::Error> {
+
+        if !(strike > 0.0) {
+            return Err(qm::Error::new(&format!("SABR log-normal vols require \
+                positive strikes: {}", strike)))
+        }
+
+        let one_beta = 1.0 - self.beta;
+        let log_fk = (forward / strike).ln();
+        let fk_beta = (forward * strike).powf(0.5 * one_beta);
+        let z = self.nu / self.alpha * fk_beta * log_fk;
+        let log2 = log_fk * log_fk;
+        let denominator = fk_beta * (1.0 + one_beta * one_beta / 24.0 * log2
+            + one_beta.powi(4) / 1920.0 * log2 * log2);
+        let correction = 1.0 + self.time * (
+            one_beta * one_beta / 24.0 * self.alpha * self.alpha
+                / (fk_beta * fk_beta)
+            + 0.25 * self.rho * self.beta * self.nu * self.alpha / fk_beta
+            + (2.0 - 3.0 * self.rho * self.rho) / 24.0 * self.nu * self.nu);
+
+        Ok(self.alpha / denominator * self.z_over_x(z) * correction)
+    }
+
+    /// Hagan's formula for the normal vol, equation (B.69a) in the 2002 paper
+    fn normal_vol(&self, strike: f64) -> Result<f64, qm::Error> {
+
+        let forward = self.forward;
+        let beta = self.beta;
+        let one_beta = 1.0 - beta;
+
+        // With beta of zero, the model is normal and we allow negative
+        // forwards and strikes
+        let (ratio, f_mid) = if beta == 0.0 {
+            (1.0, 1.0)
+        } else {
+            if !(strike > 0.0) {
+                return Err(qm::Error::new(&format!("SABR normal vols with \
+                    non-zero beta require positive strikes: {}", strike)))
+            }
+            let f_mid = (forward * strike).sqrt();
+
+            // ratio is (F - K)(1 - beta) / (F^(1-beta) - K^(1-beta)), which
+            // tends to F^beta at the money, and (F - K) / ln(F/K) if beta is 1
+            let ratio = if (forward - strike).abs() < 1e-12 * forward {
+                f_mid.powf(beta)
+            } else if one_beta == 0.0 {
+                (forward - strike) / (forward / strike).ln()
+            } else {
+                (forward - strike) * one_beta
+                    / (forward.powf(one_beta) - strike.powf(one_beta))
+            };
+            (ratio, f_mid)
+        };
+
+        let f_mid_beta = f_mid.powf(beta);
+        let f_mid_one_beta = f_mid.powf(one_beta);
+        let z = self.nu / self.alpha * (forward - strike) / f_mid_beta;
+        let correction = 1.0 + self.time * (
+            -beta * (2.0 - beta) / 24.0 * self.alpha * self.alpha
+                / (f_mid_one_beta * f_mid_one_beta)
+            + 0.25 * self.rho * self.alpha * self.nu * beta / f_mid_one_beta
+            + (2.0 - 3.0 * self.rho * self.rho) / 24.0 * self.nu * self.nu);
+
+        Ok(self.alpha * ratio * self.z_over_x(z) * correction)
+    }
+
+    /// z / x(z), where x(z) = ln((sqrt(1 - 2 rho z + z^2) + z - rho)
+    /// / (1 - rho)). This tends to one as z tends to zero, so we use a
+    /// series expansion near there.
+    fn z_over_x(&self, z: f64) -> f64 {
+        let rho = self.rho;
+        if z.abs() < 1e-6 {
+            return 1.0 - 0.5 * rho * z
+        }
+        let x = (((1.0 - 2.0 * rho * z + z * z).sqrt() + z - rho)
+            / (1.0 - rho)).ln();
+        z / x
+    }
+}
+
+/// Validates the quotes and weights for a smile calibration, returning the
+/// square roots of the weights
+fn calibration_weights(quotes: &[(f64, f64)], weights: &[f64],
+    n_params: usize) -> Result<Vec<f64>, qm::Error> {
+
+    if quotes.len() != weights.len() {
+        return Err(qm::Error::new(&format!("Number of weights {} must match \
+            number of quotes {}", weights.len(), quotes.len())))
+    }
+    let mut n_weighted = 0;
+    let mut sqrt_weights = Vec::with_capacity(quotes.len());
+    for (quote, weight) in quotes.iter().zip(weights.iter()) {
+        if !(quote.1 > 0.0) || !quote.0.is_finite() {
+            return Err(qm::Error::new(&format!("Invalid quote: strike={} \
+                vol={}", quote.0, quote.1)))
+        }
+        if !(*weight >= 0.0) {
+            return Err(qm::Error::new(&format!("Negative weight {}", weight)))
+        }
+        if *weight > 0.0 {
+            n_weighted += 1;
+        }
+        sqrt_weights.push(weight.sqrt());
+    }
+    if n_weighted < n_params {
+        return Err(qm::Error::new(&format!("Need at least {} weighted quotes \
+            to calibrate, but only have {}", n_params, n_weighted)))
+    }
+    Ok(sqrt_weights)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -132,4 +572,158 @@ mod tests {
                 "vol={} expected={}", vols[i], expected[i]);
         }
     }
+
+    #[test]
+    fn svi_raw_and_natural_agree() {
+        let natural = SviSmile::new_natural(100.0, 0.5, 0.01, 0.05, -0.4,
+            0.03, 2.0).unwrap();
+        let (a, b, rho, m, sigma) = natural.raw_parameters();
+        let raw = SviSmile::new_raw(100.0, 0.5, a, b, rho, m, sigma).unwrap();
+
+        let strikes = vec![40.0, 70.0, 90.0, 100.0, 110.0, 150.0, 300.0];
+        let mut natural_vols = vec![0.0; strikes.len()];
+        let mut raw_vols = vec![0.0; strikes.len()];
+        natural.volatilities(&strikes, &mut natural_vols).unwrap();
+        raw.volatilities(&strikes, &mut raw_vols).unwrap();
+
+        for i in 0..strikes.len() {
+            assert_approx(raw_vols[i], natural_vols[i], 1e-14);
+
+            // check the natural formula directly
+            let k = (strikes[i] / 100.0_f64).ln();
+            let x = 2.0 * (k - 0.05);
+            let w = 0.01 + 0.015 * (1.0 - 0.4 * x
+                + ((x - 0.4) * (x - 0.4) + 1.0 - 0.16).sqrt());
+            assert_approx(natural_vols[i], (w / 0.5).sqrt(), 1e-14);
+        }
+
+        // and round-trip the natural parameters
+        let (delta, mu, rho, omega, zeta) = raw.natural_parameters();
+        assert_approx(delta, 0.01, 1e-14);
+        assert_approx(mu, 0.05, 1e-14);
+        assert_approx(rho, -0.4, 1e-14);
+        assert_approx(omega, 0.03, 1e-14);
+        assert_approx(zeta, 2.0, 1e-14);
+    }
+
+    #[test]
+    fn svi_rejects_negative_variance() {
+        assert!(SviSmile::new_raw(100.0, 1.0, -0.1, 0.1, 0.0, 0.0, 0.1)
+            .is_err());
+        assert!(SviSmile::new_raw(100.0, 1.0, 0.01, 0.1, 1.0, 0.0, 0.1)
+            .is_err());
+    }
+
+    #[test]
+    fn svi_calibration_recovers_smile() {
+        let time = 0.75;
+        let target = SviSmile::new_raw(100.0, time, 0.02, 0.08, -0.5, 0.1,
+            0.2).unwrap();
+
+        let strikes = [50.0, 65.0, 80.0, 90.0, 100.0, 110.0, 125.0, 150.0];
+        let quotes: Vec<(f64, f64)> = strikes.iter()
+            .map(|k| (*k, target.volatility(*k).unwrap())).collect();
+        let weights = vec![1.0; quotes.len()];
+
+        let smile = SviSmile::calibrate(100.0, time, &quotes, &weights)
+            .unwrap();
+        for quote in quotes.iter() {
+            assert_approx(smile.volatility(quote.0).unwrap(), quote.1, 1e-6);
+        }
+
+        // the wings should also match, as the parameters are recovered
+        assert_approx(smile.volatility(20.0).unwrap(),
+            target.volatility(20.0).unwrap(), 1e-4);
+        assert_approx(smile.volatility(400.0).unwrap(),
+            target.volatility(400.0).unwrap(), 1e-4);
+
+        // too few quotes
+        assert!(SviSmile::calibrate(100.0, time, &quotes[0..4],
+            &weights[0..4]).is_err());
+    }
+
+    #[test]
+    fn svi_calibration_respects_lee_bound() {
+
+        // the put wing of the target has slope b (1 - rho) = 2.7, which
+        // violates Lee's bound, so the fit must flatten it
+        let time = 1.0;
+        let target = SviSmile::new_raw(100.0, time, 0.01, 1.5, -0.8, 0.0,
+            0.1).unwrap();
+        let strikes = [30.0, 45.0, 60.0, 80.0, 100.0, 120.0, 150.0];
+        let quotes: Vec<(f64, f64)> = strikes.iter()
+            .map(|k| (*k, target.volatility(*k).unwrap())).collect();
+        let weights = vec![1.0; quotes.len()];
+
+        let smile = SviSmile::calibrate(100.0, time, &quotes, &weights)
+            .unwrap();
+        let (_, b, rho, _, _) = smile.raw_parameters();
+        assert!(b * (1.0 + rho.abs()) <= 2.0 + 1e-12, "b={} rho={}", b, rho);
+    }
+
+    #[test]
+    fn sabr_atm_vols() {
+        // with beta of one and zero correlation, the atm log-normal vol is
+        // alpha (1 + nu^2 t / 12)
+        let smile = SabrSmile::new(100.0, 2.0, 0.2, 1.0, 0.0, 0.6,
+            VolQuoting::LogNormal).unwrap();
+        assert_approx(smile.volatility(100.0).unwrap(),
+            0.2 * (1.0 + 0.36 * 2.0 / 12.0), 1e-12);
+
+        // with beta of zero and normal quoting, the atm normal vol is
+        // alpha (1 + (2 - 3 rho^2) nu^2 t / 24), even for negative forwards
+        let smile = SabrSmile::new(-0.005, 2.0, 0.008, 0.0, -0.3, 0.4,
+            VolQuoting::Normal).unwrap();
+        assert_approx(smile.volatility(-0.005).unwrap(),
+            0.008 * (1.0 + (2.0 - 0.27) * 0.16 * 2.0 / 24.0), 1e-14);
+        for strike in [-0.03, -0.01, 0.0, 0.01, 0.03].iter() {
+            let vol = smile.volatility(*strike).unwrap();
+            assert!(vol > 0.0 && vol < 0.02, "strike={} vol={}", strike, vol);
+        }
+
+        // log-normal quoting cannot cope with negative forwards, unless they
+        // are shifted
+        assert!(SabrSmile::new(-0.005, 2.0, 0.2, 0.5, -0.3, 0.4,
+            VolQuoting::LogNormal).is_err());
+        let shifted = SabrSmile::new(-0.005, 2.0, 0.2, 0.5, -0.3, 0.4,
+            VolQuoting::ShiftedLogNormal { shift: 0.02 }).unwrap();
+        assert!(shifted.volatility(-0.01).unwrap() > 0.0);
+    }
+
+    #[test]
+    fn sabr_lognormal_and_normal_vols_are_consistent() {
+        // The Hagan normal vol should approximately convert to the
+        // Hagan log-normal vol. At the money, sigma_N ~ sigma_B F (1 -
+        // sigma_B^2 t / 24).
+        let lognormal = SabrSmile::new(100.0, 1.0, 2.0, 0.5, -0.2, 0.5,
+            VolQuoting::LogNormal).unwrap();
+        let normal = SabrSmile::new(100.0, 1.0, 2.0, 0.5, -0.2, 0.5,
+            VolQuoting::Normal).unwrap();
+        let black = lognormal.volatility(100.0).unwrap();
+        let bachelier = normal.volatility(100.0).unwrap();
+        assert_approx(bachelier, black * 100.0 * (1.0 - black * black / 24.0),
+            1e-2);
+    }
+
+    #[test]
+    fn sabr_calibration_recovers_parameters() {
+        let target = SabrSmile::new(0.03, 5.0, 0.04, 0.5, -0.35, 0.45,
+            VolQuoting::LogNormal).unwrap();
+
+        let strikes = [0.01, 0.015, 0.02, 0.025, 0.03, 0.035, 0.04, 0.05, 0.06];
+        let quotes: Vec<(f64, f64)> = strikes.iter()
+            .map(|k| (*k, target.volatility(*k).unwrap())).collect();
+        let weights = vec![1.0; quotes.len()];
+
+        let smile = SabrSmile::calibrate(0.03, 5.0, 0.5,
+            VolQuoting::LogNormal, &quotes, &weights).unwrap();
+        assert_approx(smile.alpha(), 0.04, 1e-6);
+        assert_approx(smile.rho(), -0.35, 1e-5);
+        assert_approx(smile.nu(), 0.45, 1e-5);
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
 }
@@ -11,7 +11,7 @@ use math::interpolation::Interpolable;
 use math::numerics::approx_eq;
 use core::qm;
 use std::f64::NAN;
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// The low-level representation of a vol surface, as supplied in the input
 /// market data. We always return variances rather than vols, because vols
@@ -19,7 +19,7 @@ use std::rc::Rc;
 /// to the vol surface. (For example, it may differ from underlier to 
 /// underlier.)
 
-pub trait VolSurface {
+pub trait VolSurface : Send + Sync {
 
     /// This is the call that implementers of VolSurface must implement to
     /// supply variances, and which decorator patterns should wrap. It has
@@ -47,6 +47,15 @@ pub trait VolSurface {
     /// None.
     fn forward(&self) -> Option<&Forward>;
 
+    /// For vol surfaces built from smiles at a set of expiry pillars, returns
+    /// the pillar dates in ascending order. These are what bucketed risks
+    /// such as vega are reported against. Surfaces with no term structure of
+    /// pillars return None. Decorators should delegate to the surface they
+    /// wrap.
+    fn pillar_dates(&self) -> Option<Vec<DateDayFraction>> {
+        None
+    }
+
     /// Fetch the business day time, which is multiplied by the volatility
     /// squared to give the variance. This is the same as calling volatilities
     /// with no strikes, so that is how we implement it by default.
@@ -210,6 +219,16 @@ pub trait VolSurface {
     /// IndependentLogNormals, or FixedDivs and there are none after the given
     /// date.
     fn displacement(&self, date: Date) -> Result<f64, qm::Error>;
+
+    /// Specifies the convention used for the vols in this surface, for
+    /// example whether they are log-normal or normal vols. This determines
+    /// which closed-form formula they should be used with, and whether the
+    /// forward is allowed to go negative. Defaults to log-normal, so you
+    /// need to override this for surfaces quoted in normal or shifted
+    /// log-normal vols.
+    fn vol_quoting(&self) -> VolQuoting {
+        VolQuoting::LogNormal
+    }
 }
 
 /// Enum which defines what assumptions were made about dividends when the vol
@@ -246,6 +265,76 @@ pub enum DivAssumptions {
     JumpDivs
 }
 
+/// Enum which defines the convention used for quoting the volatilities in a
+/// vol surface. Equity and FX vols are almost always log-normal, but rates
+/// and spread-like underlyings, which may go negative, are often quoted
+/// as normal or shifted log-normal vols.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VolQuoting {
+    /// The forward is log-normally distributed, so vols should be used with
+    /// the Black76 formula. Forwards and strikes must be positive.
+    LogNormal,
+
+    /// The forward is normally distributed, so vols should be used with the
+    /// Bachelier formula. The vols are absolute, in the same units as the
+    /// forward, and the forward and strikes may take any value.
+    Normal,
+
+    /// The forward plus a constant shift is log-normally distributed. The
+    /// forward and strikes must be greater than minus the shift.
+    ShiftedLogNormal { shift: f64 }
+}
+
+impl VolQuoting {
+    /// Converts strikes to normalised strikes, which measure the
+    /// probability of a strike in a date and forward independent way. For
+    /// log-normal vols, this is ln(K/F) / vol. For normal vols, it is
+    /// (K - F) / vol.
+    pub fn to_normalised(&self, strikes: &[f64], forward: f64,
+        sqrt_variance: f64) -> Vec<f64> {
+
+        match *self {
+            VolQuoting::LogNormal =>
+                to_normalised(strikes, forward, sqrt_variance),
+            VolQuoting::Normal => strikes.iter()
+                .map(|strike| (strike - forward) / sqrt_variance).collect(),
+            VolQuoting::ShiftedLogNormal { shift } => strikes.iter()
+                .map(|strike| ((strike + shift) / (forward + shift)).ln()
+                    / sqrt_variance).collect()
+        }
+    }
+
+    /// The inverse of to_normalised
+    pub fn to_strikes(&self, normalised: &[f64], forward: f64,
+        sqrt_variance: f64) -> Vec<f64> {
+
+        match *self {
+            VolQuoting::LogNormal =>
+                to_strikes(normalised, forward, sqrt_variance),
+            VolQuoting::Normal => normalised.iter()
+                .map(|n| forward + n * sqrt_variance).collect(),
+            VolQuoting::ShiftedLogNormal { shift } => normalised.iter()
+                .map(|n| (n * sqrt_variance).exp() * (forward + shift) - shift)
+                .collect()
+        }
+    }
+
+    /// Given a strike relative to one forward, returns the strike with the
+    /// same moneyness relative to another forward. This is what we need to
+    /// move a smile in a sticky delta way.
+    pub fn move_strike(&self, strike: f64, from_forward: f64,
+        to_forward: f64) -> f64 {
+
+        match *self {
+            VolQuoting::LogNormal => strike * to_forward / from_forward,
+            VolQuoting::Normal => strike + to_forward - from_forward,
+            VolQuoting::ShiftedLogNormal { shift } =>
+                (strike + shift) * (to_forward + shift) / (from_forward + shift)
+                    - shift
+        }
+    }
+}
+
 /// Enum which defines how a vol surface is time evolved if it is out of date,
 /// and also how it changes during a theta or time forward calculation.
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -260,7 +349,7 @@ pub enum VolTimeDynamics {
 impl VolTimeDynamics {
     /// Decorate or modify a vol surface to cope with a change from the base
     /// date when the surface was calibrated to the spot date now.
-    pub fn modify(&self, surface: &mut Rc<VolSurface>, spot_date: Date)
+    pub fn modify(&self, surface: &mut Arc<VolSurface>, spot_date: Date)
         -> Result<(), qm::Error> {
 
         let base_date = surface.base_date();
@@ -282,11 +371,11 @@ impl VolTimeDynamics {
 
         match self {
             &VolTimeDynamics::ConstantExpiry => {
-                *surface = Rc::new(ConstantExpiryTimeEvolution::new(
+                *surface = Arc::new(ConstantExpiryTimeEvolution::new(
                     surface.clone(), year_fraction, target));
             }
             &VolTimeDynamics::RollingExpiry => {
-                *surface = Rc::new(RollingExpiryTimeEvolution::new(
+                *surface = Arc::new(RollingExpiryTimeEvolution::new(
                     surface.clone(), year_fraction, target));
             }
         };
@@ -307,7 +396,7 @@ pub enum VolForwardDynamics {
 impl VolForwardDynamics {
     /// Decorate or modify a vol surface to cope with a change from the forward
     /// when the surface was calibrated to the forward now.
-    pub fn modify(&self, surface: &mut Rc<VolSurface>, forward: Rc<Forward>)
+    pub fn modify(&self, surface: &mut Arc<VolSurface>, forward: Arc<Forward>)
         -> Result<(), qm::Error> {
 
         // Sticky strike surfaces are unaffected by changes to the forward.
@@ -333,7 +422,7 @@ impl VolForwardDynamics {
                 // nothing to do. code should never reach here
             }
             &VolForwardDynamics::StickyDelta => {
-                *surface = Rc::new(StickyDeltaBumpVol::new(
+                *surface = Arc::new(StickyDeltaBumpVol::new(
                     surface.clone(), forward));
             }
         };
@@ -353,7 +442,8 @@ impl VolForwardDynamics {
 pub struct FlatVolSurface {
     vol: f64,
     calendar: Box<Calendar>,
-    base_date: DateDayFraction
+    base_date: DateDayFraction,
+    quoting: VolQuoting
 }
 
 impl VolSurface for FlatVolSurface {
@@ -394,6 +484,10 @@ impl VolSurface for FlatVolSurface {
     fn displacement(&self, _date: Date) -> Result<f64, qm::Error> {
         Ok(0.0)
     }
+
+    fn vol_quoting(&self) -> VolQuoting {
+        self.quoting
+    }
 }
 
 impl FlatVolSurface {
@@ -405,10 +499,20 @@ impl FlatVolSurface {
     pub fn new(vol: f64, calendar: Box<Calendar>, base_date: DateDayFraction)
         -> FlatVolSurface {
 
+        FlatVolSurface::new_with_quoting(vol, calendar, base_date,
+            VolQuoting::LogNormal)
+    }
+
+    /// Creates a flat vol surface where the vol is quoted in some convention
+    /// other than log-normal, for example a normal vol.
+    pub fn new_with_quoting(vol: f64, calendar: Box<Calendar>,
+        base_date: DateDayFraction, quoting: VolQuoting) -> FlatVolSurface {
+
         FlatVolSurface {
             vol: vol,
             calendar: calendar,
-            base_date: base_date
+            base_date: base_date,
+            quoting: quoting
         }
     }
 }
@@ -435,7 +539,8 @@ pub struct VolByProbability<T> where T: VolSmile + Clone {
     pillar_forwards: Vec<f64>,
     pillar_sqrt_variances: Vec<f64>,
     pillar_vol_times: Vec<f64>,
-    div_assumptions: DivAssumptions
+    div_assumptions: DivAssumptions,
+    quoting: VolQuoting
 }
 
 impl<T: VolSmile + Clone> VolSurface for VolByProbability<T> {
@@ -475,6 +580,10 @@ impl<T: VolSmile + Clone> VolSurface for VolByProbability<T> {
         Some(&*self.forward)
     }
 
+    fn pillar_dates(&self) -> Option<Vec<DateDayFraction>> {
+        Some(self.smiles.iter().map(|smile| smile.0).collect())
+    }
+
     fn base_date(&self) -> DateDayFraction {
         self.base_date
     }
@@ -493,6 +602,10 @@ impl<T: VolSmile + Clone> VolSurface for VolByProbability<T> {
                 surface. This needs more careful handling."))
         }
     }
+
+    fn vol_quoting(&self) -> VolQuoting {
+        self.quoting
+    }
 }
 
 impl<T: VolSmile + Clone> VolByProbability<T> {
@@ -512,6 +625,22 @@ impl<T: VolSmile + Clone> VolByProbability<T> {
         div_assumptions: DivAssumptions) 
         -> Result<VolByProbability<T>, qm::Error> {
 
+        VolByProbability::new_with_quoting(smiles, calendar, base_date,
+            forward, div_assumptions, VolQuoting::LogNormal)
+    }
+
+    /// Creates a vol surface that interpolates along lines of constant
+    /// probability, where the smiles are quoted in some convention other
+    /// than log-normal. The quoting also determines what we mean by
+    /// normalised strike when interpolating between pillars.
+    pub fn new_with_quoting(smiles: &[(DateDayFraction, T)],
+        calendar: Box<Calendar>,
+        base_date: DateDayFraction,
+        forward: Box<Forward>,
+        div_assumptions: DivAssumptions,
+        quoting: VolQuoting)
+        -> Result<VolByProbability<T>, qm::Error> {
+
         // We could consider suppressing errors here, then storing
         // them so we only throw them if they are needed. As it stands,
         // we throw if any of the pillars have errors.
@@ -556,7 +685,8 @@ impl<T: VolSmile + Clone> VolByProbability<T> {
             pillar_forwards: pillar_forwards,
             pillar_sqrt_variances: pillar_sqrt_variances,
             pillar_vol_times: pillar_vol_times,
-            div_assumptions: div_assumptions
+            div_assumptions: div_assumptions,
+            quoting: quoting
         })
     }
 
@@ -576,15 +706,16 @@ impl<T: VolSmile + Clone> VolByProbability<T> {
         // sqrt(vol_time) as a substitute for atm_variance, effectively
         // pretending vol is one.
         let forward = self.forward.forward(date_time.date())?;
-        let normalised = to_normalised(strikes, forward, vol_time.sqrt());
+        let normalised = self.quoting.to_normalised(strikes, forward,
+            vol_time.sqrt());
 
         // It is not very efficient using two local vectors. Consider
         // refactoring to use one local vector, or even passing workspace
         // in. Profile first to see if it is worth the effort.
         let pillar_time = self.pillar_vol_times[pillar];
         let pillar_forward = self.pillar_forwards[pillar];
-        let adj_strikes = to_strikes(&normalised, pillar_forward,
-            pillar_time.sqrt()); 
+        let adj_strikes = self.quoting.to_strikes(&normalised,
+            pillar_forward, pillar_time.sqrt());
 
         // flat extrapolation in normalised strike space
         self.smiles[pillar].1.volatilities(&adj_strikes, &mut out)?;
@@ -621,11 +752,13 @@ impl<T: VolSmile + Clone> VolByProbability<T> {
         let left_atm_var = left_sqrt_var * left_sqrt_var;
         let right_atm_var = right_sqrt_var * right_sqrt_var;
         let atm_variance = lerp(left_atm_var, right_atm_var, fraction);
-        let normalised = to_normalised(&strikes, forward, atm_variance.sqrt());
+        let normalised = self.quoting.to_normalised(&strikes, forward,
+            atm_variance.sqrt());
 
         // fetch the left pillar variances
         let left_fwd = self.pillar_forwards[left];
-        let left_strikes = to_strikes(&normalised, left_fwd, left_sqrt_var); 
+        let left_strikes = self.quoting.to_strikes(&normalised, left_fwd,
+            left_sqrt_var);
         let mut left_vars = Vec::new();
         left_vars.resize(n, NAN);
         self.pillar_variances(left, left_time, &left_strikes, 
@@ -633,7 +766,8 @@ impl<T: VolSmile + Clone> VolByProbability<T> {
 
         // fetch the right pillar variances
         let right_fwd = self.pillar_forwards[right];
-        let right_strikes = to_strikes(&normalised, right_fwd, right_sqrt_var); 
+        let right_strikes = self.quoting.to_strikes(&normalised, right_fwd,
+            right_sqrt_var);
         let mut right_vars = Vec::new();
         right_vars.resize(n, NAN);
         self.pillar_variances(right, right_time, &right_strikes, 
@@ -697,6 +831,7 @@ mod tests {
     use dates::Date;
     use dates::calendar::WeekdayCalendar;
     use data::volsmile::CubicSplineSmile;
+    use data::volsmile::SviSmile;
     use data::forward::InterpolatedForward;
     use math::interpolation::Extrap;
     use math::interpolation::CubicSpline;
@@ -799,6 +934,83 @@ mod tests {
         assert_vars(&variances, &vec![0.1818659647814821, 0.157460749746587, 0.13810453054820163, 0.12339321103154893, 0.1129545784723951, 0.1064822559332999, 0.10339037433701886, 0.10292981173388706]);
     }
 
+    #[test]
+    fn vol_by_probability_with_svi_smiles() {
+        let calendar = Box::new(WeekdayCalendar());
+        let base_date = Date::from_ymd(2012, 05, 25);
+        let base = DateDayFraction::new(base_date, 0.2);
+
+        let d = base_date;
+        let points = [(d, 90.0), (d+30, 90.1), (d+60, 90.2), (d+90, 90.1),
+            (d+120, 90.0), (d+240, 89.9), (d+480, 89.8), (d+960, 89.8)];
+        let cs = Box::new(CubicSpline::new(&points,
+            Extrap::Natural, Extrap::Natural).unwrap());
+        let fwd = Box::new(InterpolatedForward::new(cs));
+
+        // SVI smiles need the vol time to their pillar, so that they can
+        // convert total variance to vol
+        let first = DateDayFraction::new(base_date + 28, 0.7);
+        let second = DateDayFraction::new(base_date + 364, 0.7);
+        let first_time = calendar.year_fraction(base, first);
+        let second_time = calendar.year_fraction(base, second);
+        let first_fwd = fwd.forward(first.date()).unwrap();
+        let second_fwd = fwd.forward(second.date()).unwrap();
+        let smiles = vec![
+            (first, SviSmile::new_raw(first_fwd, first_time, 0.004, 0.02,
+                -0.6, 0.0, 0.1).unwrap()),
+            (second, SviSmile::new_raw(second_fwd, second_time, 0.04, 0.1,
+                -0.5, 0.0, 0.2).unwrap())];
+
+        let v = VolByProbability::new(&smiles, calendar, base, fwd,
+            DivAssumptions::NoCashDivs).unwrap();
+
+        // on a pillar, the variance is exactly the SVI total variance, even
+        // far into the wings
+        let strikes = vec![10.0, 45.0, 90.0, 135.0, 500.0];
+        let mut variances = vec![0.0; strikes.len()];
+        v.variances(second, &strikes, &mut variances).unwrap();
+        for i in 0..strikes.len() {
+            let expected = smiles[1].1.total_variance(
+                (strikes[i] / second_fwd).ln());
+            assert_approx(variances[i], expected, 1e-14);
+        }
+
+        // between pillars, the variances are in between
+        let mid = DateDayFraction::new(base_date + 180, 0.7);
+        v.variances(mid, &strikes, &mut variances).unwrap();
+        for variance in variances.iter() {
+            assert!(*variance > 0.0 && *variance < 1.0);
+        }
+    }
+
+    #[test]
+    fn vol_quoting_normalised_strikes_round_trip() {
+
+        let strikes = [-0.02, -0.005, 0.0, 0.01, 0.03];
+        let forward = 0.004;
+        let sqrt_var = 0.01;
+        for quoting in [VolQuoting::Normal,
+            VolQuoting::ShiftedLogNormal { shift: 0.05 }].iter() {
+
+            let normalised = quoting.to_normalised(&strikes, forward,
+                sqrt_var);
+            let round_trip = quoting.to_strikes(&normalised, forward,
+                sqrt_var);
+            for (strike, result) in strikes.iter().zip(round_trip.iter()) {
+                assert_approx(*result, *strike, 1e-15);
+            }
+
+            // the forward itself has a normalised strike of zero, and
+            // moves with the forward
+            let moved = quoting.move_strike(forward, forward, -0.001);
+            assert_approx(moved, -0.001, 1e-15);
+        }
+
+        // normal vols are translation invariant
+        let moved = VolQuoting::Normal.move_strike(0.01, 0.004, -0.001);
+        assert_approx(moved, 0.005, 1e-15);
+    }
+
     fn assert_approx(value: f64, expected: f64, tolerance: f64) {
         assert!(approx_eq(value, expected, tolerance),
             "value={} expected={} tolerance={}", value, expected, tolerance);
@@ -6,7 +6,7 @@ use std::cmp::max;
 /// business day volatility, settlement calculations, and the roll-out of
 /// schedules for exotic products and swaps.
   
-pub trait Calendar {
+pub trait Calendar : Send + Sync {
     /// The name of this calendar. Conventionally, the name is a three-letter
     /// upper-case string such as "TGT" or "NYS", though this is not required.
     fn name(&self) -> &str;
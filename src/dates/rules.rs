@@ -1,13 +1,14 @@
+use core::qm;
 use dates::Date;
 use dates::calendar::Calendar;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::fmt::Debug;
 use std::fmt;
 
 /// Date rules are used for rolling out schedules of dates and for adjusting
 /// dates to move them onto business dates.
 
-pub trait DateRule {
+pub trait DateRule : Send + Sync {
 
     /// Applies this date rule to the given date, returning an adjusted date.
     fn apply(&self, date: Date) -> Date;
@@ -35,7 +36,7 @@ impl NullRule {
 /// Move to the next business day in a given calendar
 
 pub struct BusinessDays {
-    calendar: Rc<Calendar>,
+    calendar: Arc<Calendar>,
     step: i32,
     slip_forward: bool
 }
@@ -44,7 +45,7 @@ impl BusinessDays {
 
     /// Creates a rule that steps to the next business day or stays put if
     /// today is a business day.
-    pub fn new_next(calendar: Rc<Calendar>) -> BusinessDays {
+    pub fn new_next(calendar: Arc<Calendar>) -> BusinessDays {
         BusinessDays { 
             calendar: calendar,
             step: 0,
@@ -53,7 +54,7 @@ impl BusinessDays {
 
     /// Creates a rule that steps to the previous business day or stays put if
     /// today is a business day.
-    pub fn new_prev(calendar: Rc<Calendar>) -> BusinessDays {
+    pub fn new_prev(calendar: Arc<Calendar>) -> BusinessDays {
         BusinessDays {
             calendar: calendar,
             step: 0,
@@ -61,7 +62,7 @@ impl BusinessDays {
     }
 
     /// Creates a rule that steps forward a given number of business days
-    pub fn new_step(calendar: Rc<Calendar>, step: u32) -> BusinessDays {
+    pub fn new_step(calendar: Arc<Calendar>, step: u32) -> BusinessDays {
         BusinessDays {
             calendar: calendar,
             step: step as i32,
@@ -69,7 +70,7 @@ impl BusinessDays {
     }
 
     /// Creates a rule that steps backward a given number of business days
-    pub fn new_back(calendar: Rc<Calendar>, step: u32) -> BusinessDays {
+    pub fn new_back(calendar: Arc<Calendar>, step: u32) -> BusinessDays {
         BusinessDays {
             calendar: calendar,
             step: -(step as i32),
@@ -87,11 +88,11 @@ impl DateRule for BusinessDays {
 /// month, in which case we move to the previous business day.
 
 pub struct ModifiedFollowing {
-    calendar: Rc<Calendar>
+    calendar: Arc<Calendar>
 }
 
 impl ModifiedFollowing {
-    pub fn new(calendar: Rc<Calendar>) -> ModifiedFollowing {
+    pub fn new(calendar: Arc<Calendar>) -> ModifiedFollowing {
         ModifiedFollowing { calendar: calendar }
     }
 }
@@ -112,6 +113,38 @@ impl DateRule for ModifiedFollowing {
     }
 }
 
+/// Rolls out a schedule of dates by repeatedly applying a rule, starting
+/// from the anchor date and stopping before passing the bound. The rule
+/// may step forwards or backwards, so for example an exercise schedule can
+/// be rolled back from expiry. The result includes the anchor, and is
+/// sorted into ascending order. It is an error if the rule does not step
+/// towards the bound.
+pub fn roll_schedule(anchor: Date, bound: Date, rule: &DateRule)
+    -> Result<Vec<Date>, qm::Error> {
+
+    let mut dates = vec![anchor];
+    let mut date = anchor;
+    loop {
+        let next = rule.apply(date);
+        if next == date || (next > date) != (bound > anchor) {
+            if anchor == bound {
+                break
+            }
+            return Err(qm::Error::new(&format!("Rule does not roll from {} \
+                towards {}", date, bound)))
+        }
+        if (bound > anchor && next > bound)
+            || (bound < anchor && next < bound) {
+            break
+        }
+        dates.push(next);
+        date = next;
+    }
+
+    dates.sort();
+    Ok(dates)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -120,7 +153,7 @@ mod tests {
 
     #[test]
     fn next_business_date() {
-        let calendar = Rc::new(WeekdayCalendar{});
+        let calendar = Arc::new(WeekdayCalendar{});
         let rule = BusinessDays::new_next(calendar);
 
         let start = Date::from_str("2017-01-01").unwrap();
@@ -134,7 +167,7 @@ mod tests {
 
     #[test]
     fn prev_business_date() {
-        let calendar = Rc::new(WeekdayCalendar{});
+        let calendar = Arc::new(WeekdayCalendar{});
         let rule = BusinessDays::new_prev(calendar);
 
         let start = Date::from_str("2017-01-01").unwrap();
@@ -151,7 +184,7 @@ mod tests {
 
     #[test]
     fn step_forward_business_date() {
-        let calendar = Rc::new(WeekdayCalendar{});
+        let calendar = Arc::new(WeekdayCalendar{});
         let rule = BusinessDays::new_step(calendar, 2);
 
         let start = Date::from_str("2017-01-01").unwrap();
@@ -167,7 +200,7 @@ mod tests {
 
     #[test]
     fn step_back_business_date() {
-        let calendar = Rc::new(WeekdayCalendar{});
+        let calendar = Arc::new(WeekdayCalendar{});
         let rule = BusinessDays::new_back(calendar, 2);
 
         let start = Date::from_str("2017-01-01").unwrap();
@@ -180,7 +213,7 @@ mod tests {
 
     #[test]
     fn modified_following() {
-        let calendar = Rc::new(WeekdayCalendar{});
+        let calendar = Arc::new(WeekdayCalendar{});
         let rule = ModifiedFollowing::new(calendar);
 
         let start1 = Date::from_str("2016-12-31").unwrap();
@@ -199,4 +232,29 @@ mod tests {
         let step4 = rule.apply(step3);
         assert_eq!(step4, next);   // no step if already on a business day
     }
+
+    #[test]
+    fn roll_schedule_back_from_expiry() {
+        let calendar = Arc::new(WeekdayCalendar{});
+        let rule = BusinessDays::new_back(calendar, 5);
+
+        let expiry = Date::from_str("2017-01-27").unwrap();
+        let start = Date::from_str("2017-01-05").unwrap();
+        let dates = roll_schedule(expiry, start, &rule).unwrap();
+        assert_eq!(dates, vec![
+            Date::from_str("2017-01-06").unwrap(),
+            Date::from_str("2017-01-13").unwrap(),
+            Date::from_str("2017-01-20").unwrap(),
+            expiry]);
+    }
+
+    #[test]
+    fn roll_schedule_wrong_direction() {
+        let calendar = Arc::new(WeekdayCalendar{});
+        let rule = BusinessDays::new_step(calendar, 5);
+
+        let expiry = Date::from_str("2017-01-27").unwrap();
+        let start = Date::from_str("2017-01-05").unwrap();
+        assert!(roll_schedule(expiry, start, &rule).is_err());
+    }
 }
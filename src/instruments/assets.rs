@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use std::fmt::Display;
 use std::fmt;
 use std::cmp::Ordering;
@@ -25,11 +25,11 @@ use core::qm;
 #[derive(Clone, Debug)]
 pub struct Currency {
     id: String,
-    settlement: Rc<DateRule>
+    settlement: Arc<DateRule>
 }
 
 impl Currency {
-    pub fn new(id: &str, settlement: Rc<DateRule>) -> Currency {
+    pub fn new(id: &str, settlement: Arc<DateRule>) -> Currency {
         Currency { id: id.to_string(), settlement: settlement }
     }
 }
@@ -48,7 +48,7 @@ impl Instrument for Currency {
         &self.id
     }
 
-    fn settlement(&self) -> &Rc<DateRule> {
+    fn settlement(&self) -> &Arc<DateRule> {
         &self.settlement
     }
 
@@ -148,13 +148,13 @@ pub fn dependence_on_spot_discount(instrument: &Instrument,
 pub struct Equity {
     id: String,
     credit_id: String,
-    currency: Rc<Currency>,
-    settlement: Rc<DateRule>
+    currency: Arc<Currency>,
+    settlement: Arc<DateRule>
 }
 
 impl Equity {
-    pub fn new(id: &str, credit_id: &str,currency: Rc<Currency>, 
-        settlement: Rc<DateRule>) -> Equity {
+    pub fn new(id: &str, credit_id: &str,currency: Arc<Currency>, 
+        settlement: Arc<DateRule>) -> Equity {
 
         Equity { id: id.to_string(), credit_id: credit_id.to_string(),
             currency: currency, settlement: settlement }
@@ -174,7 +174,7 @@ impl Instrument for Equity {
         &self.credit_id
     }
 
-    fn settlement(&self) -> &Rc<DateRule> {
+    fn settlement(&self) -> &Arc<DateRule> {
         &self.settlement
     }
 
@@ -249,13 +249,13 @@ impl Priceable for Equity {
 #[derive(Clone, Debug)]
 pub struct CreditEntity {
     id: String,
-    currency: Rc<Currency>,
-    settlement: Rc<DateRule>
+    currency: Arc<Currency>,
+    settlement: Arc<DateRule>
 }
 
 impl CreditEntity {
-    pub fn new(id: &str, currency: Rc<Currency>, 
-        settlement: Rc<DateRule>) -> CreditEntity {
+    pub fn new(id: &str, currency: Arc<Currency>, 
+        settlement: Arc<DateRule>) -> CreditEntity {
 
         CreditEntity { id: id.to_string(), currency: currency,
             settlement: settlement }
@@ -276,7 +276,7 @@ impl Instrument for CreditEntity {
         &self.id
     }
 
-    fn settlement(&self) -> &Rc<DateRule> {
+    fn settlement(&self) -> &Arc<DateRule> {
         &self.settlement
     }
 
@@ -348,15 +348,15 @@ mod tests {
     use dates::Date;
 
     fn sample_currency(step: u32) -> Currency {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        let settlement = Rc::new(BusinessDays::new_step(calendar, step));
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let settlement = Arc::new(BusinessDays::new_step(calendar, step));
         Currency::new("GBP", settlement)
     }
 
-    fn sample_equity(currency: Rc<Currency>,
+    fn sample_equity(currency: Arc<Currency>,
         step: u32) -> Equity {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        let settlement = Rc::new(BusinessDays::new_step(calendar, step));
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let settlement = Arc::new(BusinessDays::new_step(calendar, step));
         Equity::new("BP.L", "LSE", currency, settlement)
     }
 
@@ -374,14 +374,14 @@ mod tests {
         }
 
         fn yield_curve(&self, _credit_id: &str,
-            _high_water_mark: Date) -> Result<Rc<RateCurve>, qm::Error> {
+            _high_water_mark: Date) -> Result<Arc<RateCurve>, qm::Error> {
 
             let d = Date::from_ymd(2018, 05, 30);
             let points = [(d, 0.05), (d + 14, 0.08), (d + 56, 0.09),
                 (d + 112, 0.085), (d + 224, 0.082)];
             let c = RateCurveAct365::new(d, &points,
                 Extrap::Flat, Extrap::Flat)?;
-            Ok(Rc::new(c))
+            Ok(Arc::new(c))
         }
 
         fn spot(&self, _id: &str) -> Result<f64, qm::Error> {
@@ -389,17 +389,17 @@ mod tests {
         }
 
         fn forward_curve(&self, _instrument: &Instrument, 
-            _high_water_mark: Date) -> Result<Rc<Forward>, qm::Error> {
+            _high_water_mark: Date) -> Result<Arc<Forward>, qm::Error> {
             Err(qm::Error::new("unsupported"))
         }
 
-        fn vol_surface(&self, _instrument: &Instrument, _forward: Rc<Forward>,
-            _high_water_mark: Date) -> Result<Rc<VolSurface>, qm::Error> {
+        fn vol_surface(&self, _instrument: &Instrument, _forward: Arc<Forward>,
+            _high_water_mark: Date) -> Result<Arc<VolSurface>, qm::Error> {
             Err(qm::Error::new("unsupported"))
         }
 
-        fn correlation(&self, _first: &Instrument, _second: &Instrument)
-            -> Result<f64, qm::Error> {
+        fn correlation(&self, _first: &Instrument, _second: &Instrument,
+            _date: Date) -> Result<f64, qm::Error> {
             Err(qm::Error::new("unsupported"))
         }
     }
@@ -411,7 +411,7 @@ mod tests {
     #[test]
     fn test_equity_price_on_spot() {
         let spot = 123.4;
-        let currency = Rc::new(sample_currency(2));
+        let currency = Arc::new(sample_currency(2));
         let equity = sample_equity(currency, 2);
         let context = sample_pricing_context(spot);
         let price = equity.price(&context).unwrap();
@@ -429,7 +429,7 @@ mod tests {
     #[test]
     fn test_equity_price_mismatching_dates() {
         let spot = 123.4;
-        let currency = Rc::new(sample_currency(3));
+        let currency = Arc::new(sample_currency(3));
         let equity = sample_equity(currency, 3);
         let context = sample_pricing_context(spot);
         let price = equity.price(&context).unwrap();
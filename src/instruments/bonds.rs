@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use std::fmt::Display;
 use std::fmt;
 use std::hash::Hash;
@@ -19,9 +19,9 @@ use core::qm;
 pub struct ZeroCoupon {
     id: String,
     credit_id: String,
-    currency: Rc<Currency>,
+    currency: Arc<Currency>,
     payment_date: Date,
-    settlement: Rc<DateRule>
+    settlement: Arc<DateRule>
 }
 
 impl ZeroCoupon {
@@ -32,8 +32,8 @@ impl ZeroCoupon {
     /// be supplied in case the user does not pass in a discount date
     /// to discount to. Normally, the settlement rule should be that of
     /// the instrument that span off the zero coupon.
-    pub fn new(id: &str, credit_id: &str, currency: Rc<Currency>,
-        payment_date: Date, settlement: Rc<DateRule>) -> ZeroCoupon {
+    pub fn new(id: &str, credit_id: &str, currency: Arc<Currency>,
+        payment_date: Date, settlement: Arc<DateRule>) -> ZeroCoupon {
 
         ZeroCoupon { id: id.to_string(), credit_id: credit_id.to_string(),
             currency: currency, payment_date: payment_date,
@@ -54,7 +54,7 @@ impl Instrument for ZeroCoupon {
         &self.credit_id
     }
 
-    fn settlement(&self) -> &Rc<DateRule> {
+    fn settlement(&self) -> &Arc<DateRule> {
         // A settlement period for a zero coupon does not really make sense,
         // as they have explicit settlement dates. However, we need to supply
         // one in case the user supplies a discount date of None.
@@ -137,14 +137,14 @@ mod tests {
     use dates::Date;
 
     fn sample_currency(step: u32) -> Currency {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        let settlement = Rc::new(BusinessDays::new_step(calendar, step));
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let settlement = Arc::new(BusinessDays::new_step(calendar, step));
         Currency::new("GBP", settlement)
     }
 
-    fn sample_zero_coupon(currency: Rc<Currency>, step: u32) -> ZeroCoupon {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        let settlement = Rc::new(BusinessDays::new_step(calendar, step));
+    fn sample_zero_coupon(currency: Arc<Currency>, step: u32) -> ZeroCoupon {
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let settlement = Arc::new(BusinessDays::new_step(calendar, step));
         ZeroCoupon::new("GBP.2018-07-05", "OPT", currency,
             Date::from_ymd(2018, 07, 05), settlement)
     }
@@ -163,14 +163,14 @@ mod tests {
         }
 
         fn yield_curve(&self, _credit_id: &str,
-            _high_water_mark: Date) -> Result<Rc<RateCurve>, qm::Error> {
+            _high_water_mark: Date) -> Result<Arc<RateCurve>, qm::Error> {
 
             let d = Date::from_ymd(2018, 05, 30);
             let points = [(d, 0.05), (d + 14, 0.08), (d + 56, 0.09),
                 (d + 112, 0.085), (d + 224, 0.082)];
             let c = RateCurveAct365::new(d, &points,
                 Extrap::Flat, Extrap::Flat)?;
-            Ok(Rc::new(c))
+            Ok(Arc::new(c))
         }
 
         fn spot(&self, _id: &str) -> Result<f64, qm::Error> {
@@ -178,17 +178,17 @@ mod tests {
         }
 
         fn forward_curve(&self, _instrument: &Instrument, 
-            _high_water_mark: Date) -> Result<Rc<Forward>, qm::Error> {
+            _high_water_mark: Date) -> Result<Arc<Forward>, qm::Error> {
             Err(qm::Error::new("Forward not supported"))
         }
 
-        fn vol_surface(&self, _instrument: &Instrument, _forward: Rc<Forward>,
-            _high_water_mark: Date) -> Result<Rc<VolSurface>, qm::Error> {
+        fn vol_surface(&self, _instrument: &Instrument, _forward: Arc<Forward>,
+            _high_water_mark: Date) -> Result<Arc<VolSurface>, qm::Error> {
             Err(qm::Error::new("VolSurface not supported"))
         }
 
-        fn correlation(&self, _first: &Instrument, _second: &Instrument)
-            -> Result<f64, qm::Error> {
+        fn correlation(&self, _first: &Instrument, _second: &Instrument,
+            _date: Date) -> Result<f64, qm::Error> {
             Err(qm::Error::new("correlation not supported"))
         }
     }
@@ -201,7 +201,7 @@ mod tests {
     #[test]
     fn zero_coupon_with_discount_date() {
         let discount_date = Some(Date::from_ymd(2018, 06, 05));
-        let currency = Rc::new(sample_currency(2));
+        let currency = Arc::new(sample_currency(2));
         let zero = sample_zero_coupon(currency, 2);
         let context = sample_pricing_context(discount_date);
         let price = zero.price(&context).unwrap();
@@ -210,7 +210,7 @@ mod tests {
 
     #[test]
     fn zero_coupon_without_discount_date() {
-        let currency = Rc::new(sample_currency(2));
+        let currency = Arc::new(sample_currency(2));
         let zero = sample_zero_coupon(currency, 2);
         let context = sample_pricing_context(None);
         let price = zero.price(&context).unwrap();
@@ -12,13 +12,16 @@ use data::forward::Forward;
 use data::volsurface::VolSurface;
 use data::volsurface::VolTimeDynamics;
 use data::volsurface::VolForwardDynamics;
+use data::volsurface::VolQuoting;
 use data::fixings::FixingTable;
 use core::qm;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::hash::Hash;
 use std::cmp::Ordering;
 use std::hash::Hasher;
+use ndarray::Array1;
 use ndarray::ArrayView2;
+use ndarray::ArrayViewMut2;
 
 /// There are a few controversial design decisions here. The first is to do
 /// with the separation of products from indices, which is the case in
@@ -27,7 +30,7 @@ use ndarray::ArrayView2;
 /// and rather specious, so I have classed all tradeable instruments together,
 /// as Instrument.
 
-pub trait Instrument {
+pub trait Instrument : Send + Sync {
     /// The id of this instrument is used for identifying market data. For
     /// example equity ids are used to identify spots. It is also used for
     /// reporting of results, for example where composite or portfolio prices
@@ -46,7 +49,7 @@ pub trait Instrument {
 
     /// The settlement period associated with premium and payoff payments
     /// for this instrument.
-    fn settlement(&self) -> &Rc<DateRule>;
+    fn settlement(&self) -> &Arc<DateRule>;
 
     /// Reports the internal dependencies of this product. Returns an enum
     /// to specify the external dependencies for a spot value on the product
@@ -94,13 +97,22 @@ pub trait Instrument {
         VolForwardDynamics::StickyStrike
     }
 
+    /// Returns the quoting convention for vol surfaces on this instrument.
+    /// Defaults to log-normal, so you need to override this for instruments
+    /// such as rates or spreads, whose vols may be quoted as normal or
+    /// shifted log-normal. Vol surfaces in a different convention are
+    /// rejected when they are fetched from the market data.
+    fn vol_quoting(&self) -> VolQuoting {
+        VolQuoting::LogNormal
+    }
+
     /// Transforms the instrument, given a fixing table. For example, a forward-
     /// starting European may transform to a spot-starting one. Most
     /// instruments are unaffected by fixings. These instruments simply return
     /// None, as do any that happen to be unaffected by the particular fixings
     /// supplied. This is the default implementation.
     fn fix(&self, _fixing_table: &FixingTable)
-        -> Result<Option<Vec<(f64, Rc<Instrument>)>>, qm::Error> {
+        -> Result<Option<Vec<(f64, Arc<Instrument>)>>, qm::Error> {
         Ok(None) 
     }
 
@@ -113,17 +125,22 @@ pub trait Instrument {
     fn as_mc_priceable(&self) -> Option<&MonteCarloPriceable> {
         None
     }
+
+    /// Cast from instrument to a pde_priceable. Returns None if not possible.
+    fn as_pde_priceable(&self) -> Option<&PdePriceable> {
+        None
+    }
 }
 
 /// When making hash maps or sets of instruments, we only key by the id, which
 /// should be unique across all instrument types.
 #[derive(Clone)]
 pub struct RcInstrument {
-    instrument: Rc<Instrument>
+    instrument: Arc<Instrument>
 }
 
 impl RcInstrument {
-    pub fn new(instrument: Rc<Instrument>) -> RcInstrument {
+    pub fn new(instrument: Arc<Instrument>) -> RcInstrument {
         RcInstrument { instrument: instrument }
     }
 
@@ -175,7 +192,7 @@ pub trait DependencyContext {
     fn yield_curve(&mut self, credit_id: &str, high_water_mark: Date);
 
     /// Specify a dependency on a spot value, given the instrument
-    fn spot(&mut self, instrument: &Rc<Instrument>);
+    fn spot(&mut self, instrument: &Arc<Instrument>);
 
     /// Specify a dependency on a forward curve, given any instrument. Also
     /// specify a high water mark, beyond which we never directly ask for
@@ -183,13 +200,13 @@ pub trait DependencyContext {
     /// requests. If the vol surface depends on forwards beyond this mark, it
     /// is up to the supplier to provide them, regardless of this high water
     /// mark.
-    fn forward_curve(&mut self, instrument: &Rc<Instrument>, 
+    fn forward_curve(&mut self, instrument: &Arc<Instrument>, 
         high_water_mark: Date);
 
     /// Specify a dependency on a vol surfce, given any instrument. Also
     /// specify a high water mark, beyond which we never directly ask for
     /// vols.
-    fn vol_surface(&mut self, instrument: &Rc<Instrument>,
+    fn vol_surface(&mut self, instrument: &Arc<Instrument>,
         high_water_mark: Date);
 }
 
@@ -268,7 +285,7 @@ pub trait Priceable : Instrument {
 /// price itself. As we add new types of market data, we can add new methods to
 /// this interface.
 
-pub trait PricingContext {
+pub trait PricingContext : Send + Sync {
     /// Gets the date that spot is associated with. Note this is the
     /// date when that spot value is shown on a Bloomberg screen (other
     /// financial data suppliers exist), not when the payment is made.
@@ -282,7 +299,16 @@ pub trait PricingContext {
 
     /// Gets a yield curve, given an instrument to define the discounting.
     fn yield_curve(&self, credit_id: &str, high_water_mark: Date)
-        -> Result<Rc<RateCurve>, qm::Error>;
+        -> Result<Arc<RateCurve>, qm::Error>;
+
+    /// Gets a borrow (repo) curve, given the id of the instrument being
+    /// borrowed. The borrow curve is normally only used indirectly, via the
+    /// forward, but risk reports need it to find the pillars to bump.
+    /// Contexts that do not hold borrow curves return an error.
+    fn borrow_curve(&self, id: &str, _high_water_mark: Date)
+        -> Result<Arc<RateCurve>, qm::Error> {
+        Err(qm::Error::new(&format!("No borrow curve available for {}", id)))
+    }
 
     /// Gets a spot value, given the id of any instrument
     fn spot(&self, id: &str) -> Result<f64, qm::Error>;
@@ -291,26 +317,27 @@ pub trait PricingContext {
     /// specify a high water mark, beyond which we never directly ask for
     /// forwards.
     fn forward_curve(&self, instrument: &Instrument, high_water_mark: Date)
-        -> Result<Rc<Forward>, qm::Error>;
+        -> Result<Arc<Forward>, qm::Error>;
 
     /// Gets a Vol Surface, given any instrument, for example an equity.  Also
     /// specify a high water mark, beyond which we never directly ask for
     /// vols.
-    fn vol_surface(&self, instrument: &Instrument, forward: Rc<Forward>,
-         high_water_mark: Date) -> Result<Rc<VolSurface>, qm::Error>;
-
-    /// Gets an instantaneous correlation between two instruments. At present,
-    /// we consider this to be constant. (A datetime parameter could be added
-    /// in future.) However, this does not mean that the local correlation
-    /// can be considered to be the same thing as a terminal correlation,
-    /// unless both vol surfaces are flat, with the same calendars.
+    fn vol_surface(&self, instrument: &Instrument, forward: Arc<Forward>,
+         high_water_mark: Date) -> Result<Arc<VolSurface>, qm::Error>;
+
+    /// Gets an instantaneous correlation between two instruments, which
+    /// applies to increments on the given date. Correlations may be constant
+    /// or term-structured. However, this does not mean that the local
+    /// correlation can be considered to be the same thing as a terminal
+    /// correlation, unless both vol surfaces are flat, with the same
+    /// calendars.
     ///
     /// In theory, to obtain a terminal correlation, we would have to
     /// integrate in two dimensions over a local vol surface. However, it is
     /// common practice to convert to terminal correlations using only a term
     /// structure of at the money vols for each asset.
-    fn correlation(&self, first: &Instrument, second: &Instrument)
-        -> Result<f64, qm::Error>;
+    fn correlation(&self, first: &Instrument, second: &Instrument,
+        date: Date) -> Result<f64, qm::Error>;
 }
 
 /// Allow an instrument to be priced using Monte-Carlo. The way this works is
@@ -374,7 +401,7 @@ pub trait MonteCarloDependencies {
     /// All the returned observations should be in the future (or unfixed,
     /// today). Historical observations should have been handled by the freeze
     /// method.
-    fn observation(&mut self, instrument: &Rc<Instrument>,
+    fn observation(&mut self, instrument: &Arc<Instrument>,
         date_time: DateDayFraction);
 
     /// Specifies a potential cashflow or stock transfer. In theory, any
@@ -383,7 +410,7 @@ pub trait MonteCarloDependencies {
     /// restrictions on what can be used. In practice, you need to choose
     /// instruments that reflect the dates of transfer, so Bond rather than
     /// Currency, for example.
-    fn flow(&mut self, instrument: &Rc<Instrument>);
+    fn flow(&mut self, instrument: &Arc<Instrument>);
 }
 
 /// Context for Monte-Carlo pricing. The most important thing this gives is
@@ -399,12 +426,121 @@ pub trait MonteCarloContext {
     /// models may choose to represent all observations today by the spot
     /// value, but this is an approximation and a modelling choice. In that
     /// case, the model must supply the same value for all paths.
-    fn paths(&self, instrument: &Rc<Instrument>)
+    fn paths(&self, instrument: &Arc<Instrument>)
         -> Result<ArrayView2<f64>, qm::Error>;
 
     /// Value the flows resulting from the valuation. The quantities argument is
     /// an array ordered by paths then flows, where flows are in the same
     /// order as they were passed to the flow method in MonteCarloDependencies.
+    /// By default, this is the average of evaluate_flows_by_path.
     fn evaluate_flows(&self, quantities: ArrayView2<f64>) 
-        -> Result<f64, qm::Error>;
+        -> Result<f64, qm::Error> {
+        let values = self.evaluate_flows_by_path(quantities)?;
+        Ok(values.scalar_sum() / values.len() as f64)
+    }
+
+    /// Values the flows as evaluate_flows, but returns the discounted value
+    /// on each path rather than the average.
+    fn evaluate_flows_by_path(&self, quantities: ArrayView2<f64>)
+        -> Result<Array1<f64>, qm::Error>;
+
+    /// Values an instrument with early exercise, using regression across
+    /// the paths to estimate the value of continuing at each exercise
+    /// opportunity (Longstaff-Schwartz). The instrument supplies the flows
+    /// resulting from exercise, and the variables to regress against, via
+    /// the MonteCarloExercisable interface. Returns the price discounted to
+    /// the discount date. By default, this is the average of
+    /// evaluate_exercise_by_path.
+    fn evaluate_exercise(&self, exercisable: &MonteCarloExercisable)
+        -> Result<f64, qm::Error> {
+        let values = self.evaluate_exercise_by_path(exercisable)?;
+        Ok(values.scalar_sum() / values.len() as f64)
+    }
+
+    /// Values an instrument with early exercise as evaluate_exercise, but
+    /// returns the discounted value on each path rather than the average.
+    fn evaluate_exercise_by_path(&self, exercisable: &MonteCarloExercisable)
+        -> Result<Array1<f64>, qm::Error>;
+}
+
+/// Hook for instruments with early exercise, such as American or Bermudan
+/// options, to be valued by Monte-Carlo. The instrument must specify its
+/// exercise opportunities as observations and its exercise payments as
+/// flows in mc_dependencies, then invoke MonteCarloContext::evaluate_exercise
+/// from mc_price. The context works backwards through the exercise
+/// opportunities, deciding on each path whether to exercise by comparing
+/// the exercise value with a regression estimate of the continuation value.
+pub trait MonteCarloExercisable : Instrument {
+
+    /// The number of exercise opportunities. The last one is expiry, where
+    /// the holder exercises whenever the payoff is positive.
+    fn exercise_count(&self) -> usize;
+
+    /// The number of explanatory variables used in the regression, for
+    /// example one for a vanilla option, which regresses on spot.
+    fn regression_variable_count(&self) -> usize;
+
+    /// Writes the quantities of each flow that result from exercising at
+    /// the given opportunity, indexed by path then flow. The flows are in
+    /// the same order as they were passed to MonteCarloDependencies::flow.
+    /// The quantities are zero on entry.
+    fn exercise_quantities(&self, context: &MonteCarloContext,
+        exercise: usize, quantities: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error>;
+
+    /// Writes the explanatory variables at the given opportunity, indexed
+    /// by path then variable. These should be reasonably well scaled, for
+    /// example spot divided by strike.
+    fn regression_variables(&self, context: &MonteCarloContext,
+        exercise: usize, variables: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error>;
+}
+
+/// Allow an instrument to be priced by backward induction on a one-
+/// dimensional finite difference grid in the spot of a single underlying.
+/// This is the natural way to value instruments with early exercise, such as
+/// American options. The pricer rolls back the value from the expiry, taking
+/// the greater of the rolled-back value and the exercise value wherever
+/// the instrument may be exercised early.
+pub trait PdePriceable : Instrument {
+
+    /// The underlying whose spot defines the grid
+    fn pde_underlying(&self) -> &Arc<Instrument>;
+
+    /// The date and time when the final payoff is fixed
+    fn pde_expiry(&self) -> DateDayFraction;
+
+    /// Specifies when the instrument may be exercised before expiry
+    fn pde_exercise(&self) -> EarlyExercise;
+
+    /// The strike at which Black dynamics read the term structure of vols
+    /// from the surface, so that an option away from the money sees the
+    /// smile. Instruments without a single strike return None, and are
+    /// valued using at-the-money vols.
+    fn pde_strike(&self) -> Option<f64> { None }
+
+    /// Writes the value of exercising the instrument on the given date into
+    /// values, for each of the given spots. This is the payoff, discounted
+    /// from its pay date to the discount date of the context. It is called
+    /// at expiry, and at every early exercise opportunity.
+    fn pde_exercise_values(&self, context: &PricingContext, date: Date,
+        spots: &[f64], values: &mut [f64]) -> Result<(), qm::Error>;
+
+    /// Return this object as an instrument
+    fn as_instrument(&self) -> &Instrument;
+}
+
+/// Defines when an instrument may be exercised before its expiry
+#[derive(Clone, Debug, PartialEq)]
+pub enum EarlyExercise {
+    /// Exercise is only possible at expiry
+    None,
+
+    /// Exercise is possible at any time from the given date and time up to
+    /// expiry
+    American(DateDayFraction),
+
+    /// Exercise is possible at each of the given dates and times, which must
+    /// be in order and before expiry
+    Bermudan(Vec<DateDayFraction>)
 }
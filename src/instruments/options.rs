@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use instruments::Instrument;
 use instruments::Priceable;
 use instruments::PricingContext;
@@ -9,15 +9,24 @@ use instruments::SpotRequirement;
 use instruments::MonteCarloPriceable;
 use instruments::MonteCarloDependencies;
 use instruments::MonteCarloContext;
-use math::optionpricing::Black76;
+use instruments::MonteCarloExercisable;
+use instruments::PdePriceable;
+use instruments::EarlyExercise;
+use math::optionpricing::Bachelier;
+use math::optionpricing::ShiftedBlack76;
+use math::optionpricing::OptionGreeks;
 use data::fixings::FixingTable;
+use data::volsurface::VolSurface;
+use data::volsurface::VolQuoting;
 use dates::Date;
 use dates::rules::DateRule;
+use dates::rules::roll_schedule;
 use dates::datetime::DateTime;
 use dates::datetime::DateDayFraction;
 use core::qm;
 use ndarray::Axis;
 use ndarray::Array2;
+use ndarray::ArrayViewMut2;
 
 /// A call option pays (S-K).max(0).
 /// A put option pays (K-S).max(0).
@@ -37,8 +46,8 @@ pub enum OptionSettlement { Cash, Physical }
 struct VanillaOption {
     id: String,
     credit_id: String,
-    underlying: Rc<Instrument>,
-    settlement: Rc<DateRule>,
+    underlying: Arc<Instrument>,
+    settlement: Arc<DateRule>,
     expiry: DateTime,
     put_or_call: PutOrCall,
     cash_or_physical: OptionSettlement,
@@ -49,8 +58,8 @@ struct VanillaOption {
 }
 
 impl VanillaOption {
-    pub fn new(id: &str, credit_id: &str, underlying: Rc<Instrument>,
-        settlement: Rc<DateRule>, expiry: DateTime, put_or_call: PutOrCall,
+    pub fn new(id: &str, credit_id: &str, underlying: Arc<Instrument>,
+        settlement: Arc<DateRule>, expiry: DateTime, put_or_call: PutOrCall,
         cash_or_physical: OptionSettlement)
         -> Result<VanillaOption, qm::Error> {
 
@@ -67,6 +76,30 @@ impl VanillaOption {
             expiry_time: expiry_time,
             pay_date: pay_date })
     }
+
+    /// The value of exercising the option on the given date, for each of
+    /// the given spots. The payoff pays on the settlement date after the
+    /// exercise date, and is discounted from there to the discount date.
+    pub fn exercise_values(&self, context: &PricingContext, date: Date,
+        strike: f64, spots: &[f64], values: &mut [f64])
+        -> Result<(), qm::Error> {
+
+        assert_eq!(spots.len(), values.len());
+        let pay_date = self.settlement.apply(date);
+        let yc = context.yield_curve(self.credit_id(), pay_date)?;
+        let discount_date = match context.discount_date() {
+            None => self.settlement.apply(context.spot_date()),
+            Some(date) => date };
+        let df = yc.df(pay_date, discount_date)?;
+
+        let sign = match self.put_or_call {
+            PutOrCall::Call => 1.0,
+            PutOrCall::Put => -1.0 };
+        for (spot, value) in spots.iter().zip(values.iter_mut()) {
+            *value = df * (sign * (spot - strike)).max(0.0);
+        }
+        Ok(())
+    }
 }
 
 /// A European option gives the buyer the option but not the obligation to
@@ -113,16 +146,23 @@ impl SpotStartingEuropean {
     pub fn new(
         id: &str,
         credit_id: &str,
-        underlying: Rc<Instrument>,
-        settlement: Rc<DateRule>,
+        underlying: Arc<Instrument>,
+        settlement: Arc<DateRule>,
         expiry: DateTime,
         strike: f64,
         put_or_call: PutOrCall,
         cash_or_physical: OptionSettlement)
         -> Result<SpotStartingEuropean, qm::Error> {
 
-        if strike < 0.0 {
-            Err(qm::Error::new("Strike must be greater or equal to zero"))
+        // strikes may only be negative if the vols allow it
+        let min_strike = match underlying.vol_quoting() {
+            VolQuoting::LogNormal => 0.0,
+            VolQuoting::Normal => ::std::f64::NEG_INFINITY,
+            VolQuoting::ShiftedLogNormal { shift } => -shift
+        };
+        if strike < min_strike {
+            Err(qm::Error::new(&format!("Strike must be greater or equal \
+                to {}", min_strike)))
         } else {
             let vanilla = VanillaOption::new(id, credit_id, underlying,
                 settlement, expiry, put_or_call, cash_or_physical)?;
@@ -140,8 +180,8 @@ impl ForwardStartingEuropean {
     pub fn new(
         id: &str,
         credit_id: &str,
-        underlying: Rc<Instrument>,
-        settlement: Rc<DateRule>,
+        underlying: Arc<Instrument>,
+        settlement: Arc<DateRule>,
         expiry: DateTime,
         strike_fraction: f64,
         strike_date: DateTime,
@@ -178,7 +218,7 @@ impl Instrument for VanillaOption {
         &*self.credit_id
     }
 
-    fn settlement(&self) -> &Rc<DateRule> {
+    fn settlement(&self) -> &Arc<DateRule> {
         &self.settlement
     }
 
@@ -204,16 +244,17 @@ impl Instrument for SpotStartingEuropean {
     fn id(&self) -> &str { self.vanilla.id() }
     fn payoff_currency(&self) -> &Currency { self.vanilla.payoff_currency() }
     fn credit_id(&self) -> &str { self.vanilla.credit_id() }
-    fn settlement(&self) -> &Rc<DateRule> { self.vanilla.settlement() }
+    fn settlement(&self) -> &Arc<DateRule> { self.vanilla.settlement() }
     fn dependencies(&self, context: &mut DependencyContext)
         -> SpotRequirement { self.vanilla.dependencies(context) }
     fn as_priceable(&self) -> Option<&Priceable> { Some(self) }
     fn as_mc_priceable(&self) -> Option<&MonteCarloPriceable> { Some(self) }
+    fn as_pde_priceable(&self) -> Option<&PdePriceable> { Some(self) }
 
     // We cannot delegate fix to the contained vanilla, because it needs
     // to know the strike
     fn fix(&self, fixing_table: &FixingTable)
-        -> Result<Option<Vec<(f64, Rc<Instrument>)>>, qm::Error> {
+        -> Result<Option<Vec<(f64, Arc<Instrument>)>>, qm::Error> {
 
         // If there is an expiry fixing (error if missing and in the past),
         // the product turns into either a cash flow, or an equity flow and
@@ -221,7 +262,7 @@ impl Instrument for SpotStartingEuropean {
         let fixing = fixing_table.get(self.vanilla.underlying.id(),
             self.vanilla.expiry)?;
         if let Some(spot_fixing) = fixing {
-            let mut decomp : Vec<(f64, Rc<Instrument>)> = Vec::new();
+            let mut decomp : Vec<(f64, Arc<Instrument>)> = Vec::new();
             let strike = self.strike;
             let sign = match self.vanilla.put_or_call {
                         PutOrCall::Call => 1.0,
@@ -233,9 +274,9 @@ impl Instrument for SpotStartingEuropean {
                 OptionSettlement::Cash => {
                     let payment = sign * (spot_fixing - strike);
                     if payment > 0.0 {
-                        decomp.push((payment, Rc::new(ZeroCoupon::new(
+                        decomp.push((payment, Arc::new(ZeroCoupon::new(
                             &payment_id, self.credit_id(), 
-                            Rc::new(self.payoff_currency().clone()), 
+                            Arc::new(self.payoff_currency().clone()), 
                             self.vanilla.pay_date,
                             self.vanilla.settlement.clone()))));
                     }
@@ -243,9 +284,9 @@ impl Instrument for SpotStartingEuropean {
 
                 OptionSettlement::Physical => {
                     if sign * (spot_fixing - strike) > 0.0 {
-                        decomp.push((-strike * sign, Rc::new(ZeroCoupon::new(
+                        decomp.push((-strike * sign, Arc::new(ZeroCoupon::new(
                             &payment_id, self.credit_id(), 
-                            Rc::new(self.payoff_currency().clone()), 
+                            Arc::new(self.payoff_currency().clone()), 
                             self.vanilla.pay_date,
                             self.vanilla.settlement.clone()))));
                         decomp.push((sign, self.vanilla.underlying.clone()));
@@ -264,7 +305,7 @@ impl Instrument for ForwardStartingEuropean {
     fn id(&self) -> &str { self.vanilla.id() }
     fn payoff_currency(&self) -> &Currency { self.vanilla.payoff_currency() }
     fn credit_id(&self) -> &str { self.vanilla.credit_id() }
-    fn settlement(&self) -> &Rc<DateRule> { self.vanilla.settlement() }
+    fn settlement(&self) -> &Arc<DateRule> { self.vanilla.settlement() }
     fn dependencies(&self, context: &mut DependencyContext)
         -> SpotRequirement { self.vanilla.dependencies(context) }
     fn as_priceable(&self) -> Option<&Priceable> { Some(self) }
@@ -272,14 +313,14 @@ impl Instrument for ForwardStartingEuropean {
     // We cannot delegate fix to the contained vanilla, because it needs
     // to know the strike_fraction and strike date
     fn fix(&self, fixing_table: &FixingTable)
-        -> Result<Option<Vec<(f64, Rc<Instrument>)>>, qm::Error> {
+        -> Result<Option<Vec<(f64, Arc<Instrument>)>>, qm::Error> {
 
         // If there is a strike fixing (error if missing and in the past),
         // the product turns into a spot starting European
         let fixing = fixing_table.get(self.vanilla.underlying.id(),
             self.strike_date)?;
         if let Some(f) = fixing {
-            let mut decomp: Vec<(f64, Rc<Instrument>)> = Vec::new();
+            let mut decomp: Vec<(f64, Arc<Instrument>)> = Vec::new();
             let strike = f * self.strike_fraction;
             let spot_starting = SpotStartingEuropean::from_vanilla(
                 self.vanilla.clone(), strike);
@@ -289,7 +330,7 @@ impl Instrument for ForwardStartingEuropean {
             if let Some(_) = further {
                 Ok(further)
             } else {
-                decomp.push((1.0, Rc::new(spot_starting)));
+                decomp.push((1.0, Arc::new(spot_starting)));
                 Ok(Some(decomp))
             }
         } else {
@@ -298,11 +339,23 @@ impl Instrument for ForwardStartingEuropean {
     }
 }
 
-impl Priceable for SpotStartingEuropean {
-    fn as_instrument(&self) -> &Instrument { self }
+impl SpotStartingEuropean {
+    /// Values the European option using the analytic formula appropriate to
+    /// the quoting of the vol surface (normally Black 76), and also returns
+    /// the analytic greeks, calculated in the same pass. The greeks are with
+    /// respect to the inputs to the formula, so delta and gamma are with
+    /// respect to the forward, and vega is with respect to the sqrt of the
+    /// variance.
+    pub fn price_with_greeks(&self, context: &PricingContext)
+        -> Result<OptionGreeks, qm::Error> {
+
+        self.closed_form_inputs(context)?.greeks(self.vanilla.put_or_call)
+    }
 
-    // Values the European Option using the analytic formula Black 76
-    fn price(&self, context: &PricingContext) -> Result<f64, qm::Error> {
+    /// Fetches the market data and calculates the discount factor, forward,
+    /// strike and sqrt variance to pass to the closed-form formula.
+    fn closed_form_inputs(&self, context: &PricingContext)
+        -> Result<ClosedFormInputs, qm::Error> {
 
         // fetch the market data we need
         let expiry_date = self.vanilla.expiry.date();
@@ -326,29 +379,105 @@ impl Priceable for SpotStartingEuropean {
         if variance < 0.0 {
             return Err(qm::Error::new("Negative variance"));
         }
-        let sqrt_var = variance.sqrt();
-
-        // For some div assumptions, we must displace the forward and strike.
-        // (This errors for JumpDivs, which we do not currently handle.)
-        let displacement = vol.displacement(self.vanilla.expiry.date())?;
-        let k = strike + displacement;
-        let f = forward - displacement;
-        if f < 0.0 {
-            return Err(qm::Error::new("Negative forward"));
-        }
 
-        // price the option using the Black76 formula
-        let black76 = Black76::new()?;
-        let price = match self.vanilla.put_or_call {
-            PutOrCall::Put => black76.put_price(df, f, k, sqrt_var),
-            PutOrCall::Call => black76.call_price(df, f, k, sqrt_var)
+        // for helpful debug trace, uncomment the below
+        //println!("df={} F={} K={} variance={}", df, forward, strike,
+        //    variance);
+
+        ClosedFormInputs::new(&*vol, expiry_date, df, forward, strike,
+            variance.sqrt())
+    }
+}
+
+/// The inputs to a closed-form European option formula, together with
+/// the quoting of the vols, which tells us which formula to use.
+struct ClosedFormInputs {
+    quoting: VolQuoting,
+    df: f64,
+    forward: f64,
+    strike: f64,
+    sqrt_var: f64
+}
+
+impl ClosedFormInputs {
+    /// Validates the forward against the quoting of the vol surface. For
+    /// some div assumptions, we must displace the forward and strike of a
+    /// log-normal. (This errors for JumpDivs, which we do not currently
+    /// handle.) Normal vols are unaffected by a displacement, and allow
+    /// negative forwards.
+    fn new(vol: &VolSurface, expiry_date: Date, df: f64, forward: f64,
+        strike: f64, sqrt_var: f64) -> Result<ClosedFormInputs, qm::Error> {
+
+        let quoting = vol.vol_quoting();
+        let displacement = vol.displacement(expiry_date)?;
+        let (f, k) = match quoting {
+            VolQuoting::Normal => (forward, strike),
+            VolQuoting::LogNormal => {
+                let f = forward - displacement;
+                if f < 0.0 {
+                    return Err(qm::Error::new("Negative forward"));
+                }
+                (f, strike + displacement)
+            },
+            VolQuoting::ShiftedLogNormal { shift } => {
+                let f = forward - displacement;
+                if f + shift < 0.0 {
+                    return Err(qm::Error::new(&format!("Forward {} is \
+                        below minus the shift {}", f, shift)));
+                }
+                (f, strike + displacement)
+            }
         };
 
-        // for helpful debug trace, uncomment the below
-        //println!("df={} F={} K={} sqrt_var={} displacement={} price={}", df,
-        //    f, k, sqrt_var, displacement, price);
+        Ok(ClosedFormInputs { quoting: quoting, df: df, forward: f,
+            strike: k, sqrt_var: sqrt_var })
+    }
+
+    fn price(&self, put_or_call: PutOrCall) -> Result<f64, qm::Error> {
+        match put_or_call {
+            PutOrCall::Put => self.evaluate(ShiftedBlack76::put_price,
+                Bachelier::put_price),
+            PutOrCall::Call => self.evaluate(ShiftedBlack76::call_price,
+                Bachelier::call_price)
+        }
+    }
+
+    fn greeks(&self, put_or_call: PutOrCall)
+        -> Result<OptionGreeks, qm::Error> {
+        match put_or_call {
+            PutOrCall::Put => self.evaluate(ShiftedBlack76::put_greeks,
+                Bachelier::put_greeks),
+            PutOrCall::Call => self.evaluate(ShiftedBlack76::call_greeks,
+                Bachelier::call_greeks)
+        }
+    }
+
+    /// Applies whichever of the given formulae matches the quoting of the
+    /// vols. Log-normal vols use shifted Black76 with a zero shift, which
+    /// is identical to Black76.
+    fn evaluate<T, B, N>(&self, black: B, normal: N) -> Result<T, qm::Error>
+        where B: Fn(&ShiftedBlack76, f64, f64, f64, f64) -> T,
+        N: Fn(&Bachelier, f64, f64, f64, f64) -> T {
+
+        let (df, f, k, s) = (self.df, self.forward, self.strike, self.sqrt_var);
+        Ok(match self.quoting {
+            VolQuoting::LogNormal =>
+                black(&ShiftedBlack76::new(0.0)?, df, f, k, s),
+            VolQuoting::ShiftedLogNormal { shift } =>
+                black(&ShiftedBlack76::new(shift)?, df, f, k, s),
+            VolQuoting::Normal => normal(&Bachelier::new()?, df, f, k, s)
+        })
+    }
+}
+
+impl Priceable for SpotStartingEuropean {
+    fn as_instrument(&self) -> &Instrument { self }
 
-        Ok(price)
+    // Values the European Option using the analytic formula Black 76, or
+    // Bachelier if the vols are normal
+    fn price(&self, context: &PricingContext) -> Result<f64, qm::Error> {
+
+        self.closed_form_inputs(context)?.price(self.vanilla.put_or_call)
     }
 }
 
@@ -393,28 +522,36 @@ impl Priceable for ForwardStartingEuropean {
         if variance < 0.0 {
             return Err(qm::Error::new("Negative variance"));
         }
-        let sqrt_var = variance.sqrt();
-
-        // For some div assumptions, we must displace the forward and strike.
-        // (This errors for JumpDivs, which we do not currently handle.)
-        let displacement = vol.displacement(self.vanilla.expiry.date())?;
-        let k = strike + displacement;
-        let f = forward - displacement;
-        if f < 0.0 {
-            return Err(qm::Error::new("Negative forward"));
-        }
 
-        // price the option using the Black76 formula
-        let black76 = Black76::new()?;
-        let price = match self.vanilla.put_or_call {
-            PutOrCall::Put => black76.put_price(df, f, k, sqrt_var),
-            PutOrCall::Call => black76.call_price(df, f, k, sqrt_var)
-        };
+        // price the option using the formula matching the vol quoting
+        let inputs = ClosedFormInputs::new(&*vol, expiry_date, df, forward,
+            strike, variance.sqrt())?;
+        inputs.price(self.vanilla.put_or_call)
+    }
+}
 
-        // for helpful debug trace, uncomment the below
-        // println!("df={} F={} K={} sqrt_var={} price={}", df, f, k,
+impl PdePriceable for SpotStartingEuropean {
+    fn as_instrument(&self) -> &Instrument { self }
+
+    fn pde_underlying(&self) -> &Arc<Instrument> {
+        &self.vanilla.underlying
+    }
+
+    fn pde_expiry(&self) -> DateDayFraction {
+        self.vanilla.expiry_time
+    }
+
+    fn pde_exercise(&self) -> EarlyExercise {
+        EarlyExercise::None
+    }
 
-        Ok(price)
+    fn pde_strike(&self) -> Option<f64> {
+        Some(self.strike)
+    }
+
+    fn pde_exercise_values(&self, context: &PricingContext, date: Date,
+        spots: &[f64], values: &mut [f64]) -> Result<(), qm::Error> {
+        self.vanilla.exercise_values(context, date, self.strike, spots, values)
     }
 }
 
@@ -428,12 +565,12 @@ impl MonteCarloPriceable for SpotStartingEuropean {
         output.observation(&self.vanilla.underlying, self.vanilla.expiry_time);
 
         // TODO this feels inefficient and ugly
-        let currency = Rc::new(self.payoff_currency().clone());
+        let currency = Arc::new(self.payoff_currency().clone());
 
         // For the purposes of Monte-Carlo valuation we treat all vanillas as
         // if they paid cash at the pay date. (Physically settled vanillas pay
         // stock as well, but that does not affect the price before expiry.)
-        let payment : Rc<Instrument> = Rc::new(
+        let payment : Arc<Instrument> = Arc::new(
             ZeroCoupon::new(&format!("{}:Expiry", self.vanilla.id),
             &self.vanilla.credit_id, currency, self.vanilla.pay_date,
             self.vanilla.settlement.clone()));
@@ -483,10 +620,441 @@ impl MonteCarloPriceable for SpotStartingEuropean {
     }
 }
 
+/// An American option behaves like a European, except that the holder may
+/// exercise it at any time from the exercise start date until expiry.
+/// Exercising pays the intrinsic value (or delivers the stock) on the
+/// settlement date after exercise. An American put may be worth exercising
+/// early if it is deep in the money, to earn interest on the strike. An
+/// American call may be worth exercising just before the stock goes ex a
+/// large cash dividend, which the holder would otherwise miss.
+///
+/// The natural way to value an American is on a PDE grid, which applies the
+/// dividends as jumps on their ex dates, and so checks for exercise just
+/// before each dividend. For Monte-Carlo valuation, the continuous exercise
+/// is approximated by a Bermudan schedule, rolled back from expiry using a
+/// date rule. This gives a slight underestimate of the value, particularly
+/// for calls where the schedule does not include the day before each ex date.
+#[derive(Clone)]
+pub struct AmericanOption {
+    exercisable: ExercisableVanilla,
+    exercise_start: DateDayFraction
+}
+
+/// A Bermudan option may be exercised on any of a schedule of dates, the
+/// last of which is the expiry. It is valued in the same ways as an
+/// American option.
+#[derive(Clone)]
+pub struct BermudanOption {
+    exercisable: ExercisableVanilla
+}
+
+impl AmericanOption {
+    /// Creates an American option, exercisable from exercise_start up to
+    /// and including expiry. The mc_exercise_rule is only used for
+    /// Monte-Carlo valuation, to roll back a schedule of exercise dates from
+    /// expiry. For example, BusinessDays::new_back(calendar, 5) gives weekly
+    /// exercise.
+    pub fn new(
+        id: &str,
+        credit_id: &str,
+        underlying: Arc<Instrument>,
+        settlement: Arc<DateRule>,
+        exercise_start: DateTime,
+        expiry: DateTime,
+        strike: f64,
+        put_or_call: PutOrCall,
+        cash_or_physical: OptionSettlement,
+        mc_exercise_rule: &DateRule)
+        -> Result<AmericanOption, qm::Error> {
+
+        if exercise_start > expiry {
+            return Err(qm::Error::new("American exercise must not start \
+                after expiry"))
+        }
+
+        let exercise_start_time = underlying.time_to_day_fraction(
+            exercise_start)?;
+        let dates = roll_schedule(expiry.date(), exercise_start.date(),
+            mc_exercise_rule)?;
+        let exercise_dates: Vec<DateTime> = dates.iter()
+            .map(|d| DateTime::new(*d, expiry.time_of_day()))
+            .filter(|d| *d >= exercise_start).collect();
+        let vanilla = VanillaOption::new(id, credit_id, underlying,
+            settlement, expiry, put_or_call, cash_or_physical)?;
+        let exercisable = ExercisableVanilla::new(vanilla, strike,
+            exercise_dates)?;
+        Ok(AmericanOption { exercisable: exercisable,
+            exercise_start: exercise_start_time })
+    }
+}
+
+impl BermudanOption {
+    /// Creates a Bermudan option, exercisable on any of the given dates.
+    /// The latest of these is the expiry.
+    pub fn new(
+        id: &str,
+        credit_id: &str,
+        underlying: Arc<Instrument>,
+        settlement: Arc<DateRule>,
+        exercise_dates: &[DateTime],
+        strike: f64,
+        put_or_call: PutOrCall,
+        cash_or_physical: OptionSettlement)
+        -> Result<BermudanOption, qm::Error> {
+
+        let expiry = exercise_dates.iter().cloned()
+            .fold(None, |latest: Option<DateTime>, date| match latest {
+                Some(d) if d >= date => Some(d),
+                _ => Some(date) })
+            .ok_or_else(|| qm::Error::new(
+                "Bermudan option must have an exercise date"))?;
+        let vanilla = VanillaOption::new(id, credit_id, underlying,
+            settlement, expiry, put_or_call, cash_or_physical)?;
+        let exercisable = ExercisableVanilla::new(vanilla, strike,
+            exercise_dates.to_vec())?;
+        Ok(BermudanOption { exercisable: exercisable })
+    }
+
+    /// Creates a Bermudan option whose exercise dates are rolled back from
+    /// expiry using the given rule, stopping before the first exercise date.
+    /// For example, BusinessDays::new_back(calendar, 20) gives approximately
+    /// monthly exercise. All exercise is at the time of day of the expiry.
+    pub fn new_rolled(
+        id: &str,
+        credit_id: &str,
+        underlying: Arc<Instrument>,
+        settlement: Arc<DateRule>,
+        first_exercise: DateTime,
+        expiry: DateTime,
+        exercise_rule: &DateRule,
+        strike: f64,
+        put_or_call: PutOrCall,
+        cash_or_physical: OptionSettlement)
+        -> Result<BermudanOption, qm::Error> {
+
+        let dates = roll_schedule(expiry.date(), first_exercise.date(),
+            exercise_rule)?;
+        let exercise_dates: Vec<DateTime> = dates.iter()
+            .map(|d| DateTime::new(*d, expiry.time_of_day())).collect();
+        BermudanOption::new(id, credit_id, underlying, settlement,
+            &exercise_dates, strike, put_or_call, cash_or_physical)
+    }
+}
+
+/// Internal data structure to share code between vanillas with early
+/// exercise. The exercise dates are sorted and the last is the expiry.
+/// For an American, they are the dates used in Monte-Carlo valuation.
+#[derive(Clone)]
+struct ExercisableVanilla {
+    vanilla: VanillaOption,
+    strike: f64,
+    exercise_dates: Vec<DateTime>,
+
+    // fields precomputed for performance and simplicity
+    exercise_times: Vec<DateDayFraction>
+}
+
+impl ExercisableVanilla {
+    fn new(vanilla: VanillaOption, strike: f64,
+        mut exercise_dates: Vec<DateTime>)
+        -> Result<ExercisableVanilla, qm::Error> {
+
+        // the regression in Monte-Carlo is on spot over strike
+        if !(strike > 0.0) {
+            return Err(qm::Error::new("Strike of an option with early \
+                exercise must be positive"))
+        }
+
+        exercise_dates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        exercise_dates.dedup();
+        if exercise_dates.last() != Some(&vanilla.expiry) {
+            return Err(qm::Error::new("The last exercise date must be the \
+                expiry"))
+        }
+
+        let mut exercise_times = Vec::with_capacity(exercise_dates.len());
+        for date in exercise_dates.iter() {
+            exercise_times.push(vanilla.underlying.time_to_day_fraction(
+                *date)?);
+        }
+
+        Ok(ExercisableVanilla { vanilla: vanilla, strike: strike,
+            exercise_dates: exercise_dates, exercise_times: exercise_times })
+    }
+
+    /// If the option has reached expiry, it fixes into the same flows as a
+    /// European. Otherwise, we drop any exercise dates before the fixings
+    /// are known, assuming that the option was not exercised on them, and
+    /// return the modified option, or None if there was nothing to drop.
+    fn fix(&self, fixing_table: &FixingTable)
+        -> Result<Fixed, qm::Error> {
+
+        let european = SpotStartingEuropean::from_vanilla(
+            self.vanilla.clone(), self.strike);
+        if let Some(decomp) = european.fix(fixing_table)? {
+            return Ok(Fixed::Expired(decomp))
+        }
+
+        let today = fixing_table.fixings_known_until();
+        let first_live = self.exercise_dates.iter()
+            .position(|d| d.date() >= today).unwrap_or(0);
+        if first_live == 0 {
+            return Ok(Fixed::Unchanged)
+        }
+
+        let mut fixed = self.clone();
+        fixed.exercise_dates.drain(0..first_live);
+        fixed.exercise_times.drain(0..first_live);
+        Ok(Fixed::Live(fixed))
+    }
+
+    fn mc_dependencies(&self, output: &mut MonteCarloDependencies)
+        -> Result<(), qm::Error> {
+
+        // One observation and one potential payment for each exercise
+        // date. As for Europeans, we treat all vanillas as if they paid
+        // cash at the settlement date after exercise.
+        let currency = Arc::new(self.vanilla.payoff_currency().clone());
+        for (date, time) in self.exercise_dates.iter()
+            .zip(self.exercise_times.iter()) {
+
+            output.observation(&self.vanilla.underlying, *time);
+            let pay_date = self.vanilla.settlement.apply(date.date());
+            let payment : Arc<Instrument> = Arc::new(ZeroCoupon::new(
+                &format!("{}:{}", self.vanilla.id, date.date()),
+                &self.vanilla.credit_id, currency.clone(), pay_date,
+                self.vanilla.settlement.clone()));
+            output.flow(&payment);
+        }
+        Ok(())
+    }
+
+    fn sign(&self) -> f64 {
+        match self.vanilla.put_or_call {
+            PutOrCall::Call => 1.0,
+            PutOrCall::Put => -1.0 }
+    }
+
+    fn exercise_quantities(&self, context: &MonteCarloContext,
+        exercise: usize, mut quantities: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error> {
+
+        let paths = context.paths(&self.vanilla.underlying)?;
+        assert_eq!(paths.shape()[1], self.exercise_times.len());
+        let strike = self.strike;
+        let sign = self.sign();
+        let path_column = paths.subview(Axis(1), exercise);
+        let mut flow_column = quantities.subview_mut(Axis(1), exercise);
+        for (spot, flow) in path_column.iter().zip(flow_column.iter_mut()) {
+            *flow = (sign * (spot - strike)).max(0.0);
+        }
+        Ok(())
+    }
+
+    fn regression_variables(&self, context: &MonteCarloContext,
+        exercise: usize, mut variables: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error> {
+
+        let paths = context.paths(&self.vanilla.underlying)?;
+        let path_column = paths.subview(Axis(1), exercise);
+        let mut column = variables.subview_mut(Axis(1), 0);
+        for (spot, variable) in path_column.iter().zip(column.iter_mut()) {
+            *variable = spot / self.strike;
+        }
+        Ok(())
+    }
+}
+
+/// The result of fixing an option with early exercise
+enum Fixed {
+    Expired(Vec<(f64, Arc<Instrument>)>),
+    Live(ExercisableVanilla),
+    Unchanged
+}
+
+impl Instrument for AmericanOption {
+    fn id(&self) -> &str { self.exercisable.vanilla.id() }
+    fn payoff_currency(&self) -> &Currency {
+        self.exercisable.vanilla.payoff_currency() }
+    fn credit_id(&self) -> &str { self.exercisable.vanilla.credit_id() }
+    fn settlement(&self) -> &Arc<DateRule> {
+        self.exercisable.vanilla.settlement() }
+    fn dependencies(&self, context: &mut DependencyContext)
+        -> SpotRequirement { self.exercisable.vanilla.dependencies(context) }
+    fn as_mc_priceable(&self) -> Option<&MonteCarloPriceable> { Some(self) }
+    fn as_pde_priceable(&self) -> Option<&PdePriceable> { Some(self) }
+
+    fn fix(&self, fixing_table: &FixingTable)
+        -> Result<Option<Vec<(f64, Arc<Instrument>)>>, qm::Error> {
+
+        Ok(match self.exercisable.fix(fixing_table)? {
+            Fixed::Expired(decomp) => Some(decomp),
+            Fixed::Live(exercisable) => Some(vec!((1.0, Arc::new(
+                AmericanOption { exercisable: exercisable,
+                    exercise_start: self.exercise_start })))),
+            Fixed::Unchanged => None
+        })
+    }
+}
+
+impl Instrument for BermudanOption {
+    fn id(&self) -> &str { self.exercisable.vanilla.id() }
+    fn payoff_currency(&self) -> &Currency {
+        self.exercisable.vanilla.payoff_currency() }
+    fn credit_id(&self) -> &str { self.exercisable.vanilla.credit_id() }
+    fn settlement(&self) -> &Arc<DateRule> {
+        self.exercisable.vanilla.settlement() }
+    fn dependencies(&self, context: &mut DependencyContext)
+        -> SpotRequirement { self.exercisable.vanilla.dependencies(context) }
+    fn as_mc_priceable(&self) -> Option<&MonteCarloPriceable> { Some(self) }
+    fn as_pde_priceable(&self) -> Option<&PdePriceable> { Some(self) }
+
+    fn fix(&self, fixing_table: &FixingTable)
+        -> Result<Option<Vec<(f64, Arc<Instrument>)>>, qm::Error> {
+
+        Ok(match self.exercisable.fix(fixing_table)? {
+            Fixed::Expired(decomp) => Some(decomp),
+            Fixed::Live(exercisable) => Some(vec!((1.0, Arc::new(
+                BermudanOption { exercisable: exercisable })))),
+            Fixed::Unchanged => None
+        })
+    }
+}
+
+impl PdePriceable for AmericanOption {
+    fn as_instrument(&self) -> &Instrument { self }
+
+    fn pde_underlying(&self) -> &Arc<Instrument> {
+        &self.exercisable.vanilla.underlying
+    }
+
+    fn pde_expiry(&self) -> DateDayFraction {
+        self.exercisable.vanilla.expiry_time
+    }
+
+    fn pde_exercise(&self) -> EarlyExercise {
+        EarlyExercise::American(self.exercise_start)
+    }
+
+    fn pde_strike(&self) -> Option<f64> {
+        Some(self.exercisable.strike)
+    }
+
+    fn pde_exercise_values(&self, context: &PricingContext, date: Date,
+        spots: &[f64], values: &mut [f64]) -> Result<(), qm::Error> {
+        self.exercisable.vanilla.exercise_values(context, date,
+            self.exercisable.strike, spots, values)
+    }
+}
+
+impl PdePriceable for BermudanOption {
+    fn as_instrument(&self) -> &Instrument { self }
+
+    fn pde_underlying(&self) -> &Arc<Instrument> {
+        &self.exercisable.vanilla.underlying
+    }
+
+    fn pde_expiry(&self) -> DateDayFraction {
+        self.exercisable.vanilla.expiry_time
+    }
+
+    fn pde_exercise(&self) -> EarlyExercise {
+        let times = &self.exercisable.exercise_times;
+        EarlyExercise::Bermudan(times[..times.len() - 1].to_vec())
+    }
+
+    fn pde_strike(&self) -> Option<f64> {
+        Some(self.exercisable.strike)
+    }
+
+    fn pde_exercise_values(&self, context: &PricingContext, date: Date,
+        spots: &[f64], values: &mut [f64]) -> Result<(), qm::Error> {
+        self.exercisable.vanilla.exercise_values(context, date,
+            self.exercisable.strike, spots, values)
+    }
+}
+
+impl MonteCarloPriceable for AmericanOption {
+    fn as_instrument(&self) -> &Instrument { self }
+
+    fn mc_dependencies(&self, _dates: &[DateDayFraction],
+        output: &mut MonteCarloDependencies) -> Result<(), qm::Error> {
+        self.exercisable.mc_dependencies(output)
+    }
+
+    fn start_date(&self) -> Option<DateDayFraction> {
+        None
+    }
+
+    fn mc_price(&self, context: &MonteCarloContext)
+        -> Result<f64, qm::Error> {
+        context.evaluate_exercise(self)
+    }
+}
+
+impl MonteCarloPriceable for BermudanOption {
+    fn as_instrument(&self) -> &Instrument { self }
+
+    fn mc_dependencies(&self, _dates: &[DateDayFraction],
+        output: &mut MonteCarloDependencies) -> Result<(), qm::Error> {
+        self.exercisable.mc_dependencies(output)
+    }
+
+    fn start_date(&self) -> Option<DateDayFraction> {
+        None
+    }
+
+    fn mc_price(&self, context: &MonteCarloContext)
+        -> Result<f64, qm::Error> {
+        context.evaluate_exercise(self)
+    }
+}
+
+impl MonteCarloExercisable for AmericanOption {
+    fn exercise_count(&self) -> usize {
+        self.exercisable.exercise_times.len()
+    }
+
+    fn regression_variable_count(&self) -> usize { 1 }
+
+    fn exercise_quantities(&self, context: &MonteCarloContext,
+        exercise: usize, quantities: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error> {
+        self.exercisable.exercise_quantities(context, exercise, quantities)
+    }
+
+    fn regression_variables(&self, context: &MonteCarloContext,
+        exercise: usize, variables: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error> {
+        self.exercisable.regression_variables(context, exercise, variables)
+    }
+}
+
+impl MonteCarloExercisable for BermudanOption {
+    fn exercise_count(&self) -> usize {
+        self.exercisable.exercise_times.len()
+    }
+
+    fn regression_variable_count(&self) -> usize { 1 }
+
+    fn exercise_quantities(&self, context: &MonteCarloContext,
+        exercise: usize, quantities: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error> {
+        self.exercisable.exercise_quantities(context, exercise, quantities)
+    }
+
+    fn regression_variables(&self, context: &MonteCarloContext,
+        exercise: usize, variables: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error> {
+        self.exercisable.regression_variables(context, exercise, variables)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use math::numerics::approx_eq;
+    use math::optionpricing::Black76;
     use math::interpolation::Extrap;
     use math::interpolation::CubicSpline;
     use data::forward::Forward;
@@ -500,25 +1068,54 @@ mod tests {
     use dates::datetime::TimeOfDay;
     use dates::Date;
     use instruments::assets::Equity;
+    use pricers::PricerFactory;
+    use pricers::pde::PdePricerFactory;
+    use pricers::pde::PdeDynamics;
+    use pricers::montecarlo::MonteCarloPricerFactory;
+    use models::blackdiffusion::BlackDiffusionFactory;
+    use risk::marketdata::MarketData;
+    use risk::marketdata::tests::sample_market_data;
 
     fn sample_currency(step: u32) -> Currency {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        let settlement = Rc::new(BusinessDays::new_step(calendar, step));
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let settlement = Arc::new(BusinessDays::new_step(calendar, step));
         Currency::new("GBP", settlement)
     }
 
-    fn sample_settlement(step: u32) -> Rc<DateRule> {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        Rc::new(BusinessDays::new_step(calendar, step))
+    fn sample_settlement(step: u32) -> Arc<DateRule> {
+        let calendar = Arc::new(WeekdayCalendar::new());
+        Arc::new(BusinessDays::new_step(calendar, step))
     }
 
-    fn sample_equity(currency: Rc<Currency>, step: u32) -> Equity {
+    fn sample_equity(currency: Arc<Currency>, step: u32) -> Equity {
         let settlement = sample_settlement(step);
         Equity::new("BP.L", "LSE", currency, settlement)
     }
 
+    /// An equity whose vols are quoted as normal vols, so that it can
+    /// have negative forwards and strikes
+    struct NormalQuotedEquity {
+        equity: Equity
+    }
+
+    impl Instrument for NormalQuotedEquity {
+        fn id(&self) -> &str { self.equity.id() }
+        fn payoff_currency(&self) -> &Currency { self.equity.payoff_currency() }
+        fn credit_id(&self) -> &str { self.equity.credit_id() }
+        fn settlement(&self) -> &Arc<DateRule> { self.equity.settlement() }
+        fn dependencies(&self, context: &mut DependencyContext)
+            -> SpotRequirement { self.equity.dependencies(context) }
+        fn time_to_day_fraction(&self, date_time: DateTime)
+            -> Result<DateDayFraction, qm::Error> {
+            self.equity.time_to_day_fraction(date_time)
+        }
+        fn vol_quoting(&self) -> VolQuoting { VolQuoting::Normal }
+    }
+
     struct SamplePricingContext { 
-        spot: f64
+        spot: f64,
+        vol: f64,
+        quoting: VolQuoting
     }
 
     impl PricingContext for SamplePricingContext {
@@ -531,14 +1128,14 @@ mod tests {
         }
 
         fn yield_curve(&self, _credit_id: &str, _high_water_mark: Date)
-                -> Result<Rc<RateCurve>, qm::Error> {
+                -> Result<Arc<RateCurve>, qm::Error> {
 
             let d = Date::from_ymd(2018, 05, 30);
             let points = [(d, 0.05), (d + 14, 0.08), (d + 56, 0.09),
                 (d + 112, 0.085), (d + 224, 0.082)];
             let c = RateCurveAct365::new(d, &points,
                 Extrap::Flat, Extrap::Flat)?;
-            Ok(Rc::new(c))
+            Ok(Arc::new(c))
         }
 
         fn spot(&self, _id: &str) -> Result<f64, qm::Error> {
@@ -546,7 +1143,7 @@ mod tests {
         }
 
         fn forward_curve(&self, _instrument: &Instrument, 
-            _high_water_mark: Date) -> Result<Rc<Forward>, qm::Error> {
+            _high_water_mark: Date) -> Result<Arc<Forward>, qm::Error> {
 
             let d = Date::from_ymd(2018, 06, 01);
 
@@ -556,28 +1153,30 @@ mod tests {
             let cs = Box::new(CubicSpline::new(&points,
                 Extrap::Natural, Extrap::Natural).unwrap());
             let fwd = InterpolatedForward::new(cs);
-            Ok(Rc::new(fwd))
+            Ok(Arc::new(fwd))
         }
 
         fn vol_surface(&self, _instrument: &Instrument,
-            _forward: Rc<Forward>, _high_water_mark: Date)
-            -> Result<Rc<VolSurface>, qm::Error> {
+            _forward: Arc<Forward>, _high_water_mark: Date)
+            -> Result<Arc<VolSurface>, qm::Error> {
 
             let calendar = Box::new(WeekdayCalendar());
             let base_date = Date::from_ymd(2018, 05, 30);
             let base = DateDayFraction::new(base_date, 0.2);
-            let vol = FlatVolSurface::new(0.3, calendar, base);
-            Ok(Rc::new(vol))
+            let vol = FlatVolSurface::new_with_quoting(self.vol, calendar,
+                base, self.quoting);
+            Ok(Arc::new(vol))
         }
 
-        fn correlation(&self, _first: &Instrument, _second: &Instrument)
-            -> Result<f64, qm::Error> {
+        fn correlation(&self, _first: &Instrument, _second: &Instrument,
+            _date: Date) -> Result<f64, qm::Error> {
             Err(qm::Error::new("unsupported"))
         }
     }
 
     fn sample_pricing_context(spot: f64) -> SamplePricingContext {
-        SamplePricingContext { spot: spot }
+        SamplePricingContext { spot: spot, vol: 0.3,
+            quoting: VolQuoting::LogNormal }
     }
 
     fn sample_fixings() -> FixingTable {
@@ -734,12 +1333,104 @@ mod tests {
             PutOrCall::Put, 10.121695405560875);
     }
 
+    #[test]
+    fn european_greeks_match_price() {
+
+        let spot = 100.0;
+        let strike = 115.170375;
+        let expiry = DateTime::new(
+            Date::from_ymd(2018, 12, 01), TimeOfDay::Close);
+
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let equity = Arc::new(sample_equity(currency, 2));
+        let european = SpotStartingEuropean::new("SampleEuropean", "OPT",
+            equity.clone(), settlement, expiry,
+            strike, PutOrCall::Call, OptionSettlement::Cash).unwrap();
+
+        let context = sample_pricing_context(spot);
+        let greeks = european.price_with_greeks(&context).unwrap();
+        assert_approx(greeks.price(), 9.576591266363513, 1e-8);
+
+        // compare with the textbook Black76 formulae, evaluated here from
+        // the inputs the option passes to Black76
+        let inputs = european.closed_form_inputs(&context).unwrap();
+        let (df, f, k, s) = (inputs.df, inputs.forward, inputs.strike,
+            inputs.sqrt_var);
+        let black76 = Black76::new().unwrap();
+        let d_plus = (f / k).ln() / s + 0.5 * s;
+        let density = (-0.5 * d_plus * d_plus).exp()
+            / (2.0 * ::std::f64::consts::PI).sqrt();
+        assert_approx(greeks.delta(), df * black76.cdf(d_plus), 1e-8);
+        assert_approx(greeks.gamma(), df * density / (f * s), 1e-8);
+        assert_approx(greeks.vega(), df * f * density, 1e-8);
+
+        // at the money forward, so delta is a little over half the df
+        assert!(greeks.delta() > 0.5 && greeks.delta() < 0.6);
+
+        // the put differs by a forward, so only the delta changes
+        let put = SpotStartingEuropean::new("SampleEuropean", "OPT",
+            equity.clone(), sample_settlement(2), expiry,
+            strike, PutOrCall::Put, OptionSettlement::Cash).unwrap();
+        let put_greeks = put.price_with_greeks(&context).unwrap();
+        assert_approx(put_greeks.delta(), df * (black76.cdf(d_plus) - 1.0),
+            1e-8);
+        assert_approx(put_greeks.gamma(), greeks.gamma(), 1e-8);
+        assert_approx(put_greeks.vega(), greeks.vega(), 1e-8);
+    }
+
+    #[test]
+    fn european_with_normal_vols_allows_negative_forward() {
+
+        let spot = -2.0;
+        let strike = -1.5;
+        let expiry = DateTime::new(
+            Date::from_ymd(2018, 12, 01), TimeOfDay::Close);
+
+        // log-normal vols cannot cope with a negative strike
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let equity = sample_equity(currency, 2);
+        assert!(SpotStartingEuropean::new("SampleCall", "OPT",
+            Arc::new(equity.clone()), settlement.clone(), expiry,
+            strike, PutOrCall::Call, OptionSettlement::Cash).is_err());
+
+        let equity: Arc<Instrument> = Arc::new(NormalQuotedEquity {
+            equity: equity });
+        let call = SpotStartingEuropean::new("SampleCall", "OPT",
+            equity.clone(), settlement.clone(), expiry,
+            strike, PutOrCall::Call, OptionSettlement::Cash).unwrap();
+        let put = SpotStartingEuropean::new("SamplePut", "OPT",
+            equity.clone(), settlement, expiry,
+            strike, PutOrCall::Put, OptionSettlement::Cash).unwrap();
+
+        // nor can they cope with a negative forward
+        let context = sample_pricing_context(spot);
+        assert!(call.price(&context).is_err());
+
+        let context = SamplePricingContext { spot: spot, vol: 1.5,
+            quoting: VolQuoting::Normal };
+        let call_price = call.price(&context).unwrap();
+        let put_price = put.price(&context).unwrap();
+        let greeks = call.price_with_greeks(&context).unwrap();
+        assert_approx(greeks.price(), call_price, 1e-12);
+        assert!(call_price > 0.0 && put_price > 0.0);
+
+        // put/call parity holds with the undisplaced negative forward
+        let forward = context.forward_curve(&*equity, expiry.date()).unwrap()
+            .forward(expiry.date()).unwrap();
+        assert!(forward < 0.0);
+        let df = greeks.price() / greeks.df_sensitivity();
+        assert_approx(call_price - put_price, df * (forward - strike), 1e-12);
+        assert!(greeks.delta() > 0.0 && greeks.delta() < 1.0);
+    }
+
     fn check_european_value(spot: f64, strike: f64, expiry: DateTime,
         put_or_call: PutOrCall, expected: f64) {
 
-        let currency = Rc::new(sample_currency(2));
+        let currency = Arc::new(sample_currency(2));
         let settlement = sample_settlement(2);
-        let equity = Rc::new(sample_equity(currency, 2));
+        let equity = Arc::new(sample_equity(currency, 2));
         let cash_or_physical = OptionSettlement::Cash;
         let european = SpotStartingEuropean::new("SampleEuropean", "OPT",
             equity.clone(), settlement, expiry,
@@ -754,9 +1445,9 @@ mod tests {
         strike_date: DateTime, expiry: DateTime,
         put_or_call: PutOrCall, expected: f64) {
 
-        let currency = Rc::new(sample_currency(2));
+        let currency = Arc::new(sample_currency(2));
         let settlement = sample_settlement(2);
-        let equity = Rc::new(sample_equity(currency, 2));
+        let equity = Arc::new(sample_equity(currency, 2));
         let cash_or_physical = OptionSettlement::Cash;
         let european = ForwardStartingEuropean::new("SampleEuropean", "OPT",
             equity.clone(), settlement, expiry, strike_fraction,
@@ -771,9 +1462,9 @@ mod tests {
         strike_date: DateTime, expiry: DateTime,
         put_or_call: PutOrCall, expected: f64) {
 
-        let currency = Rc::new(sample_currency(2));
+        let currency = Arc::new(sample_currency(2));
         let settlement = sample_settlement(2);
-        let equity = Rc::new(sample_equity(currency, 2));
+        let equity = Arc::new(sample_equity(currency, 2));
         let cash_or_physical = OptionSettlement::Cash;
         let european = ForwardStartingEuropean::new("SampleEuropean", "OPT",
             equity.clone(), settlement, expiry, strike_fraction,
@@ -798,6 +1489,181 @@ mod tests {
         }
     }
 
+    fn bermudan_put(dates: &[DateTime], strike: f64) -> BermudanOption {
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let equity = Arc::new(sample_equity(currency, 2));
+        BermudanOption::new("SampleBermudan", "OPT", equity, settlement,
+            dates, strike, PutOrCall::Put, OptionSettlement::Cash).unwrap()
+    }
+
+    #[test]
+    fn bermudan_fix_drops_past_exercise_dates() {
+        let today = Date::from_ymd(2018, 06, 01);
+        let dates = [DateTime::new(today - 7, TimeOfDay::Close),
+            DateTime::new(today + 14, TimeOfDay::Close),
+            DateTime::new(today + 31, TimeOfDay::Close)];
+        let bermudan = bermudan_put(&dates, 90.0);
+
+        let fixed = bermudan.fix(&sample_fixings()).unwrap().unwrap();
+        assert_eq!(fixed.len(), 1);
+        let pde = fixed[0].1.as_pde_priceable().unwrap();
+        assert_eq!(pde.pde_exercise(), EarlyExercise::Bermudan(vec![
+            DateDayFraction::new(today + 14, 0.8)]));
+    }
+
+    #[test]
+    fn bermudan_fix_at_expiry_pays_intrinsic() {
+        let today = Date::from_ymd(2018, 06, 01);
+        let dates = [DateTime::new(today - 7, TimeOfDay::Close),
+            DateTime::new(today, TimeOfDay::Close)];
+
+        // the fixing at expiry is 100, so the put is out of the money
+        let bermudan = bermudan_put(&dates, 90.0);
+        let fixed = bermudan.fix(&sample_fixings()).unwrap().unwrap();
+        assert_eq!(fixed.len(), 0);
+
+        let bermudan = bermudan_put(&dates, 110.0);
+        let fixed = bermudan.fix(&sample_fixings()).unwrap().unwrap();
+        assert_eq!(fixed.len(), 1);
+        assert_approx(fixed[0].0, 10.0, 1e-12);
+        assert!(fixed[0].1.is_pure_rates());
+    }
+
+    #[test]
+    fn american_rejects_start_after_expiry() {
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let equity = Arc::new(sample_equity(currency, 2));
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let rule = BusinessDays::new_back(calendar, 5);
+        let expiry = DateTime::new(Date::from_ymd(2018, 06, 01),
+            TimeOfDay::Close);
+        let start = DateTime::new(Date::from_ymd(2018, 06, 08),
+            TimeOfDay::Open);
+        assert!(AmericanOption::new("SampleAmerican", "OPT", equity,
+            settlement, start, expiry, 100.0, PutOrCall::Put,
+            OptionSettlement::Cash, &rule).is_err());
+    }
+
+    fn early_exercise_options(strike: f64, put_or_call: PutOrCall)
+        -> (Arc<SpotStartingEuropean>, Arc<BermudanOption>,
+        Arc<AmericanOption>) {
+
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let equity = Arc::new(sample_equity(currency, 2));
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let expiry = DateTime::new(Date::from_ymd(2018, 06, 01),
+            TimeOfDay::Close);
+        let start = DateTime::new(Date::from_ymd(2017, 01, 02),
+            TimeOfDay::Open);
+
+        let european = SpotStartingEuropean::new("SampleEuropean", "OPT",
+            equity.clone(), settlement.clone(), expiry, strike, put_or_call,
+            OptionSettlement::Cash).unwrap();
+
+        // roughly monthly exercise for the Bermudan, weekly for Monte-Carlo
+        // valuation of the American
+        let monthly = BusinessDays::new_back(calendar.clone(), 21);
+        let bermudan = BermudanOption::new_rolled("SampleBermudan", "OPT",
+            equity.clone(), settlement.clone(), start, expiry, &monthly,
+            strike, put_or_call, OptionSettlement::Cash).unwrap();
+
+        let weekly = BusinessDays::new_back(calendar, 5);
+        let american = AmericanOption::new("SampleAmerican", "OPT",
+            equity, settlement, start, expiry, strike, put_or_call,
+            OptionSettlement::Cash, &weekly).unwrap();
+
+        (Arc::new(european), Arc::new(bermudan), Arc::new(american))
+    }
+
+    fn market_fixings() -> Arc<FixingTable> {
+        let today = Date::from_ymd(2017, 01, 02);
+        Arc::new(FixingTable::new(today, &[
+            ("BP.L", &[
+            (DateTime::new(today - 7, TimeOfDay::Close), 102.0)])]).unwrap())
+    }
+
+    fn price_with(factory: &PricerFactory, instrument: Arc<Instrument>)
+        -> f64 {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let pricer = factory.new(instrument, market_fixings(), market_data)
+            .unwrap();
+        pricer.price().unwrap()
+    }
+
+    #[test]
+    fn american_put_worth_more_than_bermudan() {
+        let pde = PdePricerFactory::new(PdeDynamics::Black, 200, 400, 5.0)
+            .unwrap();
+        let (european, bermudan, american) = early_exercise_options(110.0,
+            PutOrCall::Put);
+        let european_price = price_with(&pde, european);
+        let bermudan_price = price_with(&pde, bermudan);
+        let american_price = price_with(&pde, american);
+
+        assert!(european_price + 0.1 < bermudan_price,
+            "european={} bermudan={}", european_price, bermudan_price);
+        assert!(bermudan_price < american_price
+            && american_price < bermudan_price + 0.2,
+            "bermudan={} american={}", bermudan_price, american_price);
+    }
+
+    #[test]
+    fn american_call_exercises_before_dividends() {
+
+        // A deep in the money call may be worth exercising just before a
+        // cash dividend, if the dividend outweighs the interest lost by
+        // paying the strike early. The sample dividends are small, so the
+        // effect is small too.
+        let pde = PdePricerFactory::new(PdeDynamics::Black, 200, 400, 5.0)
+            .unwrap();
+        let (european, _, american) = early_exercise_options(50.0,
+            PutOrCall::Call);
+        let european_price = price_with(&pde, european);
+        let american_price = price_with(&pde, american);
+        assert!(american_price > european_price + 0.1,
+            "european={} american={}", european_price, american_price);
+    }
+
+    #[test]
+    fn bermudan_put_monte_carlo_matches_pde() {
+        let pde = PdePricerFactory::new(PdeDynamics::Black, 200, 400, 5.0)
+            .unwrap();
+        let mc = MonteCarloPricerFactory::new(Box::new(
+            BlackDiffusionFactory::new(20, 0.01, 30000)));
+        let (_, bermudan, american) = early_exercise_options(110.0,
+            PutOrCall::Put);
+
+        // Longstaff-Schwartz is biased low, as the exercise boundary is
+        // suboptimal, but only slightly compared to the statistical error
+        let pde_price = price_with(&pde, bermudan.clone());
+        let (mc_price, standard_error) = mc_price_with(&mc, bermudan);
+        assert_within_errors(mc_price, pde_price, standard_error, 4.0);
+
+        // The American is valued as a weekly Bermudan in Monte-Carlo
+        let pde_price = price_with(&pde, american.clone());
+        let (mc_price, standard_error) = mc_price_with(&mc, american);
+        assert_within_errors(mc_price, pde_price, standard_error, 4.0);
+    }
+
+    fn mc_price_with(factory: &MonteCarloPricerFactory,
+        instrument: Arc<Instrument>) -> (f64, f64) {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let pricer = factory.new_mc_pricer(instrument, market_fixings(),
+            market_data).unwrap();
+        let result = pricer.mc_result().unwrap();
+        (result.mean(), result.standard_error())
+    }
+
+    fn assert_within_errors(value: f64, expected: f64, standard_error: f64,
+        n_errors: f64) {
+        assert!((value - expected).abs() < n_errors * standard_error,
+            "value={} expected={} standard_error={}", value, expected,
+            standard_error);
+    }
+
     fn assert_approx(value: f64, expected: f64, tolerance: f64) {
         assert!(approx_eq(value, expected, tolerance),
             "value={} expected={}", value, expected);
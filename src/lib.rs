@@ -2,6 +2,7 @@ extern crate statrs;
 extern crate ndarray;
 extern crate nalgebra;
 extern crate rand;
+extern crate num_complex;
 // listed in dependency order, though this is not essential for compilation
 pub mod core;
 pub mod math;
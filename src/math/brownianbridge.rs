@@ -0,0 +1,186 @@
+use core::qm;
+
+/// Brownian bridge construction of a Wiener path over a set of times. The
+/// first gaussian fixes the value at the final time, the next fixes the
+/// value at the midpoint conditional on that, and so on by bisection. When
+/// the gaussians come from a low-discrepancy sequence, this puts the best
+/// distributed dimensions where they have most effect on the payoff, which
+/// greatly reduces the effective dimension of the problem.
+///
+/// The output is the increments of the path, each divided by the square
+/// root of its time step. These are independent standard gaussians, just
+/// as if they had been drawn directly, so the bridge can be used anywhere
+/// that the gaussians for each step are used.
+#[derive(Clone, Debug)]
+pub struct BrownianBridge {
+    left: Vec<usize>,
+    right: Vec<usize>,
+    bridge: Vec<usize>,
+    left_weight: Vec<f64>,
+    right_weight: Vec<f64>,
+    std_dev: Vec<f64>,
+    sqrt_dt: Vec<f64>
+}
+
+impl BrownianBridge {
+    /// Creates a bridge over the given times, which must be positive and
+    /// strictly increasing. The path starts at zero at time zero. Only the
+    /// proportions between the times matter, so they can be in any units.
+    pub fn new(times: &[f64]) -> Result<BrownianBridge, qm::Error> {
+        let n = times.len();
+        if n == 0 {
+            return Err(qm::Error::new("Brownian bridge needs some times"))
+        }
+        let mut previous = 0.0;
+        let mut sqrt_dt = Vec::with_capacity(n);
+        for &t in times.iter() {
+            if !(t > previous) {
+                return Err(qm::Error::new(&format!("Brownian bridge times \
+                    must be positive and strictly increasing: {} follows {}",
+                    t, previous)))
+            }
+            sqrt_dt.push((t - previous).sqrt());
+            previous = t;
+        }
+
+        // The first point is the last time. We then repeatedly bisect the
+        // unpopulated ranges. The left index is one more than the index of
+        // the populated point to the left, so zero means time zero.
+        let mut populated = vec![false; n];
+        let mut left = vec![0; n];
+        let mut right = vec![0; n];
+        let mut bridge = vec![0; n];
+        let mut left_weight = vec![0.0; n];
+        let mut right_weight = vec![0.0; n];
+        let mut std_dev = vec![0.0; n];
+        populated[n - 1] = true;
+        bridge[0] = n - 1;
+        std_dev[0] = times[n - 1].sqrt();
+
+        let mut j = 0;
+        for i in 1..n {
+            // find the next unpopulated range j..k
+            while populated[j] {
+                j += 1;
+            }
+            let mut k = j;
+            while !populated[k] {
+                k += 1;
+            }
+
+            let l = j + ((k - 1 - j) >> 1);
+            populated[l] = true;
+            bridge[i] = l;
+            left[i] = j;
+            right[i] = k;
+
+            let t_left = if j == 0 { 0.0 } else { times[j - 1] };
+            let span = times[k] - t_left;
+            left_weight[i] = (times[k] - times[l]) / span;
+            right_weight[i] = (times[l] - t_left) / span;
+            std_dev[i] = ((times[l] - t_left) * (times[k] - times[l])
+                / span).sqrt();
+
+            j = k + 1;
+            if j >= n {
+                j = 0;
+            }
+        }
+
+        Ok(BrownianBridge { left: left, right: right, bridge: bridge,
+            left_weight: left_weight, right_weight: right_weight,
+            std_dev: std_dev, sqrt_dt: sqrt_dt })
+    }
+
+    /// The number of times in the bridge
+    pub fn size(&self) -> usize {
+        self.sqrt_dt.len()
+    }
+
+    /// Converts gaussians, in order of importance, into standardised
+    /// increments along the path. Both slices must be the same size as the
+    /// bridge.
+    pub fn transform(&self, gaussians: &[f64], increments: &mut [f64]) {
+        let n = self.size();
+        assert_eq!(gaussians.len(), n);
+        assert_eq!(increments.len(), n);
+
+        // build the path in place in the output, then difference it
+        let path = increments;
+        path[n - 1] = self.std_dev[0] * gaussians[0];
+        for i in 1..n {
+            let j = self.left[i];
+            let k = self.right[i];
+            let from_left = if j == 0 {
+                0.0
+            } else {
+                self.left_weight[i] * path[j - 1]
+            };
+            path[self.bridge[i]] = from_left + self.right_weight[i] * path[k]
+                + self.std_dev[i] * gaussians[i];
+        }
+
+        for i in (1..n).rev() {
+            path[i] = (path[i] - path[i - 1]) / self.sqrt_dt[i];
+        }
+        path[0] /= self.sqrt_dt[0];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::numerics::approx_eq;
+
+    #[test]
+    fn first_gaussian_fixes_the_end() {
+        let times = [0.5, 1.0, 1.25, 3.0];
+        let bridge = BrownianBridge::new(&times).unwrap();
+        let mut increments = [0.0; 4];
+        bridge.transform(&[1.0, 0.0, 0.0, 0.0], &mut increments);
+
+        // with no other randomness, the path is a straight line to
+        // sqrt(T), so every standardised increment is sqrt(dt / T)
+        let mut previous = 0.0;
+        for (t, x) in times.iter().zip(increments.iter()) {
+            let expected = ((t - previous) / 3.0).sqrt();
+            assert!(approx_eq(*x, expected, 1e-14), "x={} expected={}", x,
+                expected);
+            previous = *t;
+        }
+    }
+
+    #[test]
+    fn increments_are_independent_gaussians() {
+        // The transform is linear, so the increments are independent
+        // standard gaussians if and only if its matrix is orthogonal.
+        let times = [0.1, 0.2, 0.5, 0.7, 1.0, 1.1, 2.0];
+        let n = times.len();
+        let bridge = BrownianBridge::new(&times).unwrap();
+        let mut columns = Vec::new();
+        for i in 0..n {
+            let mut unit = vec![0.0; n];
+            unit[i] = 1.0;
+            let mut column = vec![0.0; n];
+            bridge.transform(&unit, &mut column);
+            columns.push(column);
+        }
+        for i in 0..n {
+            for j in 0..n {
+                let dot: f64 = (0..n).map(|k| columns[k][i] * columns[k][j])
+                    .sum();
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!(approx_eq(dot, expected, 1e-12), "i={} j={} dot={}",
+                    i, j, dot);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_times() {
+        assert!(BrownianBridge::new(&[]).is_err());
+        assert!(BrownianBridge::new(&[0.0, 1.0]).is_err());
+        assert!(BrownianBridge::new(&[1.0, 1.0]).is_err());
+        assert!(BrownianBridge::new(&[1.0]).is_ok());
+    }
+}
@@ -0,0 +1,151 @@
+use nalgebra::base::DMatrix;
+use core::qm;
+
+/// Returns the smallest eigenvalue of a symmetric matrix. A correlation
+/// matrix is only valid if this is non-negative.
+pub fn min_eigenvalue(matrix: &DMatrix<f64>) -> f64 {
+    matrix.symmetric_eigenvalues().iter()
+        .fold(::std::f64::INFINITY, |m, e| m.min(*e))
+}
+
+/// Returns the Frobenius norm of the difference between two matrices, in
+/// other words the square root of the sum of the squared differences.
+pub fn frobenius_distance(a: &DMatrix<f64>, b: &DMatrix<f64>) -> f64 {
+    assert_eq!(a.shape(), b.shape());
+    (a - b).norm()
+}
+
+/// Finds the nearest correlation matrix to the given symmetric matrix, in
+/// the Frobenius norm, using the alternating projections method of Higham,
+/// "Computing the nearest correlation matrix -- a problem from finance",
+/// IMA Journal of Numerical Analysis 22 (2002). We alternate between
+/// projecting onto the matrices with unit diagonal, and onto the positive
+/// semi-definite matrices, using Dykstra's correction on the latter so that
+/// the iteration converges on the nearest point of the intersection.
+///
+/// The eigenvalues of the result are floored at min_eigenvalue before the
+/// diagonal is reset, so a small positive floor gives a result that is
+/// positive definite, and can safely be factorised by Cholesky.
+///
+/// Iteration stops when the relative change in the result between
+/// iterations is less than the tolerance. An error is returned if this does
+/// not happen within max_iterations.
+pub fn nearest_correlation(matrix: &DMatrix<f64>, min_eigenvalue: f64,
+    tolerance: f64, max_iterations: usize)
+    -> Result<DMatrix<f64>, qm::Error> {
+
+    let n = matrix.nrows();
+    if matrix.ncols() != n {
+        return Err(qm::Error::new("Correlation matrix must be square"))
+    }
+    for i in 0..n {
+        for j in 0..i {
+            if matrix[(i, j)] != matrix[(j, i)] {
+                return Err(qm::Error::new(
+                    "Correlation matrix must be symmetric"))
+            }
+        }
+    }
+
+    let mut correction = DMatrix::<f64>::zeros(n, n);
+    let mut y = matrix.clone();
+    for _ in 0..max_iterations {
+
+        // project onto the positive semi-definite matrices, with Dykstra's
+        // correction
+        let r = &y - &correction;
+        let x = project_to_psd(&r, min_eigenvalue);
+        correction = &x - &r;
+
+        // project onto the unit diagonal matrices
+        let mut next = x;
+        for i in 0..n {
+            next[(i, i)] = 1.0;
+        }
+
+        let change = frobenius_distance(&next, &y) / next.norm();
+        y = next;
+        if change < tolerance {
+            return Ok(y)
+        }
+    }
+
+    Err(qm::Error::new(&format!("Nearest correlation matrix did not \
+        converge in {} iterations", max_iterations)))
+}
+
+/// Projects a symmetric matrix onto the positive semi-definite matrices, by
+/// flooring its eigenvalues.
+fn project_to_psd(matrix: &DMatrix<f64>, min_eigenvalue: f64)
+    -> DMatrix<f64> {
+
+    let mut eigen = matrix.clone().symmetric_eigen();
+    for value in eigen.eigenvalues.iter_mut() {
+        *value = value.max(min_eigenvalue);
+    }
+    let result = eigen.recompose();
+
+    // recomposition leaves tiny asymmetries, which we remove
+    (&result + &result.transpose()) * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::numerics::approx_eq;
+
+    #[test]
+    fn higham_example() {
+        // the example from section 4 of Higham's paper
+        let a = DMatrix::from_row_slice(3, 3, &[
+            1.0, 1.0, 0.0,
+            1.0, 1.0, 1.0,
+            0.0, 1.0, 1.0]);
+        assert!(min_eigenvalue(&a) < 0.0);
+
+        let x = nearest_correlation(&a, 0.0, 1e-10, 1000).unwrap();
+        let expected = [
+            1.0, 0.7607, 0.1573,
+            0.7607, 1.0, 0.7607,
+            0.1573, 0.7607, 1.0];
+        for i in 0..3 {
+            for j in 0..3 {
+                assert_approx(x[(i, j)], expected[i * 3 + j], 1e-4);
+            }
+        }
+        assert!(min_eigenvalue(&x) > -1e-8);
+        assert_approx(frobenius_distance(&a, &x), 0.5278, 1e-4);
+    }
+
+    #[test]
+    fn valid_matrix_is_unchanged() {
+        let a = DMatrix::from_row_slice(3, 3, &[
+            1.0, 0.5, 0.2,
+            0.5, 1.0, 0.3,
+            0.2, 0.3, 1.0]);
+        let x = nearest_correlation(&a, 1e-8, 1e-12, 100).unwrap();
+        assert_approx(frobenius_distance(&a, &x), 0.0, 1e-12);
+    }
+
+    #[test]
+    fn floored_result_is_positive_definite() {
+        // perfect correlation is positive semi-definite but singular
+        let a = DMatrix::from_row_slice(3, 3, &[
+            1.0, 1.0, 0.9,
+            1.0, 1.0, 0.9,
+            0.9, 0.9, 1.0]);
+        let x = nearest_correlation(&a, 1e-6, 1e-12, 1000).unwrap();
+        assert!(min_eigenvalue(&x) > 0.0);
+        for i in 0..3 {
+            assert_eq!(x[(i, i)], 1.0);
+        }
+
+        let asymmetric = DMatrix::from_row_slice(2, 2, &[1.0, 0.5, 0.4, 1.0]);
+        assert!(nearest_correlation(&asymmetric, 0.0, 1e-10, 100).is_err());
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+}
@@ -59,7 +59,7 @@ pub trait FlyweightInterpolate<T> where T : Interpolable<T> {
 /// Interpolation with date or number for the abscissa and number for the
 /// ordinal. In this implementation, the array of points is supplied in
 /// the constructor to the interpolation object.
-pub trait Interpolate<T> where T : Interpolable<T> {
+pub trait Interpolate<T> : Send + Sync where T : Interpolable<T> {
     fn interpolate(&self, x: T) -> Result<f64, qm::Error>;
 }
 
@@ -100,6 +100,63 @@ pub fn lerp(y0: f64, y1: f64, t: f64) -> f64 {
     y0 * (1.0 - t) + y1 * t
 } 
 
+/// Weight of one of a set of ascending points, used for localised risk
+/// bumps. The weight is one at points[index], falls linearly to zero at the
+/// neighbouring points, and is flat beyond the first and last points. The
+/// weights of all the points therefore sum to one everywhere.
+pub fn hat_weight<T : Interpolable<T> + Copy>(points: &[T], index: usize,
+    x: T) -> f64 {
+
+    let n = points.len();
+    if index >= n {
+        return 0.0
+    }
+
+    let centre = points[index];
+    if x.interp_cmp(centre) != Ordering::Greater {
+        if index == 0 {
+            return 1.0
+        }
+        let left = points[index - 1];
+        (left.interp_diff(x) / left.interp_diff(centre)).max(0.0)
+    } else {
+        if index == n - 1 {
+            return 1.0
+        }
+        let right = points[index + 1];
+        (x.interp_diff(right) / centre.interp_diff(right)).max(0.0)
+    }
+}
+
+/// Weight of one of a set of ascending points, where each point owns the
+/// region from the midpoint with its left neighbour (inclusive) to the
+/// midpoint with its right neighbour (exclusive). The weight is one within
+/// the region and zero outside. The first and last points own everything
+/// beyond them, so again the weights sum to one everywhere.
+pub fn bucket_weight<T : Interpolable<T> + Copy>(points: &[T], index: usize,
+    x: T) -> f64 {
+
+    let n = points.len();
+    if index >= n {
+        return 0.0
+    }
+
+    let centre = points[index];
+    if index > 0 {
+        let left = points[index - 1];
+        if left.interp_diff(x) < 0.5 * left.interp_diff(centre) {
+            return 0.0
+        }
+    }
+    if index < n - 1 {
+        let right = points[index + 1];
+        if x.interp_diff(right) <= 0.5 * centre.interp_diff(right) {
+            return 0.0
+        }
+    }
+    1.0
+}
+
 /// Linear interpolation function. The y value and result must be f64.
 /// The x value can be any type supporting subtraction giving a numeric
 /// type.
@@ -223,7 +280,8 @@ pub struct Linear<T> where T : Interpolable<T> {
     points: Vec<(T, f64)>
 }
 
-impl<T : Interpolable<T> + Copy> Interpolate<T> for Linear<T> {
+impl<T : Interpolable<T> + Copy + Send + Sync> Interpolate<T>
+    for Linear<T> {
     fn interpolate(&self, x: T) -> Result<f64, qm::Error> {
         linear_interpolate_extrapolate(
             x, &self.points, self.left, self.right)
@@ -240,6 +298,11 @@ impl<T : Interpolable<T> + Copy> Linear<T> {
         validate_abscissae(&points)?;
         Ok(Linear { left: left, right: right, points: points.to_vec() })
     }
+
+    /// The points being interpolated, in ascending order of x
+    pub fn points(&self) -> &[(T, f64)] {
+        &self.points
+    }
 }
 
 /// Cubic spline interpolation is continuous up to the second derivative.
@@ -257,7 +320,8 @@ pub struct CubicSpline<T> where T : Interpolable<T> {
     second_deriv: Vec<f64>
 }
 
-impl<T : Interpolable<T> + Copy> Interpolate<T> for CubicSpline<T> {
+impl<T : Interpolable<T> + Copy + Send + Sync> Interpolate<T>
+    for CubicSpline<T> {
     fn interpolate(&self, x: T) -> Result<f64, qm::Error> {
 
         let n = self.points.len();
@@ -414,6 +478,29 @@ mod tests {
         assert!(approx_eq(r4, 9.0, tol));
     }
 
+    #[test]
+    fn localised_weights_sum_to_one() {
+        let points = [1.0, 2.0, 4.0, 8.0];
+        for &x in [0.0, 1.0, 1.5, 2.0, 3.0, 3.5, 5.0, 6.0, 8.0, 10.0].iter() {
+            let hats: f64 = (0..4).map(|i| hat_weight(&points, i, x)).sum();
+            let buckets: f64 = (0..4).map(|i| bucket_weight(&points, i, x))
+                .sum();
+            assert!(approx_eq(hats, 1.0, 1e-12), "x={} sum={}", x, hats);
+            assert!(approx_eq(buckets, 1.0, 1e-12), "x={} sum={}", x, buckets);
+        }
+
+        assert!(approx_eq(hat_weight(&points, 2, 3.0), 0.5, 1e-12));
+        assert!(approx_eq(hat_weight(&points, 2, 5.0), 0.75, 1e-12));
+        assert!(approx_eq(hat_weight(&points, 2, 1.5), 0.0, 1e-12));
+        assert!(approx_eq(hat_weight(&points, 3, 10.0), 1.0, 1e-12));
+
+        // midpoints belong to the right hand bucket
+        assert!(approx_eq(bucket_weight(&points, 1, 3.0), 0.0, 1e-12));
+        assert!(approx_eq(bucket_weight(&points, 2, 3.0), 1.0, 1e-12));
+        assert!(approx_eq(bucket_weight(&points, 2, 5.9), 1.0, 1e-12));
+        assert!(approx_eq(bucket_weight(&points, 0, -5.0), 1.0, 1e-12));
+    }
+
     #[test]
     fn interpolate_integers_flyweight() {
         let points = [(0, 0.0), (2, 3.0), (4, 8.0), (6, 9.0), (7, 10.0)];
@@ -1,3 +1,8 @@
 pub mod numerics;
 pub mod interpolation;
 pub mod optionpricing;
+pub mod optimization;
+pub mod regression;
+pub mod correlation;
+pub mod sobol;
+pub mod brownianbridge;
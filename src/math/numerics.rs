@@ -1,10 +1,69 @@
 
+use statrs::distribution::Normal;
+use statrs::distribution::Univariate;
+
 /// Compares two floating point numbers for equality, with margin for error
 pub fn approx_eq(first: f64, second: f64, tolerance: f64) -> bool {
     let diff = first - second;
     diff.abs() < tolerance
 }
 
+/// The inverse of the cumulative normal distribution. This is needed for
+/// turning low-discrepancy uniforms into gaussians, where lossy methods
+/// such as Box-Muller would destroy the structure of the sequence.
+///
+/// We use the rational approximation of Acklam, which has a relative error
+/// of about 1e-9, followed by a single Halley step against the statrs
+/// cumulative normal, so the two are consistent to round trip accuracy.
+/// Returns minus or plus infinity for zero or one.
+pub fn inverse_cumulative_normal(p: f64) -> f64 {
+    assert!(p >= 0.0 && p <= 1.0);
+    if p == 0.0 {
+        return ::std::f64::NEG_INFINITY
+    } else if p == 1.0 {
+        return ::std::f64::INFINITY
+    }
+
+    const A: [f64; 6] = [-3.969683028665376e+01, 2.209460984245205e+02,
+        -2.759285104469687e+02, 1.383577518672690e+02,
+        -3.066479806614716e+01, 2.506628277459239e+00];
+    const B: [f64; 5] = [-5.447609879822406e+01, 1.615858368580409e+02,
+        -1.556989798598866e+02, 6.680131188771972e+01,
+        -1.328068155288572e+01];
+    const C: [f64; 6] = [-7.784894002430293e-03, -3.223964580411365e-01,
+        -2.400758277161838e+00, -2.549732539343734e+00,
+        4.374664141464968e+00, 2.938163982698783e+00];
+    const D: [f64; 4] = [7.784695709041462e-03, 3.224671290700398e-01,
+        2.445134137142996e+00, 3.754408661907416e+00];
+    const P_LOW: f64 = 0.02425;
+
+    let tail = |q: f64| (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q
+        + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3])
+        * q + 1.0);
+
+    let x = if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5])
+            * q / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4])
+            * r + 1.0)
+    };
+
+    // refine with a Halley step, working in whichever tail is more accurate
+    let normal = Normal::new(0.0, 1.0).unwrap();
+    let e = if x < 0.0 {
+        normal.cdf(x) - p
+    } else {
+        (1.0 - p) - normal.cdf(-x)
+    };
+    let u = e * (2.0 * ::std::f64::consts::PI).sqrt() * (0.5 * x * x).exp();
+    x - u / (1.0 + 0.5 * x * u)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -14,5 +73,23 @@ mod tests {
         assert!(approx_eq(123.456, 123.4562, 0.001));
         assert!(!approx_eq(123.456, 123.4562, 0.0001));
     }
-}
 
+    #[test]
+    fn inverse_cumulative_normal_tests() {
+        assert_eq!(inverse_cumulative_normal(0.5), 0.0);
+        assert!(approx_eq(inverse_cumulative_normal(0.975),
+            1.959963984540054, 1e-9));
+        assert!(approx_eq(inverse_cumulative_normal(0.025),
+            -1.959963984540054, 1e-9));
+
+        // round trip through the cumulative normal, including the tails
+        let normal = Normal::new(0.0, 1.0).unwrap();
+        for &p in [1e-12, 1e-6, 0.01, 0.02425, 0.1, 0.3, 0.6, 0.9, 0.99]
+            .iter() {
+            let x = inverse_cumulative_normal(p);
+            assert!(approx_eq(normal.cdf(x) / p, 1.0, 1e-12),
+                "p={} x={} cdf={}", p, x, normal.cdf(x));
+        }
+        assert_eq!(inverse_cumulative_normal(0.0), ::std::f64::NEG_INFINITY);
+    }
+}
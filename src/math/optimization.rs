@@ -0,0 +1,182 @@
+use nalgebra::base::DMatrix;
+use core::qm;
+
+/// Least-squares minimiser using the Levenberg-Marquardt algorithm, with
+/// simple box constraints on the parameters. This is the workhorse for
+/// calibrating parametric models such as SVI or SABR smiles to market
+/// quotes, where the number of parameters is small and the residuals are
+/// smooth functions of them.
+///
+/// The Jacobian is calculated by forward finite differences, so the residual
+/// function must be reasonably cheap to evaluate. Bounds are enforced by
+/// projecting each trial step back into the box.
+pub struct LevenbergMarquardt {
+    max_iterations: usize,
+    tolerance: f64
+}
+
+impl LevenbergMarquardt {
+    /// Creates a minimiser. The max_iterations limits the number of
+    /// Jacobian evaluations. The tolerance is the relative reduction in the
+    /// sum of squares below which we consider the minimisation converged.
+    pub fn new(max_iterations: usize, tolerance: f64) -> LevenbergMarquardt {
+        LevenbergMarquardt {
+            max_iterations: max_iterations,
+            tolerance: tolerance
+        }
+    }
+
+    /// Minimises the sum of squares of the residuals, starting from the
+    /// initial parameters, which must lie within lower and upper. The
+    /// residuals function is passed the parameters and fills in the
+    /// residuals, of which there are n_residuals. Returns the parameters at
+    /// the minimum. Failing to converge within max_iterations is not an
+    /// error, as the result is still the best we have found.
+    pub fn minimise<F>(&self, residuals: F, initial: &[f64], lower: &[f64],
+        upper: &[f64], n_residuals: usize) -> Result<Vec<f64>, qm::Error>
+        where F: Fn(&[f64], &mut [f64]) -> Result<(), qm::Error> {
+
+        let n = initial.len();
+        if lower.len() != n || upper.len() != n {
+            return Err(qm::Error::new("Bounds must be the same size as the \
+                parameters"))
+        }
+        if n_residuals < n {
+            return Err(qm::Error::new(&format!("Cannot fit {} parameters to \
+                only {} residuals", n, n_residuals)))
+        }
+        for i in 0..n {
+            if !(lower[i] <= upper[i]) {
+                return Err(qm::Error::new(&format!("Lower bound {} is above \
+                    upper bound {}", lower[i], upper[i])))
+            }
+        }
+
+        let mut x: Vec<f64> = (0..n)
+            .map(|i| initial[i].max(lower[i]).min(upper[i])).collect();
+        let mut r = vec![0.0; n_residuals];
+        residuals(&x, &mut r)?;
+        let mut cost = sum_of_squares(&r)?;
+
+        let mut lambda = 1e-3;
+        let max_lambda = 1e12;
+        let mut jacobian = DMatrix::<f64>::zeros(n_residuals, n);
+        let mut bumped = vec![0.0; n_residuals];
+        let mut trial = vec![0.0; n];
+        let mut trial_r = vec![0.0; n_residuals];
+
+        for _ in 0..self.max_iterations {
+            if cost == 0.0 {
+                break
+            }
+
+            // Forward difference Jacobian, stepping away from the upper
+            // bound if we are sitting on it
+            for j in 0..n {
+                let mut h = 1e-7 * x[j].abs().max(1e-3);
+                if x[j] + h > upper[j] {
+                    h = -h;
+                }
+                trial.copy_from_slice(&x);
+                trial[j] += h;
+                residuals(&trial, &mut bumped)?;
+                for i in 0..n_residuals {
+                    jacobian[(i, j)] = (bumped[i] - r[i]) / h;
+                }
+            }
+
+            // normal equations, J'J delta = -J'r
+            let jt = jacobian.transpose();
+            let jtj = &jt * &jacobian;
+            let jtr = &jt * DMatrix::from_column_slice(n_residuals, 1, &r);
+
+            // Try steps with increasing damping until one reduces the cost
+            let mut improved = false;
+            while lambda < max_lambda {
+                let mut damped = jtj.clone();
+                for j in 0..n {
+                    damped[(j, j)] += lambda * jtj[(j, j)].max(1e-12);
+                }
+                let step = damped.lu().solve(&(-&jtr));
+                if let Some(delta) = step {
+                    for j in 0..n {
+                        trial[j] = (x[j] + delta[(j, 0)])
+                            .max(lower[j]).min(upper[j]);
+                    }
+                    // treat failures as a bad step, as the trial point may
+                    // just be somewhere the model cannot be evaluated
+                    if residuals(&trial, &mut trial_r).is_ok() {
+                        if let Ok(trial_cost) = sum_of_squares(&trial_r) {
+                            if trial_cost < cost {
+                                let reduction = cost - trial_cost;
+                                x.copy_from_slice(&trial);
+                                r.copy_from_slice(&trial_r);
+                                cost = trial_cost;
+                                lambda = (lambda * 0.1).max(1e-12);
+                                improved = reduction > self.tolerance * cost
+                                    && reduction > 1e-30;
+                                if !improved {
+                                    return Ok(x)
+                                }
+                                break
+                            }
+                        }
+                    }
+                }
+                lambda *= 10.0;
+            }
+
+            if !improved {
+                break
+            }
+        }
+
+        Ok(x)
+    }
+}
+
+fn sum_of_squares(residuals: &[f64]) -> Result<f64, qm::Error> {
+    let sum = residuals.iter().fold(0.0, |acc, r| acc + r * r);
+    if sum.is_finite() {
+        Ok(sum)
+    } else {
+        Err(qm::Error::new("Non-finite residual in minimisation"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::numerics::approx_eq;
+
+    #[test]
+    fn fit_exponential_decay() {
+        // fit y = a exp(-b x) to exact data
+        let xs: [f64; 6] = [0.0, 0.5, 1.0, 1.5, 2.0, 3.0];
+        let ys: Vec<f64> = xs.iter().map(|x| 2.5 * (-0.7 * x).exp()).collect();
+
+        let lm = LevenbergMarquardt::new(100, 1e-15);
+        let result = lm.minimise(|p, r| {
+            for i in 0..xs.len() {
+                r[i] = p[0] * (-p[1] * xs[i]).exp() - ys[i];
+            }
+            Ok(())
+        }, &[1.0, 0.1], &[0.0, 0.0], &[10.0, 10.0], xs.len()).unwrap();
+
+        assert!(approx_eq(result[0], 2.5, 1e-6), "a={}", result[0]);
+        assert!(approx_eq(result[1], 0.7, 1e-6), "b={}", result[1]);
+    }
+
+    #[test]
+    fn fit_respects_bounds() {
+        // the unconstrained minimum is at 3, but we cap at 2
+        let lm = LevenbergMarquardt::new(100, 1e-15);
+        let result = lm.minimise(|p, r| {
+            r[0] = p[0] - 3.0;
+            r[1] = 0.5 * (p[0] - 3.0);
+            Ok(())
+        }, &[0.0], &[-1.0], &[2.0], 2).unwrap();
+
+        assert!(approx_eq(result[0], 2.0, 1e-12), "x={}", result[0]);
+    }
+}
@@ -1,6 +1,8 @@
 use statrs::distribution::Normal;
 use statrs::distribution::Univariate;
+use statrs::distribution::Continuous;
 use core::qm;
+use std::f64::consts::PI;
 
 /// The 1976 reformulation of the Black-Scholes formula, where the price of
 /// a European option is expressed in terms of the Forward and the Strike.
@@ -39,9 +41,454 @@ impl Black76 {
         df * (self.cdf(-d_minus) * strike - self.cdf(-d_plus) * forward)
     }
 
+    /// Calculates the PV and analytic sensitivities of a European call
+    /// option under Black Scholes. This is cheaper than calling call_price
+    /// and then bumping, as the d_plus and d_minus terms are shared.
+    pub fn call_greeks(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> OptionGreeks {
+        self.greeks(1.0, df, forward, strike, sqrt_variance)
+    }
+
+    /// Calculates the PV and analytic sensitivities of a European put
+    /// option under Black Scholes.
+    pub fn put_greeks(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> OptionGreeks {
+        self.greeks(-1.0, df, forward, strike, sqrt_variance)
+    }
+
+    /// Calculates the sqrt of the variance that reproduces the given PV of a
+    /// European call option under Black Scholes. This is the inverse of
+    /// call_price. Returns an error if the price is outside the bounds
+    /// allowed by no-arbitrage, which for a call are df * (F - K).max(0)
+    /// and df * F.
+    pub fn call_implied_sqrt_variance(&self, df: f64, forward: f64,
+        strike: f64, price: f64) -> Result<f64, qm::Error> {
+        self.implied_sqrt_variance(1.0, df, forward, strike, price)
+    }
+
+    /// Calculates the sqrt of the variance that reproduces the given PV of a
+    /// European put option under Black Scholes. This is the inverse of
+    /// put_price. The no-arbitrage bounds for a put are df * (K - F).max(0)
+    /// and df * K.
+    pub fn put_implied_sqrt_variance(&self, df: f64, forward: f64,
+        strike: f64, price: f64) -> Result<f64, qm::Error> {
+        self.implied_sqrt_variance(-1.0, df, forward, strike, price)
+    }
+
     pub fn cdf(&self, x: f64) -> f64 {
         self.normal.cdf(x)
     }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        self.normal.pdf(x)
+    }
+
+    /// Shared implementation of call_greeks and put_greeks. The sign is
+    /// 1.0 for a call and -1.0 for a put. The second order greeks are the
+    /// same for puts and calls, as they differ only by a forward.
+    fn greeks(&self, sign: f64, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> OptionGreeks {
+
+        let log_moneyness = (forward / strike).ln();
+        let (d_plus, d_minus) = d_plus_minus(log_moneyness, sqrt_variance);
+        let n_plus = self.cdf(sign * d_plus);
+        let n_minus = self.cdf(sign * d_minus);
+        let density = self.pdf(d_plus);
+
+        let undiscounted = sign * (n_plus * forward - n_minus * strike);
+        let vega = df * forward * density;
+
+        OptionGreeks {
+            price: df * undiscounted,
+            delta: sign * df * n_plus,
+            gamma: df * density / (forward * sqrt_variance),
+            vega: vega,
+            dual_delta: -sign * df * n_minus,
+            vanna: -df * density * d_minus / sqrt_variance,
+            volga: vega * d_plus * d_minus / sqrt_variance,
+            df_sensitivity: undiscounted }
+    }
+
+    /// Shared implementation of the implied vol calculations. The sign is
+    /// 1.0 for a call and -1.0 for a put.
+    ///
+    /// We first use put/call parity to convert the price to that of the out
+    /// of the money option, which avoids cancellation errors for deep in the
+    /// money options. We then solve using Newton-Raphson, starting from the
+    /// inflection point of the price as a function of sqrt variance. From
+    /// this point, Newton-Raphson converges monotonically in exact
+    /// arithmetic, but we safeguard it with a bracket and fall back to
+    /// bisection if a step leaves the bracket or the vega vanishes, as it
+    /// does for deep out of the money options and very short expiries.
+    fn implied_sqrt_variance(&self, sign: f64, df: f64, forward: f64,
+        strike: f64, price: f64) -> Result<f64, qm::Error> {
+
+        if !(df > 0.0) || !(forward > 0.0) || !(strike > 0.0) {
+            return Err(qm::Error::new(&format!("Cannot imply vol: df={} \
+                forward={} strike={} must all be positive", df, forward,
+                strike)))
+        }
+        if !price.is_finite() {
+            return Err(qm::Error::new("Cannot imply vol from a non-finite \
+                price"))
+        }
+
+        // convert to an undiscounted out of the money price. Otm_sign is
+        // 1.0 if the out of the money option is a call.
+        let undiscounted = price / df;
+        let otm_sign = if strike >= forward { 1.0 } else { -1.0 };
+        let intrinsic = (sign * (forward - strike)).max(0.0);
+        let target = if sign == otm_sign {
+            undiscounted
+        } else {
+            undiscounted - intrinsic
+        };
+
+        // check the no-arbitrage bounds. We allow a little leeway below
+        // intrinsic for rounding errors in the input price.
+        let upper = if otm_sign > 0.0 { forward } else { strike };
+        let tolerance = 1e-14 * forward.max(strike);
+        if target < -tolerance {
+            return Err(qm::Error::new(&format!("Cannot imply vol: price {} \
+                is below the intrinsic value {}", price, df * intrinsic)))
+        }
+        if target >= upper {
+            return Err(qm::Error::new(&format!("Cannot imply vol: price {} \
+                is at or above the upper bound {}", price,
+                df * (upper + intrinsic))))
+        }
+        if target <= tolerance {
+            return Ok(0.0)
+        }
+
+        let log_moneyness = (forward / strike).ln();
+        let otm_price = |sqrt_var: f64| -> f64 {
+            if sqrt_var <= 0.0 {
+                return 0.0
+            }
+            let (d_plus, d_minus) = d_plus_minus(log_moneyness, sqrt_var);
+            otm_sign * (self.cdf(otm_sign * d_plus) * forward
+                - self.cdf(otm_sign * d_minus) * strike)
+        };
+
+        // Find an upper bracket. The price tends to the upper bound as the
+        // variance increases, so we must eventually exceed the target,
+        // unless the target is so close to the upper bound that it cannot
+        // be represented.
+        let max_sqrt_variance = 100.0;
+        let mut lo = 0.0;
+        let mut hi = 1.0_f64.max(2.0 * (2.0 * log_moneyness.abs()).sqrt());
+        while otm_price(hi) < target {
+            lo = hi;
+            hi *= 2.0;
+            if hi > max_sqrt_variance {
+                return Err(qm::Error::new(&format!("Cannot imply vol: price \
+                    {} is too close to the upper bound {}", price,
+                    df * (upper + intrinsic))))
+            }
+        }
+
+        // start at the inflection point, if it is within the bracket
+        let inflection = (2.0 * log_moneyness.abs()).sqrt();
+        let x = if inflection > lo && inflection < hi {
+            inflection
+        } else {
+            0.5 * (lo + hi)
+        };
+
+        let vega = |sqrt_var: f64| -> f64 {
+            let (d_plus, _) = d_plus_minus(log_moneyness, sqrt_var);
+            forward * self.pdf(d_plus)
+        };
+        safeguarded_newton(|x| (otm_price(x) - target, vega(x)), lo, hi, x)
+            .ok_or_else(|| qm::Error::new(&format!("Implied vol failed to \
+            converge for df={} forward={} strike={} price={}",
+            df, forward, strike, price)))
+    }
+}
+
+/// The Bachelier formula, where the underlying is normally rather than
+/// log-normally distributed. Unlike Black76, this allows the forward and
+/// strike to be zero or negative, which is needed for rates-like and spread
+/// underlyings. The sqrt_variance here is the normal vol times the sqrt of
+/// the time, and is in the same units as the forward.
+pub struct Bachelier {
+    normal: Normal
+}
+
+impl Bachelier {
+    pub fn new() -> Result<Bachelier, qm::Error> {
+        match Normal::new(0.0, 1.0) {
+            Ok(normal) => Ok(Bachelier { normal: normal }),
+            Err(e) => Err(qm::Error::new(&format!("RSStat error: {}", e)))
+        }
+    }
+
+    /// Calculates the PV of a European call option under Bachelier
+    pub fn call_price(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> f64 {
+        df * self.undiscounted_price(1.0, forward, strike, sqrt_variance)
+    }
+
+    /// Calculates the PV of a European put option under Bachelier
+    pub fn put_price(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> f64 {
+        df * self.undiscounted_price(-1.0, forward, strike, sqrt_variance)
+    }
+
+    /// Calculates the PV and analytic sensitivities of a European call
+    /// option under Bachelier.
+    pub fn call_greeks(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> OptionGreeks {
+        self.greeks(1.0, df, forward, strike, sqrt_variance)
+    }
+
+    /// Calculates the PV and analytic sensitivities of a European put
+    /// option under Bachelier.
+    pub fn put_greeks(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> OptionGreeks {
+        self.greeks(-1.0, df, forward, strike, sqrt_variance)
+    }
+
+    /// Calculates the normal sqrt variance that reproduces the given PV of a
+    /// European call option under Bachelier. The price must be at least
+    /// the discounted intrinsic value. There is no upper bound.
+    pub fn call_implied_sqrt_variance(&self, df: f64, forward: f64,
+        strike: f64, price: f64) -> Result<f64, qm::Error> {
+        self.implied_sqrt_variance(1.0, df, forward, strike, price)
+    }
+
+    /// Calculates the normal sqrt variance that reproduces the given PV of a
+    /// European put option under Bachelier.
+    pub fn put_implied_sqrt_variance(&self, df: f64, forward: f64,
+        strike: f64, price: f64) -> Result<f64, qm::Error> {
+        self.implied_sqrt_variance(-1.0, df, forward, strike, price)
+    }
+
+    fn undiscounted_price(&self, sign: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> f64 {
+
+        let intrinsic = sign * (forward - strike);
+        if sqrt_variance <= 0.0 {
+            return intrinsic.max(0.0)
+        }
+        let d = intrinsic / sqrt_variance;
+        intrinsic * self.normal.cdf(d) + sqrt_variance * self.normal.pdf(d)
+    }
+
+    fn greeks(&self, sign: f64, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> OptionGreeks {
+
+        let d = (forward - strike) / sqrt_variance;
+        let n = self.normal.cdf(sign * d);
+        let density = self.normal.pdf(d);
+        let undiscounted = sign * (forward - strike) * n
+            + sqrt_variance * density;
+
+        OptionGreeks {
+            price: df * undiscounted,
+            delta: sign * df * n,
+            gamma: df * density / sqrt_variance,
+            vega: df * density,
+            dual_delta: -sign * df * n,
+            vanna: -df * density * d / sqrt_variance,
+            volga: df * density * d * d / sqrt_variance,
+            df_sensitivity: undiscounted }
+    }
+
+    /// The price is strictly increasing in the sqrt variance, and is
+    /// unbounded, so we just need to find a bracket and then solve it.
+    fn implied_sqrt_variance(&self, sign: f64, df: f64, forward: f64,
+        strike: f64, price: f64) -> Result<f64, qm::Error> {
+
+        if !(df > 0.0) {
+            return Err(qm::Error::new(&format!("Cannot imply normal vol: \
+                df={} must be positive", df)))
+        }
+        if !price.is_finite() || !forward.is_finite() || !strike.is_finite() {
+            return Err(qm::Error::new("Cannot imply normal vol from \
+                non-finite inputs"))
+        }
+
+        // work with the out of the money option, or equivalently the time
+        // value, as the time value is the same for puts and calls
+        let intrinsic = (sign * (forward - strike)).max(0.0);
+        let target = price / df - intrinsic;
+        let otm_sign = if strike >= forward { 1.0 } else { -1.0 };
+        let scale = forward.abs().max(strike.abs()).max(1.0);
+        let tolerance = 1e-14 * scale;
+        if target < -tolerance {
+            return Err(qm::Error::new(&format!("Cannot imply normal vol: \
+                price {} is below the intrinsic value {}", price,
+                df * intrinsic)))
+        }
+        if target <= tolerance {
+            return Ok(0.0)
+        }
+
+        let otm_price = |sqrt_var: f64| self.undiscounted_price(otm_sign,
+            forward, strike, sqrt_var);
+
+        // at the money, the price is sqrt_var / sqrt(2 pi), so start there
+        let mut lo = 0.0;
+        let mut hi = target * (2.0 * PI).sqrt() + (forward - strike).abs();
+        let max_doublings = 100;
+        let mut doublings = 0;
+        while otm_price(hi) < target {
+            lo = hi;
+            hi *= 2.0;
+            doublings += 1;
+            if doublings > max_doublings {
+                return Err(qm::Error::new("Cannot imply normal vol: failed \
+                    to bracket the solution"))
+            }
+        }
+
+        let x0 = (target * (2.0 * PI).sqrt()).max(lo).min(hi);
+        let vega = |sqrt_var: f64|
+            self.normal.pdf((forward - strike) / sqrt_var);
+        safeguarded_newton(|x| (otm_price(x) - target, vega(x)), lo, hi, x0)
+            .ok_or_else(|| qm::Error::new(&format!("Implied normal vol failed \
+            to converge for df={} forward={} strike={} price={}",
+            df, forward, strike, price)))
+    }
+}
+
+/// The shifted (or displaced) log-normal formula. The forward plus the shift
+/// is assumed to be log-normally distributed, which allows forwards and
+/// strikes down to minus the shift. This is the market convention for
+/// quoting vols on negative rates, and is also what we use when cash
+/// dividends displace an equity forward.
+pub struct ShiftedBlack76 {
+    black76: Black76,
+    shift: f64
+}
+
+impl ShiftedBlack76 {
+    pub fn new(shift: f64) -> Result<ShiftedBlack76, qm::Error> {
+        Ok(ShiftedBlack76 { black76: Black76::new()?, shift: shift })
+    }
+
+    pub fn shift(&self) -> f64 { self.shift }
+
+    /// Calculates the PV of a European call option under shifted Black76
+    pub fn call_price(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> f64 {
+        self.black76.call_price(df, forward + self.shift, strike + self.shift,
+            sqrt_variance)
+    }
+
+    /// Calculates the PV of a European put option under shifted Black76
+    pub fn put_price(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> f64 {
+        self.black76.put_price(df, forward + self.shift, strike + self.shift,
+            sqrt_variance)
+    }
+
+    /// Calculates the PV and greeks of a European call under shifted Black76.
+    /// As the shift is constant, the sensitivities to the forward and strike
+    /// are the same as those to the shifted forward and strike.
+    pub fn call_greeks(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> OptionGreeks {
+        self.black76.call_greeks(df, forward + self.shift,
+            strike + self.shift, sqrt_variance)
+    }
+
+    /// Calculates the PV and greeks of a European put under shifted Black76.
+    pub fn put_greeks(&self, df: f64, forward: f64, strike: f64,
+        sqrt_variance: f64) -> OptionGreeks {
+        self.black76.put_greeks(df, forward + self.shift,
+            strike + self.shift, sqrt_variance)
+    }
+
+    /// Calculates the shifted log-normal sqrt variance that reproduces the
+    /// given PV of a European call option.
+    pub fn call_implied_sqrt_variance(&self, df: f64, forward: f64,
+        strike: f64, price: f64) -> Result<f64, qm::Error> {
+        self.black76.call_implied_sqrt_variance(df, forward + self.shift,
+            strike + self.shift, price)
+    }
+
+    /// Calculates the shifted log-normal sqrt variance that reproduces the
+    /// given PV of a European put option.
+    pub fn put_implied_sqrt_variance(&self, df: f64, forward: f64,
+        strike: f64, price: f64) -> Result<f64, qm::Error> {
+        self.black76.put_implied_sqrt_variance(df, forward + self.shift,
+            strike + self.shift, price)
+    }
+}
+
+/// Solves func(x) = 0 for an increasing function, given a bracket lo..hi
+/// and a starting point x within it. The function returns its value and its
+/// derivative. We take Newton-Raphson steps where possible, but fall back to
+/// bisection if a step leaves the bracket or the derivative vanishes.
+/// Returns None if it fails to converge.
+fn safeguarded_newton<F>(func: F, lo: f64, hi: f64, x: f64) -> Option<f64>
+    where F: Fn(f64) -> (f64, f64) {
+
+    let mut lo = lo;
+    let mut hi = hi;
+    let mut x = x;
+    let max_iterations = 200;
+    let x_tolerance = 1e-15;
+    for _ in 0..max_iterations {
+        let (value, deriv) = func(x);
+        if value == 0.0 {
+            return Some(x)
+        }
+
+        // narrow the bracket
+        if value < 0.0 {
+            lo = x;
+        } else {
+            hi = x;
+        }
+
+        // try a Newton step, falling back to bisection
+        let newton = x - value / deriv;
+        let next = if deriv > 0.0 && newton > lo && newton < hi {
+            newton
+        } else {
+            0.5 * (lo + hi)
+        };
+
+        if (next - x).abs() <= x_tolerance * (1.0 + x)
+            || hi - lo <= x_tolerance * (1.0 + hi) {
+            return Some(next)
+        }
+        x = next;
+    }
+    None
+}
+
+/// The price and analytic sensitivities of a European option, as returned
+/// by Black76, Bachelier or ShiftedBlack76.
+/// Delta and gamma are with respect to the forward, vega and volga are with
+/// respect to the sqrt of the variance, and vanna is the cross derivative
+/// of the two. Dual delta is the sensitivity to the strike, and the
+/// df_sensitivity is the derivative with respect to the discount factor,
+/// which is just the undiscounted price.
+#[derive(Debug, Clone, Copy)]
+pub struct OptionGreeks {
+    price: f64,
+    delta: f64,
+    gamma: f64,
+    vega: f64,
+    dual_delta: f64,
+    vanna: f64,
+    volga: f64,
+    df_sensitivity: f64
+}
+
+impl OptionGreeks {
+    pub fn price(&self) -> f64 { self.price }
+    pub fn delta(&self) -> f64 { self.delta }
+    pub fn gamma(&self) -> f64 { self.gamma }
+    pub fn vega(&self) -> f64 { self.vega }
+    pub fn dual_delta(&self) -> f64 { self.dual_delta }
+    pub fn vanna(&self) -> f64 { self.vanna }
+    pub fn volga(&self) -> f64 { self.volga }
+    pub fn df_sensitivity(&self) -> f64 { self.df_sensitivity }
 }
 
 /// Calculates the internal d_plus and d_minus values needed for many of the
@@ -94,6 +541,317 @@ mod tests {
         }
     }
 
+    #[test]
+    fn black76_greeks_match_finite_differences() {
+
+        let forward = 100.0;
+        let df = 0.99;
+        let sqrt_var = 0.3;
+        let black76 = Black76::new().unwrap();
+        let df_bump = 1e-4;
+        let fwd_bump = 1e-2;
+        let var_bump = 1e-4;
+
+        for strike in [50.0, 70.0, 90.0, 100.0, 110.0, 130.0, 160.0].iter() {
+            let k = *strike;
+            for &call in [true, false].iter() {
+                let price = |d: f64, f: f64, k: f64, s: f64| if call {
+                    black76.call_price(d, f, k, s)
+                } else {
+                    black76.put_price(d, f, k, s)
+                };
+                let greeks = if call {
+                    black76.call_greeks(df, forward, k, sqrt_var)
+                } else {
+                    black76.put_greeks(df, forward, k, sqrt_var)
+                };
+
+                let p = price(df, forward, k, sqrt_var);
+                assert_approx(greeks.price(), p, 1e-12, "price");
+
+                let up = price(df, forward + fwd_bump, k, sqrt_var);
+                let down = price(df, forward - fwd_bump, k, sqrt_var);
+                assert_approx(greeks.delta(), (up - down) / (2.0 * fwd_bump),
+                    1e-6, "delta");
+                assert_approx(greeks.gamma(),
+                    (up + down - 2.0 * p) / (fwd_bump * fwd_bump),
+                    1e-5, "gamma");
+
+                let up = price(df, forward, k, sqrt_var + var_bump);
+                let down = price(df, forward, k, sqrt_var - var_bump);
+                assert_approx(greeks.vega(), (up - down) / (2.0 * var_bump),
+                    1e-5, "vega");
+                assert_approx(greeks.volga(),
+                    (up + down - 2.0 * p) / (var_bump * var_bump),
+                    1e-2, "volga");
+
+                let up = price(df, forward, k + fwd_bump, sqrt_var);
+                let down = price(df, forward, k - fwd_bump, sqrt_var);
+                assert_approx(greeks.dual_delta(),
+                    (up - down) / (2.0 * fwd_bump), 1e-6, "dual delta");
+
+                let uu = price(df, forward + fwd_bump, k, sqrt_var + var_bump);
+                let ud = price(df, forward + fwd_bump, k, sqrt_var - var_bump);
+                let du = price(df, forward - fwd_bump, k, sqrt_var + var_bump);
+                let dd = price(df, forward - fwd_bump, k, sqrt_var - var_bump);
+                assert_approx(greeks.vanna(),
+                    (uu - ud - du + dd) / (4.0 * fwd_bump * var_bump),
+                    1e-4, "vanna");
+
+                let up = price(df + df_bump, forward, k, sqrt_var);
+                let down = price(df - df_bump, forward, k, sqrt_var);
+                assert_approx(greeks.df_sensitivity(),
+                    (up - down) / (2.0 * df_bump), 1e-8, "df sensitivity");
+            }
+        }
+    }
+
+    #[test]
+    fn black76_implied_vol_round_trip() {
+
+        let forward = 100.0;
+        let df = 0.95;
+        let black76 = Black76::new().unwrap();
+
+        // the very short expiry cases are as if vol=0.2 for one hour
+        for sqrt_var in [0.0025, 0.01, 0.05, 0.2, 0.5, 1.0, 3.0].iter() {
+            for strike in [20.0, 50.0, 70.0, 90.0, 99.9, 100.0, 100.1, 110.0,
+                130.0, 160.0, 500.0].iter() {
+
+                let call = black76.call_price(df, forward, *strike, *sqrt_var);
+                let put = black76.put_price(df, forward, *strike, *sqrt_var);
+
+                // we cannot recover the vol if the time value is lost
+                // in rounding errors
+                let call_intrinsic = df * (forward - *strike).max(0.0);
+                let put_intrinsic = df * (*strike - forward).max(0.0);
+                if call - call_intrinsic > 1e-10 {
+                    let implied = black76.call_implied_sqrt_variance(df,
+                        forward, *strike, call).unwrap();
+                    assert_approx(implied, *sqrt_var, 1e-5 * sqrt_var,
+                        "call implied vol");
+                }
+                if put - put_intrinsic > 1e-10 {
+                    let implied = black76.put_implied_sqrt_variance(df,
+                        forward, *strike, put).unwrap();
+                    assert_approx(implied, *sqrt_var, 1e-5 * sqrt_var,
+                        "put implied vol");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn black76_implied_vol_accuracy_near_the_money() {
+
+        let black76 = Black76::new().unwrap();
+        let price = black76.call_price(0.99, 100.0, 105.0, 0.3);
+        let implied = black76.call_implied_sqrt_variance(0.99, 100.0, 105.0,
+            price).unwrap();
+        assert_approx(implied, 0.3, 1e-12, "call implied vol");
+
+        let price = black76.put_price(0.99, 100.0, 105.0, 0.3);
+        let implied = black76.put_implied_sqrt_variance(0.99, 100.0, 105.0,
+            price).unwrap();
+        assert_approx(implied, 0.3, 1e-12, "put implied vol");
+    }
+
+    #[test]
+    fn black76_implied_vol_at_intrinsic() {
+        let black76 = Black76::new().unwrap();
+        let implied = black76.call_implied_sqrt_variance(0.9, 100.0, 80.0,
+            18.0).unwrap();
+        assert_approx(implied, 0.0, 1e-12, "intrinsic call");
+        let implied = black76.put_implied_sqrt_variance(0.9, 100.0, 120.0,
+            18.0).unwrap();
+        assert_approx(implied, 0.0, 1e-12, "intrinsic put");
+    }
+
+    #[test]
+    fn black76_implied_vol_outside_bounds() {
+        let black76 = Black76::new().unwrap();
+        assert!(black76.call_implied_sqrt_variance(0.9, 100.0, 80.0, 17.0)
+            .is_err());
+        assert!(black76.call_implied_sqrt_variance(0.9, 100.0, 80.0, 90.0)
+            .is_err());
+        assert!(black76.put_implied_sqrt_variance(0.9, 100.0, 120.0, 17.0)
+            .is_err());
+        assert!(black76.put_implied_sqrt_variance(0.9, 100.0, 120.0, 108.0)
+            .is_err());
+        assert!(black76.put_implied_sqrt_variance(0.9, 100.0, 120.0, -1.0)
+            .is_err());
+        assert!(black76.call_implied_sqrt_variance(0.9, -100.0, 120.0, 1.0)
+            .is_err());
+    }
+
+    #[test]
+    fn bachelier_price_allows_negative_forwards() {
+
+        let df = 0.99;
+        let sqrt_var = 0.01;
+        let bachelier = Bachelier::new().unwrap();
+
+        for forward in [-0.005, 0.0, 0.02].iter() {
+            for strike in [-0.02, -0.005, 0.0, 0.01, 0.03].iter() {
+                let call = bachelier.call_price(df, *forward, *strike,
+                    sqrt_var);
+                let put = bachelier.put_price(df, *forward, *strike, sqrt_var);
+                let parity = df * (*forward - *strike) + put - call;
+                assert!(call >= df * (*forward - *strike).max(0.0));
+                assert!(put >= df * (*strike - *forward).max(0.0));
+                assert_approx(parity, 0.0, 1e-15, "put/call parity");
+            }
+        }
+
+        // at the money the price is df * sqrt_var / sqrt(2 pi)
+        let atm = bachelier.call_price(df, -0.005, -0.005, sqrt_var);
+        assert_approx(atm, df * sqrt_var / (2.0 * PI).sqrt(), 1e-15, "atm");
+    }
+
+    #[test]
+    fn bachelier_greeks_match_finite_differences() {
+
+        let forward = -0.002;
+        let df = 0.99;
+        let sqrt_var = 0.008;
+        let bachelier = Bachelier::new().unwrap();
+        let df_bump = 1e-4;
+        let fwd_bump = 1e-5;
+        let var_bump = 1e-5;
+
+        for strike in [-0.02, -0.01, -0.002, 0.0, 0.005, 0.015].iter() {
+            let k = *strike;
+            for &call in [true, false].iter() {
+                let price = |d: f64, f: f64, k: f64, s: f64| if call {
+                    bachelier.call_price(d, f, k, s)
+                } else {
+                    bachelier.put_price(d, f, k, s)
+                };
+                let greeks = if call {
+                    bachelier.call_greeks(df, forward, k, sqrt_var)
+                } else {
+                    bachelier.put_greeks(df, forward, k, sqrt_var)
+                };
+
+                let p = price(df, forward, k, sqrt_var);
+                assert_approx(greeks.price(), p, 1e-15, "price");
+
+                let up = price(df, forward + fwd_bump, k, sqrt_var);
+                let down = price(df, forward - fwd_bump, k, sqrt_var);
+                assert_approx(greeks.delta(), (up - down) / (2.0 * fwd_bump),
+                    1e-6, "delta");
+                assert_approx(greeks.gamma(),
+                    (up + down - 2.0 * p) / (fwd_bump * fwd_bump),
+                    1e-1, "gamma");
+
+                let up = price(df, forward, k, sqrt_var + var_bump);
+                let down = price(df, forward, k, sqrt_var - var_bump);
+                assert_approx(greeks.vega(), (up - down) / (2.0 * var_bump),
+                    1e-6, "vega");
+                assert_approx(greeks.volga(),
+                    (up + down - 2.0 * p) / (var_bump * var_bump),
+                    1e-1, "volga");
+
+                let up = price(df, forward, k + fwd_bump, sqrt_var);
+                let down = price(df, forward, k - fwd_bump, sqrt_var);
+                assert_approx(greeks.dual_delta(),
+                    (up - down) / (2.0 * fwd_bump), 1e-6, "dual delta");
+
+                let uu = price(df, forward + fwd_bump, k, sqrt_var + var_bump);
+                let ud = price(df, forward + fwd_bump, k, sqrt_var - var_bump);
+                let du = price(df, forward - fwd_bump, k, sqrt_var + var_bump);
+                let dd = price(df, forward - fwd_bump, k, sqrt_var - var_bump);
+                assert_approx(greeks.vanna(),
+                    (uu - ud - du + dd) / (4.0 * fwd_bump * var_bump),
+                    1e-1, "vanna");
+
+                let up = price(df + df_bump, forward, k, sqrt_var);
+                let down = price(df - df_bump, forward, k, sqrt_var);
+                assert_approx(greeks.df_sensitivity(),
+                    (up - down) / (2.0 * df_bump), 1e-10, "df sensitivity");
+            }
+        }
+    }
+
+    #[test]
+    fn bachelier_implied_vol_round_trip() {
+
+        let forward = -0.003;
+        let df = 0.95;
+        let bachelier = Bachelier::new().unwrap();
+
+        for sqrt_var in [0.0001, 0.001, 0.005, 0.01, 0.03].iter() {
+            for strike in [-0.03, -0.01, -0.004, -0.003, -0.002, 0.0, 0.01,
+                0.05].iter() {
+
+                let call = bachelier.call_price(df, forward, *strike,
+                    *sqrt_var);
+                let put = bachelier.put_price(df, forward, *strike, *sqrt_var);
+
+                let call_intrinsic = df * (forward - *strike).max(0.0);
+                let put_intrinsic = df * (*strike - forward).max(0.0);
+                if call - call_intrinsic > 1e-12 {
+                    let implied = bachelier.call_implied_sqrt_variance(df,
+                        forward, *strike, call).unwrap();
+                    assert_approx(implied, *sqrt_var, 1e-5 * sqrt_var,
+                        "call implied vol");
+                }
+                if put - put_intrinsic > 1e-12 {
+                    let implied = bachelier.put_implied_sqrt_variance(df,
+                        forward, *strike, put).unwrap();
+                    assert_approx(implied, *sqrt_var, 1e-5 * sqrt_var,
+                        "put implied vol");
+                }
+            }
+        }
+
+        assert!(bachelier.call_implied_sqrt_variance(df, 0.01, 0.0, 0.009)
+            .is_err());
+        assert_approx(bachelier.put_implied_sqrt_variance(df, 0.0, 0.01,
+            df * 0.01).unwrap(), 0.0, 1e-15, "intrinsic put");
+    }
+
+    #[test]
+    fn shifted_black76_matches_black76_on_shifted_inputs() {
+
+        let df = 0.98;
+        let shift = 0.03;
+        let sqrt_var = 0.2;
+        let black76 = Black76::new().unwrap();
+        let shifted = ShiftedBlack76::new(shift).unwrap();
+
+        for forward in [-0.01, 0.0, 0.02].iter() {
+            for strike in [-0.02, -0.005, 0.0, 0.01, 0.03].iter() {
+                let f = *forward;
+                let k = *strike;
+                let call = shifted.call_price(df, f, k, sqrt_var);
+                let put = shifted.put_price(df, f, k, sqrt_var);
+                assert_approx(call, black76.call_price(df, f + shift,
+                    k + shift, sqrt_var), 1e-15, "call");
+                assert_approx(df * (f - k) + put - call, 0.0, 1e-15,
+                    "put/call parity");
+
+                let greeks = shifted.put_greeks(df, f, k, sqrt_var);
+                assert_approx(greeks.price(), put, 1e-15, "put greeks");
+
+                // skip deep in the money cases where the time value is
+                // lost in rounding errors
+                if call.min(put) > 1e-10 {
+                    let implied = shifted.call_implied_sqrt_variance(df, f, k,
+                        call).unwrap();
+                    assert_approx(implied, sqrt_var, 1e-6, "call implied vol");
+                    let implied = shifted.put_implied_sqrt_variance(df, f, k,
+                        put).unwrap();
+                    assert_approx(implied, sqrt_var, 1e-6, "put implied vol");
+                }
+            }
+        }
+
+        // below minus the shift there is no lognormal distribution
+        assert!(shifted.call_implied_sqrt_variance(df, -0.04, 0.0, 0.001)
+            .is_err());
+    }
+
     fn assert_approx(value: f64, expected: f64, tolerance: f64, message: &str) {
         assert!(approx_eq(value, expected, tolerance),
             "{}: value={} expected={}", message, value, expected);
@@ -0,0 +1,199 @@
+use nalgebra::base::DMatrix;
+use ndarray::ArrayView1;
+use ndarray::ArrayView2;
+
+/// Ordinary least-squares fit of the targets to a linear combination of the
+/// columns of the design matrix, which is indexed by observation then basis
+/// function. Returns the coefficient of each basis function, or None if
+/// there are too few observations or the basis functions are not linearly
+/// independent over them.
+///
+/// This solves the normal equations, which is adequate for the small,
+/// well-scaled bases used in regression-based Monte-Carlo, such as low
+/// order polynomials in spot divided by strike.
+pub fn least_squares(design: ArrayView2<f64>, targets: ArrayView1<f64>)
+    -> Option<Vec<f64>> {
+
+    let n_obs = design.shape()[0];
+    let n_basis = design.shape()[1];
+    assert_eq!(targets.len(), n_obs);
+    if n_obs < n_basis || n_basis == 0 {
+        return None
+    }
+
+    // build X'X and X'y directly, to avoid copying the design matrix
+    let mut xtx = DMatrix::<f64>::zeros(n_basis, n_basis);
+    let mut xty = DMatrix::<f64>::zeros(n_basis, 1);
+    for (row, target) in design.outer_iter().zip(targets.iter()) {
+        for i in 0..n_basis {
+            xty[(i, 0)] += row[i] * target;
+            for j in 0..(i + 1) {
+                xtx[(i, j)] += row[i] * row[j];
+            }
+        }
+    }
+    for i in 0..n_basis {
+        for j in (i + 1)..n_basis {
+            xtx[(i, j)] = xtx[(j, i)];
+        }
+    }
+
+    // treat a tiny pivot relative to the diagonal as singular
+    let scale = (0..n_basis).fold(0.0_f64, |m, i| m.max(xtx[(i, i)].abs()));
+    if !(scale > 0.0) {
+        return None
+    }
+    let lu = xtx.lu();
+    let u = lu.u();
+    for i in 0..n_basis {
+        if !(u[(i, i)].abs() > scale * 1e-12) {
+            return None
+        }
+    }
+    lu.solve(&xty).map(|solution| solution.iter().cloned().collect())
+}
+
+/// A set of basis functions of some explanatory variables, used to build
+/// the design matrix for a regression. For example, regression-based Monte-
+/// Carlo estimates continuation values as a linear combination of basis
+/// functions of the state on each path.
+pub trait RegressionBasis : Send + Sync {
+    /// The number of basis functions, given the number of variables
+    fn size(&self, n_variables: usize) -> usize;
+
+    /// Evaluates the basis functions for one observation of the variables.
+    /// The basis slice has the length returned by size.
+    fn evaluate(&self, variables: &[f64], basis: &mut [f64]);
+}
+
+/// All monomials in the variables up to the given total degree, including
+/// the constant and all cross terms. For example, degree two in variables x
+/// and y gives 1, x, y, x^2, xy and y^2.
+#[derive(Clone, Debug)]
+pub struct PolynomialBasis {
+    degree: usize
+}
+
+impl PolynomialBasis {
+    pub fn new(degree: usize) -> PolynomialBasis {
+        PolynomialBasis { degree: degree }
+    }
+}
+
+impl RegressionBasis for PolynomialBasis {
+    fn size(&self, n_variables: usize) -> usize {
+        // the number of monomials of degree at most d in n variables is
+        // (n + d) choose d
+        let mut size = 1;
+        for i in 0..self.degree {
+            size = size * (n_variables + i + 1) / (i + 1);
+        }
+        size
+    }
+
+    fn evaluate(&self, variables: &[f64], basis: &mut [f64]) {
+        assert_eq!(basis.len(), self.size(variables.len()));
+
+        // Each monomial of one degree is made by multiplying one of the
+        // previous degree by a variable whose index is no less than the last
+        // one used, so each combination appears exactly once.
+        basis[0] = 1.0;
+        let mut start = 0;
+        let mut end = 1;
+        let mut last_index = vec![0];
+        for _ in 0..self.degree {
+            let mut k = end;
+            for m in start..end {
+                for v in last_index[m - start]..variables.len() {
+                    basis[k] = basis[m] * variables[v];
+                    last_index.push(v);
+                    k += 1;
+                }
+            }
+            last_index.drain(0..(end - start));
+            start = end;
+            end = k;
+        }
+    }
+}
+
+/// Basis functions supplied by the user as closures of the variables, which
+/// allows any basis, such as Laguerre polynomials or payoff-like functions.
+pub struct FunctionBasis {
+    functions: Vec<Box<Fn(&[f64]) -> f64 + Send + Sync>>
+}
+
+impl FunctionBasis {
+    pub fn new(functions: Vec<Box<Fn(&[f64]) -> f64 + Send + Sync>>)
+        -> FunctionBasis {
+        FunctionBasis { functions: functions }
+    }
+}
+
+impl RegressionBasis for FunctionBasis {
+    fn size(&self, _n_variables: usize) -> usize {
+        self.functions.len()
+    }
+
+    fn evaluate(&self, variables: &[f64], basis: &mut [f64]) {
+        for (b, f) in basis.iter_mut().zip(self.functions.iter()) {
+            *b = f(variables);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array1;
+    use ndarray::Array2;
+    use math::numerics::approx_eq;
+
+    #[test]
+    fn fit_exact_quadratic() {
+        let xs = [0.5, 0.8, 1.0, 1.1, 1.3, 1.7];
+        let mut design = Array2::zeros((xs.len(), 3));
+        let mut targets = Array1::zeros(xs.len());
+        for (i, x) in xs.iter().enumerate() {
+            design[[i, 0]] = 1.0;
+            design[[i, 1]] = *x;
+            design[[i, 2]] = x * x;
+            targets[i] = 2.0 - 3.0 * x + 0.5 * x * x;
+        }
+
+        let coeffs = least_squares(design.view(), targets.view()).unwrap();
+        assert!(approx_eq(coeffs[0], 2.0, 1e-10), "c0={}", coeffs[0]);
+        assert!(approx_eq(coeffs[1], -3.0, 1e-10), "c1={}", coeffs[1]);
+        assert!(approx_eq(coeffs[2], 0.5, 1e-10), "c2={}", coeffs[2]);
+    }
+
+    #[test]
+    fn degenerate_design_is_rejected() {
+        // every observation has the same regressor, so we cannot fit a slope
+        let mut design = Array2::zeros((4, 2));
+        let targets = Array1::from_vec(vec![1.0, 2.0, 3.0, 4.0]);
+        for i in 0..4 {
+            design[[i, 0]] = 1.0;
+            design[[i, 1]] = 1.3;
+        }
+        assert!(least_squares(design.view(), targets.view()).is_none());
+    }
+
+    #[test]
+    fn polynomial_basis_with_cross_terms() {
+        let basis = PolynomialBasis::new(2);
+        assert_eq!(basis.size(1), 3);
+        assert_eq!(basis.size(2), 6);
+        assert_eq!(basis.size(3), 10);
+
+        let mut values = vec![0.0; 6];
+        basis.evaluate(&[2.0, 3.0], &mut values);
+        assert_eq!(values, vec![1.0, 2.0, 3.0, 4.0, 6.0, 9.0]);
+
+        let cubic = PolynomialBasis::new(3);
+        let mut values = vec![0.0; cubic.size(2)];
+        cubic.evaluate(&[2.0, 3.0], &mut values);
+        assert_eq!(values, vec![1.0, 2.0, 3.0, 4.0, 6.0, 9.0,
+            8.0, 12.0, 18.0, 27.0]);
+    }
+}
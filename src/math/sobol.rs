@@ -0,0 +1,404 @@
+use core::qm;
+
+/// The number of bits of precision in each coordinate
+const BITS: usize = 32;
+
+/// The highest degree of primitive polynomial we use. There are 1110
+/// primitive polynomials of degree 13 or less, giving 1111 dimensions.
+const MAX_DEGREE: u32 = 13;
+const MAX_DIMENSIONS: usize = 1111;
+
+/// Seed for the initial direction numbers of the dimensions beyond the
+/// Joe and Kuo table
+const INITIAL_SEED: u64 = 0x5eed_50b0;
+
+/// Primitive polynomials and initial direction numbers for the second and
+/// subsequent dimensions, from S. Joe and F. Y. Kuo, "Constructing Sobol
+/// sequences with better two-dimensional projections", SIAM Journal on
+/// Scientific Computing 30 (2008), as published in new-joe-kuo-6.21201.
+/// Each entry is the degree s of the polynomial, its inner coefficients a
+/// as a bit pattern, and the initial odd integers m_1 .. m_s. The
+/// polynomials are in order of degree then coefficients, so they are the
+/// first of the ones enumerated by primitive_polynomials.
+const JOE_KUO: &[(u32, u32, &[u32])] = &[
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+    (6, 19, &[1, 1, 1, 15, 7, 5]),
+    (6, 22, &[1, 3, 1, 15, 13, 25]),
+    (6, 25, &[1, 1, 5, 5, 19, 61]),
+    (7, 1, &[1, 3, 7, 11, 23, 15, 103]),
+    (7, 4, &[1, 3, 7, 13, 13, 15, 69]),
+    (7, 7, &[1, 1, 3, 13, 7, 35, 63]),
+    (7, 8, &[1, 3, 5, 9, 1, 25, 53]),
+    (7, 14, &[1, 3, 1, 13, 9, 35, 107]),
+    (7, 19, &[1, 3, 1, 5, 27, 61, 31]),
+    (7, 21, &[1, 1, 5, 11, 19, 41, 61]),
+    (7, 28, &[1, 3, 5, 3, 3, 13, 69]),
+    (7, 31, &[1, 1, 7, 13, 1, 19, 1]),
+    (7, 32, &[1, 3, 7, 5, 13, 19, 59]),
+    (7, 37, &[1, 1, 3, 9, 25, 29, 41]),
+    (7, 41, &[1, 3, 5, 13, 23, 1, 55]),
+    (7, 42, &[1, 3, 7, 3, 13, 59, 17]),
+    (7, 50, &[1, 3, 1, 3, 5, 53, 69]),
+    (7, 55, &[1, 1, 5, 5, 23, 33, 13]),
+    (7, 56, &[1, 1, 7, 7, 1, 61, 123]),
+    (7, 59, &[1, 1, 7, 9, 13, 61, 49]),
+    (7, 62, &[1, 3, 3, 5, 3, 55, 33]),
+    (8, 14, &[1, 3, 5, 15, 31, 59, 63, 97]),
+    (8, 21, &[1, 3, 1, 11, 11, 11, 77, 249]),
+    (8, 22, &[1, 3, 1, 11, 27, 43, 71, 9]),
+    (8, 38, &[1, 1, 7, 15, 21, 11, 81, 45]),
+    (8, 47, &[1, 3, 7, 3, 25, 31, 65, 79]),
+    (8, 49, &[1, 3, 1, 1, 19, 11, 3, 205]),
+    (8, 50, &[1, 1, 5, 9, 19, 21, 29, 157]),
+    (8, 52, &[1, 3, 7, 11, 1, 33, 89, 185]),
+    (8, 56, &[1, 3, 3, 3, 15, 9, 79, 71]),
+    (8, 67, &[1, 3, 7, 11, 15, 39, 119, 27]),
+    (8, 70, &[1, 1, 3, 1, 11, 31, 97, 225]),
+    (8, 84, &[1, 1, 1, 3, 23, 43, 57, 177]),
+    (8, 97, &[1, 3, 7, 7, 17, 17, 37, 71]),
+    (8, 103, &[1, 3, 1, 5, 27, 63, 123, 213]),
+    (8, 115, &[1, 1, 3, 5, 11, 43, 53, 133]),
+    (8, 122, &[1, 3, 5, 5, 29, 17, 47, 173]),
+];
+
+/// A Sobol low-discrepancy sequence, optionally with Owen scrambling.
+///
+/// Points are generated by random access, in Gray code order, so any block
+/// of points can be generated independently of the others. Point zero is
+/// the origin in the unscrambled sequence, and is normally skipped.
+///
+/// The first dimensions use the direction numbers of Joe and Kuo, which are
+/// chosen to give good two-dimensional projections. Beyond those, up to
+/// max_dimensions, we take the next primitive polynomials in order, with
+/// initial direction numbers drawn at random from the odd integers that are
+/// valid for them, as suggested by Jaeckel, "Monte Carlo Methods in
+/// Finance" (2002). These dimensions are still stratified individually, but
+/// their two-dimensional projections are less even. With a Brownian bridge,
+/// they only drive the finest detail of the paths.
+///
+/// Owen's nested uniform scrambling randomises each point while preserving
+/// the stratification of the sequence, so that independently scrambled
+/// copies can be used to estimate the error. We implement it by flipping
+/// each bit according to a hash of the seed, the dimension and all the bits
+/// above it, which avoids storing the tree of random permutations.
+#[derive(Clone, Debug)]
+pub struct Sobol {
+    directions: Vec<[u32; BITS]>,
+    scrambling: Option<Vec<u64>>
+}
+
+impl Sobol {
+    /// Creates an unscrambled sequence with the given number of dimensions,
+    /// which must be no more than max_dimensions.
+    pub fn new(dimensions: usize) -> Result<Sobol, qm::Error> {
+        if dimensions == 0 || dimensions > Sobol::max_dimensions() {
+            return Err(qm::Error::new(&format!("Sobol sequence must have \
+                between 1 and {} dimensions: {} requested",
+                Sobol::max_dimensions(), dimensions)))
+        }
+
+        // the first dimension is the van der Corput sequence
+        let mut directions = Vec::with_capacity(dimensions);
+        let mut first = [0; BITS];
+        for (k, v) in first.iter_mut().enumerate() {
+            *v = 1 << (BITS - 1 - k);
+        }
+        directions.push(first);
+        for &(s, a, m) in JOE_KUO.iter().take(dimensions - 1) {
+            directions.push(direction_numbers(s, a, m));
+        }
+
+        // further dimensions use random initial direction numbers
+        if dimensions > JOE_KUO.len() + 1 {
+            let polynomials = primitive_polynomials(dimensions - 1);
+            let mut m = Vec::with_capacity(MAX_DEGREE as usize);
+            for (d, &(s, a)) in polynomials.iter().enumerate()
+                .skip(JOE_KUO.len()) {
+                m.clear();
+                for k in 0..s {
+                    let random = mix(INITIAL_SEED
+                        ^ mix(((d as u64) << 8) | k as u64));
+                    m.push(((random % (1 << k)) as u32) << 1 | 1);
+                }
+                directions.push(direction_numbers(s, a, &m));
+            }
+        }
+
+        Ok(Sobol { directions: directions, scrambling: None })
+    }
+
+    /// Creates a sequence with Owen scrambling. Sequences with the same
+    /// seed are identical.
+    pub fn new_scrambled(dimensions: usize, seed: u64)
+        -> Result<Sobol, qm::Error> {
+        let mut sobol = Sobol::new(dimensions)?;
+        sobol.scrambling = Some((0..dimensions)
+            .map(|d| mix(seed ^ mix(d as u64))).collect());
+        Ok(sobol)
+    }
+
+    /// The largest number of dimensions we have direction numbers for
+    pub fn max_dimensions() -> usize {
+        MAX_DIMENSIONS
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.directions.len()
+    }
+
+    /// Writes the point with the given index into the supplied slice, which
+    /// must be the same length as the number of dimensions. Each coordinate
+    /// is a uniform in the range [0, 1).
+    pub fn point(&self, index: u32, point: &mut [f64]) {
+        assert_eq!(point.len(), self.directions.len());
+
+        let gray = index ^ (index >> 1);
+        let scale = 1.0 / (1u64 << BITS) as f64;
+        for (d, (coordinate, directions)) in point.iter_mut()
+            .zip(self.directions.iter()).enumerate() {
+
+            let mut x = 0;
+            for (k, v) in directions.iter().enumerate() {
+                if gray >> k == 0 {
+                    break
+                }
+                if (gray >> k) & 1 == 1 {
+                    x ^= *v;
+                }
+            }
+
+            if let Some(ref seeds) = self.scrambling {
+                x = owen_scramble(x, seeds[d]);
+            }
+            *coordinate = x as f64 * scale;
+        }
+    }
+}
+
+/// Expands the initial direction numbers using the recurrence defined by
+/// the primitive polynomial, returning them scaled to the full 32 bits.
+fn direction_numbers(s: u32, a: u32, m: &[u32]) -> [u32; BITS] {
+    let s = s as usize;
+    assert_eq!(m.len(), s);
+    let mut v = [0; BITS];
+    for k in 0..s {
+        v[k] = m[k] << (BITS - 1 - k);
+    }
+    for k in s..BITS {
+        v[k] = v[k - s] ^ (v[k - s] >> s);
+        for j in 1..s {
+            if (a >> (s - 1 - j)) & 1 == 1 {
+                v[k] ^= v[k - j];
+            }
+        }
+    }
+    v
+}
+
+/// Enumerates the first n primitive polynomials over GF(2), in order of
+/// degree then inner coefficients, as pairs of the degree s and the inner
+/// coefficients a in the same form as the Joe and Kuo table. There must be
+/// no more than MAX_DIMENSIONS - 1 of them.
+fn primitive_polynomials(n: usize) -> Vec<(u32, u32)> {
+    assert!(n < MAX_DIMENSIONS);
+    let mut polynomials = Vec::with_capacity(n);
+    for s in 1..(MAX_DEGREE + 1) {
+        for a in 0..(1 << (s - 1)) {
+            if polynomials.len() == n {
+                return polynomials
+            }
+            if is_primitive(s, (1 << s) | (a << 1) | 1) {
+                polynomials.push((s, a));
+            }
+        }
+    }
+    polynomials
+}
+
+/// A polynomial of degree s is primitive if x has order 2^s - 1 modulo the
+/// polynomial, which we test by checking that x to that power is one, and
+/// x to that power divided by any of its prime factors is not.
+fn is_primitive(s: u32, polynomial: u32) -> bool {
+    let order = (1u32 << s) - 1;
+    if power_of_x(order, polynomial, s) != 1 {
+        return false
+    }
+
+    let mut remaining = order;
+    let mut factor = 2;
+    while remaining > 1 {
+        if remaining % factor == 0 {
+            if power_of_x(order / factor, polynomial, s) == 1 {
+                return false
+            }
+            while remaining % factor == 0 {
+                remaining /= factor;
+            }
+        }
+        factor += 1;
+    }
+    true
+}
+
+/// Calculates x^n modulo the given polynomial of degree s over GF(2), by
+/// repeated squaring. Polynomials are bit patterns of their coefficients.
+fn power_of_x(n: u32, polynomial: u32, s: u32) -> u32 {
+    let multiply = |x: u32, y: u32| {
+        let mut product = 0;
+        let mut x = x;
+        for bit in 0..s {
+            if (y >> bit) & 1 == 1 {
+                product ^= x;
+            }
+            x <<= 1;
+            if (x >> s) & 1 == 1 {
+                x ^= polynomial;
+            }
+        }
+        product
+    };
+
+    let mut result = 1;
+    let mut square = if s == 1 { 2 ^ polynomial } else { 2 };
+    let mut n = n;
+    while n > 0 {
+        if n & 1 == 1 {
+            result = multiply(result, square);
+        }
+        square = multiply(square, square);
+        n >>= 1;
+    }
+    result
+}
+
+/// Nested uniform scrambling of a single coordinate. Each bit is flipped
+/// according to the hash of the bits above it, so points that share their
+/// leading bits are permuted the same way.
+fn owen_scramble(x: u32, seed: u64) -> u32 {
+    let mut result = x;
+    for bit in 0..BITS {
+        // the prefix is the bits above this one, with a marker bit above
+        // them so that prefixes of different lengths are distinct
+        let prefix = ((x as u64) >> (BITS - bit)) | (1u64 << bit);
+        if mix(seed ^ mix(prefix)) & 1 == 1 {
+            result ^= 1 << (BITS - 1 - bit);
+        }
+    }
+    result
+}
+
+/// The splitmix64 finaliser, a cheap hash with good avalanche properties
+fn mix(z: u64) -> u64 {
+    let z = z.wrapping_add(0x9e3779b97f4a7c15);
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_points_in_two_dimensions() {
+        let sobol = Sobol::new(2).unwrap();
+        let expected = [(0.0, 0.0), (0.5, 0.5), (0.75, 0.25), (0.25, 0.75),
+            (0.375, 0.375), (0.875, 0.875), (0.625, 0.125), (0.125, 0.625)];
+        let mut point = [0.0; 2];
+        for (i, &(x, y)) in expected.iter().enumerate() {
+            sobol.point(i as u32, &mut point);
+            assert_eq!(point, [x, y], "index={}", i);
+        }
+    }
+
+    #[test]
+    fn every_dimension_is_stratified() {
+        // the first 2^k points of each dimension are one per interval of
+        // width 2^-k, whether or not they are scrambled
+        let n = Sobol::max_dimensions();
+        let unscrambled = Sobol::new(n).unwrap();
+        let scrambled = Sobol::new_scrambled(n, 12345).unwrap();
+        for sobol in [unscrambled, scrambled].iter() {
+            let n_points = 256;
+            let mut counts = vec![vec![0; n_points]; n];
+            let mut point = vec![0.0; n];
+            for i in 0..n_points {
+                sobol.point(i as u32, &mut point);
+                for d in 0..n {
+                    counts[d][(point[d] * n_points as f64) as usize] += 1;
+                }
+            }
+            for d in 0..n {
+                assert!(counts[d].iter().all(|c| *c == 1), "dimension={}", d);
+            }
+        }
+    }
+
+    #[test]
+    fn two_dimensional_projections_are_stratified() {
+        // the first 2^k points of the first two dimensions are one per
+        // square of side 2^-(k/2)
+        let sobol = Sobol::new_scrambled(2, 99).unwrap();
+        let mut counts = [[0; 16]; 16];
+        let mut point = [0.0; 2];
+        for i in 0..256 {
+            sobol.point(i, &mut point);
+            counts[(point[0] * 16.0) as usize][(point[1] * 16.0) as usize]
+                += 1;
+        }
+        assert!(counts.iter().all(|row| row.iter().all(|c| *c == 1)));
+    }
+
+    #[test]
+    fn scrambling_is_reproducible() {
+        let first = Sobol::new_scrambled(3, 42).unwrap();
+        let second = Sobol::new_scrambled(3, 42).unwrap();
+        let other = Sobol::new_scrambled(3, 43).unwrap();
+        let unscrambled = Sobol::new(3).unwrap();
+        let (mut a, mut b, mut c, mut d) = ([0.0; 3], [0.0; 3], [0.0; 3],
+            [0.0; 3]);
+        for i in 0..10 {
+            first.point(i, &mut a);
+            second.point(i, &mut b);
+            other.point(i, &mut c);
+            unscrambled.point(i, &mut d);
+            assert_eq!(a, b);
+            assert!(a != c);
+            assert!(a != d);
+        }
+    }
+
+    #[test]
+    fn joe_kuo_polynomials_are_enumerated_in_order() {
+        let polynomials = primitive_polynomials(JOE_KUO.len());
+        for (&(s, a, _), &(t, b)) in JOE_KUO.iter().zip(polynomials.iter()) {
+            assert_eq!((s, a), (t, b));
+        }
+
+        // there are enough polynomials for all the dimensions
+        let all = primitive_polynomials(MAX_DIMENSIONS - 1);
+        assert_eq!(all.len(), MAX_DIMENSIONS - 1);
+        assert_eq!(all.last().unwrap().0, MAX_DEGREE);
+    }
+
+    #[test]
+    fn too_many_dimensions() {
+        assert!(Sobol::new(Sobol::max_dimensions()).is_ok());
+        assert!(Sobol::new(Sobol::max_dimensions() + 1).is_err());
+        assert!(Sobol::new(0).is_err());
+    }
+}
@@ -1,8 +1,8 @@
 use std::any::Any;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::collections::HashMap;
-use rand;
 use rand::StdRng;
+use rand::SeedableRng;
 use nalgebra::linalg::Cholesky;
 use nalgebra::base::DMatrix;
 use statrs::distribution::Distribution;
@@ -13,10 +13,13 @@ use ndarray::Array2;
 use ndarray::Array3;
 use ndarray::ArrayView2;
 use ndarray::ArrayViewMut2;
+use ndarray::ArrayViewMut3;
 use ndarray::Axis;
 use core::qm;
+use core::parallel::map_parallel;
 use instruments::Instrument;
 use instruments::MonteCarloContext;
+use instruments::MonteCarloExercisable;
 use instruments::PricingContext;
 use instruments::RcInstrument;
 use risk::BumpablePricingContext;
@@ -26,9 +29,27 @@ use data::bumpspot::BumpSpot;
 use data::bumpyield::BumpYield;
 use data::bumpdivs::BumpDivs;
 use data::bumpvol::BumpVol;
+use data::bumpcorrelation::BumpCorrelation;
 use models::MonteCarloModel;
 use models::MonteCarloTimeline;
 use models::MonteCarloModelFactory;
+use models::VarianceReduction;
+use models::MomentMatching;
+use models::PathBlocks;
+use models::evaluate_deterministic_rate_flows;
+use models::evaluate_deterministic_rate_exercise;
+use models::substep_dates;
+use models::longstaffschwartz::LongstaffSchwartz;
+use models::longstaffschwartz::ExerciseCalibration;
+use data::forward::Forward;
+use data::volsurface::VolSurface;
+use data::volsurface::DivAssumptions;
+use math::correlation::nearest_correlation;
+use math::correlation::frobenius_distance;
+use math::correlation::min_eigenvalue;
+use math::sobol::Sobol;
+use math::brownianbridge::BrownianBridge;
+use math::numerics::inverse_cumulative_normal;
 use dates::datetime::DateDayFraction;
 use dates::Date;
 
@@ -37,12 +58,128 @@ use dates::Date;
 /// with. The factory itself just needs the parameters of the BlackDiffusion
 /// itself, and there are only two: the time-stepping to use when converting
 /// local correlations from the market data to the integrated correlations
-/// needed by the model, and the number of paths.
+/// needed by the model, and the number of paths. Optionally, it also takes
+/// the settings for valuing early exercise by Longstaff-Schwartz, what
+/// to do if the correlation matrix is not positive definite, how to
+/// discretise the paths, how to sample the gaussians that drive them, and
+/// how to divide the work between threads.
 pub struct BlackDiffusionFactory {
     /// Substep size in business days for correlation calculation
     correlation_substep: usize,
     path_substep: f64,
-    number_of_paths: usize
+    number_of_paths: usize,
+    regression: Option<LongstaffSchwartz>,
+    correlation_repair: CorrelationRepair,
+    discretisation: PathDiscretisation,
+    sampling: GaussianSampling,
+    path_block_size: Option<usize>,
+    n_threads: usize
+}
+
+/// What to do if the correlation matrix cannot be factorised, because it is
+/// not positive definite. This is common with pairwise marked correlations,
+/// and after correlation bumps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CorrelationRepair {
+    /// Fail to build the model, or to bump it
+    Error,
+    /// Replace the matrix by the nearest valid correlation matrix, and
+    /// report the Frobenius distance of the repair in the MonteCarloResult,
+    /// so the caller can decide whether to warn about it
+    Warn,
+    /// Silently replace the matrix by the nearest valid correlation matrix.
+    /// The distance is still available from the model, but is not reported
+    /// in the results.
+    Repair
+}
+
+/// How each path is evolved over a time step with sqrt variance sigma, given
+/// a gaussian draw g.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathDiscretisation {
+    /// Multiply by exp(sigma g - sigma^2 / 2). This is exact for a lognormal
+    /// process, so the terminal distribution is correct however large the
+    /// steps, and the paths can never go negative.
+    LogEuler,
+    /// Multiply by 1 + sigma g. This preserves the mean, but only converges
+    /// on a lognormal distribution as the steps become small, and can go
+    /// negative for large sigma. Kept for comparison with old results.
+    ArithmeticEuler
+}
+
+/// The sequence of numbers used to generate the gaussians driving the paths
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RandomSequence {
+    /// Pseudo-random numbers from the standard library generator, as
+    /// SeededPseudoRandom with DEFAULT_SEED, so every model built on the
+    /// same market data has the same paths
+    PseudoRandom,
+    /// Pseudo-random numbers from the standard library generator, where
+    /// each block of paths has its own generator, seeded from the given
+    /// seed and the index of the first path in the block. The paths are
+    /// reproducible, however many threads generate them.
+    SeededPseudoRandom(u64),
+    /// A Sobol low-discrepancy sequence. There are direction numbers for
+    /// Sobol::max_dimensions() dimensions, currently 1111, which is enough
+    /// for four years of daily steps on one asset. If there are more
+    /// dimensions (steps times assets), the rest are filled with
+    /// pseudo-random numbers, and the number of them is reported as a
+    /// warning in the MonteCarloResult.
+    Sobol,
+    /// A Sobol sequence with Owen scrambling, using the given seed. The
+    /// same limit on the number of dimensions applies.
+    ScrambledSobol(u64)
+}
+
+/// How the independent gaussians for the paths are sampled: the sequence
+/// they come from, whether they are assembled into paths by Brownian
+/// bridge, and any variance reduction. A bridge makes little difference to
+/// pseudo-random numbers, but makes Sobol sequences far more effective, as
+/// it puts their best dimensions into the terminal values of the assets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GaussianSampling {
+    sequence: RandomSequence,
+    brownian_bridge: bool,
+    variance_reduction: VarianceReduction
+}
+
+impl GaussianSampling {
+    /// Creates a sampling with no variance reduction
+    pub fn new(sequence: RandomSequence, brownian_bridge: bool)
+        -> GaussianSampling {
+        GaussianSampling::new_with_variance_reduction(sequence,
+            brownian_bridge, VarianceReduction::none())
+    }
+
+    pub fn new_with_variance_reduction(sequence: RandomSequence,
+        brownian_bridge: bool, variance_reduction: VarianceReduction)
+        -> GaussianSampling {
+        GaussianSampling { sequence: sequence,
+            brownian_bridge: brownian_bridge,
+            variance_reduction: variance_reduction }
+    }
+
+    /// Pseudo-random numbers with no Brownian bridge
+    pub fn pseudo_random() -> GaussianSampling {
+        GaussianSampling::new(RandomSequence::PseudoRandom, false)
+    }
+
+    pub fn sequence(&self) -> RandomSequence { self.sequence }
+
+    /// The number of the given dimensions (steps times assets) that have
+    /// to be filled with pseudo-random numbers, because the sequence is
+    /// Sobol and there are no direction numbers for them
+    pub fn sobol_padding(&self, n_dimensions: usize) -> usize {
+        match self.sequence {
+            RandomSequence::Sobol | RandomSequence::ScrambledSobol(_) =>
+                n_dimensions.saturating_sub(Sobol::max_dimensions()),
+            _ => 0
+        }
+    }
+    pub fn brownian_bridge(&self) -> bool { self.brownian_bridge }
+    pub fn variance_reduction(&self) -> VarianceReduction {
+        self.variance_reduction
+    }
 }
 
 impl BlackDiffusionFactory {
@@ -50,7 +187,81 @@ impl BlackDiffusionFactory {
         number_of_paths: usize) -> BlackDiffusionFactory {
 
         BlackDiffusionFactory { correlation_substep: correlation_substep,
-            path_substep: path_substep, number_of_paths: number_of_paths }
+            path_substep: path_substep, number_of_paths: number_of_paths,
+            regression: None, correlation_repair: CorrelationRepair::Error,
+            discretisation: PathDiscretisation::LogEuler,
+            sampling: GaussianSampling::pseudo_random(),
+            path_block_size: None, n_threads: 1 }
+    }
+
+    /// Creates a factory whose models fit the exercise boundaries of any
+    /// instruments with early exercise on a separate set of calibration
+    /// paths, using the given basis functions. Without this, boundaries are
+    /// fitted on the pricing paths, with a quadratic basis.
+    pub fn new_with_regression(correlation_substep: usize, path_substep: f64,
+        number_of_paths: usize, regression: LongstaffSchwartz)
+        -> BlackDiffusionFactory {
+
+        BlackDiffusionFactory { correlation_substep: correlation_substep,
+            path_substep: path_substep, number_of_paths: number_of_paths,
+            regression: Some(regression),
+            correlation_repair: CorrelationRepair::Error,
+            discretisation: PathDiscretisation::LogEuler,
+            sampling: GaussianSampling::pseudo_random(),
+            path_block_size: None, n_threads: 1 }
+    }
+
+    /// Sets what the models do if the correlation matrix is not positive
+    /// definite. By default, this is an error.
+    pub fn set_correlation_repair(&mut self, repair: CorrelationRepair) {
+        self.correlation_repair = repair;
+    }
+
+    /// Sets how the models evolve their paths. By default, this is by exact
+    /// lognormal steps.
+    pub fn set_discretisation(&mut self, discretisation: PathDiscretisation) {
+        self.discretisation = discretisation;
+    }
+
+    /// Sets the sequence the gaussians are drawn from. By default, this is
+    /// pseudo-random.
+    pub fn set_random_sequence(&mut self, sequence: RandomSequence) {
+        self.sampling.sequence = sequence;
+    }
+
+    /// Sets whether the gaussians are assembled into paths by Brownian
+    /// bridge over the steps of the timeline. By default, they are not.
+    pub fn set_brownian_bridge(&mut self, brownian_bridge: bool) {
+        self.sampling.brownian_bridge = brownian_bridge;
+    }
+
+    /// Sets whether the paths come in antithetic pairs. If so, the number
+    /// of paths must be even. By default, they do not.
+    pub fn set_antithetic(&mut self, antithetic: bool) {
+        self.sampling.variance_reduction.antithetic = antithetic;
+    }
+
+    /// Sets whether the gaussians are adjusted to match the moments of a
+    /// standard normal distribution. By default, they are not.
+    pub fn set_moment_matching(&mut self, moment_matching: MomentMatching) {
+        self.sampling.variance_reduction.moment_matching = moment_matching;
+    }
+
+    /// Sets the number of threads used to generate and value the paths. By
+    /// default, everything is done on the calling thread. The paths and
+    /// prices do not depend on the number of threads.
+    pub fn set_threads(&mut self, n_threads: usize) {
+        self.n_threads = n_threads;
+    }
+
+    /// Sets the number of paths in each block. Each block is generated from
+    /// its own random stream and valued separately, so the blocks are what
+    /// is spread across the threads. By default, all the paths are in one
+    /// block. Instruments with early exercise should use calibration paths
+    /// if there is more than one block, or the exercise boundary is fitted
+    /// separately within each block.
+    pub fn set_path_block_size(&mut self, block_size: usize) {
+        self.path_block_size = Some(block_size);
     }
 }
 
@@ -60,8 +271,13 @@ impl MonteCarloModelFactory for BlackDiffusionFactory {
         context: Box<BumpablePricingContext>)
         -> Result<Box<MonteCarloModel>, qm::Error> {
 
+        let blocks = PathBlocks::new(self.number_of_paths,
+            self.path_block_size.unwrap_or(self.number_of_paths),
+            self.n_threads)?;
         let model = BlackDiffusion::new(timeline, context,
-            self.correlation_substep, self.path_substep, self.number_of_paths)?;
+            self.correlation_substep, self.path_substep, blocks,
+            self.regression.clone(), self.correlation_repair,
+            self.discretisation, self.sampling)?;
         Ok(Box::new(model))
     }
 }
@@ -80,8 +296,14 @@ impl MonteCarloModelFactory for BlackDiffusionFactory {
 /// Valuation is particularly inaccurate for instruments with skew dependence,
 /// such as barriers, autocalls or digitals.
 ///
+/// If the vol surface has JumpDivs assumptions, the spot is log-normal
+/// between dividends, and jumps down by the cash amount on each ex date.
+/// Otherwise, any cash dividends are handled by displacing the log-normal.
+///
 /// Internally, the model uses large time steps, as there is no advantage to
-/// substepping between the vols that affect the payoff. Rather than sigma dW,
+/// substepping between the vols that affect the payoff. By default, each step
+/// is an exact lognormal step, so the large steps introduce no bias. Rather
+/// than sigma dW,
 /// we take advantage of the fact that dW is scaled by sqrt(dt) to use the
 /// square root of the variance instead. We also work with an underlier
 /// scaled such that mu(t) = 0 everywhere (a martingale), so that we can ignore
@@ -110,35 +332,72 @@ impl MonteCarloModelFactory for BlackDiffusionFactory {
 /// by evolving over the union of the dates, then discarding some. 
 pub struct BlackDiffusion {
     observations: Vec<DateDayFraction>,
-    flows: Vec<Rc<Instrument>>,
+    flows: Vec<Arc<Instrument>>,
     context: Box<BumpablePricingContext>,
     key: HashMap<String, usize>,
     instruments: Vec<RcInstrument>,
     substepping: Vec<usize>,
+    correlation_substep: usize,
+    correlation_repair: CorrelationRepair,
+    repair_distance: Option<f64>,
+    discretisation: PathDiscretisation,
+    sobol_padding: usize,
+    variance_reduction: VarianceReduction,
+    blocks: PathBlocks,
+    gaussians: Array3<f64>,
     correlated_gaussians: Array3<f64>,
-    paths: Array3<f64>
+    paths: Array3<f64>,
+    exercise: Option<ExerciseCalibration>
 }
 
 impl BlackDiffusion {
 
     /// Create a new BlackDiffusion model, given a timeline to define the
     /// instrument(s) we want to price, a context to define the market data,
-    /// and a count of paths.
+    /// and the paths, divided into blocks.
     ///
     /// The correlation_substep parameter is a count of days, and is used for
     /// walking through the local correlations to create the terminal
     /// correlation required by BlackDiffusion.
     ///
     /// The path_substep parameter is a measure of the maximum sqrt_variance
-    /// step size. As volatilities increase, it becomes necessary to take
-    /// smaller steps in time, to converge on the correct drift and variance.
+    /// step size. With the arithmetic discretisation, it becomes necessary
+    /// to take smaller steps in time as volatilities increase, to converge
+    /// on the correct distribution. Log-Euler steps are exact, so need no
+    /// substepping.
+    ///
+    /// If regression settings are supplied, we also generate the calibration
+    /// paths for Longstaff-Schwartz, from independent random numbers.
+    ///
+    /// The correlation_repair parameter says what to do if the correlation
+    /// matrix is not positive definite, both now and after any bump. The
+    /// discretisation parameter says how the paths are evolved, and the
+    /// sampling parameter how the gaussians driving them are generated.
+    ///
+    /// The paths are generated on as many threads as the blocks allow, as
+    /// are the paths refetched after any bump.
     pub fn new(timeline: &MonteCarloTimeline,
         context: Box<BumpablePricingContext>,
         correlation_substep: usize,
         path_substep: f64,
-        n_paths: usize)
+        blocks: PathBlocks,
+        regression: Option<LongstaffSchwartz>,
+        correlation_repair: CorrelationRepair,
+        discretisation: PathDiscretisation,
+        sampling: GaussianSampling)
         -> Result<BlackDiffusion, qm::Error> {
 
+        // The standard error is calculated over antithetic pairs, which
+        // must not be split between blocks
+        let n_paths = blocks.n_paths();
+        let n_threads = blocks.n_threads();
+        if sampling.variance_reduction().antithetic()
+            && (n_paths % 2 != 0 || blocks.block_size() % 2 != 0) {
+            return Err(qm::Error::new(&format!("Antithetic sampling needs an \
+                even number of paths and block size, not {} and {}",
+                n_paths, blocks.block_size())))
+        }
+
         // key to all observations and all instruments
         let mut observations = Vec::new();
         let mut key = HashMap::new();
@@ -165,14 +424,47 @@ impl BlackDiffusion {
 
         // Populate the correlated gaussians. (Really, this should be redone
         // whenever any forward or vol changes, but that would slow all 
-        // risks down, and it is only a second order effect.)
-        let correlated_gaussians = fetch_correlated_gaussians(
-            context.as_pricing_context(), &instruments,
-            correlation_substep, &substepping, n_paths)?;
+        // risks down, and it is only a second order effect.) We keep the
+        // independent gaussians, in case the correlations are bumped.
+        let gaussians = fetch_sampled_gaussians(context.as_pricing_context(),
+            &observations, instruments.len(), &substepping, sampling, 0,
+            blocks)?;
+        let sobol_padding = sampling.sobol_padding(
+            instruments.len() * substepping.iter().sum::<usize>());
+        let (correlated_gaussians, repair_distance) = correlate_gaussians(
+            context.as_pricing_context(), &instruments, &observations,
+            correlation_substep, correlation_repair, &substepping,
+            &gaussians, n_threads)?;
 
         let paths = fetch_paths(&observations, &correlated_gaussians,
             context.as_pricing_context(), &instruments, 
-            correlation_substep, &substepping, n_paths)?;
+            correlation_substep, &substepping, discretisation, n_paths,
+            n_threads)?;
+
+        let exercise = match regression {
+            None => None,
+            Some(settings) => {
+                // the calibration paths follow on from the pricing paths
+                // in the sequence, so they are independent of them
+                let n_calibration = settings.calibration_paths();
+                let calibration_blocks = PathBlocks::new(n_calibration,
+                    blocks.block_size(), n_threads)?;
+                let gaussians = fetch_sampled_gaussians(
+                    context.as_pricing_context(), &observations,
+                    instruments.len(), &substepping, sampling, n_paths,
+                    calibration_blocks)?;
+                let (calibration_gaussians, _) = correlate_gaussians(
+                    context.as_pricing_context(), &instruments,
+                    &observations, correlation_substep, correlation_repair,
+                    &substepping, &gaussians, n_threads)?;
+                let calibration_paths = fetch_paths(&observations,
+                    &calibration_gaussians, context.as_pricing_context(),
+                    &instruments, correlation_substep, &substepping,
+                    discretisation, n_calibration, n_threads)?;
+                Some(ExerciseCalibration::new(settings, key.clone(),
+                    calibration_paths))
+            }
+        };
 
         // create the model with these paths and gaussians
         Ok(BlackDiffusion { 
@@ -182,8 +474,60 @@ impl BlackDiffusion {
             key: key,
             instruments: instruments,
             substepping: substepping,
+            correlation_substep: correlation_substep,
+            correlation_repair: correlation_repair,
+            repair_distance: repair_distance,
+            discretisation: discretisation,
+            sobol_padding: sobol_padding,
+            variance_reduction: sampling.variance_reduction(),
+            blocks: blocks,
+            gaussians: gaussians,
             correlated_gaussians: correlated_gaussians,
-            paths: paths })
+            paths: paths,
+            exercise: exercise })
+    }
+
+    /// If the correlation matrix currently in use had to be repaired, the
+    /// Frobenius distance between the repaired matrix and the original
+    pub fn correlation_repair_distance(&self) -> Option<f64> {
+        self.repair_distance
+    }
+
+    /// Correlate the gaussians again after a correlation bump, and refetch
+    /// all the paths. Nothing needs doing unless both assets are in the
+    /// model.
+    pub fn recorrelate(&mut self, first: &str, second: &str, bumped: bool,
+        saved: &mut SavedBlackDiffusion) -> Result<bool, qm::Error> {
+
+        if !bumped || !self.key.contains_key(first)
+            || !self.key.contains_key(second) {
+            return Ok(false)
+        }
+
+        let (correlated_gaussians, repair_distance) = correlate_gaussians(
+            self.context.as_pricing_context(), &self.instruments,
+            &self.observations, self.correlation_substep,
+            self.correlation_repair, &self.substepping, &self.gaussians,
+            self.blocks.n_threads())?;
+        let old = ::std::mem::replace(&mut self.correlated_gaussians,
+            correlated_gaussians);
+        let old_distance = ::std::mem::replace(&mut self.repair_distance,
+            repair_distance);
+        if saved.correlated_gaussians.is_none() {
+            saved.correlated_gaussians = Some((old, old_distance));
+        }
+
+        for asset in 0..self.instruments.len() {
+            let path = self.paths.subview_mut(Axis(2), asset);
+            saved.paths.entry(asset).or_insert_with(|| path.to_owned());
+            fetch_path_in_parallel(self.instruments[asset].instrument(),
+                self.context.as_pricing_context(), &self.observations,
+                self.correlated_gaussians.subview(Axis(2), asset),
+                &self.substepping, self.discretisation,
+                path, self.blocks.n_threads())?;
+        }
+
+        Ok(true)
     }
 
     /// Refetch a single asset
@@ -201,11 +545,11 @@ impl BlackDiffusion {
             // save the old path then replace it
             let path = self.paths.subview_mut(Axis(2), *asset);
             saved.paths.insert(*asset, path.to_owned());
-            fetch_path(self.instruments[*asset].instrument(), 
+            fetch_path_in_parallel(self.instruments[*asset].instrument(),
                 self.context.as_pricing_context(), &self.observations,
                 self.correlated_gaussians.subview(Axis(2), *asset),
-                &self.substepping,
-                path)?;
+                &self.substepping, self.discretisation,
+                path, self.blocks.n_threads())?;
 
         } else {
             return Err(qm::Error::new("Failed to find asset"))
@@ -260,90 +604,522 @@ pub fn calculate_substepping(
     Ok(substepping)
 }
 
+/// The seed of the pseudo-random numbers driving the paths
+pub const DEFAULT_SEED: u64 = 0;
+
 /// Fetch the correlated gaussians. In other words, a set of random
 /// numbers weighted by a gaussian distribution with correlations defined
-/// by the correlation matrix in the pricing context.
+/// by the correlation matrix in the pricing context. The gaussians are for
+/// the paths starting at first_path, so models that need more than one
+/// independent set can fetch them as if for further paths.
 pub fn fetch_correlated_gaussians(
     context: &PricingContext,
     instruments: &Vec<RcInstrument>,
-    _correlation_substep: usize,
+    observations: &[DateDayFraction],
+    correlation_substep: usize,
+    correlation_repair: CorrelationRepair,
     substepping: &[usize],
+    first_path: usize,
     n_paths: usize) -> Result<Array3<f64>, qm::Error> {
 
+    let gaussians = fetch_gaussians(instruments.len(), substepping,
+        first_path, n_paths);
+    let (correlated, _) = correlate_gaussians(context, instruments,
+        observations, correlation_substep, correlation_repair, substepping,
+        &gaussians, 1)?;
+    Ok(correlated)
+}
+
+/// Fetch independent gaussians, indexed by path, then step, then asset,
+/// for the paths starting at first_path. Models keep these, so that they
+/// can be correlated again if the correlations are bumped.
+pub fn fetch_gaussians(n_assets: usize, substepping: &[usize],
+    first_path: usize, n_paths: usize) -> Array3<f64> {
+
     // calculate how many substeps we need altogether
     let n_steps = substepping.iter().sum();
     assert!(n_steps > 0);
-
-    // create a 3d tensor indexed by path, then observation, then asset
-    let n_assets = instruments.len();
     assert!(n_assets > 0);
     assert!(n_paths > 0);
+
+    // Use the standard library random number generator. (See
+    // fetch_sampled_gaussians for low-discrepancy sequences.) It has a fixed
+    // seed, so that the paths, and the tests that use them, are repeatable.
     let mut result = Array3::<f64>::zeros((n_paths, n_steps, n_assets));
+    fill_pseudo_random(&mut seeded_rng(DEFAULT_SEED, first_path),
+        result.view_mut());
+    result
+}
 
-    // TODO we currently just use the raw correlations, but we ought to
-    // calculate correlations between the timeline points. If there is a
-    // term structure to vol, this is likely to be different, even with
-    // flat correlation structure.
+/// Fetch independent gaussians, indexed by path, then step, then asset, as
+/// fetch_gaussians, but sampled as specified. If there is a Brownian
+/// bridge, it runs over the substep dates of the observations, measured in
+/// calendar time from the spot date, and is applied to each asset
+/// separately.
+///
+/// The first_path is the index of the first path within the sequence, so
+/// that further sets of paths, such as calibration paths, can follow on.
+///
+/// The paths are drawn in the given blocks, which are spread across the
+/// given number of threads. Each block of a seeded pseudo-random sequence
+/// has its own generator, and each path of a Sobol sequence is a point at
+/// a known index, so the gaussians are the same whatever the number of
+/// threads.
+///
+/// With antithetic sampling, only half the paths are drawn, and each is
+/// followed by its negation, so the number of paths must be even. Any
+/// moment matching is done last, across all the paths.
+pub fn fetch_sampled_gaussians(context: &PricingContext,
+    observations: &[DateDayFraction], n_assets: usize, substepping: &[usize],
+    sampling: GaussianSampling, first_path: usize, blocks: PathBlocks)
+    -> Result<Array3<f64>, qm::Error> {
+
+    let n_steps: usize = substepping.iter().sum();
+    assert!(n_steps > 0);
+    assert!(n_assets > 0);
+    let n_paths = blocks.n_paths();
+    assert!(n_paths > 0);
 
-    // Create a correlation matrix. Starting with an identity matrix (eye)
-    // fills in the diagonals.
-    let mut correl = Array2::<f64>::eye(n_assets);
-    for i in 0..n_assets {
-        let first = instruments[i].instrument();
-        for j in 0..i {
-            let second = instruments[j].instrument();
-            let c = context.correlation(first, second)?;
-            correl[(i, j)] = c;
-            correl[(j, i)] = c;
+    // antithetic draws are blocked in the same way as the pairs they form
+    let variance_reduction = sampling.variance_reduction();
+    let (n_draws, draw_block) = if variance_reduction.antithetic() {
+        if n_paths % 2 != 0 || blocks.block_size() % 2 != 0 {
+            return Err(qm::Error::new(&format!("Antithetic sampling needs an \
+                even number of paths and block size, not {} and {}",
+                n_paths, blocks.block_size())))
         }
+        (n_paths / 2, blocks.block_size() / 2)
+    } else {
+        (n_paths, blocks.block_size())
+    };
+
+    let sobol = match sampling.sequence() {
+        RandomSequence::PseudoRandom | RandomSequence::SeededPseudoRandom(_)
+            => None,
+        RandomSequence::Sobol =>
+            Some(sobol_sequence(None, n_assets * n_steps, first_path,
+                n_draws)?),
+        RandomSequence::ScrambledSobol(seed) =>
+            Some(sobol_sequence(Some(seed), n_assets * n_steps, first_path,
+                n_draws)?)
+    };
+
+    let bridge = if sampling.brownian_bridge() {
+        let start = DateDayFraction::new(context.spot_date(), 0.0);
+        let origin = start.date();
+        let times: Vec<f64> = substep_dates(start, observations, substepping)
+            .iter().map(|d| (d.date() - origin) as f64 + d.day_fraction())
+            .collect();
+        Some(BrownianBridge::new(&times)?)
+    } else {
+        None
+    };
+
+    let mut result = Array3::<f64>::zeros((n_draws, n_steps, n_assets));
+    {
+        let chunks: Vec<_> = result.axis_chunks_iter_mut(Axis(0), draw_block)
+            .enumerate().collect();
+        map_parallel(blocks.n_threads(), chunks, |(block, mut draws)| {
+            let first = first_path + block * draw_block;
+            match sampling.sequence() {
+                RandomSequence::PseudoRandom => fill_pseudo_random(
+                    &mut seeded_rng(DEFAULT_SEED, first), draws.view_mut()),
+                RandomSequence::SeededPseudoRandom(seed) => fill_pseudo_random(
+                    &mut seeded_rng(seed, first), draws.view_mut()),
+                RandomSequence::Sobol | RandomSequence::ScrambledSobol(_) => {
+                    let seed = match sampling.sequence() {
+                        RandomSequence::ScrambledSobol(seed) => seed,
+                        _ => DEFAULT_SEED
+                    };
+                    fill_sobol(sobol.as_ref().unwrap(), &mut seeded_rng(seed,
+                        first), first, draws.view_mut())
+                }
+            }
+            if let Some(ref bridge) = bridge {
+                apply_bridge(bridge, draws);
+            }
+            Ok(())
+        })?;
     }
 
-    // Use Cholesky decomposition to create a matrix to use for generating
-    // correlated gaussians. (There are alternative ways of producing
-    // copulae. This should be user-settable.)
+    // the bridge is linear, so it makes no difference whether we negate
+    // the gaussians before or after it
+    if variance_reduction.antithetic() {
+        result = antithetic_pairs(&result);
+    }
 
-    // This is what it would look like if we could use ndarray_linalg
-    // if let Some(cholesky) = correl.cholesky(UPLO::Lower) {
+    match variance_reduction.moment_matching() {
+        MomentMatching::Off => {},
+        MomentMatching::Mean => match_moments(&mut result, false)?,
+        MomentMatching::MeanAndVariance => match_moments(&mut result, true)?
+    }
 
-    // convert to a DMatrix
-    let slice = correl.as_slice().ok_or_else(|| qm::Error::new(
-        "Correlation cannot be accessed as a slice"))?;
-    let correld = DMatrix::from_column_slice(n_assets, n_assets, slice);
-    let rootd = Cholesky::new(correld).ok_or_else(|| qm::Error::new(
-        "Correlation matrix is not positive semi-definite"))?;
+    Ok(result)
+}
 
-    // convert back to an Array2
-    let root_slice = rootd.unpack().as_slice().to_vec();
-    let root = Array::from_shape_vec((n_assets, n_assets), root_slice)?;
+/// Creates a generator for the block of paths starting at the given index,
+/// seeded from that index and the given seed, so that each block has its
+/// own stream.
+fn seeded_rng(seed: u64, first_path: usize) -> StdRng {
+    let words = [seed as u32 as usize, (seed >> 32) as usize, first_path];
+    StdRng::from_seed(&words[..])
+}
 
-    // Use the standard library random number generator for now. (Look
-    // at better generators such as Mersenne Twister, or better still
-    // Sobol sequences -- this should be user-settable.)
-    let mut rand = rand::StdRng::new().unwrap();
+/// Fills a block of gaussians, indexed by path, then step, then asset, from
+/// the given generator.
+fn fill_pseudo_random(rand: &mut StdRng, mut draws: ArrayViewMut3<f64>) {
 
     // Use the normal statrs package for turning the random numbers into
     // gaussians for now. Internally it uses Box-Mueller, which is a
     // lossy algorithm, so it cannot be used for low-discrepancy
     // sequences like Sobol.
     let normal = Normal::new(0.0, 1.0).unwrap();
+    for draw in draws.iter_mut() {
+        *draw = normal.sample::<StdRng>(rand);
+    }
+}
 
-    let mut draws = Array1::zeros(n_assets);
+/// Runs each asset of each path in a block of gaussians, indexed by path,
+/// then step, then asset, through the Brownian bridge.
+fn apply_bridge(bridge: &BrownianBridge, mut draws: ArrayViewMut3<f64>) {
+    let n_steps = bridge.size();
+    let n_assets = draws.shape()[2];
+    let mut gaussians = vec![0.0; n_steps];
+    let mut increments = vec![0.0; n_steps];
+    for mut path in draws.outer_iter_mut() {
+        for asset in 0..n_assets {
+            for step in 0..n_steps {
+                gaussians[step] = path[(step, asset)];
+            }
+            bridge.transform(&gaussians, &mut increments);
+            for step in 0..n_steps {
+                path[(step, asset)] = increments[step];
+            }
+        }
+    }
+}
 
-    for mut path in result.outer_iter_mut() {
-        for mut step in path.outer_iter_mut() {
+/// Expands the given gaussians, indexed by path, then step, then asset,
+/// into twice as many paths, by following each path with its negation.
+fn antithetic_pairs(gaussians: &Array3<f64>) -> Array3<f64> {
+    let shape = gaussians.shape();
+    let mut result = Array3::<f64>::zeros((shape[0] * 2, shape[1], shape[2]));
+    for (i, mut path) in result.outer_iter_mut().enumerate() {
+        path.assign(&gaussians.subview(Axis(0), i / 2));
+        if i % 2 == 1 {
+            path.mapv_inplace(|g| -g);
+        }
+    }
+    result
+}
 
-            // create uncorrelated gaussians
-            for draw in draws.iter_mut() {
-                *draw = normal.sample::<StdRng>(&mut rand);
+/// Adjusts the gaussians, indexed by path, then step, then asset, so that
+/// for each step and asset they have a mean of exactly zero across the
+/// paths, and optionally a variance of exactly one.
+fn match_moments(gaussians: &mut Array3<f64>, match_variance: bool)
+    -> Result<(), qm::Error> {
+
+    let shape = gaussians.shape().to_vec();
+    let n_paths = shape[0];
+    if match_variance && n_paths < 2 {
+        return Err(qm::Error::new(
+            "Matching the variance of the gaussians needs at least two paths"))
+    }
+
+    for step in 0..shape[1] {
+        for asset in 0..shape[2] {
+            let mean = (0..n_paths).map(|path| gaussians[(path, step, asset)])
+                .sum::<f64>() / n_paths as f64;
+            for path in 0..n_paths {
+                gaussians[(path, step, asset)] -= mean;
             }
 
-            // turn them into correlated gaussians. TODO ensure that this
-            // multiplication does not result in an allocation.
-            step.assign(&root.dot(&draws));
+            if match_variance {
+                let variance = (0..n_paths)
+                    .map(|path| gaussians[(path, step, asset)].powi(2))
+                    .sum::<f64>() / n_paths as f64;
+                if !(variance > 0.0) {
+                    return Err(qm::Error::new("Cannot match the variance of \
+                        gaussians that are all the same"))
+                }
+                let scale = 1.0 / variance.sqrt();
+                for path in 0..n_paths {
+                    gaussians[(path, step, asset)] *= scale;
+                }
+            }
         }
     }
+    Ok(())
+}
 
-    Ok(result)
+/// Creates a Sobol sequence, optionally scrambled with the given seed, for
+/// gaussians with the given number of dimensions, checking that the
+/// sequence is long enough for the paths.
+fn sobol_sequence(scramble_seed: Option<u64>, n_dimensions: usize,
+    first_path: usize, n_paths: usize) -> Result<Sobol, qm::Error> {
+
+    if first_path + n_paths >= ::std::u32::MAX as usize {
+        return Err(qm::Error::new("Too many paths for a Sobol sequence"))
+    }
+
+    // any dimensions we have no direction numbers for are pseudo-random
+    let n_sobol = n_dimensions.min(Sobol::max_dimensions());
+    match scramble_seed {
+        None => Sobol::new(n_sobol),
+        Some(seed) => Sobol::new_scrambled(n_sobol, seed)
+    }
+}
+
+/// Fills a block of gaussians from a Sobol sequence, starting at the given
+/// path. Each path is one point of the sequence, with dimensions ordered by
+/// step then asset, so the earliest and best distributed dimensions drive
+/// the first step of each asset, or the terminal values if there is a
+/// Brownian bridge. The origin is skipped. Any dimensions beyond those of
+/// the sequence come from the given generator.
+fn fill_sobol(sobol: &Sobol, rand: &mut StdRng, first_path: usize,
+    mut draws: ArrayViewMut3<f64>) {
+
+    let n_sobol = sobol.dimensions();
+    let normal = Normal::new(0.0, 1.0).unwrap();
+
+    // Scrambling can give a uniform of exactly zero, so we keep the
+    // uniforms away from the ends of the range.
+    let min_uniform = 0.5 / (1u64 << 32) as f64;
+
+    let mut point = vec![0.0; n_sobol];
+    for (i, mut path) in draws.outer_iter_mut().enumerate() {
+        sobol.point((first_path + i + 1) as u32, &mut point);
+        for (dimension, draw) in path.iter_mut().enumerate() {
+            *draw = if dimension < n_sobol {
+                inverse_cumulative_normal(point[dimension]
+                    .max(min_uniform).min(1.0 - min_uniform))
+            } else {
+                normal.sample::<StdRng>(rand)
+            };
+        }
+    }
+}
+
+/// Correlate a set of independent gaussians, indexed by path, then step,
+/// then asset. Each step has its own correlation matrix, as calculated by
+/// step_correlations. Also returns the Frobenius distance of any repair to
+/// the matrices, or the largest distance if more than one was repaired.
+/// The paths are shared between the given number of threads.
+pub fn correlate_gaussians(
+    context: &PricingContext,
+    instruments: &Vec<RcInstrument>,
+    observations: &[DateDayFraction],
+    correlation_substep: usize,
+    correlation_repair: CorrelationRepair,
+    substepping: &[usize],
+    gaussians: &Array3<f64>,
+    n_threads: usize)
+    -> Result<(Array3<f64>, Option<f64>), qm::Error> {
+
+    let n_assets = instruments.len();
+    assert_eq!(gaussians.shape()[2], n_assets);
+
+    // with only one asset, there is nothing to correlate
+    if n_assets == 1 {
+        return Ok((gaussians.clone(), None))
+    }
+
+    let correlations = step_correlations(context, instruments, observations,
+        correlation_substep, substepping)?;
+    assert_eq!(correlations.len(), gaussians.shape()[1]);
+
+    // Factorise each matrix. Often they are all the same, in which case we
+    // only need to do this once.
+    let mut roots: Vec<Array2<f64>> = Vec::with_capacity(correlations.len());
+    let mut repair_distance: Option<f64> = None;
+    for (i, correl) in correlations.iter().enumerate() {
+        if i > 0 && *correl == correlations[i - 1] {
+            let root = roots[i - 1].clone();
+            roots.push(root);
+            continue;
+        }
+        let (root, distance) = correlation_root(correl.clone(),
+            correlation_repair)?;
+        if let Some(d) = distance {
+            repair_distance = Some(repair_distance.map_or(d, |r| r.max(d)));
+        }
+        roots.push(root);
+    }
+
+    // each path is correlated separately, so we share them between threads
+    let mut result = Array3::<f64>::zeros(gaussians.dim());
+    {
+        let chunk = chunk_size(gaussians.shape()[0], n_threads);
+        let chunks: Vec<_> = gaussians.axis_chunks_iter(Axis(0), chunk).zip(
+            result.axis_chunks_iter_mut(Axis(0), chunk)).collect();
+        map_parallel(n_threads, chunks, |(draws, mut correlated)| {
+            for (path, mut out_path) in draws.outer_iter().zip(
+                correlated.outer_iter_mut()) {
+                for ((step, mut out), root) in path.outer_iter().zip(
+                    out_path.outer_iter_mut()).zip(roots.iter()) {
+
+                    // TODO ensure that this multiplication does not result
+                    // in an allocation.
+                    out.assign(&root.dot(&step));
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok((result, repair_distance))
+}
+
+/// Calculates the effective correlation matrix for each step, where the
+/// steps are the observations split by the substepping, as for
+/// substep_dates. The local correlations in the market data apply to the
+/// instantaneous increments, so the effective correlation over a step is
+/// the integral of the local correlation weighted by the instantaneous
+/// vols of the two assets, divided by the root of the two integrated
+/// variances:
+///
+///  rho_ij = int rho_ij(t) sigma_i(t) sigma_j(t) dt
+///             / sqrt(int sigma_i(t)^2 dt int sigma_j(t)^2 dt)
+///
+/// We integrate by walking through each step in increments of
+/// correlation_substep business days, using the calendar of the vol
+/// surface of the first asset, and treating the vols and correlations as
+/// constant over each increment. The vols are at the money, measured
+/// against the forward, as in the paths themselves. A correlation_substep
+/// of zero means we treat each step as a single increment.
+pub fn step_correlations(
+    context: &PricingContext,
+    instruments: &Vec<RcInstrument>,
+    observations: &[DateDayFraction],
+    correlation_substep: usize,
+    substepping: &[usize]) -> Result<Vec<DMatrix<f64>>, qm::Error> {
+
+    let n_assets = instruments.len();
+    let start = DateDayFraction::new(context.spot_date(), 0.0);
+    let step_dates = substep_dates(start, observations, substepping);
+    let hwm = observations.last().ok_or_else(|| qm::Error::new(
+        "No observations"))?.date();
+
+    let mut forwards = Vec::with_capacity(n_assets);
+    let mut surfaces = Vec::with_capacity(n_assets);
+    for instrument in instruments.iter() {
+        let instr = instrument.instrument();
+        let forward = context.forward_curve(instr, hwm)?;
+        surfaces.push(context.vol_surface(instr, forward.clone(), hwm)?);
+        forwards.push(forward);
+    }
+
+    // the at the money variance of each asset at the given time
+    let variances = |date: DateDayFraction| -> Result<Vec<f64>, qm::Error> {
+        let mut result = Vec::with_capacity(n_assets);
+        for (forward, surface) in forwards.iter().zip(surfaces.iter()) {
+            let atm = forward.forward(date.date())?;
+            result.push(surface.variance(date, atm)?);
+        }
+        Ok(result)
+    };
+
+    let mut matrices = Vec::with_capacity(step_dates.len());
+    let mut from = start;
+    let mut prev_vars = variances(from)?;
+    for to in step_dates.iter() {
+
+        let mut covariance = DMatrix::<f64>::zeros(n_assets, n_assets);
+        loop {
+            // find the end of this increment, which must not be past the
+            // end of the step
+            let mut next = *to;
+            if correlation_substep > 0 {
+                let date = surfaces[0].calendar().step(from.date(),
+                    correlation_substep as i32, true);
+                if date < to.date() {
+                    next = DateDayFraction::new(date, 0.0);
+                }
+            }
+
+            // accumulate the covariance over the increment, with the
+            // correlations that apply on its end date
+            let vars = variances(next)?;
+            let sigmas: Vec<f64> = vars.iter().zip(prev_vars.iter())
+                .map(|(v, p)| (v - p).max(0.0).sqrt()).collect();
+            for i in 0..n_assets {
+                covariance[(i, i)] += sigmas[i] * sigmas[i];
+                for j in 0..i {
+                    let c = context.correlation(instruments[i].instrument(),
+                        instruments[j].instrument(), next.date())?;
+                    covariance[(i, j)] += c * sigmas[i] * sigmas[j];
+                }
+            }
+
+            from = next;
+            prev_vars = vars;
+            if next == *to {
+                break;
+            }
+        }
+
+        // Normalise to give a correlation matrix. If either asset has no
+        // variance in this step, the correlation does not matter, so we
+        // use the local correlation at the end of the step.
+        let mut correl = DMatrix::<f64>::identity(n_assets, n_assets);
+        for i in 0..n_assets {
+            for j in 0..i {
+                let norm = (covariance[(i, i)] * covariance[(j, j)]).sqrt();
+                let c = if norm > 0.0 {
+                    covariance[(i, j)] / norm
+                } else {
+                    context.correlation(instruments[i].instrument(),
+                        instruments[j].instrument(), to.date())?
+                };
+                correl[(i, j)] = c;
+                correl[(j, i)] = c;
+            }
+        }
+        matrices.push(correl);
+    }
+
+    Ok(matrices)
+}
+
+/// Use Cholesky decomposition to create a matrix to use for generating
+/// correlated gaussians. (There are alternative ways of producing copulae.
+/// This should be user-settable.) If the correlation matrix is not positive
+/// definite, it may be repaired first, depending on the correlation_repair
+/// setting, in which case we also return the Frobenius distance of the
+/// repair.
+pub fn correlation_root(correl: DMatrix<f64>,
+    correlation_repair: CorrelationRepair)
+    -> Result<(Array2<f64>, Option<f64>), qm::Error> {
+
+    // This is what it would look like if we could use ndarray_linalg
+    // if let Some(cholesky) = correl.cholesky(UPLO::Lower) {
+
+    let n_assets = correl.nrows();
+    let (rootd, repair_distance) = match Cholesky::new(correl.clone()) {
+        Some(root) => (root, None),
+        None => {
+            if correlation_repair == CorrelationRepair::Error {
+                return Err(qm::Error::new(&format!("Correlation matrix is \
+                    not positive definite: smallest eigenvalue {}",
+                    min_eigenvalue(&correl))))
+            }
+
+            // floor the eigenvalues a little above zero, so the repaired
+            // matrix can be factorised
+            let repaired = nearest_correlation(&correl, 1e-8, 1e-10, 10000)?;
+            let distance = frobenius_distance(&correl, &repaired);
+            let root = Cholesky::new(repaired).ok_or_else(|| qm::Error::new(
+                "Repaired correlation matrix is not positive definite"))?;
+            (root, Some(distance))
+        }
+    };
+
+    // convert back to an Array2. The DMatrix is stored in column-major
+    // order, so we read it in as the transpose then reverse the axes.
+    let root_slice = rootd.unpack().as_slice().to_vec();
+    let root = Array::from_shape_vec((n_assets, n_assets), root_slice)?
+        .reversed_axes();
+    Ok((root, repair_distance))
 }
 
 pub fn fetch_paths(
@@ -353,7 +1129,9 @@ pub fn fetch_paths(
     instruments: &Vec<RcInstrument>,
     _correlation_substep: usize,
     substepping: &[usize],
-    n_paths: usize) -> Result<Array3<f64>, qm::Error> {
+    discretisation: PathDiscretisation,
+    n_paths: usize,
+    n_threads: usize) -> Result<Array3<f64>, qm::Error> {
 
     // create a 3d tensor indexed by path, then observation, then asset
     let n_assets = instruments.len();
@@ -368,16 +1146,46 @@ pub fn fetch_paths(
         correlated_gaussians.axis_iter(Axis(2))).zip(
         paths.axis_iter_mut(Axis(2))) {
 
-        fetch_path(asset.instrument(), context, &observations, gaussians,
-            substepping, path)?;
+        fetch_path_in_parallel(asset.instrument(), context, &observations,
+            gaussians, substepping, discretisation, path, n_threads)?;
     }
 
     Ok(paths)
 }
 
+/// As fetch_path, but with the paths shared between the given number of
+/// threads. Each path only depends on its own gaussians, so the result is
+/// the same however they are shared.
+pub fn fetch_path_in_parallel(instrument: &Instrument,
+    context: &PricingContext, observations: &[DateDayFraction],
+    correlated_gaussians: ArrayView2<f64>, substepping: &[usize],
+    discretisation: PathDiscretisation, mut path: ArrayViewMut2<f64>,
+    n_threads: usize) -> Result<(), qm::Error> {
+
+    if n_threads <= 1 {
+        return fetch_path(instrument, context, observations,
+            correlated_gaussians, substepping, discretisation, path)
+    }
+
+    let chunk = chunk_size(path.shape()[0], n_threads);
+    let chunks: Vec<_> = correlated_gaussians.axis_chunks_iter(Axis(0), chunk)
+        .zip(path.axis_chunks_iter_mut(Axis(0), chunk)).collect();
+    map_parallel(n_threads, chunks, |(gaussians, path)| fetch_path(
+        instrument, context, observations, gaussians, substepping,
+        discretisation, path))?;
+    Ok(())
+}
+
+/// The number of paths to give each thread, so that they have about the
+/// same amount of work
+fn chunk_size(n_paths: usize, n_threads: usize) -> usize {
+    let n_threads = n_threads.max(1);
+    ((n_paths + n_threads - 1) / n_threads).max(1)
+}
+
 pub fn fetch_path(instrument: &Instrument, context: &PricingContext,
     observations: &[DateDayFraction], correlated_gaussians: ArrayView2<f64>,
-    substepping: &[usize],
+    substepping: &[usize], discretisation: PathDiscretisation,
     mut path: ArrayViewMut2<f64>) -> Result<(), qm::Error> {
 
     let n_obs = observations.len();
@@ -396,13 +1204,17 @@ pub fn fetch_path(instrument: &Instrument, context: &PricingContext,
     // Fetch the forwards and variances on each observation date
     // We use the at the forward variances, using the live forward curve
     // (consider optionally using the forwards in the vol surface).
+    // JumpDivs surfaces are never displaced, as the cash dividends are
+    // applied as jumps in the spot instead.
+    let jump_divs = vol_surface.div_assumptions() == DivAssumptions::JumpDivs;
     let mut forwards = Vec::with_capacity(n_obs);
     let mut variances = Vec::with_capacity(n_obs);
     let mut displacements = Vec::with_capacity(n_obs);
     for obs in observations.iter() {
         let fwd = forward_curve.forward(obs.date())?;
         variances.push(vol_surface.variance(*obs, fwd)?);
-        let displacement = vol_surface.displacement(obs.date())?;
+        let displacement = if jump_divs { 0.0 } else {
+            vol_surface.displacement(obs.date())? };
         displacements.push(displacement);
         forwards.push(fwd - displacement);
     }
@@ -423,6 +1235,18 @@ pub fn fetch_path(instrument: &Instrument, context: &PricingContext,
         prev_var = *var;
     }
 
+    let jumps = if jump_divs {
+        dividend_jumps(&*forward_curve, &*vol_surface, context.spot_date(),
+            observations, &variances, substepping)?
+    } else {
+        Vec::new()
+    };
+
+    // In log-Euler, the lognormal drift of each step is fixed, so we can
+    // calculate it up front.
+    let log_euler = discretisation == PathDiscretisation::LogEuler;
+    let drifts: Vec<f64> = sigmas.iter().map(|s| -0.5 * s * s).collect();
+
     // for each of the paths
     for (ref gaussians, ref mut one_path) in 
         correlated_gaussians.outer_iter().zip(path.outer_iter_mut()) {
@@ -430,11 +1254,20 @@ pub fn fetch_path(instrument: &Instrument, context: &PricingContext,
         // walk along each path
         let mut point = 1.0;
         let mut g = 0;	// index into the gaussians
+        let mut jump = 0;	// index into the dividend jumps
         for i in 0..n_obs {
             let sigma = sigmas[i];
             for _ in 0..substepping[i] {
-                point *= 1.0 + gaussians[g] * sigma;
+                if log_euler {
+                    point *= (gaussians[g] * sigma + drifts[i]).exp();
+                } else {
+                    point *= 1.0 + gaussians[g] * sigma;
+                }
                 g += 1;
+                while jump < jumps.len() && jumps[jump].step == g {
+                    point = jumps[jump].apply(point);
+                    jump += 1;
+                }
             }
                 
             one_path[i] = point * forwards[i] + displacements[i];
@@ -447,16 +1280,147 @@ pub fn fetch_path(instrument: &Instrument, context: &PricingContext,
     Ok(())
 }
 
+/// All the cash dividends going ex on a single date, for a JumpDivs vol
+/// surface. The step is the number of substeps after which the jump is
+/// applied.
+struct DividendJump {
+    step: usize,
+    forward_before: f64,
+    forward_after: f64,
+    cash: f64,
+    keep: f64
+}
+
+impl DividendJump {
+    /// Applies the jump to a point, which is the spot divided by the
+    /// forward. The spot cannot go negative, so we limit the cash.
+    fn apply(&self, point: f64) -> f64 {
+        let spot_before = point * self.forward_before;
+        let spot_after = (spot_before * self.keep - self.cash)
+            .max(spot_before * 1e-6);
+        spot_after / self.forward_after
+    }
+}
+
+/// Collects the dividends from the forward that go ex after the spot date,
+/// up to the last observation, grouping any on the same date. Each jump is
+/// applied at the end of the substep nearest its ex date, measured in
+/// variance, which is exact if the ex date is an observation date.
+fn dividend_jumps(forward: &Forward, vol_surface: &VolSurface,
+    spot_date: Date, observations: &[DateDayFraction], variances: &[f64],
+    substepping: &[usize]) -> Result<Vec<DividendJump>, qm::Error> {
+
+    let mut jumps: Vec<DividendJump> = Vec::new();
+    let mut ex_date = spot_date;
+    for div in forward.dividends().iter() {
+        if div.ex_date() <= spot_date {
+            continue
+        }
+
+        // group any dividends on the same date
+        if div.ex_date() == ex_date {
+            if let Some(last) = jumps.last_mut() {
+                last.cash = last.cash * (1.0 - div.relative()) + div.cash();
+                last.keep *= 1.0 - div.relative();
+                last.forward_before = (last.forward_after + last.cash)
+                    / last.keep;
+                continue
+            }
+        }
+
+        // find the observation on or after the start of the ex date
+        ex_date = div.ex_date();
+        let ex_time = DateDayFraction::new(ex_date, 0.0);
+        let i = match observations.iter().position(|obs| *obs >= ex_time) {
+            Some(i) => i,
+            None => break
+        };
+
+        // find the substep nearest the ex date within this observation
+        let prev_var = if i == 0 { 0.0 } else { variances[i - 1] };
+        let forward_after = forward.forward(ex_date)?;
+        let ex_var = vol_surface.variance(ex_time, forward_after)?;
+        let fraction = if variances[i] > prev_var {
+            (ex_var - prev_var) / (variances[i] - prev_var) } else { 1.0 };
+        let n = substepping[i];
+        let substep = ((fraction * n as f64).round() as usize).max(1).min(n);
+        let step = substepping[..i].iter().sum::<usize>() + substep;
+
+        let keep = 1.0 - div.relative();
+        jumps.push(DividendJump { step: step,
+            forward_before: (forward_after + div.cash()) / keep,
+            forward_after: forward_after, cash: div.cash(), keep: keep });
+    }
+    Ok(jumps)
+}
+
 impl MonteCarloModel for BlackDiffusion {
 
     fn as_mc_context(&self) -> &MonteCarloContext { self }
+    fn as_pricing_context(&self) -> &PricingContext {
+        self.context.as_pricing_context()
+    }
+    fn clone_context(&self) -> Box<BumpablePricingContext> {
+        self.context.clone_box()
+    }
+    fn clone_box(&self) -> Box<MonteCarloModel> {
+        Box::new(BlackDiffusion {
+            observations: self.observations.clone(),
+            flows: self.flows.clone(),
+            context: self.context.clone_box(),
+            key: self.key.clone(),
+            instruments: self.instruments.clone(),
+            substepping: self.substepping.clone(),
+            correlation_substep: self.correlation_substep,
+            correlation_repair: self.correlation_repair,
+            repair_distance: self.repair_distance,
+            discretisation: self.discretisation,
+            sobol_padding: self.sobol_padding,
+            variance_reduction: self.variance_reduction,
+            blocks: self.blocks,
+            gaussians: self.gaussians.clone(),
+            correlated_gaussians: self.correlated_gaussians.clone(),
+            paths: self.paths.clone(),
+            exercise: self.exercise.clone() })
+    }
     fn as_bumpable(&self) -> &Bumpable { self }
     fn as_mut_bumpable(&mut self) -> &mut Bumpable { self }
+
+    fn correlation_repair_warning(&self) -> Option<f64> {
+        match self.correlation_repair {
+            CorrelationRepair::Warn => self.repair_distance,
+            _ => None
+        }
+    }
+
+    fn sobol_padding_warning(&self) -> Option<usize> {
+        if self.sobol_padding > 0 { Some(self.sobol_padding) } else { None }
+    }
+
+    fn variance_reduction(&self) -> VarianceReduction {
+        self.variance_reduction
+    }
+
+    fn path_blocks(&self) -> Option<PathBlocks> {
+        Some(self.blocks)
+    }
+
+    fn path_block<'a>(&'a self, first_path: usize, n_paths: usize)
+        -> Result<Box<MonteCarloContext + 'a>, qm::Error> {
+
+        if first_path + n_paths > self.paths.shape()[0] {
+            return Err(qm::Error::new(&format!("Path block {}..{} is \
+                outside the {} paths", first_path, first_path + n_paths,
+                self.paths.shape()[0])))
+        }
+        Ok(Box::new(BlackDiffusionBlock { model: self,
+            first_path: first_path, n_paths: n_paths }))
+    }
 }
 
 impl MonteCarloContext for BlackDiffusion {
 
-    fn paths(&self, instrument: &Rc<Instrument>)
+    fn paths(&self, instrument: &Arc<Instrument>)
         -> Result<ArrayView2<f64>, qm::Error> {
 
         let id = instrument.id().to_string();
@@ -465,43 +1429,57 @@ impl MonteCarloContext for BlackDiffusion {
         Ok(self.paths.subview(Axis(2), *asset))
     }
 
-    fn evaluate_flows(&self, quantities: ArrayView2<f64>)
-        -> Result<f64, qm::Error> {
+    fn evaluate_flows_by_path(&self, quantities: ArrayView2<f64>)
+        -> Result<Array1<f64>, qm::Error> {
 
-        let flows_shape = quantities.shape();
-        let paths_shape = self.paths.shape();
-        let n_paths = paths_shape[0];
-        let n_paths_f64: f64 = n_paths as f64;
-        assert_eq!(flows_shape[0], n_paths);
-        assert_eq!(flows_shape[1], self.flows.len());
+        // BlackDiffusion is a non-stochastic-rate model
+        evaluate_deterministic_rate_flows(&self.flows, quantities,
+            self.paths.shape()[0], self.context.as_pricing_context())
+    }
 
-        // weighted sum of all of the flows
-        let mut total = 0.0;
-        for (flow, quantity) in self.flows.iter().zip(
-            quantities.axis_iter(Axis(1))) {
+    fn evaluate_exercise_by_path(&self, exercisable: &MonteCarloExercisable)
+        -> Result<Array1<f64>, qm::Error> {
 
-            // BlackDiffusion is a non-stochastic-rate model, so we can
-            // save time by evaluating the pure rate flows using Priceable
-            if flow.is_pure_rates() {
+        evaluate_deterministic_rate_exercise(&self.flows, exercisable, self,
+            self.paths.shape()[0], self.context.as_pricing_context(),
+            self.exercise.as_ref())
+    }
+}
 
-                // value of the instrument times the average quantity
-                let average = quantity.scalar_sum() / n_paths_f64;
-                let pricer = flow.as_priceable().ok_or_else(|| qm::Error::new(
-                    "All pure-rates flows must be priceable"))?;
-                let value = pricer.price(self.context.as_pricing_context())?;
-                total += average * value;
+/// A block of consecutive paths of a BlackDiffusion, which can be valued
+/// independently of the other blocks. Any exercise boundary comes from the
+/// calibration paths if there are any, otherwise it is fitted within the
+/// block.
+struct BlackDiffusionBlock<'a> {
+    model: &'a BlackDiffusion,
+    first_path: usize,
+    n_paths: usize
+}
 
-                //println!("BlackDiffusion::evaluate_flows value={} average={} \
-                //    total={}", value, average, total);
+impl<'a> MonteCarloContext for BlackDiffusionBlock<'a> {
 
-            } else {
+    fn paths(&self, instrument: &Arc<Instrument>)
+        -> Result<ArrayView2<f64>, qm::Error> {
 
-                // otherwise we must price by Monte-Carlo over each path
-                // TODO how do we pass in the weights?
-                return Err(qm::Error::new("not implemented"))
-            }
-        }
-        Ok(total)
+        let paths = self.model.paths(instrument)?;
+        let (_, rest) = paths.split_at(Axis(0), self.first_path);
+        let (block, _) = rest.split_at(Axis(0), self.n_paths);
+        Ok(block)
+    }
+
+    fn evaluate_flows_by_path(&self, quantities: ArrayView2<f64>)
+        -> Result<Array1<f64>, qm::Error> {
+
+        evaluate_deterministic_rate_flows(&self.model.flows, quantities,
+            self.n_paths, self.model.context.as_pricing_context())
+    }
+
+    fn evaluate_exercise_by_path(&self, exercisable: &MonteCarloExercisable)
+        -> Result<Array1<f64>, qm::Error> {
+
+        evaluate_deterministic_rate_exercise(&self.model.flows, exercisable,
+            self, self.n_paths, self.model.context.as_pricing_context(),
+            self.model.exercise.as_ref())
     }
 }
 
@@ -556,6 +1534,15 @@ impl Bumpable for BlackDiffusion {
         self.refetch(id, bumped, saved)
     }
 
+    fn bump_correlation(&mut self, first: &str, second: &str,
+        bump: &BumpCorrelation, any_saved: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_correlation(first,
+            second, bump, &mut *saved.saved_data)?;
+        self.recorrelate(first, second, bumped, saved)
+    }
+
     fn bump_discount_date(&mut self, replacement: Date,
         any_saved: &mut Saveable) -> Result<bool, qm::Error> {
         let saved = to_saved(any_saved)?;
@@ -582,7 +1569,12 @@ impl Bumpable for BlackDiffusion {
             // first restore the underlying market data and cached curves
             self.context.as_mut_bumpable().restore(&*saved.saved_data)?;
 
-            // now restore any cached paths
+            // now restore any cached gaussians and paths
+            if let Some((ref gaussians, distance))
+                = saved.correlated_gaussians {
+                self.correlated_gaussians.assign(gaussians);
+                self.repair_distance = distance;
+            }
             for (asset, paths) in saved.paths.iter() {
                 let mut dest = self.paths.subview_mut(Axis(2), *asset);
                 dest.assign(paths);
@@ -609,6 +1601,7 @@ fn to_saved(saveable: &mut Saveable)
 /// Save space for BlackDiffusion to use during bumping
 pub struct SavedBlackDiffusion {
     saved_data: Box<Saveable>,
+    correlated_gaussians: Option<(Array3<f64>, Option<f64>)>,
     paths: HashMap<usize, Array2<f64>>
 }
 
@@ -619,6 +1612,7 @@ impl SavedBlackDiffusion {
     pub fn new(saved_data: Box<Saveable>) -> SavedBlackDiffusion {
         SavedBlackDiffusion {
             saved_data: saved_data,
+            correlated_gaussians: None,
             paths: HashMap::new() }
     }
 }
@@ -629,8 +1623,422 @@ impl Saveable for SavedBlackDiffusion {
 
     fn clear(&mut self) {
         self.saved_data.clear();
+        self.correlated_gaussians = None;
         self.paths.clear();
     }
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::ArrayView1;
+    use math::numerics::approx_eq;
+    use instruments::MonteCarloDependencies;
+    use instruments::assets::Equity;
+    use data::correlation::LocalCorrelation;
+    use risk::marketdata::tests::sample_currency;
+    use risk::marketdata::tests::sample_settlement;
+    use risk::marketdata::tests::sample_market_data;
+    use risk::marketdata::tests::sample_market_data_with_correlations;
+    use risk::marketdata::tests::sample_market_data_with_vol_and_correlations;
+    use data::volsurface::VolByProbability;
+    use data::volsurface::DivAssumptions;
+    use data::volsmile::CubicSplineSmile;
+    use data::forward::DriftlessForward;
+    use dates::calendar::WeekdayCalendar;
+    use dates::calendar::Calendar;
+
+    fn sample_correlation(x: ArrayView1<f64>, y: ArrayView1<f64>) -> f64 {
+        let n = x.len() as f64;
+        let (mx, my) = (x.scalar_sum() / n, y.scalar_sum() / n);
+        let mut sxy = 0.0;
+        let mut sxx = 0.0;
+        let mut syy = 0.0;
+        for (a, b) in x.iter().zip(y.iter()) {
+            sxy += (a - mx) * (b - my);
+            sxx += (a - mx) * (a - mx);
+            syy += (b - my) * (b - my);
+        }
+        sxy / (sxx * syy).sqrt()
+    }
+
+    fn sample_model(correlation_repair: CorrelationRepair)
+        -> (BlackDiffusion, Arc<Instrument>, Arc<Instrument>) {
+
+        let currency = Arc::new(sample_currency(2));
+        let bp: Arc<Instrument> = Arc::new(Equity::new("BP.L", "LSE",
+            currency.clone(), sample_settlement(2)));
+        let gsk: Arc<Instrument> = Arc::new(Equity::new("GSK.L", "LSE",
+            currency, sample_settlement(2)));
+        let correlation = Arc::new(LocalCorrelation::new_constant(0.6)
+            .unwrap());
+        let market_data = sample_market_data_with_correlations(&[
+            ("BP.L", "GSK.L", correlation)]).unwrap();
+
+        // a single observation, with no substepping, so the log returns of
+        // the paths have the local correlation
+        let expiry = DateDayFraction::new(Date::from_ymd(2017, 07, 03), 0.0);
+        let mut timeline = MonteCarloTimeline::new(market_data.spot_date());
+        timeline.observation(&bp, expiry);
+        timeline.observation(&gsk, expiry);
+        timeline.collate().unwrap();
+
+        let model = BlackDiffusion::new(&timeline, Box::new(market_data), 1,
+            1.0, PathBlocks::single(20000), None, correlation_repair,
+            PathDiscretisation::LogEuler, GaussianSampling::pseudo_random())
+            .unwrap();
+        (model, bp, gsk)
+    }
+
+    #[test]
+    fn correlated_paths_and_correlation_bump() {
+        let (mut model, bp, gsk) = sample_model(CorrelationRepair::Error);
+        assert_eq!(model.correlation_repair_distance(), None);
+        let unbumped_bp = model.paths(&bp).unwrap().to_owned();
+        let unbumped_gsk = model.paths(&gsk).unwrap().to_owned();
+        let c = sample_correlation(unbumped_bp.column(0),
+            unbumped_gsk.column(0));
+        assert!((c - 0.6).abs() < 0.03, "correlation={}", c);
+
+        // bumping the correlation moves both paths
+        let mut save = model.new_saveable();
+        let bump = BumpCorrelation::new_replace(-0.5);
+        assert!(model.bump_correlation("GSK.L", "BP.L", &bump, &mut *save)
+            .unwrap());
+        let c = sample_correlation(model.paths(&bp).unwrap().column(0),
+            model.paths(&gsk).unwrap().column(0));
+        assert!((c + 0.5).abs() < 0.03, "correlation={}", c);
+
+        // restoring puts them back exactly
+        model.restore(&*save).unwrap();
+        assert_eq!(model.paths(&bp).unwrap(), unbumped_bp.view());
+        assert_eq!(model.paths(&gsk).unwrap(), unbumped_gsk.view());
+    }
+
+    #[test]
+    fn correlation_repair_after_bump() {
+
+        // perfect correlation cannot be factorised by Cholesky, so by
+        // default the bump fails
+        let bump = BumpCorrelation::new_replace(1.0);
+        let (mut model, _, _) = sample_model(CorrelationRepair::Error);
+        let mut save = model.new_saveable();
+        assert!(model.bump_correlation("BP.L", "GSK.L", &bump, &mut *save)
+            .is_err());
+
+        // but it can be repaired, at the cost of a tiny change in the matrix
+        let (mut model, bp, gsk) = sample_model(CorrelationRepair::Repair);
+        let mut save = model.new_saveable();
+        assert!(model.bump_correlation("BP.L", "GSK.L", &bump, &mut *save)
+            .unwrap());
+        let distance = model.correlation_repair_distance().unwrap();
+        assert!(distance > 0.0 && distance < 1e-6, "distance={}", distance);
+        assert_eq!(model.correlation_repair_warning(), None);
+        let c = sample_correlation(model.paths(&bp).unwrap().column(0),
+            model.paths(&gsk).unwrap().column(0));
+        assert!(c > 0.999, "correlation={}", c);
+
+        model.restore(&*save).unwrap();
+        assert_eq!(model.correlation_repair_distance(), None);
+
+        // when asked to warn, the distance is reported as a warning
+        let (mut model, _, _) = sample_model(CorrelationRepair::Warn);
+        let mut save = model.new_saveable();
+        assert!(model.bump_correlation("BP.L", "GSK.L", &bump, &mut *save)
+            .unwrap());
+        assert_eq!(model.correlation_repair_warning(), Some(distance));
+        model.restore(&*save).unwrap();
+        assert_eq!(model.correlation_repair_warning(), None);
+    }
+
+    #[test]
+    fn step_correlations_weight_by_forward_vols() {
+
+        // BP.L has a high vol until the end of March, then a low vol, and
+        // GSK.L has a flat vol. The local correlation is high until the end
+        // of March, then zero.
+        let d = Date::from_ymd(2017, 01, 02);
+        let march = DateDayFraction::new(d + 88, 0.0);
+        let june = DateDayFraction::new(d + 179, 0.0);
+        let calendar = Box::new(WeekdayCalendar());
+        let base = DateDayFraction::new(Date::from_ymd(2016, 12, 30), 0.2);
+        let t_march = calendar.year_fraction(base, march);
+        let t_june = calendar.year_fraction(base, june);
+        let june_vol = ((0.36 * t_march + 0.01 * (t_june - t_march))
+            / t_june).sqrt();
+        let flat = |vol: f64| CubicSplineSmile::new(&[(50.0, vol),
+            (100.0, vol), (200.0, vol)]).unwrap();
+        let smiles = [(march, flat(0.6)), (june, flat(june_vol))];
+        let vol = Arc::new(VolByProbability::new(&smiles, calendar, base,
+            Box::new(DriftlessForward::new(100.0)),
+            DivAssumptions::NoCashDivs).unwrap());
+        let correlation = Arc::new(LocalCorrelation::new_term_structure(&[
+            (march.date(), 0.9), (june.date(), 0.0)]).unwrap());
+        let market_data = sample_market_data_with_vol_and_correlations(vol,
+            &[("BP.L", "GSK.L", correlation)]).unwrap();
+
+        let currency = Arc::new(sample_currency(2));
+        let instruments = vec!(
+            RcInstrument::new(Arc::new(Equity::new("BP.L", "LSE",
+                currency.clone(), sample_settlement(2)))),
+            RcInstrument::new(Arc::new(Equity::new("GSK.L", "LSE",
+                currency, sample_settlement(2)))));
+
+        // The vols are piecewise flat, so the integral reduces to the
+        // variances to the end of March as a fraction of the total.
+        let variance = |instrument: &RcInstrument, date: DateDayFraction| {
+            let instr = instrument.instrument();
+            let forward = market_data.forward_curve(instr, june.date())
+                .unwrap();
+            let surface = market_data.vol_surface(instr, forward.clone(),
+                june.date()).unwrap();
+            surface.variance(date, forward.forward(date.date()).unwrap())
+                .unwrap()
+        };
+        let start = DateDayFraction::new(d, 0.0);
+        let increment = |i: usize, to: DateDayFraction|
+            variance(&instruments[i], to) - variance(&instruments[i], start);
+        let expected = 0.9 * (increment(0, march) * increment(1, march)
+            / (increment(0, june) * increment(1, june))).sqrt();
+
+        let correlations = step_correlations(&market_data, &instruments,
+            &[june], 1, &[1]).unwrap();
+        assert_eq!(correlations.len(), 1);
+        let c = correlations[0][(0, 1)];
+        assert!((c - expected).abs() < 1e-4, "c={} expected={}", c, expected);
+        assert_eq!(correlations[0][(1, 0)], c);
+
+        // Ignoring the vol term structure would give a correlation much
+        // lower than this, and treating the step as a single increment
+        // would use only the correlation at its end.
+        let time_weighted = 0.9 * increment(1, march) / increment(1, june);
+        assert!(c > time_weighted + 0.1, "c={} time_weighted={}", c,
+            time_weighted);
+        let correlations = step_correlations(&market_data, &instruments,
+            &[june], 0, &[1]).unwrap();
+        assert_eq!(correlations[0][(0, 1)], 0.0);
+
+        // with two substeps, each has its own matrix
+        let correlations = step_correlations(&market_data, &instruments,
+            &[june], 1, &[2]).unwrap();
+        assert_eq!(correlations.len(), 2);
+        assert!(correlations[0][(0, 1)] > 0.85);
+        assert!(correlations[1][(0, 1)] < 0.05);
+    }
+
+    #[test]
+    fn log_euler_and_arithmetic_steps() {
+
+        // a single large step, with gaussians chosen so that an arithmetic
+        // step would take the path negative
+        let market_data = sample_market_data();
+        let currency = Arc::new(sample_currency(2));
+        let bp = Equity::new("BP.L", "LSE", currency, sample_settlement(2));
+        let expiry = DateDayFraction::new(Date::from_ymd(2018, 06, 01), 0.0);
+        let gaussians = Array2::from_shape_vec((3, 1), vec![-3.0, 0.0, 3.0])
+            .unwrap();
+
+        let forward = market_data.forward_curve(&bp, expiry.date()).unwrap();
+        let fwd = forward.forward(expiry.date()).unwrap();
+        let surface = market_data.vol_surface(&bp, forward.clone(),
+            expiry.date()).unwrap();
+        let sigma = surface.variance(expiry, fwd).unwrap().sqrt();
+        assert!(3.0 * sigma > 1.0);
+
+        let mut path = Array2::<f64>::zeros((3, 1));
+        fetch_path(&bp, &market_data, &[expiry], gaussians.view(), &[1],
+            PathDiscretisation::LogEuler, path.view_mut()).unwrap();
+        for (i, g) in gaussians.iter().enumerate() {
+            let expected = fwd * (sigma * g - 0.5 * sigma * sigma).exp();
+            assert_approx(path[(i, 0)], expected, 1e-10);
+            assert!(path[(i, 0)] > 0.0);
+        }
+
+        fetch_path(&bp, &market_data, &[expiry], gaussians.view(), &[1],
+            PathDiscretisation::ArithmeticEuler, path.view_mut()).unwrap();
+        for (i, g) in gaussians.iter().enumerate() {
+            assert_approx(path[(i, 0)], fwd * (1.0 + sigma * g), 1e-10);
+        }
+        assert!(path[(0, 0)] < 0.0);
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+
+    #[test]
+    fn sobol_gaussians_through_brownian_bridge() {
+        let market_data = sample_market_data();
+        let d = market_data.spot_date();
+        let observations: Vec<DateDayFraction> = [30, 91, 182, 365].iter()
+            .map(|days| DateDayFraction::new(d + *days, 0.7)).collect();
+        let substepping = [1, 2, 1, 3];
+        let n_paths = 4096;
+
+        // Sobol gaussians are evenly spread, so their moments are very
+        // accurate, and this survives the Brownian bridge.
+        let sampling = GaussianSampling::new(RandomSequence::Sobol, true);
+        let gaussians = fetch_sampled_gaussians(&market_data, &observations,
+            2, &substepping, sampling, 0, PathBlocks::single(n_paths))
+            .unwrap();
+        assert_eq!(gaussians.shape(), &[n_paths, 7, 2]);
+        for step in 0..7 {
+            for asset in 0..2 {
+                let draws: Vec<f64> = gaussians.outer_iter()
+                    .map(|path| path[(step, asset)]).collect();
+                let mean = draws.iter().sum::<f64>() / n_paths as f64;
+                let variance = draws.iter().map(|g| g * g).sum::<f64>()
+                    / n_paths as f64;
+                assert!(mean.abs() < 1e-3, "step={} mean={}", step, mean);
+                assert_approx(variance, 1.0, 0.02);
+            }
+        }
+
+        // The calibration paths follow on in the sequence, so they differ
+        let more = fetch_sampled_gaussians(&market_data, &observations, 2,
+            &substepping, sampling, n_paths, PathBlocks::single(10)).unwrap();
+        assert!(more.subview(Axis(0), 0) != gaussians.subview(Axis(0), 0));
+
+        // The bridge fails if two steps are at the same time
+        let clash = [observations[0], observations[0]];
+        assert!(fetch_sampled_gaussians(&market_data, &clash, 1, &[1, 1],
+            sampling, 0, PathBlocks::single(10)).is_err());
+    }
+
+    #[test]
+    fn sobol_padding_is_reported() {
+        let market_data = sample_market_data();
+        let currency = Arc::new(sample_currency(2));
+        let bp: Arc<Instrument> = Arc::new(Equity::new("BP.L", "LSE",
+            currency, sample_settlement(2)));
+
+        // daily observations for three and a half years need 1280
+        // dimensions, which is more than we have direction numbers for
+        let mut timeline = MonteCarloTimeline::new(market_data.spot_date());
+        for day in 1..1281 {
+            timeline.observation(&bp, DateDayFraction::new(
+                market_data.spot_date() + day, 0.0));
+        }
+        timeline.collate().unwrap();
+        let padding = 1280 - Sobol::max_dimensions();
+
+        for &(sequence, expected) in [
+            (RandomSequence::Sobol, Some(padding)),
+            (RandomSequence::ScrambledSobol(42), Some(padding)),
+            (RandomSequence::PseudoRandom, None)].iter() {
+            let model = BlackDiffusion::new(&timeline,
+                Box::new(market_data.clone()), 1, 1.0, PathBlocks::single(16),
+                None, CorrelationRepair::Error, PathDiscretisation::LogEuler,
+                GaussianSampling::new(sequence, true)).unwrap();
+            assert_eq!(model.sobol_padding_warning(), expected);
+        }
+    }
+
+    #[test]
+    fn antithetic_and_moment_matched_gaussians() {
+        let market_data = sample_market_data();
+        let d = market_data.spot_date();
+        let observations = [DateDayFraction::new(d + 91, 0.7),
+            DateDayFraction::new(d + 365, 0.7)];
+        let substepping = [1, 2];
+
+        // antithetic pairs cancel exactly, so there must be an even number
+        // of paths
+        let sampling = GaussianSampling::new_with_variance_reduction(
+            RandomSequence::PseudoRandom, false,
+            VarianceReduction::new(true, MomentMatching::Off));
+        let gaussians = fetch_sampled_gaussians(&market_data, &observations,
+            2, &substepping, sampling, 0, PathBlocks::single(8)).unwrap();
+        assert_eq!(gaussians.shape(), &[8, 3, 2]);
+        for pair in 0..4 {
+            let first = gaussians.subview(Axis(0), 2 * pair);
+            let second = gaussians.subview(Axis(0), 2 * pair + 1);
+            assert_eq!(first.to_owned(), second.mapv(|g| -g));
+        }
+        assert!(fetch_sampled_gaussians(&market_data, &observations, 2,
+            &substepping, sampling, 0, PathBlocks::single(7)).is_err());
+
+        // matching gives exact moments for each step and asset, whether or
+        // not the paths are antithetic
+        for &antithetic in [false, true].iter() {
+            let sampling = GaussianSampling::new_with_variance_reduction(
+                RandomSequence::PseudoRandom, false, VarianceReduction::new(
+                antithetic, MomentMatching::MeanAndVariance));
+            let gaussians = fetch_sampled_gaussians(&market_data,
+                &observations, 2, &substepping, sampling, 0,
+                PathBlocks::single(100)).unwrap();
+            for step in 0..3 {
+                for asset in 0..2 {
+                    let draws: Vec<f64> = gaussians.outer_iter()
+                        .map(|path| path[(step, asset)]).collect();
+                    let mean = draws.iter().sum::<f64>() / 100.0;
+                    let variance = draws.iter().map(|g| g * g).sum::<f64>()
+                        / 100.0;
+                    assert_approx(mean, 0.0, 1e-14);
+                    assert_approx(variance, 1.0, 1e-13);
+                }
+            }
+        }
+
+        // the mean alone can be matched on a single path, but not the
+        // variance
+        let mean_only = GaussianSampling::new_with_variance_reduction(
+            RandomSequence::PseudoRandom, false,
+            VarianceReduction::new(false, MomentMatching::Mean));
+        let gaussians = fetch_sampled_gaussians(&market_data, &observations,
+            2, &substepping, mean_only, 0, PathBlocks::single(1)).unwrap();
+        assert!(gaussians.iter().all(|g| *g == 0.0));
+        let variance = GaussianSampling::new_with_variance_reduction(
+            RandomSequence::PseudoRandom, false,
+            VarianceReduction::new(false, MomentMatching::MeanAndVariance));
+        assert!(fetch_sampled_gaussians(&market_data, &observations, 2,
+            &substepping, variance, 0, PathBlocks::single(1)).is_err());
+    }
+
+    #[test]
+    fn blocked_gaussians_do_not_depend_on_threads() {
+        let market_data = sample_market_data();
+        let d = market_data.spot_date();
+        let observations = [DateDayFraction::new(d + 91, 0.7),
+            DateDayFraction::new(d + 365, 0.7)];
+        let substepping = [1, 2];
+        let fetch = |sequence: RandomSequence, antithetic: bool,
+            blocks: PathBlocks| {
+            let sampling = GaussianSampling::new_with_variance_reduction(
+                sequence, true, VarianceReduction::new(antithetic,
+                MomentMatching::Off));
+            fetch_sampled_gaussians(&market_data, &observations, 2,
+                &substepping, sampling, 0, blocks).unwrap()
+        };
+
+        // seeded blocks are reproducible on any number of threads, and
+        // antithetic pairs stay within their blocks
+        let seeded = RandomSequence::SeededPseudoRandom(42);
+        for &antithetic in [false, true].iter() {
+            let single = fetch(seeded, antithetic,
+                PathBlocks::new(100, 16, 1).unwrap());
+            let parallel = fetch(seeded, antithetic,
+                PathBlocks::new(100, 16, 4).unwrap());
+            assert_eq!(single, parallel);
+        }
+        let other = fetch(RandomSequence::SeededPseudoRandom(43), false,
+            PathBlocks::new(100, 16, 4).unwrap());
+        assert!(other != fetch(seeded, false,
+            PathBlocks::new(100, 16, 4).unwrap()));
+
+        // the default sequence is seeded too
+        assert_eq!(fetch(RandomSequence::PseudoRandom, false,
+            PathBlocks::new(100, 16, 1).unwrap()),
+            fetch(RandomSequence::PseudoRandom, false,
+            PathBlocks::new(100, 16, 4).unwrap()));
+
+        // the points of a Sobol sequence do not depend on the blocks
+        let sobol = fetch(RandomSequence::Sobol, false,
+            PathBlocks::single(100));
+        assert_eq!(sobol, fetch(RandomSequence::Sobol, false,
+            PathBlocks::new(100, 7, 3).unwrap()));
+    }
+}
@@ -0,0 +1,1202 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::f64::consts::PI;
+use std::collections::HashMap;
+use num_complex::Complex;
+use ndarray::Array1;
+use ndarray::Array2;
+use ndarray::Array3;
+use ndarray::ArrayView2;
+use ndarray::ArrayViewMut2;
+use ndarray::Axis;
+use core::qm;
+use instruments::Instrument;
+use instruments::MonteCarloContext;
+use instruments::MonteCarloExercisable;
+use instruments::PricingContext;
+use instruments::RcInstrument;
+use risk::BumpablePricingContext;
+use risk::Bumpable;
+use risk::Saveable;
+use data::bumpspot::BumpSpot;
+use data::bumpyield::BumpYield;
+use data::bumpdivs::BumpDivs;
+use data::bumpvol::BumpVol;
+use data::bumpcorrelation::BumpCorrelation;
+use data::forward::Forward;
+use data::volsurface::VolSurface;
+use data::volsurface::VolQuoting;
+use math::optionpricing::Black76;
+use math::optimization::LevenbergMarquardt;
+use models::MonteCarloModel;
+use models::MonteCarloTimeline;
+use models::MonteCarloModelFactory;
+use models::evaluate_deterministic_rate_flows;
+use models::evaluate_deterministic_rate_exercise;
+use models::longstaffschwartz::LongstaffSchwartz;
+use models::longstaffschwartz::ExerciseCalibration;
+use models::substep_dates;
+use models::blackdiffusion::calculate_substepping;
+use models::blackdiffusion::fetch_correlated_gaussians;
+use models::blackdiffusion::fetch_gaussians;
+use models::blackdiffusion::correlate_gaussians;
+use models::blackdiffusion::CorrelationRepair;
+use dates::datetime::DateDayFraction;
+use dates::Date;
+
+/// Parameters of the Heston stochastic volatility model:
+///
+///  dS/S = mu(t) dt + sqrt(v) dW1
+///  dv = kappa (theta - v) dt + xi sqrt(v) dW2
+///  dW1 dW2 = rho dt
+///
+/// Time is measured in vol time, as defined by the calendar of the vol
+/// surface, so that these parameters are directly comparable with the
+/// variances in the surface.
+///
+/// Also supplies the semi-analytic price of European options, using Lewis's
+/// single-integral formula over the characteristic function. We use the
+/// formulation of the characteristic function from Albrecher et al, "The
+/// Little Heston Trap", which avoids discontinuities in the complex log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HestonParameters {
+    initial_variance: f64,
+    mean_reversion: f64,
+    long_term_variance: f64,
+    vol_of_variance: f64,
+    correlation: f64
+}
+
+impl HestonParameters {
+    /// Creates a set of Heston parameters. The variances and mean reversion
+    /// must be non-negative, the vol of variance must be strictly positive,
+    /// and the correlation must lie strictly between -1 and 1.
+    pub fn new(initial_variance: f64, mean_reversion: f64,
+        long_term_variance: f64, vol_of_variance: f64, correlation: f64)
+        -> Result<HestonParameters, qm::Error> {
+
+        if !(initial_variance >= 0.0) || !(long_term_variance >= 0.0) {
+            return Err(qm::Error::new("Heston variances must be non-negative"))
+        }
+        if !(mean_reversion >= 0.0) {
+            return Err(qm::Error::new("Heston mean reversion must be \
+                non-negative"))
+        }
+        if !(vol_of_variance > 0.0) {
+            return Err(qm::Error::new("Heston vol of variance must be \
+                positive"))
+        }
+        if !(correlation > -1.0 && correlation < 1.0) {
+            return Err(qm::Error::new("Heston correlation must be strictly \
+                between -1 and 1"))
+        }
+
+        Ok(HestonParameters {
+            initial_variance: initial_variance,
+            mean_reversion: mean_reversion,
+            long_term_variance: long_term_variance,
+            vol_of_variance: vol_of_variance,
+            correlation: correlation })
+    }
+
+    pub fn initial_variance(&self) -> f64 { self.initial_variance }
+    pub fn mean_reversion(&self) -> f64 { self.mean_reversion }
+    pub fn long_term_variance(&self) -> f64 { self.long_term_variance }
+    pub fn vol_of_variance(&self) -> f64 { self.vol_of_variance }
+    pub fn correlation(&self) -> f64 { self.correlation }
+
+    /// Calculates the PV of a European call option under Heston, where the
+    /// time is the vol time to expiry.
+    pub fn call_price(&self, df: f64, forward: f64, strike: f64, time: f64)
+        -> Result<f64, qm::Error> {
+
+        let mut call = [0.0];
+        self.undiscounted_calls(forward, &[strike], time, &mut call)?;
+        Ok(df * call[0])
+    }
+
+    /// Calculates the PV of a European put option under Heston, where the
+    /// time is the vol time to expiry.
+    pub fn put_price(&self, df: f64, forward: f64, strike: f64, time: f64)
+        -> Result<f64, qm::Error> {
+
+        let mut call = [0.0];
+        self.undiscounted_calls(forward, &[strike], time, &mut call)?;
+        Ok(df * (call[0] - forward + strike))
+    }
+
+    /// The characteristic function of the log of the ratio of the spot at
+    /// the given time to the forward. This is a martingale, so the value at
+    /// u = -i is one.
+    pub fn characteristic_function(&self, u: Complex<f64>, time: f64)
+        -> Complex<f64> {
+
+        let i = Complex::new(0.0, 1.0);
+        let one = Complex::new(1.0, 0.0);
+        let xi2 = self.vol_of_variance * self.vol_of_variance;
+
+        let a = -i * u * (self.correlation * self.vol_of_variance)
+            + self.mean_reversion;
+        let d = (a * a + (i * u + u * u) * xi2).sqrt();
+        let g = (a - d) / (a + d);
+        let e = (-d * time).exp();
+
+        let c = ((a - d) * time - ((one - g * e) / (one - g)).ln() * 2.0)
+            * (self.mean_reversion * self.long_term_variance / xi2);
+        let dv = (a - d) / xi2 * (one - e) / (one - g * e);
+
+        (c + dv * self.initial_variance).exp()
+    }
+
+    /// Undiscounted call prices for a range of strikes at one expiry. The
+    /// characteristic function only depends on the expiry, so it is shared
+    /// between the strikes.
+    fn undiscounted_calls(&self, forward: f64, strikes: &[f64], time: f64,
+        calls: &mut [f64]) -> Result<(), qm::Error> {
+
+        assert_eq!(strikes.len(), calls.len());
+        if !(forward > 0.0) {
+            return Err(qm::Error::new("Heston requires a positive forward"))
+        }
+        if strikes.iter().any(|k| !(*k > 0.0)) {
+            return Err(qm::Error::new("Heston requires positive strikes"))
+        }
+        if time <= 0.0 {
+            for (call, strike) in calls.iter_mut().zip(strikes.iter()) {
+                *call = (forward - strike).max(0.0);
+            }
+            return Ok(())
+        }
+
+        // The integrand oscillates with frequency given by the log
+        // moneyness, so we need finer steps for strikes far from the money.
+        let log_moneyness: Vec<f64> = strikes.iter()
+            .map(|k| (forward / k).ln()).collect();
+        let max_k = log_moneyness.iter().fold(1.0_f64, |m, k| m.max(k.abs()));
+        let h = 0.1 / max_k;
+
+        // Composite Simpson integration, in panels, stopping once the
+        // envelope of the integrand is negligible
+        const PANEL: usize = 64;
+        const MAX_U: f64 = 1e5;
+        let mut integrals = vec![0.0; strikes.len()];
+        let mut u_start = 0.0;
+        loop {
+            let mut envelope: f64 = 0.0;
+            for j in 0..(PANEL + 1) {
+                let weight = if j == 0 || j == PANEL { 1.0 }
+                    else if j % 2 == 1 { 4.0 } else { 2.0 };
+                let u = u_start + h * (j as f64);
+                let phi = self.characteristic_function(
+                    Complex::new(u, -0.5), time);
+                let denominator = u * u + 0.25;
+                envelope = phi.norm() / denominator;
+                for (integral, k) in integrals.iter_mut()
+                    .zip(log_moneyness.iter()) {
+                    let rotation = Complex::new(0.0, u * k).exp();
+                    *integral += weight * (rotation * phi).re / denominator;
+                }
+            }
+            u_start += h * (PANEL as f64);
+
+            if !envelope.is_finite() {
+                return Err(qm::Error::new("Heston characteristic function \
+                    is not finite"))
+            }
+            if envelope < 1e-14 {
+                break
+            }
+            if u_start > MAX_U {
+                return Err(qm::Error::new("Heston integral failed to \
+                    converge"))
+            }
+        }
+
+        for ((call, strike), integral) in calls.iter_mut()
+            .zip(strikes.iter()).zip(integrals.iter()) {
+            let integral = integral * h / 3.0;
+            let value = forward - (forward * strike).sqrt() * integral / PI;
+
+            // clip to the no-arbitrage bounds, to remove integration noise
+            *call = value.max((forward - strike).max(0.0)).min(forward);
+        }
+        Ok(())
+    }
+}
+
+/// Calibrates Heston parameters to a vol surface, by matching the prices of
+/// out-of-the-money options on a grid of expiries and strikes. The expiries
+/// are specified in days from the base date of the surface, and the strikes
+/// in standard deviations from the forward, measured using the at-the-money
+/// variance at each expiry. Price differences are divided by the Black vega,
+/// so the fit is roughly in terms of vol.
+#[derive(Debug, Clone)]
+pub struct HestonCalibrator {
+    expiry_days: Vec<i32>,
+    normalised_strikes: Vec<f64>,
+    initial: HestonParameters
+}
+
+impl HestonCalibrator {
+    pub fn new(expiry_days: &[i32], normalised_strikes: &[f64],
+        initial: HestonParameters) -> Result<HestonCalibrator, qm::Error> {
+
+        if expiry_days.is_empty() || normalised_strikes.is_empty() {
+            return Err(qm::Error::new("Heston calibration needs at least one \
+                expiry and one strike"))
+        }
+        if expiry_days.len() * normalised_strikes.len() < 5 {
+            return Err(qm::Error::new("Heston calibration needs at least as \
+                many quotes as parameters"))
+        }
+        if expiry_days.iter().any(|d| *d <= 0) {
+            return Err(qm::Error::new("Heston calibration expiries must be \
+                after the base date"))
+        }
+
+        Ok(HestonCalibrator {
+            expiry_days: expiry_days.to_vec(),
+            normalised_strikes: normalised_strikes.to_vec(),
+            initial: initial })
+    }
+
+    /// Fits Heston parameters to the surface, using the given forward to
+    /// place the strikes. Only log-normal surfaces without displacement are
+    /// supported.
+    pub fn calibrate(&self, surface: &VolSurface, forward: &Forward)
+        -> Result<HestonParameters, qm::Error> {
+
+        let quoting = surface.vol_quoting();
+        if quoting != VolQuoting::LogNormal {
+            return Err(qm::Error::new(&format!("Heston calibration requires a \
+                log-normal vol surface, not {:?}", quoting)))
+        }
+
+        // Collect the market quotes, as out-of-the-money prices and vegas
+        let black76 = Black76::new()?;
+        let base = surface.base_date();
+        let n_strikes = self.normalised_strikes.len();
+        let mut expiries = Vec::with_capacity(self.expiry_days.len());
+        for days in self.expiry_days.iter() {
+            let date = DateDayFraction::new(base.date() + *days,
+                base.day_fraction());
+            if surface.displacement(date.date())? != 0.0 {
+                return Err(qm::Error::new("Heston calibration does not \
+                    support displaced vol surfaces"))
+            }
+
+            let fwd = forward.forward(date.date())?;
+            let time = surface.vol_time(date)?;
+            let atm_sqrt_var = surface.variance(date, fwd)?.sqrt();
+            let strikes: Vec<f64> = self.normalised_strikes.iter()
+                .map(|z| fwd * (z * atm_sqrt_var).exp()).collect();
+            let mut variances = vec![0.0; n_strikes];
+            surface.variances(date, &strikes, &mut variances)?;
+
+            let mut prices = Vec::with_capacity(n_strikes);
+            let mut vegas = Vec::with_capacity(n_strikes);
+            for (strike, variance) in strikes.iter().zip(variances.iter()) {
+                let greeks = if *strike < fwd {
+                    black76.put_greeks(1.0, fwd, *strike, variance.sqrt())
+                } else {
+                    black76.call_greeks(1.0, fwd, *strike, variance.sqrt())
+                };
+                prices.push(greeks.price());
+                vegas.push(greeks.vega().max(1e-4 * fwd));
+            }
+            expiries.push((fwd, time, strikes, prices, vegas));
+        }
+
+        let residuals = |p: &[f64], r: &mut [f64]| {
+            let params = HestonParameters::new(p[0], p[1], p[2], p[3], p[4])?;
+            let mut calls = vec![0.0; n_strikes];
+            for (e, &(fwd, time, ref strikes, ref prices, ref vegas))
+                in expiries.iter().enumerate() {
+                params.undiscounted_calls(fwd, strikes, time, &mut calls)?;
+                for i in 0..n_strikes {
+                    let strike = strikes[i];
+                    let model = if strike < fwd {
+                        calls[i] - fwd + strike } else { calls[i] };
+                    r[e * n_strikes + i] = (model - prices[i]) / vegas[i];
+                }
+            }
+            Ok(())
+        };
+
+        let initial = [self.initial.initial_variance,
+            self.initial.mean_reversion, self.initial.long_term_variance,
+            self.initial.vol_of_variance, self.initial.correlation];
+        let lower = [1e-6, 1e-3, 1e-6, 1e-3, -0.999];
+        let upper = [4.0, 20.0, 4.0, 5.0, 0.999];
+        let lm = LevenbergMarquardt::new(100, 1e-10);
+        let p = lm.minimise(residuals, &initial, &lower, &upper,
+            expiries.len() * n_strikes)?;
+        HestonParameters::new(p[0], p[1], p[2], p[3], p[4])
+    }
+}
+
+/// The HestonFactory creates a Heston model, given the timeline of the
+/// product(s) to value, and the market data to value it with. The Heston
+/// parameters for each underlying are either supplied directly, keyed by
+/// the id of the underlying, or calibrated to the vol surface in the market
+/// data. Calibrated parameters are refitted whenever the market data for
+/// that underlying is bumped, so that vega risk makes sense. Optionally, it
+/// also takes the settings for valuing early exercise by Longstaff-Schwartz.
+pub struct HestonFactory {
+    /// Substep size in business days for correlation calculation
+    correlation_substep: usize,
+    path_substep: f64,
+    number_of_paths: usize,
+    parameters: HashMap<String, HestonParameters>,
+    calibrator: Option<HestonCalibrator>,
+    regression: Option<LongstaffSchwartz>
+}
+
+impl HestonFactory {
+    /// Creates a factory with fixed parameters for each underlying
+    pub fn new(correlation_substep: usize, path_substep: f64,
+        number_of_paths: usize, parameters: HashMap<String, HestonParameters>)
+        -> HestonFactory {
+
+        HestonFactory { correlation_substep: correlation_substep,
+            path_substep: path_substep, number_of_paths: number_of_paths,
+            parameters: parameters, calibrator: None, regression: None }
+    }
+
+    /// Creates a factory that calibrates the parameters for each underlying
+    /// to its vol surface
+    pub fn new_calibrated(correlation_substep: usize, path_substep: f64,
+        number_of_paths: usize, calibrator: HestonCalibrator)
+        -> HestonFactory {
+
+        HestonFactory { correlation_substep: correlation_substep,
+            path_substep: path_substep, number_of_paths: number_of_paths,
+            parameters: HashMap::new(), calibrator: Some(calibrator),
+            regression: None }
+    }
+
+    /// Makes the models fit the exercise boundaries of any instruments with
+    /// early exercise on a separate set of calibration paths, using the
+    /// given basis functions. See BlackDiffusionFactory::new_with_regression.
+    pub fn set_regression(&mut self, regression: LongstaffSchwartz) {
+        self.regression = Some(regression);
+    }
+}
+
+impl MonteCarloModelFactory for HestonFactory {
+
+    fn factory(&self, timeline: &MonteCarloTimeline,
+        context: Box<BumpablePricingContext>)
+        -> Result<Box<MonteCarloModel>, qm::Error> {
+
+        let model = Heston::new(timeline, context, &self.parameters,
+            self.calibrator.clone(), self.correlation_substep,
+            self.path_substep, self.number_of_paths,
+            self.regression.clone())?;
+        Ok(Box::new(model))
+    }
+}
+
+/// A Monte-Carlo implementation of the Heston stochastic volatility model.
+/// Unlike BlackDiffusion or LocalVol, the forward smile is driven by the
+/// vol of variance, which makes it a better choice for forward-skew
+/// sensitive products such as cliquets and forward-starting options.
+///
+/// As with the other models, we evolve the log of the underlier divided by
+/// its forward, which is a martingale. The variance is evolved with the
+/// full truncation scheme of Lord, Koekkoek and van Dijk, where negative
+/// variances are allowed, but are treated as zero in the drift and
+/// diffusion terms. This is biased for large time steps, so use a small
+/// path_substep.
+///
+/// The gaussians driving the underliers come from fetch_correlated_gaussians
+/// so they are correlated with each other as defined by the market data.
+/// The gaussians driving the variances are a second, independent, set of
+/// draws with the same correlation structure, which are then mixed with the
+/// spot gaussians according to the spot/vol correlation. The independent
+/// draws are kept, so both sets can be correlated again after a bump.
+pub struct Heston {
+    observations: Vec<DateDayFraction>,
+    flows: Vec<Arc<Instrument>>,
+    context: Box<BumpablePricingContext>,
+    key: HashMap<String, usize>,
+    instruments: Vec<RcInstrument>,
+    substepping: Vec<usize>,
+    parameters: Vec<HestonParameters>,
+    calibrator: Option<HestonCalibrator>,
+    calibrated: Vec<bool>,
+    correlation_substep: usize,
+    spot_draws: Array3<f64>,
+    variance_draws: Array3<f64>,
+    spot_gaussians: Array3<f64>,
+    variance_gaussians: Array3<f64>,
+    paths: Array3<f64>,
+    exercise: Option<ExerciseCalibration>
+}
+
+impl Heston {
+
+    /// Create a new Heston model, given a timeline to define the
+    /// instrument(s) we want to price, a context to define the market data,
+    /// and a count of paths. Underlyings with no entry in the parameters
+    /// are calibrated using the calibrator, which must then be supplied.
+    ///
+    /// The correlation_substep and path_substep parameters are the same as
+    /// for BlackDiffusion. If regression settings are supplied, we also
+    /// generate the calibration paths for Longstaff-Schwartz.
+    pub fn new(timeline: &MonteCarloTimeline,
+        context: Box<BumpablePricingContext>,
+        parameters: &HashMap<String, HestonParameters>,
+        calibrator: Option<HestonCalibrator>,
+        correlation_substep: usize,
+        path_substep: f64,
+        n_paths: usize,
+        regression: Option<LongstaffSchwartz>)
+        -> Result<Heston, qm::Error> {
+
+        // key to all observations and all instruments
+        let mut observations = Vec::new();
+        let mut key = HashMap::new();
+        let mut instruments = Vec::new();
+        for (asset, obs) in timeline.observations().iter() {
+
+            // at present, we just insist that all observations are the same
+            if observations.is_empty() {
+                observations = obs.to_vec();
+            }
+
+            // store the assets in the order we are told about them
+            key.insert(asset.id().to_string(), instruments.len());
+            instruments.push(asset.clone());
+        }
+
+        // find or calibrate the parameters for each asset
+        let hwm = observations.last().ok_or_else(|| qm::Error::new(
+            "No observations"))?.date();
+        let mut asset_parameters = Vec::with_capacity(instruments.len());
+        let mut calibrated = Vec::with_capacity(instruments.len());
+        for asset in instruments.iter() {
+            let id = asset.id();
+            if let Some(p) = parameters.get(id) {
+                asset_parameters.push(*p);
+                calibrated.push(false);
+            } else if let Some(ref c) = calibrator {
+                asset_parameters.push(calibrate(c, asset.instrument(),
+                    context.as_pricing_context(), hwm)?);
+                calibrated.push(true);
+            } else {
+                return Err(qm::Error::new(&format!(
+                    "No Heston parameters or calibrator for '{}'", id)))
+            }
+        }
+
+        let substepping = calculate_substepping(&observations,
+            context.as_pricing_context(), &instruments, path_substep)?;
+
+        // the variance draws must be independent of the spot ones, so we
+        // fetch them as if for the next n_paths paths
+        let spot_draws = fetch_gaussians(instruments.len(), &substepping,
+            0, n_paths);
+        let variance_draws = fetch_gaussians(instruments.len(),
+            &substepping, n_paths, n_paths);
+        let (spot_gaussians, _) = correlate_gaussians(
+            context.as_pricing_context(), &instruments, &observations,
+            correlation_substep, CorrelationRepair::Error,
+            &substepping, &spot_draws, 1)?;
+        let (variance_gaussians, _) = correlate_gaussians(
+            context.as_pricing_context(), &instruments, &observations,
+            correlation_substep, CorrelationRepair::Error,
+            &substepping, &variance_draws, 1)?;
+
+        let paths = fetch_heston_paths(&observations, &spot_gaussians,
+            &variance_gaussians, context.as_pricing_context(), &instruments,
+            &asset_parameters, &substepping)?;
+
+        // the calibration paths follow on from the pricing paths, so their
+        // random numbers are independent
+        let exercise = match regression {
+            None => None,
+            Some(settings) => {
+                let n_calibration = settings.calibration_paths();
+                let calibration_spot_gaussians = fetch_correlated_gaussians(
+                    context.as_pricing_context(), &instruments,
+                    &observations, correlation_substep,
+                    CorrelationRepair::Error, &substepping, 2 * n_paths,
+                    n_calibration)?;
+                let calibration_variance_gaussians =
+                    fetch_correlated_gaussians(context.as_pricing_context(),
+                    &instruments, &observations, correlation_substep,
+                    CorrelationRepair::Error, &substepping,
+                    2 * n_paths + n_calibration, n_calibration)?;
+                let calibration_paths = fetch_heston_paths(&observations,
+                    &calibration_spot_gaussians,
+                    &calibration_variance_gaussians,
+                    context.as_pricing_context(), &instruments,
+                    &asset_parameters, &substepping)?;
+                Some(ExerciseCalibration::new(settings, key.clone(),
+                    calibration_paths))
+            }
+        };
+
+        Ok(Heston {
+            observations: observations,
+            flows: timeline.flows().to_vec(),
+            context: context,
+            key: key,
+            instruments: instruments,
+            substepping: substepping,
+            parameters: asset_parameters,
+            calibrator: calibrator,
+            calibrated: calibrated,
+            correlation_substep: correlation_substep,
+            spot_draws: spot_draws,
+            variance_draws: variance_draws,
+            spot_gaussians: spot_gaussians,
+            variance_gaussians: variance_gaussians,
+            paths: paths,
+            exercise: exercise })
+    }
+
+    /// The Heston parameters in use for the given underlying
+    pub fn parameters(&self, id: &str) -> Option<&HestonParameters> {
+        self.key.get(id).map(|asset| &self.parameters[*asset])
+    }
+
+    /// Refetch a single asset, recalibrating if necessary
+    pub fn refetch(&mut self, id: &str, bumped: bool,
+        saved: &mut SavedHeston) -> Result<bool, qm::Error> {
+
+        // if nothing was bumped, there is nothing to do
+        if !bumped {
+            return Ok(false)
+        }
+
+        let asset = *self.key.get(id).ok_or_else(|| qm::Error::new(
+            "Failed to find asset"))?;
+
+        // save the old parameters and path, then replace them
+        if self.calibrated[asset] {
+            if let Some(ref c) = self.calibrator {
+                let hwm = self.observations.last().unwrap().date();
+                let params = calibrate(c, self.instruments[asset].instrument(),
+                    self.context.as_pricing_context(), hwm)?;
+                saved.parameters.entry(asset).or_insert(
+                    self.parameters[asset]);
+                self.parameters[asset] = params;
+            }
+        }
+
+        let path = self.paths.subview_mut(Axis(2), asset);
+        saved.paths.insert(asset, path.to_owned());
+        fetch_heston_path(self.instruments[asset].instrument(),
+            self.context.as_pricing_context(), &self.parameters[asset],
+            &self.observations,
+            self.spot_gaussians.subview(Axis(2), asset),
+            self.variance_gaussians.subview(Axis(2), asset),
+            &self.substepping,
+            path)?;
+
+        Ok(true)
+    }
+
+    /// Correlate both sets of gaussians again after a correlation bump, and
+    /// refetch all the paths. Nothing needs doing unless both assets are in
+    /// the model.
+    pub fn recorrelate(&mut self, first: &str, second: &str, bumped: bool,
+        saved: &mut SavedHeston) -> Result<bool, qm::Error> {
+
+        if !bumped || !self.key.contains_key(first)
+            || !self.key.contains_key(second) {
+            return Ok(false)
+        }
+
+        let (spot_gaussians, _) = correlate_gaussians(
+            self.context.as_pricing_context(), &self.instruments,
+            &self.observations, self.correlation_substep,
+            CorrelationRepair::Error, &self.substepping, &self.spot_draws,
+            1)?;
+        let (variance_gaussians, _) = correlate_gaussians(
+            self.context.as_pricing_context(), &self.instruments,
+            &self.observations, self.correlation_substep,
+            CorrelationRepair::Error, &self.substepping, &self.variance_draws,
+            1)?;
+        let old_spot = ::std::mem::replace(&mut self.spot_gaussians,
+            spot_gaussians);
+        let old_variance = ::std::mem::replace(&mut self.variance_gaussians,
+            variance_gaussians);
+        if saved.gaussians.is_none() {
+            saved.gaussians = Some((old_spot, old_variance));
+        }
+
+        for asset in 0..self.instruments.len() {
+            let path = self.paths.subview_mut(Axis(2), asset);
+            saved.paths.entry(asset).or_insert_with(|| path.to_owned());
+            fetch_heston_path(self.instruments[asset].instrument(),
+                self.context.as_pricing_context(), &self.parameters[asset],
+                &self.observations,
+                self.spot_gaussians.subview(Axis(2), asset),
+                self.variance_gaussians.subview(Axis(2), asset),
+                &self.substepping,
+                path)?;
+        }
+
+        Ok(true)
+    }
+}
+
+fn calibrate(calibrator: &HestonCalibrator, instrument: &Instrument,
+    context: &PricingContext, hwm: Date)
+    -> Result<HestonParameters, qm::Error> {
+
+    let forward = context.forward_curve(instrument, hwm)?;
+    let surface = context.vol_surface(instrument, forward.clone(), hwm)?;
+    calibrator.calibrate(&*surface, &*forward)
+}
+
+/// Evolves the paths of all the assets under Heston, returning a tensor
+/// indexed by path, then observation, then asset.
+fn fetch_heston_paths(
+    observations: &[DateDayFraction],
+    spot_gaussians: &Array3<f64>,
+    variance_gaussians: &Array3<f64>,
+    context: &PricingContext,
+    instruments: &Vec<RcInstrument>,
+    parameters: &[HestonParameters],
+    substepping: &[usize]) -> Result<Array3<f64>, qm::Error> {
+
+    let n_paths = spot_gaussians.shape()[0];
+    let n_assets = instruments.len();
+    let mut paths = Array3::<f64>::zeros(
+        (n_paths, observations.len(), n_assets));
+    for (asset, path) in paths.axis_iter_mut(Axis(2)).enumerate() {
+        fetch_heston_path(instruments[asset].instrument(), context,
+            &parameters[asset], observations,
+            spot_gaussians.subview(Axis(2), asset),
+            variance_gaussians.subview(Axis(2), asset),
+            substepping, path)?;
+    }
+    Ok(paths)
+}
+
+/// Evolves the paths of a single asset under Heston, writing the values on
+/// each observation date into the path, which is indexed by path then
+/// observation.
+pub fn fetch_heston_path(instrument: &Instrument, context: &PricingContext,
+    parameters: &HestonParameters, observations: &[DateDayFraction],
+    spot_gaussians: ArrayView2<f64>, variance_gaussians: ArrayView2<f64>,
+    substepping: &[usize],
+    mut path: ArrayViewMut2<f64>) -> Result<(), qm::Error> {
+
+    let n_obs = observations.len();
+    assert!(n_obs > 0);  // otherwise we should not be evolving this asset
+    assert_eq!(spot_gaussians.shape(), variance_gaussians.shape());
+    assert_eq!(path.shape()[0], spot_gaussians.shape()[0]);
+    assert_eq!(substepping.len(), n_obs);
+    let n_steps: usize = substepping.iter().sum();
+    assert!(spot_gaussians.shape()[1] >= n_steps);
+
+    // Fetch the market data we need. The vol surface is only used to
+    // define the vol times.
+    let hwm = observations.last().unwrap().date();
+    let forward_curve = context.forward_curve(instrument, hwm)?;
+    let vol_surface = context.vol_surface(instrument, forward_curve.clone(),
+        hwm)?;
+
+    let mut forwards = Vec::with_capacity(n_obs);
+    for obs in observations.iter() {
+        forwards.push(forward_curve.forward(obs.date())?);
+    }
+
+    let start = DateDayFraction::new(context.spot_date(), 0.0);
+    let mut time_steps = Vec::with_capacity(n_steps);
+    let mut prev_time = 0.0;
+    for date in substep_dates(start, observations, substepping).iter() {
+        let time = vol_surface.vol_time(*date)?;
+        time_steps.push((time - prev_time).max(0.0));
+        prev_time = time;
+    }
+
+    let kappa = parameters.mean_reversion;
+    let theta = parameters.long_term_variance;
+    let xi = parameters.vol_of_variance;
+    let rho = parameters.correlation;
+    let rho_perp = (1.0 - rho * rho).sqrt();
+
+    // for each of the paths
+    for ((spot_g, var_g), ref mut one_path) in spot_gaussians.outer_iter()
+        .zip(variance_gaussians.outer_iter()).zip(path.outer_iter_mut()) {
+
+        // walk along each path, in log space for the underlier
+        let mut x = 0.0;
+        let mut v = parameters.initial_variance;
+        let mut g = 0;	// index into the gaussians and time steps
+        for i in 0..n_obs {
+            for _ in 0..substepping[i] {
+                let dt = time_steps[g];
+                let v_plus = v.max(0.0);
+                let sqrt_var = (v_plus * dt).sqrt();
+                let z_spot = spot_g[g];
+                let z_var = rho * z_spot + rho_perp * var_g[g];
+                x += sqrt_var * z_spot - 0.5 * v_plus * dt;
+                v += kappa * (theta - v_plus) * dt + xi * sqrt_var * z_var;
+                g += 1;
+            }
+
+            one_path[i] = x.exp() * forwards[i];
+        }
+    }
+
+    Ok(())
+}
+
+impl MonteCarloModel for Heston {
+
+    fn as_mc_context(&self) -> &MonteCarloContext { self }
+    fn as_pricing_context(&self) -> &PricingContext {
+        self.context.as_pricing_context()
+    }
+    fn clone_context(&self) -> Box<BumpablePricingContext> {
+        self.context.clone_box()
+    }
+    fn clone_box(&self) -> Box<MonteCarloModel> {
+        Box::new(Heston {
+            observations: self.observations.clone(),
+            flows: self.flows.clone(),
+            context: self.context.clone_box(),
+            key: self.key.clone(),
+            instruments: self.instruments.clone(),
+            substepping: self.substepping.clone(),
+            parameters: self.parameters.clone(),
+            calibrator: self.calibrator.clone(),
+            calibrated: self.calibrated.clone(),
+            correlation_substep: self.correlation_substep,
+            spot_draws: self.spot_draws.clone(),
+            variance_draws: self.variance_draws.clone(),
+            spot_gaussians: self.spot_gaussians.clone(),
+            variance_gaussians: self.variance_gaussians.clone(),
+            paths: self.paths.clone(),
+            exercise: self.exercise.clone() })
+    }
+    fn as_bumpable(&self) -> &Bumpable { self }
+    fn as_mut_bumpable(&mut self) -> &mut Bumpable { self }
+}
+
+impl MonteCarloContext for Heston {
+
+    fn paths(&self, instrument: &Arc<Instrument>)
+        -> Result<ArrayView2<f64>, qm::Error> {
+
+        let id = instrument.id().to_string();
+        let asset = self.key.get(&id).ok_or_else(|| qm::Error::new(
+            &format!("Heston does not know about '{}'", id)))?;
+        Ok(self.paths.subview(Axis(2), *asset))
+    }
+
+    fn evaluate_flows_by_path(&self, quantities: ArrayView2<f64>)
+        -> Result<Array1<f64>, qm::Error> {
+
+        // Heston is a non-stochastic-rate model
+        evaluate_deterministic_rate_flows(&self.flows, quantities,
+            self.paths.shape()[0], self.context.as_pricing_context())
+    }
+
+    fn evaluate_exercise_by_path(&self, exercisable: &MonteCarloExercisable)
+        -> Result<Array1<f64>, qm::Error> {
+
+        evaluate_deterministic_rate_exercise(&self.flows, exercisable, self,
+            self.paths.shape()[0], self.context.as_pricing_context(),
+            self.exercise.as_ref())
+    }
+}
+
+impl Bumpable for Heston {
+
+    fn bump_spot(&mut self, id: &str, bump: &BumpSpot, any_saved: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_spot(id, bump,
+            &mut *saved.saved_data)?;
+        self.refetch(id, bumped, saved)
+    }
+
+    fn bump_yield(&mut self, credit_id: &str, bump: &BumpYield,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_yield(credit_id, bump,
+            &mut *saved.saved_data)?;
+
+        // we have to copy these ids to avoid a tangle with borrowing
+        let v = self.forward_id_by_credit_id(credit_id)?.to_vec();
+        for id in v.iter() {
+            self.refetch(&id, bumped, saved)?;
+        }
+
+        Ok(bumped)
+    }
+
+    fn bump_borrow(&mut self, id: &str, bump: &BumpYield,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_borrow(id, bump,
+            &mut *saved.saved_data)?;
+        self.refetch(id, bumped, saved)
+    }
+
+    fn bump_divs(&mut self, id: &str, bump: &BumpDivs,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_divs(id, bump,
+            &mut *saved.saved_data)?;
+        self.refetch(id, bumped, saved)
+    }
+
+    fn bump_vol(&mut self, id: &str, bump: &BumpVol,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_vol(id, bump,
+            &mut *saved.saved_data)?;
+
+        // fixed Heston parameters do not depend on the vol surface, so a
+        // vol bump only changes the paths of calibrated assets
+        let drives = self.key.get(id).map_or(true,
+            |asset| self.calibrated[*asset]);
+        self.refetch(id, bumped && drives, saved)
+    }
+
+    fn bump_correlation(&mut self, first: &str, second: &str,
+        bump: &BumpCorrelation, any_saved: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_correlation(first,
+            second, bump, &mut *saved.saved_data)?;
+        self.recorrelate(first, second, bumped, saved)
+    }
+
+    fn bump_discount_date(&mut self, replacement: Date,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        self.context.as_mut_bumpable().bump_discount_date(replacement,
+            &mut *saved.saved_data)
+        // the data stored here does not depend on the discount date
+    }
+
+    fn new_saveable(&self) -> Box<Saveable> {
+        Box::new(SavedHeston::new(
+            self.context.as_bumpable().new_saveable()))
+    }
+
+    fn forward_id_by_credit_id(&self, credit_id: &str)
+        -> Result<&[String], qm::Error> {
+        self.context.as_bumpable().forward_id_by_credit_id(credit_id)
+    }
+
+    fn restore(&mut self, any_saved: &Saveable) -> Result<(), qm::Error> {
+
+        if let Some(saved)
+            = any_saved.as_any().downcast_ref::<SavedHeston>()  {
+
+            // first restore the underlying market data and cached curves
+            self.context.as_mut_bumpable().restore(&*saved.saved_data)?;
+
+            // now restore any calibrated parameters, gaussians and paths
+            if let Some((ref spot, ref variance)) = saved.gaussians {
+                self.spot_gaussians.assign(spot);
+                self.variance_gaussians.assign(variance);
+            }
+            for (asset, parameters) in saved.parameters.iter() {
+                self.parameters[*asset] = *parameters;
+            }
+            for (asset, paths) in saved.paths.iter() {
+                let mut dest = self.paths.subview_mut(Axis(2), *asset);
+                dest.assign(paths);
+            }
+            Ok(())
+
+        } else {
+            Err(qm::Error::new("Mismatching save space for restore"))
+        }
+    }
+}
+
+fn to_saved(saveable: &mut Saveable)
+    -> Result<&mut SavedHeston, qm::Error> {
+
+    if let Some(saved)
+        = saveable.as_mut_any().downcast_mut::<SavedHeston>()  {
+        Ok(saved)
+    } else {
+        Err(qm::Error::new("Mismatching save space for Heston"))
+    }
+}
+
+/// Save space for Heston to use during bumping
+pub struct SavedHeston {
+    saved_data: Box<Saveable>,
+    parameters: HashMap<usize, HestonParameters>,
+    gaussians: Option<(Array3<f64>, Array3<f64>)>,
+    paths: HashMap<usize, Array2<f64>>
+}
+
+impl SavedHeston {
+
+    /// Creates an empty set of paths and parameters, which can be used for
+    /// saving state so it can be restored after a bump
+    pub fn new(saved_data: Box<Saveable>) -> SavedHeston {
+        SavedHeston {
+            saved_data: saved_data,
+            parameters: HashMap::new(),
+            gaussians: None,
+            paths: HashMap::new() }
+    }
+}
+
+impl Saveable for SavedHeston {
+    fn as_any(&self) -> &Any { self }
+    fn as_mut_any(&mut self) -> &mut Any { self }
+
+    fn clear(&mut self) {
+        self.saved_data.clear();
+        self.parameters.clear();
+        self.gaussians = None;
+        self.paths.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::numerics::approx_eq;
+    use dates::datetime::DateTime;
+    use dates::datetime::TimeOfDay;
+    use data::fixings::FixingTable;
+    use data::forward::DriftlessForward;
+    use data::volsurface::VolByProbability;
+    use data::volsurface::DivAssumptions;
+    use data::volsmile::CubicSplineSmile;
+    use dates::calendar::Calendar;
+    use dates::calendar::WeekdayCalendar;
+    use instruments::Priceable;
+    use pricers::PricerFactory;
+    use pricers::montecarlo::MonteCarloPricerFactory;
+    use risk::marketdata::MarketData;
+    use risk::marketdata::tests::sample_market_data;
+    use risk::marketdata::tests::create_sample_flat_vol;
+    use risk::marketdata::tests::sample_european;
+    use risk::marketdata::tests::sample_currency;
+    use risk::marketdata::tests::sample_equity;
+    use risk::marketdata::tests::sample_settlement;
+    use instruments::options::AmericanOption;
+    use instruments::options::PutOrCall;
+    use instruments::options::OptionSettlement;
+    use dates::rules::BusinessDays;
+    use pricers::pde::PdePricerFactory;
+    use pricers::pde::PdeDynamics;
+    use math::regression::PolynomialBasis;
+
+    fn sample_fixings() -> Arc<FixingTable> {
+        let today = Date::from_ymd(2017, 01, 02);
+        Arc::new(FixingTable::new(today, &[
+            ("BP.L", &[
+            (DateTime::new(today - 7, TimeOfDay::Close), 102.0)])]).unwrap())
+    }
+
+    #[test]
+    fn heston_with_low_vol_of_variance_matches_black() {
+        let black76 = Black76::new().unwrap();
+        let params = HestonParameters::new(0.09, 1.5, 0.09, 1e-4, -0.5)
+            .unwrap();
+        let (df, forward, time): (f64, f64, f64) = (0.95, 100.0, 1.5);
+        for strike in [60.0, 90.0, 100.0, 110.0, 160.0].iter() {
+            let sqrt_var = (0.09 * time).sqrt();
+            let call = params.call_price(df, forward, *strike, time).unwrap();
+            let put = params.put_price(df, forward, *strike, time).unwrap();
+            assert_approx(call,
+                black76.call_price(df, forward, *strike, sqrt_var), 1e-3);
+            assert_approx(put,
+                black76.put_price(df, forward, *strike, sqrt_var), 1e-3);
+        }
+    }
+
+    #[test]
+    fn heston_characteristic_function_and_skew() {
+        let params = HestonParameters::new(0.04, 2.0, 0.06, 0.6, -0.7)
+            .unwrap();
+
+        // the forward is a martingale
+        let phi = params.characteristic_function(Complex::new(0.0, -1.0),
+            2.0);
+        assert_approx(phi.re, 1.0, 1e-12);
+        assert_approx(phi.im, 0.0, 1e-12);
+
+        // negative correlation makes downside options more expensive than
+        // the equivalent upside ones
+        let black76 = Black76::new().unwrap();
+        let (forward, time): (f64, f64) = (100.0, 1.0);
+        let implied = |strike: f64| {
+            let put = params.put_price(1.0, forward, strike, time).unwrap();
+            black76.put_implied_sqrt_variance(1.0, forward, strike, put)
+                .unwrap() / time.sqrt()
+        };
+        let low = implied(80.0);
+        let atm = implied(100.0);
+        let high = implied(125.0);
+        assert!(low > atm && atm > high, "low={} atm={} high={}",
+            low, atm, high);
+    }
+
+    #[test]
+    fn heston_calibration_round_trip() {
+
+        // build a surface from the implied vols of a known Heston model
+        let actual = HestonParameters::new(0.05, 1.5, 0.08, 0.6, -0.65)
+            .unwrap();
+        let black76 = Black76::new().unwrap();
+        let calendar = WeekdayCalendar();
+        let base_date = Date::from_ymd(2016, 12, 30);
+        let base = DateDayFraction::new(base_date, 0.2);
+        let forward = 100.0;
+        let mut smiles = Vec::new();
+        for days in [91, 182, 365, 730].iter() {
+            let pillar = DateDayFraction::new(base_date + *days, 0.2);
+            let time = calendar.year_fraction(base, pillar);
+            let mut points = Vec::new();
+            for i in 0..13 {
+                let strike = 40.0 + 15.0 * (i as f64);
+                let put = actual.put_price(1.0, forward, strike, time)
+                    .unwrap();
+                let sqrt_var = black76.put_implied_sqrt_variance(1.0,
+                    forward, strike, put).unwrap();
+                points.push((strike, sqrt_var / time.sqrt()));
+            }
+            smiles.push((pillar, CubicSplineSmile::new(&points).unwrap()));
+        }
+        let surface = VolByProbability::new(&smiles, Box::new(calendar),
+            base, Box::new(DriftlessForward::new(forward)),
+            DivAssumptions::NoCashDivs).unwrap();
+
+        let initial = HestonParameters::new(0.09, 1.0, 0.09, 0.5, 0.0)
+            .unwrap();
+        let calibrator = HestonCalibrator::new(&[91, 182, 365, 730],
+            &[-1.5, -0.75, 0.0, 0.75, 1.5], initial).unwrap();
+        let fitted = calibrator.calibrate(&surface,
+            &DriftlessForward::new(forward)).unwrap();
+        assert_approx(fitted.correlation(), actual.correlation(), 0.05);
+        assert_approx(fitted.initial_variance(), actual.initial_variance(),
+            0.005);
+
+        // the fitted model reprices the surface away from the quotes
+        let date = DateDayFraction::new(base_date + 273, 0.2);
+        let time = surface.vol_time(date).unwrap();
+        for strike in [75.0, 95.0, 115.0].iter() {
+            let vol = (surface.variance(date, *strike).unwrap() / time).sqrt();
+            let put = fitted.put_price(1.0, forward, *strike, time).unwrap();
+            let fitted_vol = black76.put_implied_sqrt_variance(1.0, forward,
+                *strike, put).unwrap() / time.sqrt();
+            assert_approx(fitted_vol, vol, 0.002);
+        }
+    }
+
+    #[test]
+    fn heston_monte_carlo_matches_characteristic_function() {
+
+        // Work out the inputs to the European price, using the analytic
+        // price of the sample European under the flat 30% vol surface to
+        // imply the discount factor.
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let european = sample_european();
+        let strike = 100.0;
+        let expiry = DateTime::new(Date::from_ymd(2018, 06, 01),
+            TimeOfDay::Close);
+        let equity = sample_equity(Arc::new(sample_currency(2)), 2);
+        let expiry_time = equity.time_to_day_fraction(expiry).unwrap();
+        let forward = market_data.forward_curve(&equity, expiry.date())
+            .unwrap().forward(expiry.date()).unwrap();
+        let time = create_sample_flat_vol().vol_time(expiry_time).unwrap();
+        let black76 = Black76::new().unwrap();
+        let black = european.price(&*market_data).unwrap();
+        let df = black / black76.call_price(1.0, forward, strike,
+            (0.09 * time).sqrt());
+
+        let params = HestonParameters::new(0.09, 2.0, 0.09, 0.5, -0.7)
+            .unwrap();
+        let expected = params.call_price(df, forward, strike, time).unwrap();
+
+        let mut parameters = HashMap::new();
+        parameters.insert("BP.L".to_string(), params);
+        let model_factory = Box::new(HestonFactory::new(20, 0.005, 100000,
+            parameters));
+        let factory = MonteCarloPricerFactory::new(model_factory);
+        let mut pricer = factory.new(european, sample_fixings(),
+            market_data).unwrap();
+        let price = pricer.price().unwrap();
+        assert_approx(price, expected, 0.3);
+
+        // bumps are applied to the paths, and restored afterwards
+        let mut save = pricer.as_bumpable().new_saveable();
+        let bump = BumpSpot::new_relative(0.01);
+        let bumped = pricer.as_mut_bumpable().bump_spot(
+            "BP.L", &bump, &mut *save).unwrap();
+        assert!(bumped);
+        let bumped_price = pricer.price().unwrap();
+        assert!(bumped_price > price + 0.4 && bumped_price < price + 0.9);
+
+        pricer.as_mut_bumpable().restore(&*save).unwrap();
+        save.clear();
+        assert_approx(pricer.price().unwrap(), price, 1e-12);
+
+        // the parameters are fixed, so the vol surface does not drive the
+        // paths, and a vol bump has no effect
+        let bump = BumpVol::new_flat_additive(0.01);
+        let bumped = pricer.as_mut_bumpable().bump_vol(
+            "BP.L", &bump, &mut *save).unwrap();
+        assert!(!bumped);
+        assert_approx(pricer.price().unwrap(), price, 1e-12);
+    }
+
+    #[test]
+    fn heston_american_matches_pde() {
+
+        // with almost no vol of variance, Heston is the same as the flat
+        // 30% vol of the sample market data, so we can compare with the PDE
+        let currency = Arc::new(sample_currency(2));
+        let equity = Arc::new(sample_equity(currency, 2));
+        let weekly = BusinessDays::new_back(Arc::new(WeekdayCalendar()), 5);
+        let american: Arc<Instrument> = Arc::new(AmericanOption::new(
+            "SampleAmerican", "OPT", equity, sample_settlement(2),
+            DateTime::new(Date::from_ymd(2017, 01, 02), TimeOfDay::Open),
+            DateTime::new(Date::from_ymd(2018, 06, 01), TimeOfDay::Close),
+            110.0, PutOrCall::Put, OptionSettlement::Cash, &weekly).unwrap());
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+
+        let pde_factory = PdePricerFactory::new(PdeDynamics::Black, 200, 400,
+            5.0).unwrap();
+        let pde = pde_factory.new(american.clone(), sample_fixings(),
+            market_data.clone()).unwrap();
+        let pde_price = pde.price().unwrap();
+
+        let mut parameters = HashMap::new();
+        parameters.insert("BP.L".to_string(),
+            HestonParameters::new(0.09, 1.5, 0.09, 1e-4, -0.5).unwrap());
+        let mut model_factory = HestonFactory::new(20, 0.01, 30000,
+            parameters);
+        model_factory.set_regression(LongstaffSchwartz::new(
+            Arc::new(PolynomialBasis::new(3)), 20000).unwrap());
+        let factory = MonteCarloPricerFactory::new(Box::new(model_factory));
+        let mc = factory.new_mc_pricer(american, sample_fixings(),
+            market_data).unwrap();
+        let result = mc.mc_result().unwrap();
+
+        // The boundary is fitted out of sample, so there is no upward bias
+        // from foresight. There is a slight downward bias from the weekly
+        // exercise and the suboptimal boundary, which is small compared to
+        // the statistical error.
+        assert!((result.mean() - pde_price).abs()
+            < 4.0 * result.standard_error(), "mc={} pde={} standard_error={}",
+            result.mean(), pde_price, result.standard_error());
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+}
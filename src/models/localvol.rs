@@ -0,0 +1,781 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::collections::HashMap;
+use ndarray::Array1;
+use ndarray::Array2;
+use ndarray::Array3;
+use ndarray::ArrayView2;
+use ndarray::ArrayViewMut2;
+use ndarray::Axis;
+use core::qm;
+use instruments::Instrument;
+use instruments::MonteCarloContext;
+use instruments::MonteCarloExercisable;
+use instruments::PricingContext;
+use instruments::RcInstrument;
+use risk::BumpablePricingContext;
+use risk::Bumpable;
+use risk::Saveable;
+use data::bumpspot::BumpSpot;
+use data::bumpyield::BumpYield;
+use data::bumpdivs::BumpDivs;
+use data::bumpvol::BumpVol;
+use data::bumpcorrelation::BumpCorrelation;
+use data::forward::Forward;
+use data::volsurface::VolSurface;
+use data::volsurface::VolQuoting;
+use models::MonteCarloModel;
+use models::MonteCarloTimeline;
+use models::MonteCarloModelFactory;
+use models::evaluate_deterministic_rate_flows;
+use models::evaluate_deterministic_rate_exercise;
+use models::longstaffschwartz::LongstaffSchwartz;
+use models::longstaffschwartz::ExerciseCalibration;
+use models::substep_dates;
+use models::blackdiffusion::calculate_substepping;
+use models::blackdiffusion::fetch_correlated_gaussians;
+use models::blackdiffusion::fetch_gaussians;
+use models::blackdiffusion::correlate_gaussians;
+use models::blackdiffusion::CorrelationRepair;
+use dates::datetime::DateDayFraction;
+use dates::Date;
+
+/// Number of log-strike nodes in the local variance table for each step
+const GRID_NODES: usize = 101;
+
+/// Half-width of the local variance table, in standard deviations of the
+/// at-the-money total variance to the end of the step
+const GRID_STDEVS: f64 = 6.0;
+
+/// Lower limit on the Dupire denominator, which goes to zero or negative
+/// where the vol surface admits butterfly arbitrage
+const MIN_DENOMINATOR: f64 = 0.05;
+
+/// The LocalVolFactory creates a LocalVol model, given the timeline of the
+/// product(s) to value, and the market data to value it with. The parameters
+/// are the same as for the BlackDiffusionFactory, though the path_substep
+/// matters rather more here, as the local vol is only sampled at the start
+/// of each step.
+pub struct LocalVolFactory {
+    /// Substep size in business days for correlation calculation
+    correlation_substep: usize,
+    path_substep: f64,
+    number_of_paths: usize,
+    regression: Option<LongstaffSchwartz>
+}
+
+impl LocalVolFactory {
+    pub fn new(correlation_substep: usize, path_substep: f64,
+        number_of_paths: usize) -> LocalVolFactory {
+
+        LocalVolFactory { correlation_substep: correlation_substep,
+            path_substep: path_substep, number_of_paths: number_of_paths,
+            regression: None }
+    }
+
+    /// Creates a factory whose models fit exercise boundaries on separate
+    /// calibration paths. See BlackDiffusionFactory::new_with_regression.
+    pub fn new_with_regression(correlation_substep: usize, path_substep: f64,
+        number_of_paths: usize, regression: LongstaffSchwartz)
+        -> LocalVolFactory {
+
+        LocalVolFactory { correlation_substep: correlation_substep,
+            path_substep: path_substep, number_of_paths: number_of_paths,
+            regression: Some(regression) }
+    }
+}
+
+impl MonteCarloModelFactory for LocalVolFactory {
+
+    fn factory(&self, timeline: &MonteCarloTimeline,
+        context: Box<BumpablePricingContext>)
+        -> Result<Box<MonteCarloModel>, qm::Error> {
+
+        let model = LocalVol::new(timeline, context,
+            self.correlation_substep, self.path_substep, self.number_of_paths,
+            self.regression.clone())?;
+        Ok(Box::new(model))
+    }
+}
+
+/// A Local Vol model represents the SDE:
+///
+///  dS/S = mu(t) dt + sigma(S, t) dW
+///
+/// where sigma(S, t) is chosen using Dupire's formula so that the model
+/// reprices every European option on the vol surface. This makes it a good
+/// choice for skew-dependent products such as digitals and barriers, though
+/// its forward smile dynamics are known to be unrealistic.
+///
+/// As with BlackDiffusion, we work with the underlier scaled by the forward,
+/// so that it is a martingale. The state is the log of this ratio, which is
+/// also the log-moneyness y = ln(K/F) used to look up the vol surface. For
+/// each time step we tabulate the local variance over the step on a grid of
+/// y, from the difference in total implied variance w(T, y) at the two ends
+/// of the step, divided by the Dupire denominator:
+///
+///  1 - y/w dw/dy + 1/4 (-1/4 - 1/w + y^2/w^2) (dw/dy)^2 + 1/2 d2w/dy2
+///
+/// evaluated at the end of the step. Derivatives in y are taken by finite
+/// differences on the grid. Paths are then evolved by log-Euler steps, with
+/// the local variance interpolated at the start of each step.
+///
+/// Only log-normally quoted vol surfaces without displacement are supported
+/// at present. Cash dividends would need jumps in the state between steps.
+pub struct LocalVol {
+    observations: Vec<DateDayFraction>,
+    flows: Vec<Arc<Instrument>>,
+    context: Box<BumpablePricingContext>,
+    key: HashMap<String, usize>,
+    instruments: Vec<RcInstrument>,
+    substepping: Vec<usize>,
+    correlation_substep: usize,
+    gaussians: Array3<f64>,
+    correlated_gaussians: Array3<f64>,
+    paths: Array3<f64>,
+    exercise: Option<ExerciseCalibration>
+}
+
+impl LocalVol {
+
+    /// Create a new LocalVol model, given a timeline to define the
+    /// instrument(s) we want to price, a context to define the market data,
+    /// and a count of paths.
+    ///
+    /// The correlation_substep and path_substep parameters are the same as
+    /// for BlackDiffusion. The substepping is calculated only once, from the
+    /// at-the-money variances, so that it is the same for all risks. If
+    /// regression settings are supplied, we also generate the calibration
+    /// paths for Longstaff-Schwartz.
+    pub fn new(timeline: &MonteCarloTimeline,
+        context: Box<BumpablePricingContext>,
+        correlation_substep: usize,
+        path_substep: f64,
+        n_paths: usize,
+        regression: Option<LongstaffSchwartz>)
+        -> Result<LocalVol, qm::Error> {
+
+        // key to all observations and all instruments
+        let mut observations = Vec::new();
+        let mut key = HashMap::new();
+        let mut instruments = Vec::new();
+        for (asset, obs) in timeline.observations().iter() {
+
+            // at present, we just insist that all observations are the same
+            if observations.is_empty() {
+                observations = obs.to_vec();
+            }
+
+            // store the assets in the order we are told about them
+            key.insert(asset.id().to_string(), instruments.len());
+            instruments.push(asset.clone());
+        }
+
+        let substepping = calculate_substepping(&observations,
+            context.as_pricing_context(), &instruments, path_substep)?;
+
+        // keep the independent gaussians, in case correlations are bumped
+        let gaussians = fetch_gaussians(instruments.len(), &substepping,
+            0, n_paths);
+        let (correlated_gaussians, _) = correlate_gaussians(
+            context.as_pricing_context(), &instruments, &observations,
+            correlation_substep, CorrelationRepair::Error,
+            &substepping, &gaussians, 1)?;
+
+        let paths = fetch_local_vol_paths(&observations,
+            &correlated_gaussians, context.as_pricing_context(),
+            &instruments, &substepping)?;
+
+        let exercise = match regression {
+            None => None,
+            Some(settings) => {
+                let calibration_gaussians = fetch_correlated_gaussians(
+                    context.as_pricing_context(), &instruments,
+                    &observations, correlation_substep,
+                    CorrelationRepair::Error, &substepping, n_paths,
+                    settings.calibration_paths())?;
+                let calibration_paths = fetch_local_vol_paths(&observations,
+                    &calibration_gaussians, context.as_pricing_context(),
+                    &instruments, &substepping)?;
+                Some(ExerciseCalibration::new(settings, key.clone(),
+                    calibration_paths))
+            }
+        };
+
+        Ok(LocalVol {
+            observations: observations,
+            flows: timeline.flows().to_vec(),
+            context: context,
+            key: key,
+            instruments: instruments,
+            substepping: substepping,
+            correlation_substep: correlation_substep,
+            gaussians: gaussians,
+            correlated_gaussians: correlated_gaussians,
+            paths: paths,
+            exercise: exercise })
+    }
+
+    /// Correlate the gaussians again after a correlation bump, and refetch
+    /// all the paths, as for BlackDiffusion
+    pub fn recorrelate(&mut self, first: &str, second: &str, bumped: bool,
+        saved: &mut SavedLocalVol) -> Result<bool, qm::Error> {
+
+        if !bumped || !self.key.contains_key(first)
+            || !self.key.contains_key(second) {
+            return Ok(false)
+        }
+
+        let (correlated_gaussians, _) = correlate_gaussians(
+            self.context.as_pricing_context(), &self.instruments,
+            &self.observations, self.correlation_substep,
+            CorrelationRepair::Error, &self.substepping, &self.gaussians,
+            1)?;
+        let old = ::std::mem::replace(&mut self.correlated_gaussians,
+            correlated_gaussians);
+        if saved.correlated_gaussians.is_none() {
+            saved.correlated_gaussians = Some(old);
+        }
+
+        for asset in 0..self.instruments.len() {
+            let path = self.paths.subview_mut(Axis(2), asset);
+            saved.paths.entry(asset).or_insert_with(|| path.to_owned());
+            fetch_local_vol_path(self.instruments[asset].instrument(),
+                self.context.as_pricing_context(), &self.observations,
+                self.correlated_gaussians.subview(Axis(2), asset),
+                &self.substepping,
+                path)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Refetch a single asset
+    pub fn refetch(&mut self, id: &str, bumped: bool,
+        saved: &mut SavedLocalVol) -> Result<bool, qm::Error> {
+
+        // if nothing was bumped, there is nothing to do
+        if !bumped {
+            return Ok(false)
+        }
+
+        let id_string = id.to_string();
+        if let Some(asset) = self.key.get(&id_string) {
+
+            // save the old path then replace it
+            let path = self.paths.subview_mut(Axis(2), *asset);
+            saved.paths.insert(*asset, path.to_owned());
+            fetch_local_vol_path(self.instruments[*asset].instrument(),
+                self.context.as_pricing_context(), &self.observations,
+                self.correlated_gaussians.subview(Axis(2), *asset),
+                &self.substepping,
+                path)?;
+
+        } else {
+            return Err(qm::Error::new("Failed to find asset"))
+        }
+
+        Ok(true)
+    }
+}
+
+/// Evolves the paths of all the assets under local vol, returning a tensor
+/// indexed by path, then observation, then asset.
+pub fn fetch_local_vol_paths(
+    observations: &[DateDayFraction],
+    correlated_gaussians: &Array3<f64>,
+    context: &PricingContext,
+    instruments: &Vec<RcInstrument>,
+    substepping: &[usize]) -> Result<Array3<f64>, qm::Error> {
+
+    let n_paths = correlated_gaussians.shape()[0];
+    let n_assets = instruments.len();
+    let mut paths = Array3::<f64>::zeros(
+        (n_paths, observations.len(), n_assets));
+    for ((asset, gaussians), path) in
+        instruments.iter().zip(
+        correlated_gaussians.axis_iter(Axis(2))).zip(
+        paths.axis_iter_mut(Axis(2))) {
+
+        fetch_local_vol_path(asset.instrument(), context, observations,
+            gaussians, substepping, path)?;
+    }
+    Ok(paths)
+}
+
+/// Evolves the paths of a single asset under local vol, writing the values
+/// on each observation date into the path, which is indexed by path then
+/// observation.
+pub fn fetch_local_vol_path(instrument: &Instrument, context: &PricingContext,
+    observations: &[DateDayFraction], correlated_gaussians: ArrayView2<f64>,
+    substepping: &[usize],
+    mut path: ArrayViewMut2<f64>) -> Result<(), qm::Error> {
+
+    let n_obs = observations.len();
+    assert!(n_obs > 0);  // otherwise we should not be evolving this asset
+    let shape = correlated_gaussians.shape();
+    assert_eq!(shape.len(), 2);
+    assert_eq!(path.shape()[0], shape[0]);
+    assert_eq!(substepping.len(), n_obs);
+    let n_steps: usize = substepping.iter().sum();
+    assert!(shape[1] >= n_steps);
+
+    // Fetch the market data we need
+    let hwm = observations.last().unwrap().date();
+    let forward_curve = context.forward_curve(instrument, hwm)?;
+    let vol_surface = context.vol_surface(instrument, forward_curve.clone(),
+        hwm)?;
+
+    let quoting = vol_surface.vol_quoting();
+    if quoting != VolQuoting::LogNormal {
+        return Err(qm::Error::new(&format!("LocalVol requires a log-normal \
+            vol surface for '{}', but it is quoted as {:?}",
+            instrument.id(), quoting)))
+    }
+
+    let mut forwards = Vec::with_capacity(n_obs);
+    for obs in observations.iter() {
+        if vol_surface.displacement(obs.date())? != 0.0 {
+            return Err(qm::Error::new(&format!("LocalVol does not support \
+                displaced vol surfaces, as used for cash dividends on '{}'",
+                instrument.id())))
+        }
+        forwards.push(forward_curve.forward(obs.date())?);
+    }
+
+    // Tabulate the local variance for every step along the timeline
+    let start = DateDayFraction::new(context.spot_date(), 0.0);
+    let step_dates = substep_dates(start, observations, substepping);
+    let mut steps = Vec::with_capacity(n_steps);
+    let mut prev: Option<DateDayFraction> = None;
+    for date in step_dates.iter() {
+        steps.push(LocalVarianceStep::new(&*vol_surface, &*forward_curve,
+            prev, *date)?);
+        prev = Some(*date);
+    }
+
+    // for each of the paths
+    for (ref gaussians, ref mut one_path) in
+        correlated_gaussians.outer_iter().zip(path.outer_iter_mut()) {
+
+        // walk along each path in log space
+        let mut x = 0.0;
+        let mut g = 0;	// index into the gaussians and steps
+        for i in 0..n_obs {
+            for _ in 0..substepping[i] {
+                let var = steps[g].variance(x);
+                x += var.sqrt() * gaussians[g] - 0.5 * var;
+                g += 1;
+            }
+
+            one_path[i] = x.exp() * forwards[i];
+        }
+    }
+
+    Ok(())
+}
+
+/// Local variance over a single time step, tabulated by log-moneyness
+/// relative to the forward at the end of the step. This is shared by any
+/// pricer that needs Dupire local vols, such as the PDE pricer.
+pub struct LocalVarianceStep {
+    y_min: f64,
+    dy: f64,
+    variances: Vec<f64>
+}
+
+impl LocalVarianceStep {
+    /// Tabulates the local variance over the step from the given date (or
+    /// from the start of the surface if None) to the given date.
+    pub fn new(surface: &VolSurface, forward: &Forward,
+        from: Option<DateDayFraction>, to: DateDayFraction)
+        -> Result<LocalVarianceStep, qm::Error> {
+
+        // centre the grid on the forward, wide enough to cover the paths
+        let fwd = forward.forward(to.date())?;
+        let atm_var = surface.variance(to, fwd)?;
+        let half_width = GRID_STDEVS * atm_var.max(1e-8).sqrt();
+        let y_min = -half_width;
+        let dy = 2.0 * half_width / ((GRID_NODES - 1) as f64);
+        let ys: Vec<f64> = (0..GRID_NODES)
+            .map(|i| y_min + dy * (i as f64)).collect();
+
+        // total variances at the end of the step
+        let strikes: Vec<f64> = ys.iter().map(|y| fwd * y.exp()).collect();
+        let mut w = vec![0.0; GRID_NODES];
+        surface.variances(to, &strikes, &mut w)?;
+
+        // and at the start, at the same log-moneyness. The first step
+        // integrates all the variance from the start of the surface.
+        let mut w_prev = vec![0.0; GRID_NODES];
+        if let Some(from) = from {
+            let prev_fwd = forward.forward(from.date())?;
+            let strikes: Vec<f64> = ys.iter()
+                .map(|y| prev_fwd * y.exp()).collect();
+            surface.variances(from, &strikes, &mut w_prev)?;
+        }
+
+        let mut variances = Vec::with_capacity(GRID_NODES);
+        for i in 0..GRID_NODES {
+            // central differences, using the neighbouring node at the edges
+            let j = i.max(1).min(GRID_NODES - 2);
+            let w_y = (w[j + 1] - w[j - 1]) / (2.0 * dy);
+            let w_yy = (w[j + 1] - 2.0 * w[j] + w[j - 1]) / (dy * dy);
+
+            let wi = w[i].max(1e-12);
+            let y = ys[i];
+            let denominator = 1.0 - y / wi * w_y
+                + 0.25 * (-0.25 - 1.0 / wi + y * y / (wi * wi)) * w_y * w_y
+                + 0.5 * w_yy;
+
+            let dw = (w[i] - w_prev[i]).max(0.0);
+            variances.push(dw / denominator.max(MIN_DENOMINATOR));
+        }
+
+        Ok(LocalVarianceStep { y_min: y_min, dy: dy, variances: variances })
+    }
+
+    /// Linearly interpolates the local variance at the given log-moneyness,
+    /// extrapolating flat
+    pub fn variance(&self, y: f64) -> f64 {
+        let n = self.variances.len();
+        let pos = (y - self.y_min) / self.dy;
+        if !(pos > 0.0) {
+            self.variances[0]
+        } else if pos >= (n - 1) as f64 {
+            self.variances[n - 1]
+        } else {
+            let i = pos.floor() as usize;
+            let frac = pos - i as f64;
+            self.variances[i] * (1.0 - frac) + self.variances[i + 1] * frac
+        }
+    }
+}
+
+impl MonteCarloModel for LocalVol {
+
+    fn as_mc_context(&self) -> &MonteCarloContext { self }
+    fn as_pricing_context(&self) -> &PricingContext {
+        self.context.as_pricing_context()
+    }
+    fn clone_context(&self) -> Box<BumpablePricingContext> {
+        self.context.clone_box()
+    }
+    fn clone_box(&self) -> Box<MonteCarloModel> {
+        Box::new(LocalVol {
+            observations: self.observations.clone(),
+            flows: self.flows.clone(),
+            context: self.context.clone_box(),
+            key: self.key.clone(),
+            instruments: self.instruments.clone(),
+            substepping: self.substepping.clone(),
+            correlation_substep: self.correlation_substep,
+            gaussians: self.gaussians.clone(),
+            correlated_gaussians: self.correlated_gaussians.clone(),
+            paths: self.paths.clone(),
+            exercise: self.exercise.clone() })
+    }
+    fn as_bumpable(&self) -> &Bumpable { self }
+    fn as_mut_bumpable(&mut self) -> &mut Bumpable { self }
+}
+
+impl MonteCarloContext for LocalVol {
+
+    fn paths(&self, instrument: &Arc<Instrument>)
+        -> Result<ArrayView2<f64>, qm::Error> {
+
+        let id = instrument.id().to_string();
+        let asset = self.key.get(&id).ok_or_else(|| qm::Error::new(
+            &format!("LocalVol does not know about '{}'", id)))?;
+        Ok(self.paths.subview(Axis(2), *asset))
+    }
+
+    fn evaluate_flows_by_path(&self, quantities: ArrayView2<f64>)
+        -> Result<Array1<f64>, qm::Error> {
+
+        // LocalVol is a non-stochastic-rate model
+        evaluate_deterministic_rate_flows(&self.flows, quantities,
+            self.paths.shape()[0], self.context.as_pricing_context())
+    }
+
+    fn evaluate_exercise_by_path(&self, exercisable: &MonteCarloExercisable)
+        -> Result<Array1<f64>, qm::Error> {
+
+        evaluate_deterministic_rate_exercise(&self.flows, exercisable, self,
+            self.paths.shape()[0], self.context.as_pricing_context(),
+            self.exercise.as_ref())
+    }
+}
+
+impl Bumpable for LocalVol {
+
+    fn bump_spot(&mut self, id: &str, bump: &BumpSpot, any_saved: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_spot(id, bump,
+            &mut *saved.saved_data)?;
+        self.refetch(id, bumped, saved)
+    }
+
+    fn bump_yield(&mut self, credit_id: &str, bump: &BumpYield,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_yield(credit_id, bump,
+            &mut *saved.saved_data)?;
+
+        // we have to copy these ids to avoid a tangle with borrowing
+        let v = self.forward_id_by_credit_id(credit_id)?.to_vec();
+        for id in v.iter() {
+            self.refetch(&id, bumped, saved)?;
+        }
+
+        Ok(bumped)
+    }
+
+    fn bump_borrow(&mut self, id: &str, bump: &BumpYield,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_borrow(id, bump,
+            &mut *saved.saved_data)?;
+        self.refetch(id, bumped, saved)
+    }
+
+    fn bump_divs(&mut self, id: &str, bump: &BumpDivs,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_divs(id, bump,
+            &mut *saved.saved_data)?;
+        self.refetch(id, bumped, saved)
+    }
+
+    fn bump_vol(&mut self, id: &str, bump: &BumpVol,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_vol(id, bump,
+            &mut *saved.saved_data)?;
+        self.refetch(id, bumped, saved)
+    }
+
+    fn bump_correlation(&mut self, first: &str, second: &str,
+        bump: &BumpCorrelation, any_saved: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        let bumped = self.context.as_mut_bumpable().bump_correlation(first,
+            second, bump, &mut *saved.saved_data)?;
+        self.recorrelate(first, second, bumped, saved)
+    }
+
+    fn bump_discount_date(&mut self, replacement: Date,
+        any_saved: &mut Saveable) -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        self.context.as_mut_bumpable().bump_discount_date(replacement,
+            &mut *saved.saved_data)
+        // the data stored here does not depend on the discount date
+    }
+
+    fn new_saveable(&self) -> Box<Saveable> {
+        Box::new(SavedLocalVol::new(
+            self.context.as_bumpable().new_saveable()))
+    }
+
+    fn forward_id_by_credit_id(&self, credit_id: &str)
+        -> Result<&[String], qm::Error> {
+        self.context.as_bumpable().forward_id_by_credit_id(credit_id)
+    }
+
+    fn restore(&mut self, any_saved: &Saveable) -> Result<(), qm::Error> {
+
+        if let Some(saved)
+            = any_saved.as_any().downcast_ref::<SavedLocalVol>()  {
+
+            // first restore the underlying market data and cached curves
+            self.context.as_mut_bumpable().restore(&*saved.saved_data)?;
+
+            // now restore any cached gaussians and paths
+            if let Some(ref gaussians) = saved.correlated_gaussians {
+                self.correlated_gaussians.assign(gaussians);
+            }
+            for (asset, paths) in saved.paths.iter() {
+                let mut dest = self.paths.subview_mut(Axis(2), *asset);
+                dest.assign(paths);
+            }
+            Ok(())
+
+        } else {
+            Err(qm::Error::new("Mismatching save space for restore"))
+        }
+    }
+}
+
+fn to_saved(saveable: &mut Saveable)
+    -> Result<&mut SavedLocalVol, qm::Error> {
+
+    if let Some(saved)
+        = saveable.as_mut_any().downcast_mut::<SavedLocalVol>()  {
+        Ok(saved)
+    } else {
+        Err(qm::Error::new("Mismatching save space for local vol"))
+    }
+}
+
+/// Save space for LocalVol to use during bumping
+pub struct SavedLocalVol {
+    saved_data: Box<Saveable>,
+    correlated_gaussians: Option<Array3<f64>>,
+    paths: HashMap<usize, Array2<f64>>
+}
+
+impl SavedLocalVol {
+
+    /// Creates an empty set of paths, which can be used for saving state
+    /// so it can be restored after a bump
+    pub fn new(saved_data: Box<Saveable>) -> SavedLocalVol {
+        SavedLocalVol {
+            saved_data: saved_data,
+            correlated_gaussians: None,
+            paths: HashMap::new() }
+    }
+}
+
+impl Saveable for SavedLocalVol {
+    fn as_any(&self) -> &Any { self }
+    fn as_mut_any(&mut self) -> &mut Any { self }
+
+    fn clear(&mut self) {
+        self.saved_data.clear();
+        self.correlated_gaussians = None;
+        self.paths.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::numerics::approx_eq;
+    use dates::datetime::DateTime;
+    use dates::datetime::TimeOfDay;
+    use data::fixings::FixingTable;
+    use instruments::Priceable;
+    use instruments::options::SpotStartingEuropean;
+    use instruments::options::PutOrCall;
+    use instruments::options::OptionSettlement;
+    use pricers::PricerFactory;
+    use pricers::montecarlo::MonteCarloPricerFactory;
+    use pricers::pde::PdePricerFactory;
+    use pricers::pde::PdeDynamics;
+    use risk::Pricer;
+    use risk::marketdata::MarketData;
+    use risk::marketdata::tests::sample_market_data;
+    use risk::marketdata::tests::sample_market_data_with_vol;
+    use risk::marketdata::tests::create_sample_skewed_vol;
+    use risk::marketdata::tests::sample_european;
+    use risk::marketdata::tests::sample_currency;
+    use risk::marketdata::tests::sample_settlement;
+    use risk::marketdata::tests::sample_equity;
+
+    fn sample_fixings() -> Arc<FixingTable> {
+        let today = Date::from_ymd(2017, 01, 02);
+        Arc::new(FixingTable::new(today, &[
+            ("BP.L", &[
+            (DateTime::new(today - 7, TimeOfDay::Close), 102.0)])]).unwrap())
+    }
+
+    fn sample_european_with_strike(strike: f64, put_or_call: PutOrCall)
+        -> Arc<SpotStartingEuropean> {
+        let expiry = DateTime::new(
+            Date::from_ymd(2018, 06, 01), TimeOfDay::Close);
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let equity = Arc::new(sample_equity(currency, 2));
+        Arc::new(SpotStartingEuropean::new("SampleEquity", "OPT",
+            equity, settlement, expiry, strike, put_or_call,
+            OptionSettlement::Cash).unwrap())
+    }
+
+    /// Returns the Monte-Carlo price and its standard error
+    fn local_vol_price(instrument: Arc<Instrument>,
+        market_data: Arc<MarketData>)
+        -> (f64, f64) {
+        let model_factory = Box::new(LocalVolFactory::new(20, 0.005, 100000));
+        let factory = MonteCarloPricerFactory::new(model_factory);
+        let pricer = factory.new_mc_pricer(instrument, sample_fixings(),
+            market_data).unwrap();
+        let result = pricer.mc_result().unwrap();
+        (result.mean(), result.standard_error())
+    }
+
+    #[test]
+    fn local_vol_on_flat_surface_matches_black() {
+
+        // with no skew, local vol is the same as black diffusion
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let n_paths = 100000;
+        let model_factory = Box::new(LocalVolFactory::new(20, 0.01, n_paths));
+        let factory = MonteCarloPricerFactory::new(model_factory);
+        let mut pricer = factory.new_mc_pricer(sample_european(),
+            sample_fixings(), market_data).unwrap();
+        let mut save = pricer.as_bumpable().new_saveable();
+
+        // the statistical error dominates the discretisation error
+        let unbumped_price = pricer.price().unwrap();
+        let standard_error = pricer.mc_result().unwrap().standard_error();
+        assert!((unbumped_price - 16.710717400832973).abs()
+            < 4.0 * standard_error, "price={} standard_error={}",
+            unbumped_price, standard_error);
+
+        // bumps are applied to the paths, and restored afterwards
+        let bump = BumpSpot::new_relative(0.01);
+        let bumped = pricer.as_mut_bumpable().bump_spot(
+            "BP.L", &bump, &mut *save).unwrap();
+        assert!(bumped);
+        let bumped_price = pricer.price().unwrap();
+        assert_approx(bumped_price - unbumped_price, 0.633187905501792, 0.01);
+
+        pricer.as_mut_bumpable().restore(&*save).unwrap();
+        save.clear();
+        let price = pricer.price().unwrap();
+        assert_approx(price, unbumped_price, 1e-12);
+
+        let bump = BumpVol::new_flat_additive(0.01);
+        let bumped = pricer.as_mut_bumpable().bump_vol(
+            "BP.L", &bump, &mut *save).unwrap();
+        assert!(bumped);
+        let bumped_price = pricer.price().unwrap();
+        assert_approx(bumped_price - unbumped_price, 0.429105019892687, 0.01);
+    }
+
+    #[test]
+    fn local_vol_reprices_skewed_europeans() {
+
+        // The reference prices come from a PDE on the same local vol
+        // surface, whose discretisation error is far smaller than the
+        // statistical error of the Monte-Carlo. Both are close to the
+        // analytic prices, which use the implied vol at each strike.
+        let pde_factory = PdePricerFactory::new(PdeDynamics::LocalVol, 200,
+            400, 5.0).unwrap();
+        let cases = [
+            (70.0, PutOrCall::Put),
+            (100.0, PutOrCall::Call),
+            (130.0, PutOrCall::Call)];
+
+        for &(strike, put_or_call) in cases.iter() {
+            let european = sample_european_with_strike(strike, put_or_call);
+            let market_data = Arc::new(sample_market_data_with_vol(
+                create_sample_skewed_vol()));
+            let analytic = european.price(&*market_data).unwrap();
+            let pde = pde_factory.new(european.clone(), sample_fixings(),
+                market_data.clone()).unwrap().price().unwrap();
+            let (price, standard_error) = local_vol_price(european,
+                market_data);
+            assert!((price - pde).abs() < 4.0 * standard_error,
+                "strike={} mc={} pde={} standard_error={}", strike, price,
+                pde, standard_error);
+            assert_approx(pde, analytic, 0.02 * analytic.max(2.0));
+        }
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+}
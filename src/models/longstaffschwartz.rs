@@ -0,0 +1,526 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::collections::HashMap;
+use core::qm;
+use instruments::Instrument;
+use instruments::MonteCarloContext;
+use instruments::MonteCarloExercisable;
+use math::regression::RegressionBasis;
+use math::regression::PolynomialBasis;
+use math::regression::least_squares;
+use ndarray::Array1;
+use ndarray::Array2;
+use ndarray::Array3;
+use ndarray::ArrayView2;
+use ndarray::Axis;
+
+/// Settings for the Longstaff-Schwartz regression service, which values
+/// instruments with early exercise in Monte-Carlo. The basis functions are
+/// applied to the regression variables supplied by each instrument. The
+/// exercise boundary is fitted on a separate set of calibration paths,
+/// which avoids the upward bias of deciding exercise with foresight of the
+/// paths being valued.
+#[derive(Clone)]
+pub struct LongstaffSchwartz {
+    basis: Arc<RegressionBasis>,
+    calibration_paths: usize
+}
+
+impl LongstaffSchwartz {
+    /// Creates the settings, given the basis functions to regress on and
+    /// the number of calibration paths to fit the boundary with
+    pub fn new(basis: Arc<RegressionBasis>, calibration_paths: usize)
+        -> Result<LongstaffSchwartz, qm::Error> {
+
+        if calibration_paths == 0 {
+            return Err(qm::Error::new("Longstaff-Schwartz needs at least \
+                one calibration path"))
+        }
+        Ok(LongstaffSchwartz { basis: basis,
+            calibration_paths: calibration_paths })
+    }
+
+    pub fn basis(&self) -> &Arc<RegressionBasis> { &self.basis }
+    pub fn calibration_paths(&self) -> usize { self.calibration_paths }
+}
+
+/// The calibration side of the regression service, owned by a model. It
+/// holds the calibration paths, which are generated once when the model is
+/// created and never refetched, and a cache of the exercise boundaries
+/// fitted on them, keyed by the id of the instrument.
+///
+/// The cached boundaries are reused whenever the model is bumped for risk.
+/// This is partly an optimisation, but mainly it keeps the greeks smooth, as
+/// refitting the boundary would add regression noise to every bumped price.
+/// As the boundary is optimal, small changes to it only have a second-order
+/// effect on the price. Call clear if the market moves far enough that the
+/// boundary should be refitted.
+///
+/// The cache is behind a mutex, so the model can value blocks of paths on
+/// several threads. The first thread to need a boundary fits it, and the
+/// others wait for it, so each boundary is fitted only once.
+pub struct ExerciseCalibration {
+    settings: LongstaffSchwartz,
+    key: HashMap<String, usize>,
+    paths: Array3<f64>,
+    boundaries: Mutex<HashMap<String, Arc<ExerciseBoundary>>>
+}
+
+impl ExerciseCalibration {
+    /// Creates the calibration, given the calibration paths, indexed by
+    /// path, observation and asset, and the key from asset id to the index
+    /// in the paths. These must be laid out the same as the pricing paths.
+    pub fn new(settings: LongstaffSchwartz, key: HashMap<String, usize>,
+        paths: Array3<f64>) -> ExerciseCalibration {
+
+        ExerciseCalibration { settings: settings, key: key, paths: paths,
+            boundaries: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn settings(&self) -> &LongstaffSchwartz { &self.settings }
+
+    /// Returns the exercise boundary for the given instrument, fitting it
+    /// on the calibration paths if it is not already in the cache. The
+    /// unit_values are the discounted values of one unit of each flow.
+    pub fn boundary(&self, exercisable: &MonteCarloExercisable,
+        unit_values: &[f64]) -> Result<Arc<ExerciseBoundary>, qm::Error> {
+
+        let id = exercisable.id().to_string();
+        let mut boundaries = self.boundaries.lock().map_err(|_| qm::Error::new(
+            "Exercise boundary cache is unusable after a panic"))?;
+        if let Some(boundary) = boundaries.get(&id) {
+            return Ok(boundary.clone())
+        }
+
+        let boundary = Arc::new(ExerciseBoundary::fit(exercisable, self,
+            self.paths.shape()[0], unit_values, &*self.settings.basis)?);
+        boundaries.insert(id, boundary.clone());
+        Ok(boundary)
+    }
+
+    /// Discards all the cached boundaries, so they are refitted on demand
+    pub fn clear(&self) {
+        // a poisoned cache is cleared anyway
+        match self.boundaries.lock() {
+            Ok(mut boundaries) => boundaries.clear(),
+            Err(poisoned) => poisoned.into_inner().clear()
+        }
+    }
+}
+
+/// A copy shares any boundaries fitted so far, as they are immutable
+impl Clone for ExerciseCalibration {
+    fn clone(&self) -> ExerciseCalibration {
+        let boundaries = match self.boundaries.lock() {
+            Ok(boundaries) => boundaries.clone(),
+            Err(poisoned) => poisoned.into_inner().clone()
+        };
+        ExerciseCalibration { settings: self.settings.clone(),
+            key: self.key.clone(), paths: self.paths.clone(),
+            boundaries: Mutex::new(boundaries) }
+    }
+}
+
+/// The calibration paths are presented to the instrument through the same
+/// interface as the pricing paths, but they cannot be used for valuation.
+impl MonteCarloContext for ExerciseCalibration {
+
+    fn paths(&self, instrument: &Arc<Instrument>)
+        -> Result<ArrayView2<f64>, qm::Error> {
+
+        let id = instrument.id();
+        let asset = self.key.get(id).ok_or_else(|| qm::Error::new(
+            &format!("Calibration paths do not include '{}'", id)))?;
+        Ok(self.paths.subview(Axis(2), *asset))
+    }
+
+    fn evaluate_flows_by_path(&self, _quantities: ArrayView2<f64>)
+        -> Result<Array1<f64>, qm::Error> {
+        Err(qm::Error::new("Calibration paths cannot be used for valuation"))
+    }
+
+    fn evaluate_exercise_by_path(&self, _exercisable: &MonteCarloExercisable)
+        -> Result<Array1<f64>, qm::Error> {
+        Err(qm::Error::new("Calibration paths cannot be used for valuation"))
+    }
+}
+
+/// How the continuation value is estimated at one exercise opportunity
+#[derive(Clone, Debug)]
+enum Continuation {
+    /// Coefficients of the basis functions
+    Regression(Vec<f64>),
+
+    /// The regression failed, for example because all the paths start at
+    /// the same spot, so use the average over the in-the-money paths
+    Average(f64),
+
+    /// No calibration path was in the money, so never exercise
+    NoExercise
+}
+
+/// An exercise boundary fitted by Longstaff-Schwartz regression, in the
+/// form of an estimate of the continuation value at each exercise
+/// opportunity other than expiry.
+pub struct ExerciseBoundary {
+    continuations: Vec<Continuation>
+}
+
+impl ExerciseBoundary {
+    /// Fits the boundary by working backwards through the exercise
+    /// opportunities on the paths in the given context. At each one, we
+    /// regress the discounted value of the future cashflows on each in-the-
+    /// money path against the basis functions, and exercise on the paths
+    /// where the exercise value exceeds the fitted continuation value. Only
+    /// paths that are in the money take part in the regression, as that is
+    /// where the decision matters.
+    pub fn fit(exercisable: &MonteCarloExercisable,
+        context: &MonteCarloContext, n_paths: usize, unit_values: &[f64],
+        basis: &RegressionBasis) -> Result<ExerciseBoundary, qm::Error> {
+
+        let n_exercises = exercisable.exercise_count();
+        let mut exercise = ExerciseValues::new(exercisable, n_paths,
+            unit_values.len(), basis)?;
+        let mut values = vec![0.0; n_paths];
+        let mut continuations = vec![Continuation::NoExercise;
+            n_exercises - 1];
+
+        for i in (0..n_exercises).rev() {
+            exercise.fetch(exercisable, context, i, unit_values)?;
+
+            // at expiry, there is no choice
+            if i == n_exercises - 1 {
+                values.copy_from_slice(&exercise.values);
+                continue
+            }
+
+            // regress the continuation values on the in-the-money paths
+            let itm = exercise.in_the_money();
+            if itm.is_empty() {
+                continue
+            }
+            let mut design = Array2::zeros((itm.len(), exercise.n_basis));
+            let mut targets = Array1::zeros(itm.len());
+            for (row, path) in itm.iter().enumerate() {
+                basis.evaluate(exercise.variables.row(*path).as_slice()
+                    .unwrap(), design.row_mut(row).as_slice_mut().unwrap());
+                targets[row] = values[*path];
+            }
+            let continuation = match least_squares(design.view(),
+                targets.view()) {
+                Some(coefficients) => Continuation::Regression(coefficients),
+                None => Continuation::Average(
+                    targets.scalar_sum() / itm.len() as f64)
+            };
+
+            // exercise where that is worth more than continuing
+            for (row, path) in itm.iter().enumerate() {
+                let estimate = match continuation {
+                    Continuation::Regression(ref c) => design.row(row).iter()
+                        .zip(c.iter()).fold(0.0, |acc, (b, c)| acc + b * c),
+                    Continuation::Average(average) => average,
+                    Continuation::NoExercise => ::std::f64::INFINITY
+                };
+                if exercise.values[*path] > estimate {
+                    values[*path] = exercise.values[*path];
+                }
+            }
+            continuations[i] = continuation;
+        }
+
+        Ok(ExerciseBoundary { continuations: continuations })
+    }
+
+    /// Values the instrument on the paths in the given context, exercising
+    /// on each path at the first opportunity where the exercise value
+    /// exceeds the continuation value estimated by this boundary. Returns
+    /// the discounted value on each path.
+    pub fn value(&self, exercisable: &MonteCarloExercisable,
+        context: &MonteCarloContext, n_paths: usize, unit_values: &[f64],
+        basis: &RegressionBasis) -> Result<Array1<f64>, qm::Error> {
+
+        let n_exercises = exercisable.exercise_count();
+        if n_exercises != self.continuations.len() + 1 {
+            return Err(qm::Error::new("Exercise boundary does not match \
+                the exercise opportunities of the instrument"))
+        }
+
+        let mut exercise = ExerciseValues::new(exercisable, n_paths,
+            unit_values.len(), basis)?;
+        let mut row = vec![0.0; exercise.n_basis];
+        let mut values = Array1::zeros(n_paths);
+
+        // Working backwards means the last decision written for each path
+        // is the first exercise
+        for i in (0..n_exercises).rev() {
+            exercise.fetch(exercisable, context, i, unit_values)?;
+            if i == n_exercises - 1 {
+                values.as_slice_mut().unwrap()
+                    .copy_from_slice(&exercise.values);
+                continue
+            }
+
+            for path in exercise.in_the_money() {
+                let estimate = match self.continuations[i] {
+                    Continuation::Regression(ref c) => {
+                        basis.evaluate(exercise.variables.row(path).as_slice()
+                            .unwrap(), &mut row);
+                        row.iter().zip(c.iter())
+                            .fold(0.0, |acc, (b, c)| acc + b * c)
+                    },
+                    Continuation::Average(average) => average,
+                    Continuation::NoExercise => ::std::f64::INFINITY
+                };
+                if exercise.values[path] > estimate {
+                    values[path] = exercise.values[path];
+                }
+            }
+        }
+
+        Ok(values)
+    }
+}
+
+/// Working space for the exercise values and regression variables at one
+/// exercise opportunity
+struct ExerciseValues {
+    quantities: Array2<f64>,
+    variables: Array2<f64>,
+    values: Vec<f64>,
+    n_basis: usize
+}
+
+impl ExerciseValues {
+    fn new(exercisable: &MonteCarloExercisable, n_paths: usize,
+        n_flows: usize, basis: &RegressionBasis)
+        -> Result<ExerciseValues, qm::Error> {
+
+        if exercisable.exercise_count() == 0 {
+            return Err(qm::Error::new("No exercise opportunities"))
+        }
+        let n_variables = exercisable.regression_variable_count();
+        Ok(ExerciseValues {
+            quantities: Array2::zeros((n_paths, n_flows)),
+            variables: Array2::zeros((n_paths, n_variables)),
+            values: vec![0.0; n_paths],
+            n_basis: basis.size(n_variables) })
+    }
+
+    /// Fetches the discounted value of exercising on each path, and the
+    /// regression variables
+    fn fetch(&mut self, exercisable: &MonteCarloExercisable,
+        context: &MonteCarloContext, exercise: usize, unit_values: &[f64])
+        -> Result<(), qm::Error> {
+
+        self.quantities.fill(0.0);
+        exercisable.exercise_quantities(context, exercise,
+            self.quantities.view_mut())?;
+        for (value, row) in self.values.iter_mut()
+            .zip(self.quantities.outer_iter()) {
+            *value = row.iter().zip(unit_values.iter())
+                .fold(0.0, |acc, (q, v)| acc + q * v);
+        }
+
+        self.variables.fill(0.0);
+        exercisable.regression_variables(context, exercise,
+            self.variables.view_mut())
+    }
+
+    fn in_the_money(&self) -> Vec<usize> {
+        (0..self.values.len()).filter(|p| self.values[*p] > 0.0).collect()
+    }
+}
+
+/// Values an instrument with early exercise on the given pricing paths. If
+/// there is a calibration, the boundary is taken from it, fitting it if
+/// necessary. Otherwise, we fit a quadratic boundary on the pricing paths
+/// themselves, which is quicker but biased slightly high. Returns the
+/// discounted value on each path.
+pub fn evaluate_exercise(exercisable: &MonteCarloExercisable,
+    context: &MonteCarloContext, n_paths: usize, unit_values: &[f64],
+    calibration: Option<&ExerciseCalibration>)
+    -> Result<Array1<f64>, qm::Error> {
+
+    match calibration {
+        Some(calibration) => {
+            let boundary = calibration.boundary(exercisable, unit_values)?;
+            boundary.value(exercisable, context, n_paths, unit_values,
+                &*calibration.settings.basis)
+        },
+        None => {
+            let basis = PolynomialBasis::new(2);
+            let boundary = ExerciseBoundary::fit(exercisable, context,
+                n_paths, unit_values, &basis)?;
+            boundary.value(exercisable, context, n_paths, unit_values, &basis)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::numerics::approx_eq;
+    use math::regression::FunctionBasis;
+    use dates::Date;
+    use dates::datetime::DateTime;
+    use dates::datetime::TimeOfDay;
+    use dates::calendar::WeekdayCalendar;
+    use dates::rules::BusinessDays;
+    use data::fixings::FixingTable;
+    use data::bumpspot::BumpSpot;
+    use instruments::options::BermudanOption;
+    use instruments::options::SpotStartingEuropean;
+    use instruments::options::PutOrCall;
+    use instruments::options::OptionSettlement;
+    use pricers::PricerFactory;
+    use pricers::pde::PdePricerFactory;
+    use pricers::pde::PdeDynamics;
+    use pricers::montecarlo::MonteCarloPricerFactory;
+    use models::blackdiffusion::BlackDiffusionFactory;
+    use risk::Pricer;
+    use risk::marketdata::MarketData;
+    use risk::marketdata::tests::sample_market_data;
+    use risk::marketdata::tests::sample_currency;
+    use risk::marketdata::tests::sample_settlement;
+    use risk::marketdata::tests::sample_equity;
+
+    fn sample_bermudan() -> Arc<BermudanOption> {
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let equity = Arc::new(sample_equity(currency, 2));
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let expiry = DateTime::new(Date::from_ymd(2018, 06, 01),
+            TimeOfDay::Close);
+        let first = DateTime::new(Date::from_ymd(2017, 01, 02),
+            TimeOfDay::Open);
+        let monthly = BusinessDays::new_back(calendar, 21);
+        Arc::new(BermudanOption::new_rolled("SampleBermudan", "OPT", equity,
+            settlement, first, expiry, &monthly, 110.0, PutOrCall::Put,
+            OptionSettlement::Cash).unwrap())
+    }
+
+    fn sample_fixings() -> Arc<FixingTable> {
+        let today = Date::from_ymd(2017, 01, 02);
+        Arc::new(FixingTable::new(today, &[
+            ("BP.L", &[
+            (DateTime::new(today - 7, TimeOfDay::Close), 102.0)])]).unwrap())
+    }
+
+    #[test]
+    fn exercise_boundary_is_cached() {
+        let bermudan = sample_bermudan();
+        let n_exercises = bermudan.exercise_count();
+
+        // spread the calibration paths out evenly around the strike
+        let n_paths = 200;
+        let mut paths = Array3::zeros((n_paths, n_exercises, 1));
+        for path in 0..n_paths {
+            for obs in 0..n_exercises {
+                paths[[path, obs, 0]] = 80.0 + (path * 7 % 41) as f64
+                    + (obs % 3) as f64;
+            }
+        }
+        let mut key = HashMap::new();
+        key.insert("BP.L".to_string(), 0);
+        let basis = Arc::new(FunctionBasis::new(vec![
+            Box::new(|_: &[f64]| 1.0), Box::new(|x: &[f64]| x[0])]));
+        let settings = LongstaffSchwartz::new(basis, n_paths).unwrap();
+        let calibration = ExerciseCalibration::new(settings, key, paths);
+
+        let unit_values = vec![0.95; n_exercises];
+        let first = calibration.boundary(&*bermudan, &unit_values).unwrap();
+        let second = calibration.boundary(&*bermudan, &unit_values).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        calibration.clear();
+        let third = calibration.boundary(&*bermudan, &unit_values).unwrap();
+        assert!(!Arc::ptr_eq(&first, &third));
+    }
+
+    #[test]
+    fn calibrated_boundary_gives_stable_delta() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+
+        let pde_factory = PdePricerFactory::new(PdeDynamics::Black, 200, 400,
+            5.0).unwrap();
+        let mut pde = pde_factory.new(sample_bermudan(), sample_fixings(),
+            market_data.clone()).unwrap();
+
+        let settings = LongstaffSchwartz::new(
+            Arc::new(PolynomialBasis::new(3)), 20000).unwrap();
+        let mc_factory = MonteCarloPricerFactory::new(Box::new(
+            BlackDiffusionFactory::new_with_regression(20, 0.01, 30000,
+            settings)));
+        let mut mc = mc_factory.new_mc_pricer(sample_bermudan(),
+            sample_fixings(), market_data).unwrap();
+
+        // Fitting out of sample removes the upward bias, leaving only the
+        // slight downward bias from the suboptimal boundary, which is well
+        // within the statistical error
+        let pde_price = pde.price().unwrap();
+        let mc_price = mc.price().unwrap();
+        let standard_error = mc.mc_result().unwrap().standard_error();
+        assert!((mc_price - pde_price).abs() < 4.0 * standard_error,
+            "mc={} pde={} standard error={}", mc_price, pde_price,
+            standard_error);
+
+        // The boundary is not refitted, so the bumped price only changes by
+        // the effect of the bump on the paths
+        let bump = BumpSpot::new_relative(0.01);
+        let mut pde_save = pde.as_bumpable().new_saveable();
+        let mut mc_save = mc.as_bumpable().new_saveable();
+        assert!(pde.as_mut_bumpable().bump_spot("BP.L", &bump,
+            &mut *pde_save).unwrap());
+        assert!(mc.as_mut_bumpable().bump_spot("BP.L", &bump,
+            &mut *mc_save).unwrap());
+        let pde_delta = pde.price().unwrap() - pde_price;
+        let mc_delta = mc.price().unwrap() - mc_price;
+        assert_approx(mc_delta, pde_delta, 0.03);
+
+        mc.as_mut_bumpable().restore(&*mc_save).unwrap();
+        assert_approx(mc.price().unwrap(), mc_price, 1e-12);
+    }
+
+    #[test]
+    fn european_control_for_bermudan() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let pde_factory = PdePricerFactory::new(PdeDynamics::Black, 200, 400,
+            5.0).unwrap();
+        let pde_price = pde_factory.new(sample_bermudan(), sample_fixings(),
+            market_data.clone()).unwrap().price().unwrap();
+
+        // the european put at the final exercise shares much of the
+        // variance of the bermudan, so it makes a useful control, though
+        // less so than for a european, as early exercise cuts the paths
+        let currency = Arc::new(sample_currency(2));
+        let equity = Arc::new(sample_equity(currency, 2));
+        let expiry = DateTime::new(Date::from_ymd(2018, 06, 01),
+            TimeOfDay::Close);
+        let european: Arc<Instrument> = Arc::new(SpotStartingEuropean::new(
+            "EuropeanPut", "OPT", equity, sample_settlement(2), expiry, 110.0,
+            PutOrCall::Put, OptionSettlement::Cash).unwrap());
+
+        let settings = LongstaffSchwartz::new(
+            Arc::new(PolynomialBasis::new(3)), 20000).unwrap();
+        let mc_factory = MonteCarloPricerFactory::new(Box::new(
+            BlackDiffusionFactory::new_with_regression(20, 0.01, 30000,
+            settings)));
+        let uncontrolled = mc_factory.new_mc_pricer(sample_bermudan(),
+            sample_fixings(), market_data.clone()).unwrap().mc_result()
+            .unwrap();
+        let controlled = mc_factory.new_mc_pricer_with_controls(
+            sample_bermudan(), &[european], sample_fixings(), market_data)
+            .unwrap().mc_result().unwrap();
+
+        assert!(controlled.standard_error()
+            < 0.8 * uncontrolled.standard_error(),
+            "controlled={} uncontrolled={}", controlled.standard_error(),
+            uncontrolled.standard_error());
+        assert_approx(controlled.mean(), pde_price, 0.2);
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+}
@@ -1,20 +1,30 @@
 pub mod blackdiffusion;
+pub mod localvol;
+pub mod heston;
+pub mod longstaffschwartz;
 
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
 use core::qm;
 use instruments::RcInstrument;
 use instruments::Instrument;
 use instruments::MonteCarloDependencies;
 use instruments::MonteCarloContext;
+use instruments::MonteCarloExercisable;
+use instruments::PricingContext;
 use risk::Bumpable;
 use risk::BumpablePricingContext;
 use dates::Date;
 use dates::datetime::DateDayFraction;
+use ndarray::Array1;
+use ndarray::ArrayView2;
+use ndarray::Axis;
+use models::longstaffschwartz::ExerciseCalibration;
+use models::longstaffschwartz::evaluate_exercise;
 
 /// Interface that must be implemented by a model factory in order to support
 /// Monte-Carlo pricing.
-pub trait MonteCarloModelFactory {
+pub trait MonteCarloModelFactory : Send + Sync {
  
     /// Given a timeline (which also specifies the underlyings we need to
     /// evolve), and a pricing context, create a Monte-Carlo model.
@@ -25,14 +35,145 @@ pub trait MonteCarloModelFactory {
 
 /// Interface that must be implemented by a model in order to support
 /// Monte-Carlo pricing.
-pub trait MonteCarloModel : MonteCarloContext + Bumpable {
+pub trait MonteCarloModel : MonteCarloContext + Bumpable + Send + Sync {
 
     /// Converts this model to a MonteCarloContext that can be used for pricing
     fn as_mc_context(&self) -> &MonteCarloContext;
 
+    /// Gives access to the market data the model was built from. Risk
+    /// reports use this to look up the current spot of each underlier.
+    fn as_pricing_context(&self) -> &PricingContext;
+
+    /// Creates an independent copy of the market data the model was built
+    /// from, including any bumps currently applied. Time bumps use this to
+    /// build a new model for the rolled market data.
+    fn clone_context(&self) -> Box<BumpablePricingContext>;
+
+    /// Creates an independent copy of the model, with the same paths and
+    /// any bumps currently applied. The market data itself is shared.
+    fn clone_box(&self) -> Box<MonteCarloModel>;
+
     /// Converts this model to a Bumpable that can be used for risk bumping
     fn as_bumpable(&self) -> &Bumpable;
     fn as_mut_bumpable(&mut self) -> &mut Bumpable;
+
+    /// If the model repaired its correlation matrix, and was asked to warn
+    /// when it did, the Frobenius distance of the repair, which is recorded
+    /// in Monte-Carlo results. Defaults to None.
+    fn correlation_repair_warning(&self) -> Option<f64> {
+        None
+    }
+
+    /// If the model ran out of Sobol direction numbers, the number of
+    /// dimensions it filled with pseudo-random numbers instead, which is
+    /// recorded in Monte-Carlo results. Defaults to None.
+    fn sobol_padding_warning(&self) -> Option<usize> {
+        None
+    }
+
+    /// The variance reduction used in generating the paths, which is
+    /// recorded in Monte-Carlo results. Defaults to none.
+    fn variance_reduction(&self) -> VarianceReduction {
+        VarianceReduction::none()
+    }
+
+    /// How the paths are divided into blocks, which can be valued on
+    /// separate threads. Defaults to None, meaning that the model can only
+    /// be valued as a whole.
+    fn path_blocks(&self) -> Option<PathBlocks> {
+        None
+    }
+
+    /// Presents a block of the paths as a context of its own, so that it
+    /// can be valued independently of the others. The block must be one of
+    /// those returned by path_blocks.
+    fn path_block<'a>(&'a self, _first_path: usize, _n_paths: usize)
+        -> Result<Box<MonteCarloContext + 'a>, qm::Error> {
+        Err(qm::Error::new("Model does not divide its paths into blocks"))
+    }
+}
+
+/// How the paths of a model are divided into blocks of consecutive paths.
+/// Each block has its own random stream, and is valued independently, so
+/// the blocks can be spread across several threads. The paths and their
+/// values depend on the block size, but never on the number of threads.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathBlocks {
+    n_paths: usize,
+    block_size: usize,
+    n_threads: usize
+}
+
+impl PathBlocks {
+    /// Divides the paths into blocks of the given size, except that the
+    /// last block may be smaller, to be worked on by up to n_threads threads
+    pub fn new(n_paths: usize, block_size: usize, n_threads: usize)
+        -> Result<PathBlocks, qm::Error> {
+        if block_size == 0 || n_threads == 0 {
+            return Err(qm::Error::new(
+                "Path block size and number of threads must be positive"))
+        }
+        Ok(PathBlocks { n_paths: n_paths, block_size: block_size,
+            n_threads: n_threads })
+    }
+
+    /// All the paths in one block, on one thread
+    pub fn single(n_paths: usize) -> PathBlocks {
+        PathBlocks { n_paths: n_paths, block_size: n_paths.max(1),
+            n_threads: 1 }
+    }
+
+    pub fn n_paths(&self) -> usize { self.n_paths }
+    pub fn block_size(&self) -> usize { self.block_size }
+    pub fn n_threads(&self) -> usize { self.n_threads }
+
+    /// The index of the first path and the number of paths in each block
+    pub fn blocks(&self) -> Vec<(usize, usize)> {
+        (0..self.n_paths).step_by(self.block_size)
+            .map(|first| (first, self.block_size.min(self.n_paths - first)))
+            .collect()
+    }
+}
+
+/// How the gaussians driving the paths are adjusted to match the moments of
+/// a standard normal distribution. Matching is done separately for each step
+/// and asset, across all the paths. It removes the error in the moments
+/// exactly, but means the paths are no longer quite independent, so the
+/// standard error across them is only an estimate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MomentMatching {
+    /// The gaussians are left as they are drawn
+    Off,
+    /// The mean is subtracted, so the gaussians have a mean of zero
+    Mean,
+    /// As Mean, then the gaussians are scaled to have a variance of one
+    MeanAndVariance
+}
+
+/// The variance reduction techniques used in generating the paths of a
+/// Monte-Carlo model. (Control variates are applied by the pricer rather
+/// than the model, so they are not included.)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VarianceReduction {
+    antithetic: bool,
+    moment_matching: MomentMatching
+}
+
+impl VarianceReduction {
+    /// If antithetic is true, the paths come in pairs, with the gaussians
+    /// of the second path the negation of those of the first.
+    pub fn new(antithetic: bool, moment_matching: MomentMatching)
+        -> VarianceReduction {
+        VarianceReduction { antithetic: antithetic,
+            moment_matching: moment_matching }
+    }
+
+    pub fn none() -> VarianceReduction {
+        VarianceReduction::new(false, MomentMatching::Off)
+    }
+
+    pub fn antithetic(&self) -> bool { self.antithetic }
+    pub fn moment_matching(&self) -> MomentMatching { self.moment_matching }
 }
 
 /// Timeline, which collects the information about an instrument that a model
@@ -40,7 +181,7 @@ pub trait MonteCarloModel : MonteCarloContext + Bumpable {
 pub struct MonteCarloTimeline {
     _spot_date: Date,
     observations: HashMap<RcInstrument, Vec<DateDayFraction>>,
-    flows: Vec<Rc<Instrument>>,
+    flows: Vec<Arc<Instrument>>,
     collated: bool
 }
 
@@ -73,7 +214,7 @@ impl MonteCarloTimeline {
         &self.observations
     }
 
-    pub fn flows(&self) -> &[Rc<Instrument>] {
+    pub fn flows(&self) -> &[Arc<Instrument>] {
         assert!(self.collated);
         &self.flows
     }
@@ -81,7 +222,7 @@ impl MonteCarloTimeline {
 
 impl MonteCarloDependencies for MonteCarloTimeline {
 
-    fn observation(&mut self, instrument: &Rc<Instrument>,
+    fn observation(&mut self, instrument: &Arc<Instrument>,
         date_time: DateDayFraction) {
 
         // Record the observations in the order the client specifies them
@@ -90,10 +231,98 @@ impl MonteCarloDependencies for MonteCarloTimeline {
             .or_insert(Vec::<DateDayFraction>::new()).push(date_time);
     }
 
-    fn flow(&mut self, instrument: &Rc<Instrument>) {
+    fn flow(&mut self, instrument: &Arc<Instrument>) {
 
         // We must record flows in the order the client specifies them, as
         // the client later relies on this order
         self.flows.push(instrument.clone());
     }
 } 
+
+/// Evaluates the flows of an instrument, given the quantities of each flow on
+/// each path, indexed by path then flow. This is the implementation of
+/// MonteCarloContext::evaluate_flows for any non-stochastic-rate model. Such
+/// models can save time by evaluating the pure rate flows using Priceable,
+/// as the value is the same on every path.
+pub fn evaluate_deterministic_rate_flows(flows: &[Arc<Instrument>],
+    quantities: ArrayView2<f64>, n_paths: usize, context: &PricingContext)
+    -> Result<Array1<f64>, qm::Error> {
+
+    let flows_shape = quantities.shape();
+    assert_eq!(flows_shape[0], n_paths);
+    assert_eq!(flows_shape[1], flows.len());
+
+    // weighted sum of all of the flows on each path
+    let mut total = Array1::zeros(n_paths);
+    for (flow, quantity) in flows.iter().zip(quantities.axis_iter(Axis(1))) {
+
+        if flow.is_pure_rates() {
+
+            // value of the instrument times the quantity on each path
+            let pricer = flow.as_priceable().ok_or_else(|| qm::Error::new(
+                "All pure-rates flows must be priceable"))?;
+            let value = pricer.price(context)?;
+            total.scaled_add(value, &quantity);
+
+        } else {
+
+            // otherwise we must price by Monte-Carlo over each path
+            // TODO how do we pass in the weights?
+            return Err(qm::Error::new("not implemented"))
+        }
+    }
+    Ok(total)
+}
+
+/// Values an instrument with early exercise by Longstaff-Schwartz. This is
+/// the implementation of MonteCarloContext::evaluate_exercise for any
+/// non-stochastic-rate model, where every flow is pure rates and so has the
+/// same discounted value on every path. The exercise boundary comes from
+/// the calibration if there is one, otherwise it is fitted on the pricing
+/// paths.
+pub fn evaluate_deterministic_rate_exercise(flows: &[Arc<Instrument>],
+    exercisable: &MonteCarloExercisable, mc_context: &MonteCarloContext,
+    n_paths: usize, context: &PricingContext,
+    calibration: Option<&ExerciseCalibration>)
+    -> Result<Array1<f64>, qm::Error> {
+
+    // the discounted value of one unit of each flow
+    let mut unit_values = Vec::with_capacity(flows.len());
+    for flow in flows.iter() {
+        if !flow.is_pure_rates() {
+            return Err(qm::Error::new("Early exercise flows must be pure \
+                rates in a non-stochastic-rate model"))
+        }
+        let pricer = flow.as_priceable().ok_or_else(|| qm::Error::new(
+            "All pure-rates flows must be priceable"))?;
+        unit_values.push(pricer.price(context)?);
+    }
+
+    evaluate_exercise(exercisable, mc_context, n_paths, &unit_values,
+        calibration)
+}
+
+/// Works out the date at the end of each substep, splitting the time between
+/// observations evenly. The start is normally the spot date, and the
+/// substepping is as calculated by blackdiffusion::calculate_substepping.
+pub fn substep_dates(start: DateDayFraction, observations: &[DateDayFraction],
+    substepping: &[usize]) -> Vec<DateDayFraction> {
+
+    let origin = start.date();
+    let offset = |d: &DateDayFraction|
+        (d.date() - origin) as f64 + d.day_fraction();
+
+    let mut dates = Vec::new();
+    let mut from = offset(&start);
+    for (obs, substeps) in observations.iter().zip(substepping.iter()) {
+        let to = offset(obs);
+        for i in 1..*substeps {
+            let t = from + (to - from) * (i as f64) / (*substeps as f64);
+            let days = t.floor();
+            dates.push(DateDayFraction::new(origin + days as i32, t - days));
+        }
+        dates.push(*obs);
+        from = to;
+    }
+    dates
+}
@@ -1,8 +1,9 @@
 pub mod montecarlo;
 pub mod selfpricer;
+pub mod pde;
 
 use core::qm;
-use std::rc::Rc;
+use std::sync::Arc;
 use instruments::Instrument;
 use data::fixings::FixingTable;
 use risk::marketdata::MarketData;
@@ -13,9 +14,9 @@ use risk::Pricer;
 pub trait PricerFactory {
     /// Creates a pricer, given all the data that is needed to get a price.
     /// All the inputs are shared pointers to const objects, which allows them
-    /// to be shared across multiple pricers. (Consider making them Arc rather
-    /// than Rc, allowing multithreaded use across different pricers.)
-    fn new(&self, instrument: Rc<Instrument>, fixings: Rc<FixingTable>, 
-        market_data: Rc<MarketData>) -> Result<Box<Pricer>, qm::Error>;
+    /// to be shared across multiple pricers, including pricers on different
+    /// threads.
+    fn new(&self, instrument: Arc<Instrument>, fixings: Arc<FixingTable>, 
+        market_data: Arc<MarketData>) -> Result<Box<Pricer>, qm::Error>;
 }
  
@@ -1,9 +1,23 @@
 use core::qm;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use ndarray::Array1;
+use ndarray::Array2;
+use ndarray::ArrayView2;
+use ndarray::ArrayViewMut2;
 use dates::Date;
+use dates::rules::DateRule;
+use dates::datetime::DateDayFraction;
 use instruments::Instrument;
 use instruments::PricingContext;
 use instruments::DependencyContext;
+use instruments::SpotRequirement;
+use instruments::MonteCarloContext;
+use instruments::MonteCarloDependencies;
+use instruments::MonteCarloPriceable;
+use instruments::MonteCarloExercisable;
+use instruments::assets::Currency;
 use risk::cache::PricingContextPrefetch;
 use risk::Pricer;
 use risk::dependencies::DependencyCollector;
@@ -11,30 +25,48 @@ use risk::Bumpable;
 use risk::TimeBumpable;
 use risk::Saveable;
 use pricers::PricerFactory;
+use pricers::selfpricer::self_price;
 use data::fixings::FixingTable;
 use data::bumpspot::BumpSpot;
 use data::bumptime::BumpTime;
 use data::bumpvol::BumpVol;
+use data::bumpcorrelation::BumpCorrelation;
 use data::bumpdivs::BumpDivs;
 use data::bumpyield::BumpYield;
 use risk::marketdata::MarketData;
 use models::MonteCarloModel;
 use models::MonteCarloModelFactory;
 use models::MonteCarloTimeline;
+use models::VarianceReduction;
+use models::PathBlocks;
+use core::parallel::map_parallel;
+use math::regression::least_squares;
 
 /// The MonteCarlo calculator uses the MonteCarloPriceable interface of an
 /// instrument to evaluate the instrument . It then exposes this
 /// interface as a Pricer, allowing bumping for risk calculation.
+///
+/// Optionally, the pricer can have control variates. These are instruments
+/// that can be priced both analytically and by Monte-Carlo, and whose
+/// values on each path are closely related to those of the instrument. The
+/// price is then adjusted by the Monte-Carlo error in the controls.
+///
+/// If the model divides its paths into blocks, each block is valued
+/// separately, on as many threads as the model allows, and the results are
+/// combined in the order of the blocks.
 pub struct MonteCarloPricer {
-    instruments: Vec<(f64, Rc<Instrument>)>,
-    model: Box<MonteCarloModel>
+    instruments: Vec<(f64, Arc<Instrument>)>,
+    controls: Vec<Vec<(f64, Arc<Instrument>)>>,
+    layouts: Vec<InstrumentLayout>,
+    model: Box<MonteCarloModel>,
+    model_factory: Arc<MonteCarloModelFactory>
 }
 
 /// The MonteCarloPricerFactory is used to construct MonteCarloPricer pricers.
 /// It means that the interface for constructing pricers is independent of
 /// what sort of pricer it is.
 pub struct MonteCarloPricerFactory {
-    model_factory: Box<MonteCarloModelFactory>
+    model_factory: Arc<MonteCarloModelFactory>
 }
 
 impl MonteCarloPricerFactory {
@@ -48,49 +80,246 @@ impl MonteCarloPricerFactory {
     pub fn new(model_factory: Box<MonteCarloModelFactory>)
         -> MonteCarloPricerFactory {
 
-        MonteCarloPricerFactory { model_factory: model_factory }
+        MonteCarloPricerFactory { model_factory: Arc::from(model_factory) }
     }
-}
 
-impl PricerFactory for MonteCarloPricerFactory {
-    fn new(&self, instrument: Rc<Instrument>, fixing_table: Rc<FixingTable>, 
-        market_data: Rc<MarketData>) -> Result<Box<Pricer>, qm::Error> {
-
-        // Apply the fixings to the instrument. (This is the last time we need
-        // the fixings.)
-        let instruments = match instrument.fix(&*fixing_table)? {
-            Some(fixed) => fixed,
-            None => vec!((1.0, instrument))
-        };
+    /// Constructs a MonteCarloPricer, as PricerFactory::new, but returns it
+    /// as itself rather than as a Pricer, so that its Monte-Carlo specific
+    /// methods such as mc_result are available.
+    pub fn new_mc_pricer(&self, instrument: Arc<Instrument>,
+        fixing_table: Arc<FixingTable>, market_data: Arc<MarketData>)
+        -> Result<MonteCarloPricer, qm::Error> {
+        self.new_mc_pricer_with_controls(instrument, &[], fixing_table,
+            market_data)
+    }
+
+    /// Constructs a MonteCarloPricer with the given control variates. Each
+    /// control must be priceable both by itself and by Monte-Carlo, and is
+    /// evaluated on the same paths as the instrument. The optimal weights
+    /// of the controls are estimated by regression across the paths, each
+    /// time the instrument is priced.
+    pub fn new_mc_pricer_with_controls(&self, instrument: Arc<Instrument>,
+        controls: &[Arc<Instrument>], fixing_table: Arc<FixingTable>,
+        market_data: Arc<MarketData>) -> Result<MonteCarloPricer, qm::Error> {
+
+        // Apply the fixings to the instrument and controls. (This is the
+        // last time we need the fixings.)
+        let instruments = fix(instrument, &*fixing_table)?;
+        let mut fixed_controls = Vec::with_capacity(controls.len());
+        for control in controls.iter() {
+            let fixed = fix(control.clone(), &*fixing_table)?;
+            validate_control(&fixed)?;
+            fixed_controls.push(fixed);
+        }
+        let controls = fixed_controls;
 
         // Find the dependencies of the resulting vector of instruments,
         // also validate that all instruments are priceable by Monte-Carlo
-        // and fetch the timeline.
+        // and fetch the timeline. The controls are evaluated on the same
+        // paths, so they are included too.
         let spot_date = market_data.spot_date();
         let mut dependencies = DependencyCollector::new(spot_date);
-        let mut timeline: MonteCarloTimeline 
-            = MonteCarloTimeline::new(spot_date);
-        let dates_to_value = Vec::new();
-        for &(_, ref instr) in instruments.iter() {
+        for &(_, ref instr) in all_instruments(&instruments, &controls).iter() {
             dependencies.spot(instr);
-            if let Some(mc) = instr.as_mc_priceable() {
-               mc.mc_dependencies(&dates_to_value, &mut timeline)?;
-            } else {
-                return Err(qm::Error::new(&format!("Instrument {} is not \
-                    priceable by MonteCarlo", instr.id())))
-            } 
         }
-        timeline.collate()?;
+        let (timeline, layouts) = mc_timeline(&instruments, &controls,
+            spot_date)?;
 
         // Create a cached pricing context, prefetching the data to price them
         let context = Box::new(PricingContextPrefetch::new(&*market_data,
-            Rc::new(dependencies))?);
+            Arc::new(dependencies))?);
 
         // Create a Monte-Carlo model
         let model = self.model_factory.factory(&timeline, context)?;
 
-        Ok(Box::new(MonteCarloPricer {
-            instruments: instruments, model: model }))
+        Ok(MonteCarloPricer {
+            instruments: instruments, controls: controls, layouts: layouts,
+            model: model, model_factory: self.model_factory.clone() })
+    }
+}
+
+fn fix(instrument: Arc<Instrument>, fixing_table: &FixingTable)
+    -> Result<Vec<(f64, Arc<Instrument>)>, qm::Error> {
+    Ok(match instrument.fix(fixing_table)? {
+        Some(fixed) => fixed,
+        None => vec!((1.0, instrument))
+    })
+}
+
+/// A control must be priceable analytically as well as by Monte-Carlo
+fn validate_control(control: &[(f64, Arc<Instrument>)])
+    -> Result<(), qm::Error> {
+    for &(_, ref instr) in control.iter() {
+        if instr.as_priceable().is_none() {
+            return Err(qm::Error::new(&format!("Control variate {} is not \
+                priceable", instr.id())))
+        }
+    }
+    Ok(())
+}
+
+/// The instruments followed by all the controls
+fn all_instruments(instruments: &[(f64, Arc<Instrument>)],
+    controls: &[Vec<(f64, Arc<Instrument>)>]) -> Vec<(f64, Arc<Instrument>)> {
+    let mut all = instruments.to_vec();
+    for control in controls.iter() {
+        all.extend_from_slice(control);
+    }
+    all
+}
+
+impl PricerFactory for MonteCarloPricerFactory {
+    fn new(&self, instrument: Arc<Instrument>, fixing_table: Arc<FixingTable>, 
+        market_data: Arc<MarketData>) -> Result<Box<Pricer>, qm::Error> {
+        Ok(Box::new(self.new_mc_pricer(instrument, fixing_table,
+            market_data)?))
+    }
+}
+
+/// Builds the timeline for the given instruments and controls, validating
+/// that they are all priceable by Monte-Carlo. If there are controls, it also
+/// returns the layout of each instrument then each control on the shared
+/// paths, otherwise the layouts are empty.
+fn mc_timeline(instruments: &[(f64, Arc<Instrument>)],
+    controls: &[Vec<(f64, Arc<Instrument>)>], spot_date: Date)
+    -> Result<(MonteCarloTimeline, Vec<InstrumentLayout>), qm::Error> {
+
+    if !controls.is_empty() {
+        return mc_shared_timeline(&all_instruments(instruments, controls),
+            spot_date)
+    }
+
+    let mut timeline = MonteCarloTimeline::new(spot_date);
+    let dates_to_value = Vec::new();
+    for &(_, ref instr) in instruments.iter() {
+        mc_priceable(instr)?.mc_dependencies(&dates_to_value, &mut timeline)?;
+    }
+    timeline.collate()?;
+    Ok((timeline, Vec::new()))
+}
+
+fn mc_priceable(instrument: &Arc<Instrument>)
+    -> Result<&MonteCarloPriceable, qm::Error> {
+    instrument.as_mc_priceable().ok_or_else(|| qm::Error::new(&format!(
+        "Instrument {} is not priceable by MonteCarlo", instrument.id())))
+}
+
+/// Builds a timeline shared by several instruments, such as an instrument
+/// and its controls. Each instrument expects to see only its own
+/// observations and flows, whereas the timeline simply appends them, so we
+/// record each instrument separately. The observations of each underlier
+/// are merged into one increasing sequence of dates, and the flows of each
+/// instrument follow those of the instrument before.
+fn mc_shared_timeline(instruments: &[(f64, Arc<Instrument>)], spot_date: Date)
+    -> Result<(MonteCarloTimeline, Vec<InstrumentLayout>), qm::Error> {
+
+    let dates_to_value = Vec::new();
+    let mut recorded = Vec::with_capacity(instruments.len());
+    let mut merged = DependencyRecorder::new();
+    for &(_, ref instr) in instruments.iter() {
+        let mut recorder = DependencyRecorder::new();
+        mc_priceable(instr)?.mc_dependencies(&dates_to_value, &mut recorder)?;
+        for &(ref underlier, ref dates) in recorder.observations.iter() {
+            for date in dates.iter() {
+                merged.observation(underlier, *date);
+            }
+        }
+        for flow in recorder.flows.iter() {
+            merged.flow(flow);
+        }
+        recorded.push(recorder);
+    }
+
+    // day fractions are never NaN, so the dates are totally ordered
+    let mut timeline = MonteCarloTimeline::new(spot_date);
+    for &mut (ref underlier, ref mut dates) in merged.observations.iter_mut() {
+        dates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        dates.dedup();
+        for date in dates.iter() {
+            timeline.observation(underlier, *date);
+        }
+    }
+    for flow in merged.flows.iter() {
+        timeline.flow(flow);
+    }
+    timeline.collate()?;
+
+    let mut layouts = Vec::with_capacity(recorded.len());
+    let mut first_flow = 0;
+    for recorder in recorded.iter() {
+        let observations = recorder.observations.iter()
+            .map(|&(ref underlier, ref dates)| {
+                let all = merged.dates(underlier.id());
+                (underlier.clone(), dates.iter()
+                    .map(|d| all.iter().position(|a| a == d).unwrap())
+                    .collect())
+            }).collect();
+        layouts.push(InstrumentLayout { observations: observations,
+            first_flow: first_flow, n_flows: recorder.flows.len(),
+            total_flows: merged.flows.len() });
+        first_flow += recorder.flows.len();
+    }
+    Ok((timeline, layouts))
+}
+
+/// Records the Monte-Carlo dependencies of an instrument, keeping the
+/// underliers in the order they are first observed.
+struct DependencyRecorder {
+    observations: Vec<(Arc<Instrument>, Vec<DateDayFraction>)>,
+    flows: Vec<Arc<Instrument>>
+}
+
+impl DependencyRecorder {
+    fn new() -> DependencyRecorder {
+        DependencyRecorder { observations: Vec::new(), flows: Vec::new() }
+    }
+
+    fn dates(&self, id: &str) -> &[DateDayFraction] {
+        self.observations.iter().find(|o| o.0.id() == id)
+            .map_or(&[], |o| &o.1)
+    }
+}
+
+impl MonteCarloDependencies for DependencyRecorder {
+    fn observation(&mut self, instrument: &Arc<Instrument>,
+        date_time: DateDayFraction) {
+        let id = instrument.id();
+        match self.observations.iter().position(|o| o.0.id() == id) {
+            Some(i) => self.observations[i].1.push(date_time),
+            None => self.observations.push((instrument.clone(),
+                vec!(date_time)))
+        }
+    }
+
+    fn flow(&mut self, instrument: &Arc<Instrument>) {
+        self.flows.push(instrument.clone());
+    }
+}
+
+/// Where the observations and flows of one instrument are to be found among
+/// those of a shared timeline. The observations are given as the column of
+/// the paths of each underlier, and the flows are a contiguous range.
+#[derive(Clone)]
+struct InstrumentLayout {
+    observations: Vec<(Arc<Instrument>, Vec<usize>)>,
+    first_flow: usize,
+    n_flows: usize,
+    total_flows: usize
+}
+
+impl InstrumentLayout {
+    /// Copies the instrument's own flow quantities into the columns of the
+    /// shared flows
+    fn assign_flows(&self, quantities: ArrayView2<f64>,
+        mut all: ArrayViewMut2<f64>) -> Result<(), qm::Error> {
+        if quantities.cols() != self.n_flows {
+            return Err(qm::Error::new(&format!("Expected quantities of {} \
+                flows but found {}", self.n_flows, quantities.cols())))
+        }
+        for i in 0..self.n_flows {
+            all.column_mut(self.first_flow + i).assign(&quantities.column(i));
+        }
+        Ok(())
     }
 }
 
@@ -99,24 +328,524 @@ impl Pricer for MonteCarloPricer {
     fn as_mut_bumpable(&mut self) -> &mut Bumpable { self }
     fn as_mut_time_bumpable(&mut self) -> &mut TimeBumpable { self }
 
+    fn instruments(&self) -> &[(f64, Arc<Instrument>)] {
+        &self.instruments
+    }
+
+    fn as_pricing_context(&self) -> &PricingContext {
+        self.model.as_pricing_context()
+    }
+
+    fn clone_box(&self) -> Box<Pricer> {
+        Box::new(MonteCarloPricer { instruments: self.instruments.clone(),
+            controls: self.controls.clone(), layouts: self.layouts.clone(),
+            model: self.model.clone_box(),
+            model_factory: self.model_factory.clone() })
+    }
+
     fn price(&self) -> Result<f64, qm::Error> {
 
+        // Control variates need the values on each path
+        if !self.controls.is_empty() {
+            return Ok(self.mc_result()?.mean())
+        }
+
+        // Each block is priced as if it were the only one, and the prices
+        // are weighted by the number of paths in each block
+        let blocks = match self.path_blocks() {
+            None => return self.price_paths(self.model.as_mc_context()),
+            Some(blocks) => blocks
+        };
+        let prices = map_parallel(blocks.n_threads(), blocks.blocks(),
+            |(first, n_paths)| {
+                let block = self.model.path_block(first, n_paths)?;
+                Ok(self.price_paths(&*block)? * n_paths as f64)
+            })?;
+        Ok(prices.iter().sum::<f64>() / blocks.n_paths() as f64)
+    }
+}
+
+impl MonteCarloPricer {
+
+    /// The blocks the paths of the model are divided into, or None if they
+    /// are all in one block
+    fn path_blocks(&self) -> Option<PathBlocks> {
+        self.model.path_blocks().and_then(|blocks|
+            if blocks.blocks().len() > 1 { Some(blocks) } else { None })
+    }
+
+    /// Prices the instruments on the paths of the given context
+    fn price_paths(&self, context: &MonteCarloContext)
+        -> Result<f64, qm::Error> {
+
         // Run a Monte-Carlo simulation to generate a matrix of cashflows
         // per path. Note that we have already verified that the instruments
         // are all mc priceable, so just skip them if they aren't
         let mut total = 0.0;
         for &(weight, ref instrument) in self.instruments.iter() {
             if let Some(mc) = instrument.as_mc_priceable() {
-               let context = self.model.as_mc_context();
                total += weight * mc.mc_price(context)?;
             }
         }
 
-        // Return a weighted sum of the individual prices. (TODO consider
-        // returning some data structure that shows the components as well as
-        // the weighted sum.)
+        // Return a weighted sum of the individual prices. (See mc_result
+        // for the standard error and the values on each path.)
         Ok(total)
     }
+
+    /// Values the instruments as price, but returns the discounted value on
+    /// each path, with the mean and standard error across them. The mean is
+    /// the same as the price, to within rounding.
+    ///
+    /// The values on each path are collected from the evaluate_flows and
+    /// evaluate_exercise calls the instruments make on the context, so this
+    /// fails for any instrument that does not value itself through them, or
+    /// combines their results other than by adding them up.
+    ///
+    /// If there are control variates, the value on each path is adjusted by
+    /// the difference between the value of each control on that path and
+    /// its analytic price, times the weight of the control. The weights
+    /// minimise the variance of the result, and are estimated by regressing
+    /// the values of the instrument against those of the controls.
+    pub fn mc_result(&self) -> Result<MonteCarloResult, qm::Error> {
+
+        let variance_reduction = self.model.variance_reduction();
+        let values = self.path_values(&self.instruments, 0)?;
+        if self.controls.is_empty() {
+            let result = MonteCarloResult::new_with_variance_reduction(
+                values.to_vec(), variance_reduction, Vec::new())?;
+            return Ok(self.with_warnings(result))
+        }
+
+        // regress the values against a constant and the controls
+        let n_paths = values.len();
+        let n_controls = self.controls.len();
+        let mut design = Array2::zeros((n_paths, n_controls + 1));
+        design.column_mut(0).fill(1.0);
+        let mut analytic = Vec::with_capacity(n_controls);
+        let mut first = self.instruments.len();
+        for (i, control) in self.controls.iter().enumerate() {
+            design.column_mut(i + 1).assign(
+                &self.path_values(control, first)?);
+            first += control.len();
+            analytic.push(self_price(control,
+                self.model.as_pricing_context())?);
+        }
+        let coefficients = least_squares(design.view(), values.view())
+            .ok_or_else(|| qm::Error::new("Failed to estimate control \
+                variate weights. Are the controls linearly independent?"))?;
+        let betas = coefficients[1..].to_vec();
+
+        let adjusted = values.iter().zip(design.outer_iter())
+            .map(|(value, row)| betas.iter().zip(analytic.iter()).enumerate()
+                .fold(*value, |acc, (i, (beta, price))|
+                    acc - beta * (row[i + 1] - price)))
+            .collect();
+        let result = MonteCarloResult::new_with_variance_reduction(adjusted,
+            variance_reduction, betas)?;
+        Ok(self.with_warnings(result))
+    }
+
+    /// Returns the weighted sum of the discounted values of the given
+    /// instruments on each path. If the paths are shared with controls, the
+    /// first instrument has the given index in the layouts.
+    fn path_values(&self, instruments: &[(f64, Arc<Instrument>)], first: usize)
+        -> Result<Array1<f64>, qm::Error> {
+
+        let blocks = match self.path_blocks() {
+            None => return self.block_path_values(self.model.as_mc_context(),
+                instruments, first),
+            Some(blocks) => blocks
+        };
+        let values = map_parallel(blocks.n_threads(), blocks.blocks(),
+            |(first_path, n_paths)| {
+                let block = self.model.path_block(first_path, n_paths)?;
+                self.block_path_values(&*block, instruments, first)
+            })?;
+
+        let mut all = Vec::with_capacity(blocks.n_paths());
+        for block in values.iter() {
+            all.extend(block.iter());
+        }
+        Ok(Array1::from_vec(all))
+    }
+
+    /// As path_values, but for the paths of the given context, which may be
+    /// one block of the paths of the model
+    fn block_path_values(&self, mc_context: &MonteCarloContext,
+        instruments: &[(f64, Arc<Instrument>)], first: usize)
+        -> Result<Array1<f64>, qm::Error> {
+
+        let mut total: Option<Array1<f64>> = None;
+        for (i, &(weight, ref instrument)) in instruments.iter().enumerate() {
+            if let Some(mc) = instrument.as_mc_priceable() {
+                let view;
+                let context = if self.layouts.is_empty() {
+                    mc_context
+                } else {
+                    view = InstrumentView::new(mc_context,
+                        &self.layouts[first + i])?;
+                    &view as &MonteCarloContext
+                };
+                let collector = PathValueCollector::new(context);
+                let price = mc.mc_price(&collector)?;
+                let values = collector.path_values(instrument.id(), price)?;
+                match total {
+                    None => total = Some(values * weight),
+                    Some(ref mut total) => total.scaled_add(weight, &values)
+                }
+            }
+        }
+
+        total.ok_or_else(|| qm::Error::new("No instruments to value"))
+    }
+
+    /// Records any warnings from the model in the result
+    fn with_warnings(&self, mut result: MonteCarloResult)
+        -> MonteCarloResult {
+        result.correlation_repair_warning =
+            self.model.correlation_repair_warning();
+        result.sobol_padding_warning = self.model.sobol_padding_warning();
+        result
+    }
+}
+
+/// The result of a Monte-Carlo valuation, keeping the discounted value on
+/// each path as well as the statistics across them. Keeping the paths
+/// means that the difference between a bumped and an unbumped valuation can
+/// be taken path by path, which gives a far smaller error than the
+/// difference of the means if the paths are the same.
+///
+/// The result also records the variance reduction used. Antithetic paths
+/// are not independent, so the standard error is calculated from the mean
+/// of each pair.
+///
+/// Any warnings from the model, such as a repair of its correlation matrix
+/// or running out of Sobol dimensions, are recorded too, for the caller to
+/// report.
+#[derive(Clone, Debug)]
+pub struct MonteCarloResult {
+    path_values: Vec<f64>,
+    mean: f64,
+    standard_error: f64,
+    variance_reduction: VarianceReduction,
+    control_betas: Vec<f64>,
+    correlation_repair_warning: Option<f64>,
+    sobol_padding_warning: Option<usize>
+}
+
+impl MonteCarloResult {
+    /// Creates a result from the discounted value on each path, with no
+    /// variance reduction
+    pub fn new(path_values: Vec<f64>) -> Result<MonteCarloResult, qm::Error> {
+        MonteCarloResult::new_with_variance_reduction(path_values,
+            VarianceReduction::none(), Vec::new())
+    }
+
+    /// Creates a result from the discounted value on each path, from paths
+    /// generated with the given variance reduction, and already adjusted by
+    /// control variates with the given weights. Antithetic results must
+    /// have an even number of paths.
+    pub fn new_with_variance_reduction(path_values: Vec<f64>,
+        variance_reduction: VarianceReduction, control_betas: Vec<f64>)
+        -> Result<MonteCarloResult, qm::Error> {
+        if path_values.is_empty() {
+            return Err(qm::Error::new("Monte-Carlo result has no paths"))
+        }
+        let antithetic = variance_reduction.antithetic();
+        if antithetic && path_values.len() % 2 != 0 {
+            return Err(qm::Error::new("Antithetic Monte-Carlo result must \
+                have an even number of paths"))
+        }
+        let (mean, standard_error) = mean_and_standard_error(&path_values,
+            antithetic);
+        Ok(MonteCarloResult { path_values: path_values, mean: mean,
+            standard_error: standard_error,
+            variance_reduction: variance_reduction,
+            control_betas: control_betas,
+            correlation_repair_warning: None, sobol_padding_warning: None })
+    }
+
+    /// The Monte-Carlo estimate of the price
+    pub fn mean(&self) -> f64 { self.mean }
+
+    /// The standard error of the mean, in other words the sample standard
+    /// deviation divided by the square root of the number of paths. This
+    /// is NaN if there is only one path, or one antithetic pair.
+    pub fn standard_error(&self) -> f64 { self.standard_error }
+
+    pub fn n_paths(&self) -> usize { self.path_values.len() }
+
+    /// The discounted value on each path
+    pub fn path_values(&self) -> &[f64] { &self.path_values }
+
+    /// The weight of each control variate, in the order they were given.
+    /// Empty if there were no controls.
+    pub fn control_betas(&self) -> &[f64] { &self.control_betas }
+
+    /// The variance reduction used in generating the paths
+    pub fn variance_reduction(&self) -> VarianceReduction {
+        self.variance_reduction
+    }
+
+    /// If the model repaired its correlation matrix under
+    /// CorrelationRepair::Warn, the Frobenius distance of the repair
+    pub fn correlation_repair_warning(&self) -> Option<f64> {
+        self.correlation_repair_warning
+    }
+
+    /// If the paths were driven by a Sobol sequence with more dimensions
+    /// than we have direction numbers for, the number of dimensions that
+    /// were filled with pseudo-random numbers instead
+    pub fn sobol_padding_warning(&self) -> Option<usize> {
+        self.sobol_padding_warning
+    }
+
+    /// Returns the estimate after each batch of the given number of paths,
+    /// showing how the mean and standard error converge as paths are
+    /// added. The last estimate is for all the paths, even if it is not a
+    /// full batch. Antithetic pairs must not be split between batches, so
+    /// the batch size must then be even.
+    pub fn convergence(&self, batch_size: usize)
+        -> Result<Vec<BatchEstimate>, qm::Error> {
+        if batch_size == 0 {
+            return Err(qm::Error::new("Batch size must be positive"))
+        }
+        let antithetic = self.variance_reduction.antithetic();
+        if antithetic && batch_size % 2 != 0 {
+            return Err(qm::Error::new("Batch size must be even for \
+                antithetic paths"))
+        }
+
+        let mut trace = Vec::new();
+        let mut end = 0;
+        while end < self.path_values.len() {
+            end = (end + batch_size).min(self.path_values.len());
+            let (mean, standard_error) = mean_and_standard_error(
+                &self.path_values[..end], antithetic);
+            trace.push(BatchEstimate { n_paths: end, mean: mean,
+                standard_error: standard_error });
+        }
+        Ok(trace)
+    }
+
+    /// Returns the result of subtracting the given base result from this
+    /// one, path by path. For example, if this is a bumped valuation and
+    /// the base is unbumped, the standard error of the result is the error
+    /// in the risk. The two results must have the same number of paths and
+    /// the same variance reduction.
+    pub fn difference(&self, base: &MonteCarloResult)
+        -> Result<MonteCarloResult, qm::Error> {
+        if base.n_paths() != self.n_paths() {
+            return Err(qm::Error::new(&format!("Cannot take the difference \
+                of Monte-Carlo results with {} and {} paths",
+                self.n_paths(), base.n_paths())))
+        }
+        if base.variance_reduction != self.variance_reduction {
+            return Err(qm::Error::new("Cannot take the difference of \
+                Monte-Carlo results with different variance reduction"))
+        }
+        MonteCarloResult::new_with_variance_reduction(self.path_values.iter()
+            .zip(base.path_values.iter()).map(|(a, b)| a - b).collect(),
+            self.variance_reduction, Vec::new())
+    }
+}
+
+/// The Monte-Carlo estimate after some number of paths
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchEstimate {
+    n_paths: usize,
+    mean: f64,
+    standard_error: f64
+}
+
+impl BatchEstimate {
+    pub fn n_paths(&self) -> usize { self.n_paths }
+    pub fn mean(&self) -> f64 { self.mean }
+    pub fn standard_error(&self) -> f64 { self.standard_error }
+}
+
+/// If the values are antithetic, the error is calculated from the mean of
+/// each pair, which are independent. The number of values must then be even.
+fn mean_and_standard_error(values: &[f64], antithetic: bool) -> (f64, f64) {
+    if antithetic {
+        let pairs: Vec<f64> = values.chunks(2)
+            .map(|pair| 0.5 * (pair[0] + pair[1])).collect();
+        return mean_and_standard_error(&pairs, false)
+    }
+
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let sum_squares = values.iter()
+        .fold(0.0, |acc, v| acc + (v - mean) * (v - mean));
+    (mean, (sum_squares / (n - 1.0) / n).sqrt())
+}
+
+/// Decorates a Monte-Carlo context, collecting the discounted value on each
+/// path of everything the instrument values through it. The context is
+/// immutable, so we collect the values in a RefCell.
+struct PathValueCollector<'a> {
+    context: &'a MonteCarloContext,
+    values: RefCell<Option<Array1<f64>>>
+}
+
+impl<'a> PathValueCollector<'a> {
+    fn new(context: &'a MonteCarloContext) -> PathValueCollector<'a> {
+        PathValueCollector { context: context, values: RefCell::new(None) }
+    }
+
+    fn collect(&self, values: Array1<f64>) -> f64 {
+        let mean = values.scalar_sum() / values.len() as f64;
+        let mut collected = self.values.borrow_mut();
+        match *collected {
+            None => *collected = Some(values),
+            Some(ref mut total) => *total += &values
+        }
+        mean
+    }
+
+    /// Returns the collected values, checking that they are consistent
+    /// with the price the instrument returned
+    fn path_values(&self, id: &str, price: f64)
+        -> Result<Array1<f64>, qm::Error> {
+        let values = self.values.borrow_mut().take().ok_or_else(||
+            qm::Error::new(&format!("Instrument {} did not value any flows \
+                on the Monte-Carlo paths", id)))?;
+        let mean = values.scalar_sum() / values.len() as f64;
+        if (mean - price).abs() > 1e-10 * (1.0 + price.abs()) {
+            return Err(qm::Error::new(&format!("The values on each path of \
+                instrument {} do not add up to its price", id)))
+        }
+        Ok(values)
+    }
+}
+
+impl<'a> MonteCarloContext for PathValueCollector<'a> {
+    fn paths(&self, instrument: &Arc<Instrument>)
+        -> Result<ArrayView2<f64>, qm::Error> {
+        self.context.paths(instrument)
+    }
+
+    fn evaluate_flows(&self, quantities: ArrayView2<f64>)
+        -> Result<f64, qm::Error> {
+        let values = self.context.evaluate_flows_by_path(quantities)?;
+        Ok(self.collect(values))
+    }
+
+    fn evaluate_flows_by_path(&self, quantities: ArrayView2<f64>)
+        -> Result<Array1<f64>, qm::Error> {
+        self.context.evaluate_flows_by_path(quantities)
+    }
+
+    fn evaluate_exercise(&self, exercisable: &MonteCarloExercisable)
+        -> Result<f64, qm::Error> {
+        let values = self.context.evaluate_exercise_by_path(exercisable)?;
+        Ok(self.collect(values))
+    }
+
+    fn evaluate_exercise_by_path(&self, exercisable: &MonteCarloExercisable)
+        -> Result<Array1<f64>, qm::Error> {
+        self.context.evaluate_exercise_by_path(exercisable)
+    }
+}
+
+/// Decorates a Monte-Carlo context whose paths are shared by several
+/// instruments, presenting only the observations and flows of one of them,
+/// as if the paths had been generated for that instrument alone. The paths
+/// of the instrument's observations are copied out when the view is made.
+struct InstrumentView<'a> {
+    context: &'a MonteCarloContext,
+    layout: &'a InstrumentLayout,
+    paths: HashMap<String, Array2<f64>>
+}
+
+impl<'a> InstrumentView<'a> {
+    fn new(context: &'a MonteCarloContext, layout: &'a InstrumentLayout)
+        -> Result<InstrumentView<'a>, qm::Error> {
+
+        let mut paths = HashMap::new();
+        for &(ref underlier, ref columns) in layout.observations.iter() {
+            let all = context.paths(underlier)?;
+            let mut own = Array2::zeros((all.rows(), columns.len()));
+            for (i, column) in columns.iter().enumerate() {
+                own.column_mut(i).assign(&all.column(*column));
+            }
+            paths.insert(underlier.id().to_string(), own);
+        }
+
+        Ok(InstrumentView { context: context, layout: layout, paths: paths })
+    }
+}
+
+impl<'a> MonteCarloContext for InstrumentView<'a> {
+    fn paths(&self, instrument: &Arc<Instrument>)
+        -> Result<ArrayView2<f64>, qm::Error> {
+        self.paths.get(instrument.id()).map(|paths| paths.view())
+            .ok_or_else(|| qm::Error::new(&format!("No observations of {} \
+                were requested", instrument.id())))
+    }
+
+    fn evaluate_flows_by_path(&self, quantities: ArrayView2<f64>)
+        -> Result<Array1<f64>, qm::Error> {
+        let mut all = Array2::zeros((quantities.rows(),
+            self.layout.total_flows));
+        self.layout.assign_flows(quantities, all.view_mut())?;
+        self.context.evaluate_flows_by_path(all.view())
+    }
+
+    fn evaluate_exercise_by_path(&self, exercisable: &MonteCarloExercisable)
+        -> Result<Array1<f64>, qm::Error> {
+        let shared = SharedExercisable { exercisable: exercisable,
+            layout: self.layout };
+        self.context.evaluate_exercise_by_path(&shared)
+    }
+}
+
+/// Decorates an exercisable instrument whose paths are shared, so that the
+/// model, which sees all the observations and flows, can fit and apply its
+/// exercise boundary. The model may pass in its calibration paths as well
+/// as its pricing paths, so each call makes a view of whatever it is given.
+struct SharedExercisable<'a> {
+    exercisable: &'a MonteCarloExercisable,
+    layout: &'a InstrumentLayout
+}
+
+impl<'a> Instrument for SharedExercisable<'a> {
+    fn id(&self) -> &str { self.exercisable.id() }
+    fn payoff_currency(&self) -> &Currency {
+        self.exercisable.payoff_currency()
+    }
+    fn credit_id(&self) -> &str { self.exercisable.credit_id() }
+    fn settlement(&self) -> &Arc<DateRule> { self.exercisable.settlement() }
+    fn dependencies(&self, context: &mut DependencyContext)
+        -> SpotRequirement {
+        self.exercisable.dependencies(context)
+    }
+}
+
+impl<'a> MonteCarloExercisable for SharedExercisable<'a> {
+    fn exercise_count(&self) -> usize { self.exercisable.exercise_count() }
+
+    fn regression_variable_count(&self) -> usize {
+        self.exercisable.regression_variable_count()
+    }
+
+    fn exercise_quantities(&self, context: &MonteCarloContext,
+        exercise: usize, quantities: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error> {
+        let view = InstrumentView::new(context, self.layout)?;
+        let mut own = Array2::zeros((quantities.rows(), self.layout.n_flows));
+        self.exercisable.exercise_quantities(&view, exercise,
+            own.view_mut())?;
+        self.layout.assign_flows(own.view(), quantities)
+    }
+
+    fn regression_variables(&self, context: &MonteCarloContext,
+        exercise: usize, variables: ArrayViewMut2<f64>)
+        -> Result<(), qm::Error> {
+        let view = InstrumentView::new(context, self.layout)?;
+        self.exercisable.regression_variables(&view, exercise, variables)
+    }
 }
 
 /// There is a lot of discussion on the Rust language forum of ways to avoid
@@ -147,6 +876,12 @@ impl Bumpable for MonteCarloPricer {
         self.model.bump_vol(id, bump, save)
     }
 
+    fn bump_correlation(&mut self, first: &str, second: &str,
+        bump: &BumpCorrelation, save: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        self.model.bump_correlation(first, second, bump, save)
+    }
+
     fn bump_discount_date(&mut self, replacement: Date, save: &mut Saveable)
         -> Result<bool, qm::Error> {
         self.model.bump_discount_date(replacement, save)
@@ -167,21 +902,63 @@ impl Bumpable for MonteCarloPricer {
 }
 
 impl TimeBumpable for MonteCarloPricer {
-    fn bump_time(&mut self, _bump: &BumpTime) -> Result<(), qm::Error> {
-        Err(qm::Error::new("Time bumps not yet supported"))
+    /// The timeline changes when time moves on, so we build a new model
+    /// from the rolled market data. This generates new paths, and also
+    /// discards any exercise boundaries fitted by the old model.
+    fn bump_time(&mut self, bump: &BumpTime) -> Result<(), qm::Error> {
+
+        // work on copies, so the pricer is unchanged if anything fails
+        let mut instruments = self.instruments.clone();
+        let mut context = self.model.clone_context();
+        if !context.bump_time(bump, &mut instruments)? {
+            return Ok(())
+        }
+
+        // Fixing may split the controls into several instruments, so we fix
+        // each on its own copy of the context to keep them apart.
+        let mut controls = Vec::with_capacity(self.controls.len());
+        for control in self.controls.iter() {
+            let mut fixed = control.clone();
+            self.model.clone_context().bump_time(bump, &mut fixed)?;
+            validate_control(&fixed)?;
+            controls.push(fixed);
+        }
+
+        let (timeline, layouts) = mc_timeline(&instruments, &controls,
+            context.spot_date())?;
+        self.model = self.model_factory.factory(&timeline, context)?;
+        self.instruments = instruments;
+        self.controls = controls;
+        self.layouts = layouts;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::rc::Rc;
+    use std::sync::Arc;
     use dates::datetime::DateTime;
     use dates::datetime::TimeOfDay;
     use math::numerics::approx_eq;
     use risk::marketdata::tests::sample_market_data;
+    use risk::marketdata::tests::sample_market_data_with_vol;
     use risk::marketdata::tests::sample_european;
+    use risk::marketdata::tests::sample_currency;
+    use risk::marketdata::tests::sample_settlement;
+    use risk::marketdata::tests::sample_equity;
+    use instruments::options::SpotStartingEuropean;
+    use instruments::options::PutOrCall;
+    use instruments::options::OptionSettlement;
     use models::blackdiffusion::BlackDiffusionFactory;
+    use models::blackdiffusion::PathDiscretisation;
+    use models::blackdiffusion::RandomSequence;
+    use models::MomentMatching;
+    use data::volsurface::FlatVolSurface;
+    use dates::calendar::WeekdayCalendar;
+    use dates::datetime::DateDayFraction;
+    use pricers::selfpricer::SelfPricerFactory;
+    use data::bumptime::SpotDynamics;
 
     fn sample_fixings() -> FixingTable {
         let today = Date::from_ymd(2017, 01, 02);
@@ -198,9 +975,9 @@ mod tests {
         // from the self-pricer bumped prices. Thus all these tests validate
         // the Monte-Carlo pricing against analytic.
 
-        let market_data: Rc<MarketData> = Rc::new(sample_market_data());
-        let instrument: Rc<Instrument> = sample_european();
-        let fixings: Rc<FixingTable> = Rc::new(sample_fixings());
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let instrument: Arc<Instrument> = sample_european();
+        let fixings: Arc<FixingTable> = Arc::new(sample_fixings());
 
         let n_paths = 100000;
         let correlation_substep = 20;
@@ -289,6 +1066,368 @@ mod tests {
         assert_approx(price, unbumped_price, 1e-12);
     }
 
+    #[test]
+    fn monte_carlo_time_bump() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let model_factory = Box::new(BlackDiffusionFactory::new(
+            20, 0.01, 100000));
+        let factory = MonteCarloPricerFactory::new(model_factory);
+        let mut pricer = factory.new(sample_european(),
+            Arc::new(sample_fixings()), market_data.clone()).unwrap();
+        let self_factory = SelfPricerFactory::new();
+        let mut self_pricer = self_factory.new(sample_european(),
+            Arc::new(sample_fixings()), market_data).unwrap();
+
+        // The model is rebuilt with new paths, so the time bumped price has
+        // the same Monte-Carlo error as the unbumped one.
+        let bump = BumpTime::new(Date::from_ymd(2017, 01, 09),
+            SpotDynamics::StickyForward);
+        pricer.as_mut_time_bumpable().bump_time(&bump).unwrap();
+        self_pricer.as_mut_time_bumpable().bump_time(&bump).unwrap();
+        assert_eq!(pricer.as_pricing_context().spot_date(),
+            Date::from_ymd(2017, 01, 09));
+        assert_approx(pricer.price().unwrap(), self_pricer.price().unwrap(),
+            0.3);
+    }
+
+    #[test]
+    fn monte_carlo_converges_to_black76_without_substepping() {
+
+        // A high vol, so that a single step to expiry is a large one. The
+        // self-pricer values the european with Black76.
+        let base = DateDayFraction::new(Date::from_ymd(2016, 12, 30), 0.2);
+        let vol = Arc::new(FlatVolSurface::new(0.8,
+            Box::new(WeekdayCalendar()), base));
+        let market_data = Arc::new(sample_market_data_with_vol(vol));
+        let self_pricer = SelfPricerFactory::new().new(sample_european(),
+            Arc::new(sample_fixings()), market_data.clone()).unwrap();
+        let black76_price = self_pricer.price().unwrap();
+
+        // a path_substep larger than the total variance means one step
+        let factory = MonteCarloPricerFactory::new(Box::new(
+            BlackDiffusionFactory::new(20, 100.0, 200000)));
+        let pricer = factory.new(sample_european(),
+            Arc::new(sample_fixings()), market_data.clone()).unwrap();
+        assert_approx(pricer.price().unwrap(), black76_price, 0.5);
+
+        // the arithmetic scheme is still available, but its single step
+        // gives a normal rather than lognormal terminal distribution
+        let mut model_factory = BlackDiffusionFactory::new(20, 100.0, 200000);
+        model_factory.set_discretisation(PathDiscretisation::ArithmeticEuler);
+        let factory = MonteCarloPricerFactory::new(Box::new(model_factory));
+        let pricer = factory.new(sample_european(),
+            Arc::new(sample_fixings()), market_data).unwrap();
+        let arithmetic_price = pricer.price().unwrap();
+        assert!(arithmetic_price > black76_price + 1.0,
+            "arithmetic={} black76={}", arithmetic_price, black76_price);
+    }
+
+    #[test]
+    fn monte_carlo_sobol_with_brownian_bridge() {
+
+        // The european has about a dozen substeps, so the bridge has some
+        // work to do. The analytic price is from the self-pricer.
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let self_pricer = SelfPricerFactory::new().new(sample_european(),
+            Arc::new(sample_fixings()), market_data.clone()).unwrap();
+        let analytic_price = self_pricer.price().unwrap();
+
+        let price_with = |sequence: RandomSequence, n_paths: usize| {
+            let mut model_factory = BlackDiffusionFactory::new(20, 0.01,
+                n_paths);
+            model_factory.set_random_sequence(sequence);
+            model_factory.set_brownian_bridge(true);
+            let factory = MonteCarloPricerFactory::new(Box::new(
+                model_factory));
+            let pricer = factory.new(sample_european(),
+                Arc::new(sample_fixings()), market_data.clone()).unwrap();
+            pricer.price().unwrap()
+        };
+
+        // The unscrambled sequence is deterministic, and a few thousand
+        // paths are more accurate than the 100000 pseudo-random paths of
+        // the other tests.
+        let sobol_price = price_with(RandomSequence::Sobol, 4096);
+        assert_eq!(sobol_price, price_with(RandomSequence::Sobol, 4096));
+        assert_approx(sobol_price, analytic_price, 0.06);
+
+        // Scrambled sequences are random, but each is similarly accurate
+        let first = price_with(RandomSequence::ScrambledSobol(1), 4096);
+        let second = price_with(RandomSequence::ScrambledSobol(2), 4096);
+        assert!(first != second);
+        assert_approx(first, analytic_price, 0.06);
+        assert_approx(second, analytic_price, 0.06);
+    }
+
+    #[test]
+    fn monte_carlo_result_with_standard_error() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let model_factory = Box::new(BlackDiffusionFactory::new(
+            20, 0.01, 100000));
+        let factory = MonteCarloPricerFactory::new(model_factory);
+        let mut pricer = factory.new_mc_pricer(sample_european(),
+            Arc::new(sample_fixings()), market_data).unwrap();
+
+        // The mean is the price. The analytic price is well within the
+        // error bars.
+        let price = pricer.price().unwrap();
+        let result = pricer.mc_result().unwrap();
+        assert_eq!(result.n_paths(), 100000);
+        assert_eq!(result.path_values().len(), 100000);
+        assert_approx(result.mean(), price, 1e-10);
+        assert!(result.standard_error() > 0.05
+            && result.standard_error() < 0.1,
+            "standard_error={}", result.standard_error());
+        assert!((result.mean() - 16.710717400832973).abs()
+            < 5.0 * result.standard_error());
+
+        // The convergence trace ends with the full result, and the error
+        // shrinks roughly as the square root of the number of paths.
+        let trace = result.convergence(30000).unwrap();
+        assert_eq!(trace.len(), 4);
+        assert_eq!(trace[0].n_paths(), 30000);
+        assert_eq!(trace[3].n_paths(), 100000);
+        assert_approx(trace[3].mean(), result.mean(), 1e-10);
+        assert_approx(trace[3].standard_error(), result.standard_error(),
+            1e-10);
+        assert!(trace[0].standard_error() > 1.5 * result.standard_error());
+
+        // The bumped minus base difference, taken path by path, has a far
+        // smaller error than either price, as the paths are the same.
+        let mut save = pricer.as_bumpable().new_saveable();
+        let bump = BumpSpot::new_relative(0.01);
+        pricer.as_mut_bumpable().bump_spot("BP.L", &bump, &mut *save)
+            .unwrap();
+        let bumped = pricer.mc_result().unwrap();
+        let delta = bumped.difference(&result).unwrap();
+        assert_approx(delta.mean(), bumped.mean() - result.mean(), 1e-10);
+        assert!(delta.standard_error() < 0.1 * result.standard_error(),
+            "delta error={} price error={}", delta.standard_error(),
+            result.standard_error());
+        assert!((delta.mean() - 0.633187905501792).abs()
+            < 5.0 * delta.standard_error());
+
+        let short = MonteCarloResult::new(vec![1.0, 2.0]).unwrap();
+        assert!(short.difference(&result).is_err());
+        assert!(MonteCarloResult::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn monte_carlo_result_records_warnings() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let result_with = |sequence: RandomSequence| {
+            // tiny substeps give the european more steps than there are
+            // Sobol dimensions
+            let mut model_factory = BlackDiffusionFactory::new(20, 0.0001,
+                64);
+            model_factory.set_random_sequence(sequence);
+            model_factory.set_brownian_bridge(true);
+            let factory = MonteCarloPricerFactory::new(Box::new(
+                model_factory));
+            let pricer = factory.new_mc_pricer(sample_european(),
+                Arc::new(sample_fixings()), market_data.clone()).unwrap();
+            pricer.mc_result().unwrap()
+        };
+
+        let result = result_with(RandomSequence::Sobol);
+        let padding = result.sobol_padding_warning().unwrap();
+        assert!(padding > 0 && padding < 1000, "padding={}", padding);
+        assert_eq!(result.correlation_repair_warning(), None);
+
+        let result = result_with(RandomSequence::PseudoRandom);
+        assert_eq!(result.sobol_padding_warning(), None);
+    }
+
+    #[test]
+    fn monte_carlo_antithetic_and_moment_matching() {
+
+        // A single step to expiry, valued against Black76 from the
+        // self-pricer with only a few thousand paths
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let fixings = Arc::new(sample_fixings());
+        let black76_price = SelfPricerFactory::new().new(sample_european(),
+            fixings.clone(), market_data.clone()).unwrap().price().unwrap();
+
+        let result_with = |antithetic: bool, moment_matching: MomentMatching| {
+            let mut model_factory = BlackDiffusionFactory::new(20, 100.0,
+                4000);
+            model_factory.set_antithetic(antithetic);
+            model_factory.set_moment_matching(moment_matching);
+            let factory = MonteCarloPricerFactory::new(Box::new(
+                model_factory));
+            let pricer = factory.new_mc_pricer(sample_european(),
+                fixings.clone(), market_data.clone()).unwrap();
+            pricer.mc_result().unwrap()
+        };
+
+        let plain = result_with(false, MomentMatching::Off);
+        assert_eq!(plain.variance_reduction(), VarianceReduction::none());
+
+        // The options used are recorded in the result. Antithetic pairs
+        // cancel the linear part of the payoff, so they reduce the error,
+        // though the kink of the call means they cannot remove it.
+        let antithetic = result_with(true, MomentMatching::Off);
+        assert!(antithetic.variance_reduction().antithetic());
+        assert!(antithetic.standard_error() < 0.9 * plain.standard_error(),
+            "antithetic={} plain={}", antithetic.standard_error(),
+            plain.standard_error());
+        assert!((antithetic.mean() - black76_price).abs()
+            < 5.0 * antithetic.standard_error());
+        assert!(antithetic.convergence(1001).is_err());
+        assert!(antithetic.difference(&plain).is_err());
+
+        // With the variance matched as well, the price is typically within
+        // a fifth of the plain standard error of Black76
+        let matched = result_with(true, MomentMatching::MeanAndVariance);
+        assert_eq!(matched.variance_reduction(), VarianceReduction::new(
+            true, MomentMatching::MeanAndVariance));
+        assert_approx(matched.mean(), black76_price, 0.3);
+
+        // Antithetic pairs need an even number of paths
+        let mut model_factory = BlackDiffusionFactory::new(20, 0.01, 999);
+        model_factory.set_antithetic(true);
+        let factory = MonteCarloPricerFactory::new(Box::new(model_factory));
+        assert!(factory.new_mc_pricer(sample_european(), fixings,
+            market_data).is_err());
+    }
+
+    fn european_call(strike: f64) -> Arc<Instrument> {
+        let currency = Arc::new(sample_currency(2));
+        let equity = Arc::new(sample_equity(currency, 2));
+        let expiry = DateTime::new(Date::from_ymd(2018, 06, 01),
+            TimeOfDay::Close);
+        Arc::new(SpotStartingEuropean::new(&format!("Call{}", strike), "OPT",
+            equity, sample_settlement(2), expiry, strike, PutOrCall::Call,
+            OptionSettlement::Cash).unwrap())
+    }
+
+    #[test]
+    fn monte_carlo_control_variates() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let fixings = Arc::new(sample_fixings());
+        let factory = MonteCarloPricerFactory::new(Box::new(
+            BlackDiffusionFactory::new(20, 0.01, 20000)));
+        let analytic = SelfPricerFactory::new().new(sample_european(),
+            fixings.clone(), market_data.clone()).unwrap().price().unwrap();
+
+        // An instrument that controls itself is priced exactly
+        let pricer = factory.new_mc_pricer_with_controls(sample_european(),
+            &[sample_european()], fixings.clone(), market_data.clone())
+            .unwrap();
+        let result = pricer.mc_result().unwrap();
+        assert_approx(result.mean(), analytic, 1e-8);
+        assert!(result.standard_error() < 1e-8);
+        assert_approx(result.control_betas()[0], 1.0, 1e-8);
+        assert_approx(pricer.price().unwrap(), result.mean(), 1e-12);
+
+        // Calls either side of the strike are good controls for the at the
+        // money call, cutting the error several times over
+        let mut pricer = factory.new_mc_pricer_with_controls(
+            sample_european(), &[european_call(90.0), european_call(110.0)],
+            fixings.clone(), market_data.clone()).unwrap();
+        let uncontrolled = factory.new_mc_pricer(sample_european(),
+            fixings.clone(), market_data.clone()).unwrap().mc_result()
+            .unwrap();
+        let result = pricer.mc_result().unwrap();
+        assert_eq!(result.control_betas().len(), 2);
+        assert!(result.standard_error() < 0.25 * uncontrolled.standard_error(),
+            "controlled={} uncontrolled={}", result.standard_error(),
+            uncontrolled.standard_error());
+        assert!((result.mean() - analytic).abs()
+            < 5.0 * result.standard_error(), "mean={} analytic={} error={}",
+            result.mean(), analytic, result.standard_error());
+
+        // The controls are rolled along with the instrument
+        let bump = BumpTime::new(Date::from_ymd(2017, 01, 09),
+            SpotDynamics::StickyForward);
+        let mut self_pricer = SelfPricerFactory::new().new(sample_european(),
+            fixings, market_data).unwrap();
+        pricer.as_mut_time_bumpable().bump_time(&bump).unwrap();
+        self_pricer.as_mut_time_bumpable().bump_time(&bump).unwrap();
+        let result = pricer.mc_result().unwrap();
+        let analytic = self_pricer.price().unwrap();
+        assert!((result.mean() - analytic).abs()
+            < 5.0 * result.standard_error(), "mean={} analytic={} error={}",
+            result.mean(), analytic, result.standard_error());
+    }
+
+    #[test]
+    fn monte_carlo_on_several_threads() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let fixings = Arc::new(sample_fixings());
+        let analytic = SelfPricerFactory::new().new(sample_european(),
+            fixings.clone(), market_data.clone()).unwrap().price().unwrap();
+
+        let pricer_with = |n_threads: usize| {
+            let mut model_factory = BlackDiffusionFactory::new(20, 0.01,
+                20000);
+            model_factory.set_random_sequence(
+                RandomSequence::SeededPseudoRandom(7));
+            model_factory.set_path_block_size(2500);
+            model_factory.set_threads(n_threads);
+            let factory = MonteCarloPricerFactory::new(Box::new(
+                model_factory));
+            factory.new_mc_pricer(sample_european(), fixings.clone(),
+                market_data.clone()).unwrap()
+        };
+
+        // The paths come from the blocks, not the threads, so the results
+        // are identical, both before and after a bump
+        let mut single = pricer_with(1);
+        let mut parallel = pricer_with(4);
+        let result = single.mc_result().unwrap();
+        assert_eq!(parallel.price().unwrap(), single.price().unwrap());
+        assert_eq!(parallel.mc_result().unwrap().path_values(),
+            result.path_values());
+        assert_approx(single.price().unwrap(), result.mean(), 1e-10);
+        assert!((result.mean() - analytic).abs()
+            < 5.0 * result.standard_error(), "mean={} analytic={} error={}",
+            result.mean(), analytic, result.standard_error());
+
+        let bump = BumpSpot::new_relative(0.01);
+        for pricer in [&mut single, &mut parallel].iter_mut() {
+            let mut save = pricer.as_bumpable().new_saveable();
+            pricer.as_mut_bumpable().bump_spot("BP.L", &bump, &mut *save)
+                .unwrap();
+        }
+        assert_eq!(parallel.price().unwrap(), single.price().unwrap());
+
+        // Pricers can be moved to other threads, sharing the market data
+        fn assert_send_sync<T: Send + Sync + ?Sized>() {}
+        assert_send_sync::<Pricer>();
+        assert_send_sync::<MarketData>();
+        let price = single.price().unwrap();
+        let moved: Box<Pricer> = Box::new(single);
+        let handle = ::std::thread::spawn(move || moved.price().unwrap());
+        assert_eq!(handle.join().unwrap(), price);
+    }
+
+    #[test]
+    fn forked_pricer_is_independent() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let factory = MonteCarloPricerFactory::new(Box::new(
+            BlackDiffusionFactory::new(20, 0.01, 5000)));
+        let mut pricer = factory.new(sample_european(),
+            Arc::new(sample_fixings()), market_data).unwrap();
+        let unbumped = pricer.price().unwrap();
+
+        // the fork has the same paths, and its bumps do not leak back
+        let mut fork = pricer.clone_box();
+        assert_eq!(fork.price().unwrap(), unbumped);
+        let mut save = fork.as_bumpable().new_saveable();
+        fork.as_mut_bumpable().bump_spot("BP.L",
+            &BumpSpot::new_relative(0.01), &mut *save).unwrap();
+        let bumped = fork.price().unwrap();
+        assert!(bumped > unbumped + 0.1, "bumped={}", bumped);
+        assert_eq!(pricer.price().unwrap(), unbumped);
+
+        // a fork of a bumped pricer is bumped too
+        save.clear();
+        pricer.as_mut_bumpable().bump_spot("BP.L",
+            &BumpSpot::new_relative(0.01), &mut *save).unwrap();
+        assert_eq!(pricer.clone_box().price().unwrap(), bumped);
+    }
+
     fn assert_approx(value: f64, expected: f64, tolerance: f64) {
         assert!(approx_eq(value, expected, tolerance),
             "value={} expected={}", value, expected);
@@ -0,0 +1,927 @@
+use core::qm;
+use std::sync::Arc;
+use dates::Date;
+use dates::datetime::DateDayFraction;
+use instruments::Instrument;
+use instruments::PricingContext;
+use instruments::DependencyContext;
+use instruments::PdePriceable;
+use instruments::EarlyExercise;
+use risk::cache::PricingContextPrefetch;
+use risk::Pricer;
+use risk::dependencies::DependencyCollector;
+use risk::Bumpable;
+use risk::BumpablePricingContext;
+use risk::TimeBumpable;
+use risk::Saveable;
+use pricers::PricerFactory;
+use data::fixings::FixingTable;
+use data::bumpspot::BumpSpot;
+use data::bumptime::BumpTime;
+use data::bumpvol::BumpVol;
+use data::bumpcorrelation::BumpCorrelation;
+use data::bumpdivs::BumpDivs;
+use data::bumpyield::BumpYield;
+use data::forward::Forward;
+use data::volsurface::VolSurface;
+use data::volsurface::VolQuoting;
+use data::volsurface::DivAssumptions;
+use models::substep_dates;
+use models::localvol::LocalVarianceStep;
+use risk::marketdata::MarketData;
+
+/// Number of fully implicit time steps, each split into two half steps,
+/// taken at the start of the backward induction and after every dividend,
+/// to damp the oscillations Crank-Nicolson suffers from non-smooth values.
+const RANNACHER_STEPS: usize = 2;
+
+/// Selects the dynamics of the underlying on the finite difference grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PdeDynamics {
+    /// Log-normal between dividends, with a term structure of vols taken
+    /// from the vol surface at the strike of the instrument, or at the
+    /// money if it has no strike (see PdePriceable::pde_strike). This
+    /// matches the JumpDivs assumptions, and is the usual way of valuing
+    /// listed Americans.
+    Black,
+
+    /// Dupire local vol, calibrated to the whole vol surface
+    LocalVol
+}
+
+/// The PdePricer uses the PdePriceable interface of an instrument to value
+/// it by backward induction on a one-dimensional finite difference grid. It
+/// exposes this as a Pricer, allowing bumping for risk calculation, in the
+/// same way as the SelfPricer.
+pub struct PdePricer {
+    instruments: Vec<(f64, Arc<Instrument>)>,
+    context: PricingContextPrefetch,
+    scheme: FiniteDifferenceScheme
+}
+
+/// The PdePricerFactory is used to construct PdePricer pricers. It is
+/// parameterised by the finite difference scheme.
+pub struct PdePricerFactory {
+    scheme: FiniteDifferenceScheme
+}
+
+impl PdePricerFactory {
+    /// Creates a factory. See FiniteDifferenceScheme::new for the meaning of
+    /// the parameters.
+    pub fn new(dynamics: PdeDynamics, time_steps: usize, space_steps: usize,
+        std_devs: f64) -> Result<PdePricerFactory, qm::Error> {
+        let scheme = FiniteDifferenceScheme::new(dynamics, time_steps,
+            space_steps, std_devs)?;
+        Ok(PdePricerFactory { scheme: scheme })
+    }
+}
+
+impl PricerFactory for PdePricerFactory {
+    fn new(&self, instrument: Arc<Instrument>, fixing_table: Arc<FixingTable>,
+        market_data: Arc<MarketData>) -> Result<Box<Pricer>, qm::Error> {
+
+        // Apply the fixings to the instrument. (This is the last time we need
+        // the fixings.)
+        let instruments = match instrument.fix(&*fixing_table)? {
+            Some(fixed) => fixed,
+            None => vec!((1.0, instrument))
+        };
+
+        // Find the dependencies of the resulting vector of instruments.
+        // Instruments that have fixed may be simple flows such as zero
+        // coupons, which are not PDE-priceable but can price themselves.
+        let mut dependencies = DependencyCollector::new(
+            market_data.spot_date());
+        for &(_, ref instr) in instruments.iter() {
+            dependencies.spot(instr);
+            if instr.as_pde_priceable().is_none()
+                && instr.as_priceable().is_none() {
+                return Err(qm::Error::new(&format!("Instrument {} is not \
+                    pde priceable", instr.id())))
+            }
+        }
+
+        // Create a cached pricing context, prefetching the data to price them
+        let context = PricingContextPrefetch::new(&*market_data,
+            Arc::new(dependencies))?;
+
+        Ok(Box::new(PdePricer { instruments: instruments, context: context,
+            scheme: self.scheme.clone() }))
+    }
+}
+
+impl Pricer for PdePricer {
+    fn as_bumpable(&self) -> &Bumpable { self }
+    fn as_mut_bumpable(&mut self) -> &mut Bumpable { self }
+    fn as_mut_time_bumpable(&mut self) -> &mut TimeBumpable { self }
+
+    fn instruments(&self) -> &[(f64, Arc<Instrument>)] {
+        &self.instruments
+    }
+
+    fn as_pricing_context(&self) -> &PricingContext {
+        &self.context
+    }
+
+    fn clone_box(&self) -> Box<Pricer> {
+        Box::new(PdePricer { instruments: self.instruments.clone(),
+            context: self.context.clone(), scheme: self.scheme.clone() })
+    }
+
+    fn price(&self) -> Result<f64, qm::Error> {
+        let mut total = 0.0;
+        for &(weight, ref instrument) in self.instruments.iter() {
+            if let Some(pde) = instrument.as_pde_priceable() {
+                total += weight * self.scheme.price(pde, &self.context)?;
+            } else if let Some(priceable) = instrument.as_priceable() {
+                total += weight * priceable.price(&self.context)?;
+            }
+        }
+        Ok(total)
+    }
+}
+
+impl Bumpable for PdePricer {
+    fn bump_spot(&mut self, id: &str, bump: &BumpSpot,
+        save: &mut Saveable) -> Result<bool, qm::Error> {
+        self.context.bump_spot(id, bump, save)
+    }
+
+    fn bump_yield(&mut self, credit_id: &str, bump: &BumpYield,
+        save: &mut Saveable) -> Result<bool, qm::Error> {
+        self.context.bump_yield(credit_id, bump, save)
+    }
+
+    fn bump_borrow(&mut self, id: &str, bump: &BumpYield,
+        save: &mut Saveable) -> Result<bool, qm::Error> {
+        self.context.bump_borrow(id, bump, save)
+    }
+
+    fn bump_divs(&mut self, id: &str, bump: &BumpDivs,
+        save: &mut Saveable) -> Result<bool, qm::Error> {
+        self.context.bump_divs(id, bump, save)
+    }
+
+    fn bump_vol(&mut self, id: &str, bump: &BumpVol,
+        save: &mut Saveable) -> Result<bool, qm::Error> {
+        self.context.bump_vol(id, bump, save)
+    }
+
+    fn bump_correlation(&mut self, first: &str, second: &str,
+        bump: &BumpCorrelation, save: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        self.context.bump_correlation(first, second, bump, save)
+    }
+
+    fn bump_discount_date(&mut self, replacement: Date, save: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        self.context.bump_discount_date(replacement, save)
+    }
+
+    fn forward_id_by_credit_id(&self, credit_id: &str)
+        -> Result<&[String], qm::Error> {
+        self.context.forward_id_by_credit_id(credit_id)
+    }
+
+    fn new_saveable(&self) -> Box<Saveable> {
+        self.context.new_saveable()
+    }
+
+    fn restore(&mut self, saved: &Saveable) -> Result<(), qm::Error> {
+        self.context.restore(saved)
+    }
+}
+
+impl TimeBumpable for PdePricer {
+    fn bump_time(&mut self, bump: &BumpTime) -> Result<(), qm::Error> {
+
+        // work on copies, so the pricer is unchanged if anything fails
+        let mut instruments = self.instruments.clone();
+        let mut context = self.context.clone();
+        if !context.bump_time(bump, &mut instruments)? {
+            return Ok(())
+        }
+
+        for &(_, ref instr) in instruments.iter() {
+            if instr.as_pde_priceable().is_none()
+                && instr.as_priceable().is_none() {
+                return Err(qm::Error::new(&format!("Instrument {} is not \
+                    pde priceable", instr.id())))
+            }
+        }
+
+        self.instruments = instruments;
+        self.context = context;
+        Ok(())
+    }
+}
+
+/// A Crank-Nicolson finite difference scheme in x = ln(S/F), where F is the
+/// forward of the underlying. In these coordinates the underlying is a
+/// martingale between dividends, so there is no drift term other than the
+/// convexity correction, and values rolled back on the grid are PVs to the
+/// discount date, so there is no discounting term either.
+///
+/// Each time step solves
+///
+///  (I - theta A) V(t1) = (I + (1 - theta) A) V(t2)
+///
+/// where A = v(x) (1/2 d2/dx2 - 1/2 d/dx), and v is the variance over the
+/// step, which depends on x for local vol. Theta is a half for
+/// Crank-Nicolson, or one for the implicit steps of the Rannacher start-up.
+/// At the edges of the grid, we assume the value is linear in spot.
+///
+/// Cash and relative dividends are applied as jump conditions on their ex
+/// dates. The spot just before the ex date, S, becomes S (1 - relative) -
+/// cash just after, so we interpolate the values from after the jump. The
+/// forward before the jump is implied in the same way from the forward after
+/// it, so the forward is repriced exactly.
+#[derive(Clone, Debug)]
+pub struct FiniteDifferenceScheme {
+    dynamics: PdeDynamics,
+    time_steps: usize,
+    space_steps: usize,
+    std_devs: f64
+}
+
+impl FiniteDifferenceScheme {
+    /// Creates a scheme. The time_steps is the approximate number of steps
+    /// to expiry, which are spread in proportion to vol time. Extra steps
+    /// are added at dividend and exercise dates. The space_steps is the
+    /// number of intervals in the grid, which is centred on the forward and
+    /// extends std_devs standard deviations of the at-the-money variance to
+    /// expiry either side.
+    pub fn new(dynamics: PdeDynamics, time_steps: usize, space_steps: usize,
+        std_devs: f64) -> Result<FiniteDifferenceScheme, qm::Error> {
+
+        if time_steps == 0 {
+            return Err(qm::Error::new("PDE needs at least one time step"))
+        }
+        if space_steps < 4 {
+            return Err(qm::Error::new("PDE needs at least four space steps"))
+        }
+        if !(std_devs > 0.0) {
+            return Err(qm::Error::new("PDE grid width must be positive"))
+        }
+
+        Ok(FiniteDifferenceScheme { dynamics: dynamics,
+            time_steps: time_steps, space_steps: space_steps,
+            std_devs: std_devs })
+    }
+
+    /// Values the instrument by backward induction, returning the PV to the
+    /// discount date of the context
+    pub fn price(&self, instrument: &PdePriceable, context: &PricingContext)
+        -> Result<f64, qm::Error> {
+
+        let underlying = instrument.pde_underlying();
+        let expiry = instrument.pde_expiry();
+        let spot_date = context.spot_date();
+        if expiry.date() < spot_date {
+            return Err(qm::Error::new("You should fix the instrument before \
+                pricing it, so it does not expire in the past"))
+        }
+
+        // fetch the market data we need
+        let hwm = expiry.date();
+        let forward = context.forward_curve(&**underlying, hwm)?;
+        let vol = context.vol_surface(&**underlying, forward.clone(), hwm)?;
+        validate_vol_surface(&*vol)?;
+
+        // the key dates in the backward induction, starting with the spot
+        // date, and then any dividends and exercise dates
+        let start = DateDayFraction::new(spot_date, 0.0);
+        let dividends = dividend_jumps(&*forward, spot_date, expiry.date());
+        let exercise = instrument.pde_exercise();
+        let strike = instrument.pde_strike();
+        let mut key_dates: Vec<DateDayFraction> = dividends.iter()
+            .map(|d| DateDayFraction::new(d.ex_date, 0.0)).collect();
+        if let EarlyExercise::Bermudan(ref dates) = exercise {
+            key_dates.extend(dates.iter()
+                .filter(|d| **d > start && **d < expiry));
+        }
+        key_dates.push(expiry);
+        key_dates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        key_dates.dedup();
+
+        // split the time between key dates in proportion to vol time
+        let start_time = vol.vol_time(start)?;
+        let total_time = vol.vol_time(expiry)? - start_time;
+        let mut substepping = Vec::with_capacity(key_dates.len());
+        let mut prev_time = start_time;
+        for date in key_dates.iter() {
+            let time = vol.vol_time(*date)?;
+            let fraction = if total_time > 0.0 {
+                (time - prev_time) / total_time } else { 0.0 };
+            let steps = (fraction * self.time_steps as f64).ceil() as usize;
+            substepping.push(steps.max(1));
+            prev_time = time;
+        }
+        let mut dates = vec![start];
+        dates.extend(substep_dates(start, &key_dates, &substepping));
+
+        // The grid, with a node on x = 0, which is where we want the price
+        let forward_at_expiry = forward.forward(expiry.date())?;
+        let atm_variance = vol.variance(expiry, forward_at_expiry)?;
+        let half_width = self.std_devs * atm_variance.max(1e-6).sqrt();
+        let m = (self.space_steps + 1) / 2;
+        let n = 2 * m + 1;
+        let dx = half_width / (m as f64);
+        let xs: Vec<f64> = (0..n).map(|i| (i as f64 - m as f64) * dx).collect();
+
+        // Values at expiry
+        let mut spots = vec![0.0; n];
+        let mut values = vec![0.0; n];
+        let mut exercise_values = vec![0.0; n];
+        set_spots(&xs, forward_at_expiry, &mut spots);
+        instrument.pde_exercise_values(context, expiry.date(), &spots,
+            &mut values)?;
+
+        // Roll back, step by step
+        let mut grid = Grid::new(n, dx);
+        let mut rannacher = RANNACHER_STEPS;
+        for k in (1..dates.len()).rev() {
+            let from = dates[k - 1];
+            let to = dates[k];
+
+            // variances over the step, at each node
+            match self.dynamics {
+                PdeDynamics::Black => {
+                    let to_var = vol.variance(to, match strike {
+                        Some(strike) => strike,
+                        None => forward.forward(to.date())? })?;
+                    let from_var = if k == 1 { 0.0 } else {
+                        vol.variance(from, match strike {
+                            Some(strike) => strike,
+                            None => forward.forward(from.date())? })? };
+                    let v = (to_var - from_var).max(0.0);
+                    for var in grid.variances.iter_mut() {
+                        *var = v;
+                    }
+                },
+                PdeDynamics::LocalVol => {
+                    let step = LocalVarianceStep::new(&*vol, &*forward,
+                        if k == 1 { None } else { Some(from) }, to)?;
+                    for (var, x) in grid.variances.iter_mut().zip(xs.iter()) {
+                        *var = step.variance(*x);
+                    }
+                }
+            }
+
+            if rannacher > 0 {
+                grid.step(&mut values, 0.5, 1.0);
+                grid.step(&mut values, 0.5, 1.0);
+                rannacher -= 1;
+            } else {
+                grid.step(&mut values, 1.0, 0.5);
+            }
+
+            // We are now at the from date. Apply any dividends going ex
+            // on this date.
+            let date = from.date();
+            let mut forward_here = forward.forward(date)?;
+            if let Some(div) = dividends.iter().find(|d|
+                k > 1 && d.ex_date == date && from.day_fraction() == 0.0) {
+
+                forward_here = div.apply(&xs, forward_here, &mut values);
+                rannacher = RANNACHER_STEPS;
+            }
+
+            // Apply any early exercise
+            let exercisable = k > 1 && match exercise {
+                EarlyExercise::None => false,
+                EarlyExercise::American(first) => from >= first,
+                EarlyExercise::Bermudan(ref dates) => dates.contains(&from)
+            };
+            if exercisable {
+                set_spots(&xs, forward_here, &mut spots);
+                instrument.pde_exercise_values(context, date, &spots,
+                    &mut exercise_values)?;
+                for (value, exercise) in values.iter_mut()
+                    .zip(exercise_values.iter()) {
+                    *value = value.max(*exercise);
+                }
+            }
+        }
+
+        Ok(values[m])
+    }
+}
+
+fn validate_vol_surface(vol: &VolSurface) -> Result<(), qm::Error> {
+    let quoting = vol.vol_quoting();
+    if quoting != VolQuoting::LogNormal {
+        return Err(qm::Error::new(&format!("The PDE pricer requires a \
+            log-normal vol surface, not {:?}", quoting)))
+    }
+    if vol.div_assumptions() == DivAssumptions::FixedDivs {
+        return Err(qm::Error::new("The PDE pricer does not support \
+            FixedDivs vol surfaces, which are displaced"))
+    }
+    Ok(())
+}
+
+fn set_spots(xs: &[f64], forward: f64, spots: &mut [f64]) {
+    for (spot, x) in spots.iter_mut().zip(xs.iter()) {
+        *spot = forward * x.exp();
+    }
+}
+
+/// All the dividends going ex on a single date
+struct DividendJump {
+    ex_date: Date,
+    cash: f64,
+    relative: f64
+}
+
+impl DividendJump {
+    /// Applies the jump condition to the values, which are on entry the
+    /// values just after the dividend, and on exit the values just before.
+    /// Takes the forward after the dividend and returns the forward before.
+    fn apply(&self, xs: &[f64], forward_after: f64, values: &mut [f64])
+        -> f64 {
+
+        let keep = 1.0 - self.relative;
+        let forward_before = (forward_after + self.cash) / keep;
+        let after = values.to_vec();
+        let n = xs.len();
+        let dx = xs[1] - xs[0];
+        for (value, x) in values.iter_mut().zip(xs.iter()) {
+
+            // the spot cannot go negative, so limit the cash
+            let spot_before = forward_before * x.exp();
+            let spot_after = (spot_before * keep - self.cash)
+                .max(spot_before * 1e-6);
+            let x_after = (spot_after / forward_after).ln();
+
+            // interpolate linearly, extrapolating flat
+            let pos = (x_after - xs[0]) / dx;
+            *value = if !(pos > 0.0) {
+                after[0]
+            } else if pos >= (n - 1) as f64 {
+                after[n - 1]
+            } else {
+                let i = pos.floor() as usize;
+                let frac = pos - i as f64;
+                after[i] * (1.0 - frac) + after[i + 1] * frac
+            };
+        }
+        forward_before
+    }
+}
+
+/// Collects the dividends from the forward that go ex after the spot date,
+/// up to and including the expiry date, grouping any on the same date
+fn dividend_jumps(forward: &Forward, spot_date: Date, expiry_date: Date)
+    -> Vec<DividendJump> {
+
+    let mut jumps: Vec<DividendJump> = Vec::new();
+    for div in forward.dividends().iter() {
+        let ex_date = div.ex_date();
+        if ex_date <= spot_date || ex_date > expiry_date {
+            continue
+        }
+        if let Some(last) = jumps.last_mut() {
+            if last.ex_date == ex_date {
+                last.cash = last.cash * (1.0 - div.relative()) + div.cash();
+                last.relative = 1.0 - (1.0 - last.relative)
+                    * (1.0 - div.relative());
+                continue
+            }
+        }
+        jumps.push(DividendJump { ex_date: ex_date, cash: div.cash(),
+            relative: div.relative() });
+    }
+    jumps
+}
+
+/// Working space for the time steps
+struct Grid {
+    dx: f64,
+    variances: Vec<f64>,
+    lower: Vec<f64>,
+    diag: Vec<f64>,
+    upper: Vec<f64>,
+    rhs: Vec<f64>
+}
+
+impl Grid {
+    fn new(n: usize, dx: f64) -> Grid {
+        Grid { dx: dx, variances: vec![0.0; n], lower: vec![0.0; n],
+            diag: vec![0.0; n], upper: vec![0.0; n], rhs: vec![0.0; n] }
+    }
+
+    /// Takes a fraction of a time step backwards, with the given theta,
+    /// updating the values in place
+    fn step(&mut self, values: &mut [f64], fraction: f64, theta: f64) {
+        let n = values.len();
+        let dx = self.dx;
+        let a = 0.5 / (dx * dx);
+        let b = 0.25 / dx;
+
+        // The operator A at each interior node, as lower, diag and upper
+        // coefficients, and the explicit part of the step
+        for i in 1..(n - 1) {
+            let v = self.variances[i] * fraction;
+            let l = v * (a + b);
+            let c = -2.0 * v * a;
+            let u = v * (a - b);
+            let explicit = 1.0 - theta;
+            self.rhs[i] = values[i] + explicit * (l * values[i - 1]
+                + c * values[i] + u * values[i + 1]);
+            self.lower[i] = -theta * l;
+            self.diag[i] = 1.0 - theta * c;
+            self.upper[i] = -theta * u;
+        }
+
+        // Eliminate the boundary nodes, using linearity in spot. With a
+        // uniform grid in log spot, this gives V0 = (1 + c0) V1 - c0 V2 at
+        // the bottom and similarly at the top.
+        let c0 = (-dx).exp();
+        let cn = dx.exp();
+        self.diag[1] += self.lower[1] * (1.0 + c0);
+        self.upper[1] -= self.lower[1] * c0;
+        self.diag[n - 2] += self.upper[n - 2] * (1.0 + cn);
+        self.lower[n - 2] -= self.upper[n - 2] * cn;
+
+        // Thomas algorithm on the interior nodes
+        for i in 2..(n - 1) {
+            let w = self.lower[i] / self.diag[i - 1];
+            self.diag[i] -= w * self.upper[i - 1];
+            self.rhs[i] -= w * self.rhs[i - 1];
+        }
+        values[n - 2] = self.rhs[n - 2] / self.diag[n - 2];
+        for i in (1..(n - 2)).rev() {
+            values[i] = (self.rhs[i] - self.upper[i] * values[i + 1])
+                / self.diag[i];
+        }
+
+        values[0] = (1.0 + c0) * values[1] - c0 * values[2];
+        values[n - 1] = (1.0 + cn) * values[n - 2] - cn * values[n - 3];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use dates::datetime::DateTime;
+    use dates::datetime::TimeOfDay;
+    use math::numerics::approx_eq;
+    use instruments::Priceable;
+    use instruments::SpotRequirement;
+    use instruments::assets::Currency;
+    use instruments::options::SpotStartingEuropean;
+    use instruments::options::PutOrCall;
+    use instruments::options::OptionSettlement;
+    use dates::rules::DateRule;
+    use risk::marketdata::tests::sample_market_data;
+    use risk::marketdata::tests::create_sample_skewed_vol;
+    use risk::marketdata::tests::create_sample_flat_vol;
+    use risk::marketdata::tests::create_sample_rate;
+    use risk::marketdata::tests::create_sample_borrow;
+    use data::divstream::DividendStream;
+    use data::curves::ZeroRateCurve;
+    use std::collections::HashMap;
+    use risk::marketdata::tests::sample_european;
+    use risk::marketdata::tests::sample_currency;
+    use risk::marketdata::tests::sample_settlement;
+    use risk::marketdata::tests::sample_equity;
+    use data::divstream::Dividend;
+    use data::forward::DriftlessForward;
+    use data::volsmile::CubicSplineSmile;
+    use data::volsurface::VolByProbability;
+    use dates::calendar::WeekdayCalendar;
+    use pricers::montecarlo::MonteCarloPricerFactory;
+    use models::blackdiffusion::BlackDiffusionFactory;
+    use pricers::selfpricer::SelfPricerFactory;
+    use data::bumptime::SpotDynamics;
+
+    fn sample_fixings() -> Arc<FixingTable> {
+        let today = Date::from_ymd(2017, 01, 02);
+        Arc::new(FixingTable::new(today, &[
+            ("BP.L", &[
+            (DateTime::new(today - 7, TimeOfDay::Close), 102.0)])]).unwrap())
+    }
+
+    fn european(strike: f64, put_or_call: PutOrCall)
+        -> Arc<SpotStartingEuropean> {
+        let expiry = DateTime::new(
+            Date::from_ymd(2018, 06, 01), TimeOfDay::Close);
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let equity = Arc::new(sample_equity(currency, 2));
+        Arc::new(SpotStartingEuropean::new("SampleEquity", "OPT",
+            equity, settlement, expiry, strike, put_or_call,
+            OptionSettlement::Cash).unwrap())
+    }
+
+    /// Test-only wrapper that allows a European to be exercised early
+    struct EarlyExercisable {
+        european: Arc<SpotStartingEuropean>,
+        exercise: EarlyExercise
+    }
+
+    impl Instrument for EarlyExercisable {
+        fn id(&self) -> &str { "EarlyExercisable" }
+        fn payoff_currency(&self) -> &Currency {
+            self.european.payoff_currency() }
+        fn credit_id(&self) -> &str { self.european.credit_id() }
+        fn settlement(&self) -> &Arc<DateRule> { self.european.settlement() }
+        fn dependencies(&self, context: &mut DependencyContext)
+            -> SpotRequirement { self.european.dependencies(context) }
+        fn as_pde_priceable(&self) -> Option<&PdePriceable> { Some(self) }
+    }
+
+    impl PdePriceable for EarlyExercisable {
+        fn as_instrument(&self) -> &Instrument { self }
+        fn pde_underlying(&self) -> &Arc<Instrument> {
+            self.european.pde_underlying() }
+        fn pde_expiry(&self) -> DateDayFraction {
+            self.european.pde_expiry() }
+        fn pde_exercise(&self) -> EarlyExercise { self.exercise.clone() }
+        fn pde_strike(&self) -> Option<f64> { self.european.pde_strike() }
+        fn pde_exercise_values(&self, context: &PricingContext, date: Date,
+            spots: &[f64], values: &mut [f64]) -> Result<(), qm::Error> {
+            self.european.pde_exercise_values(context, date, spots, values)
+        }
+    }
+
+    fn pde_price(instrument: Arc<Instrument>, market_data: MarketData,
+        dynamics: PdeDynamics) -> f64 {
+        let factory = PdePricerFactory::new(dynamics, 200, 400, 5.0).unwrap();
+        let pricer = factory.new(instrument, sample_fixings(),
+            Arc::new(market_data)).unwrap();
+        pricer.price().unwrap()
+    }
+
+    /// The sample market data, but without discrete dividends, so that the
+    /// closed form prices are exact for the log-normal dynamics of the PDE
+    fn no_div_market_data(vol: Arc<VolSurface>) -> MarketData {
+        let spot_date = Date::from_ymd(2017, 01, 02);
+        let mut spots = HashMap::new();
+        spots.insert("BP.L".to_string(), 100.0);
+
+        let mut dividends = HashMap::new();
+        dividends.insert("BP.L".to_string(), Arc::new(DividendStream::new(&[],
+            Arc::new(ZeroRateCurve::new(spot_date)))));
+
+        let mut yield_curves = HashMap::new();
+        yield_curves.insert("OPT".to_string(), create_sample_rate());
+        yield_curves.insert("LSE".to_string(), create_sample_rate());
+
+        let mut borrow_curves = HashMap::new();
+        borrow_curves.insert("BP.L".to_string(), create_sample_borrow());
+
+        let mut vol_surfaces = HashMap::new();
+        vol_surfaces.insert("BP.L".to_string(), vol);
+
+        MarketData::new(spot_date, None, spots, yield_curves,
+            borrow_curves, dividends, vol_surfaces)
+    }
+
+    /// A flat 30% vol surface with the given dividend assumptions
+    fn flat_vol_with_divs(div_assumptions: DivAssumptions)
+        -> Arc<VolSurface> {
+        let base_date = Date::from_ymd(2016, 12, 30);
+        let base = DateDayFraction::new(base_date, 0.2);
+        let flat = [(50.0, 0.3), (100.0, 0.3), (200.0, 0.3)];
+        let smiles = [
+            (DateDayFraction::new(base_date + 182, 0.7),
+                CubicSplineSmile::new(&flat).unwrap()),
+            (DateDayFraction::new(base_date + 730, 0.7),
+                CubicSplineSmile::new(&flat).unwrap())];
+        Arc::new(VolByProbability::new(&smiles,
+            Box::new(WeekdayCalendar()), base,
+            Box::new(DriftlessForward::new(100.0)), div_assumptions)
+            .unwrap())
+    }
+
+    /// Market data with large cash dividends, so that the way they are
+    /// handled makes a visible difference to the price
+    fn large_div_market_data(vol: Arc<VolSurface>) -> MarketData {
+        let spot_date = Date::from_ymd(2017, 01, 02);
+        let mut spots = HashMap::new();
+        spots.insert("BP.L".to_string(), 100.0);
+
+        let divs = [
+            Dividend::new(5.0, 0.0, spot_date + 91, spot_date + 93),
+            Dividend::new(5.0, 0.0, spot_date + 273, spot_date + 275)];
+        let mut dividends = HashMap::new();
+        dividends.insert("BP.L".to_string(), Arc::new(DividendStream::new(
+            &divs, Arc::new(ZeroRateCurve::new(spot_date)))));
+
+        let mut yield_curves = HashMap::new();
+        yield_curves.insert("OPT".to_string(), create_sample_rate());
+        yield_curves.insert("LSE".to_string(), create_sample_rate());
+
+        let mut borrow_curves = HashMap::new();
+        borrow_curves.insert("BP.L".to_string(), create_sample_borrow());
+
+        let mut vol_surfaces = HashMap::new();
+        vol_surfaces.insert("BP.L".to_string(), vol);
+
+        MarketData::new(spot_date, None, spots, yield_curves,
+            borrow_curves, dividends, vol_surfaces)
+    }
+
+    #[test]
+    fn pde_european_matches_closed_form() {
+        for &(strike, put_or_call) in [(100.0, PutOrCall::Call),
+            (80.0, PutOrCall::Put), (130.0, PutOrCall::Call)].iter() {
+            let option = european(strike, put_or_call);
+            let market_data = no_div_market_data(create_sample_flat_vol());
+            let expected = option.price(&market_data).unwrap();
+            let price = pde_price(option, market_data, PdeDynamics::Black);
+            assert_approx(price, expected, 0.002);
+        }
+    }
+
+    #[test]
+    fn pde_black_reprices_skewed_europeans() {
+
+        // Black dynamics read the vols at the strike of the option, so the
+        // skew is seen away from the money
+        for &(strike, put_or_call) in [(100.0, PutOrCall::Call),
+            (70.0, PutOrCall::Put), (130.0, PutOrCall::Call)].iter() {
+            let option = european(strike, put_or_call);
+            let market_data = no_div_market_data(create_sample_skewed_vol());
+            let expected = option.price(&market_data).unwrap();
+            let price = pde_price(option, market_data, PdeDynamics::Black);
+            assert_approx(price, expected, 0.002);
+        }
+    }
+
+    #[test]
+    fn pde_dividend_jumps_reprice_forward() {
+
+        // A call with a tiny strike is worth the discounted forward, whatever
+        // the dynamics, so this checks the jump conditions at the ex dates
+        let option = european(1e-4, PutOrCall::Call);
+        let market_data = sample_market_data();
+        let expected = option.price(&market_data).unwrap();
+        let price = pde_price(option, market_data, PdeDynamics::Black);
+        assert_approx(price, expected, 1e-3);
+
+        // Spot is log-normal between the cash dividends, so at the money the
+        // price is a little above the closed form, where the forward is
+        let option = european(100.0, PutOrCall::Call);
+        let market_data = sample_market_data();
+        let expected = option.price(&market_data).unwrap();
+        let price = pde_price(option, market_data, PdeDynamics::Black);
+        assert!(price > expected && price < expected + 0.1,
+            "price={} expected={}", price, expected);
+    }
+
+    #[test]
+    fn pde_jump_divs_match_monte_carlo() {
+
+        // Black diffusion also applies the cash dividends as jumps in the
+        // spot for a JumpDivs surface, so the two should agree to within
+        // the statistical error
+        let vol = flat_vol_with_divs(DivAssumptions::JumpDivs);
+        for &(strike, put_or_call) in [(100.0, PutOrCall::Put),
+            (90.0, PutOrCall::Call)].iter() {
+            let option = european(strike, put_or_call);
+            let price = pde_price(option.clone(),
+                large_div_market_data(vol.clone()), PdeDynamics::Black);
+
+            let model_factory = Box::new(BlackDiffusionFactory::new(
+                20, 0.005, 100000));
+            let factory = MonteCarloPricerFactory::new(model_factory);
+            let pricer = factory.new_mc_pricer(option, sample_fixings(),
+                Arc::new(large_div_market_data(vol.clone()))).unwrap();
+            let result = pricer.mc_result().unwrap();
+            assert!((price - result.mean()).abs()
+                < 4.0 * result.standard_error(),
+                "pde={} mc={} standard_error={}", price, result.mean(),
+                result.standard_error());
+
+            // the closed form, which is log-normal in the forward, gives a
+            // visibly different price, so the jumps are being tested
+            let closed_form = european(strike, put_or_call).price(
+                &large_div_market_data(flat_vol_with_divs(
+                DivAssumptions::NoCashDivs))).unwrap();
+            assert!((price - closed_form).abs() > 0.3,
+                "price={} closed_form={}", price, closed_form);
+        }
+    }
+
+    #[test]
+    fn pde_rejects_fixed_divs() {
+        let option = european(100.0, PutOrCall::Call);
+        let market_data = large_div_market_data(flat_vol_with_divs(
+            DivAssumptions::FixedDivs));
+        let factory = PdePricerFactory::new(PdeDynamics::Black, 200, 400, 5.0)
+            .unwrap();
+        let result = factory.new(option, sample_fixings(),
+            Arc::new(market_data)).and_then(|pricer| pricer.price());
+        match result {
+            Ok(price) => panic!("FixedDivs surface priced at {}", price),
+            Err(err) => assert!(err.to_string().contains("FixedDivs"),
+                "{}", err)
+        }
+    }
+
+    #[test]
+    fn pde_local_vol_reprices_skewed_europeans() {
+
+        // The error is dominated by the numerical differentiation of the
+        // vol surface to find the local vols, rather than the PDE itself
+        for &(strike, put_or_call) in [(100.0, PutOrCall::Call),
+            (70.0, PutOrCall::Put), (130.0, PutOrCall::Call)].iter() {
+            let option = european(strike, put_or_call);
+            let market_data = no_div_market_data(create_sample_skewed_vol());
+            let expected = option.price(&market_data).unwrap();
+            let price = pde_price(option, market_data, PdeDynamics::LocalVol);
+            assert_approx(price, expected, 0.015 * expected);
+        }
+    }
+
+    #[test]
+    fn pde_early_exercise_adds_value() {
+        let option = european(110.0, PutOrCall::Put);
+        let european_price = pde_price(option.clone(), sample_market_data(),
+            PdeDynamics::Black);
+
+        let expiry = option.pde_expiry();
+        let base = Date::from_ymd(2017, 01, 02);
+        let bermudan = Arc::new(EarlyExercisable { european: option.clone(),
+            exercise: EarlyExercise::Bermudan(vec![
+                DateDayFraction::new(base + 91, expiry.day_fraction()),
+                DateDayFraction::new(base + 182, expiry.day_fraction()),
+                DateDayFraction::new(base + 273, expiry.day_fraction())]) });
+        let bermudan_price = pde_price(bermudan, sample_market_data(),
+            PdeDynamics::Black);
+
+        let american = Arc::new(EarlyExercisable { european: option.clone(),
+            exercise: EarlyExercise::American(
+                DateDayFraction::new(base, 0.0)) });
+        let american_price = pde_price(american, sample_market_data(),
+            PdeDynamics::Black);
+
+        assert!(european_price < bermudan_price,
+            "european={} bermudan={}", european_price, bermudan_price);
+        assert!(bermudan_price < american_price,
+            "bermudan={} american={}", bermudan_price, american_price);
+    }
+
+    #[test]
+    fn pde_bumped_price() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let factory = PdePricerFactory::new(PdeDynamics::Black, 200, 400, 5.0)
+            .unwrap();
+        let mut pricer = factory.new(sample_european(), sample_fixings(),
+            market_data).unwrap();
+        let mut save = pricer.as_bumpable().new_saveable();
+        let unbumped_price = pricer.price().unwrap();
+
+        // compare with the self-pricer bumped prices
+        let bump = BumpSpot::new_relative(0.01);
+        let bumped = pricer.as_mut_bumpable().bump_spot(
+            "BP.L", &bump, &mut *save).unwrap();
+        assert!(bumped);
+        let bumped_price = pricer.price().unwrap();
+        assert_approx(bumped_price - unbumped_price, 0.633187905501792, 0.005);
+
+        pricer.as_mut_bumpable().restore(&*save).unwrap();
+        save.clear();
+        assert_approx(pricer.price().unwrap(), unbumped_price, 1e-12);
+
+        let bump = BumpVol::new_flat_additive(0.01);
+        let bumped = pricer.as_mut_bumpable().bump_vol(
+            "BP.L", &bump, &mut *save).unwrap();
+        assert!(bumped);
+        let bumped_price = pricer.price().unwrap();
+        assert_approx(bumped_price - unbumped_price, 0.429105019892687, 0.005);
+    }
+
+    #[test]
+    fn pde_time_bump() {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let factory = PdePricerFactory::new(PdeDynamics::Black, 200, 400, 5.0)
+            .unwrap();
+        let mut pricer = factory.new(sample_european(), sample_fixings(),
+            market_data.clone()).unwrap();
+        let self_factory = SelfPricerFactory::new();
+        let mut self_pricer = self_factory.new(sample_european(),
+            sample_fixings(), market_data).unwrap();
+
+        // compare the theta over a week with the self-pricer
+        let bump = BumpTime::new(Date::from_ymd(2017, 01, 09),
+            SpotDynamics::StickyForward);
+        let unbumped_price = pricer.price().unwrap();
+        let self_unbumped_price = self_pricer.price().unwrap();
+        pricer.as_mut_time_bumpable().bump_time(&bump).unwrap();
+        self_pricer.as_mut_time_bumpable().bump_time(&bump).unwrap();
+        let theta = pricer.price().unwrap() - unbumped_price;
+        let self_theta = self_pricer.price().unwrap() - self_unbumped_price;
+        assert!(self_theta < 0.0);
+        assert_approx(theta, self_theta, 0.005);
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+}
@@ -1,5 +1,5 @@
 use core::qm;
-use std::rc::Rc;
+use std::sync::Arc;
 use dates::Date;
 use instruments::Instrument;
 use instruments::PricingContext;
@@ -8,6 +8,7 @@ use risk::cache::PricingContextPrefetch;
 use risk::Pricer;
 use risk::dependencies::DependencyCollector;
 use risk::Bumpable;
+use risk::BumpablePricingContext;
 use risk::TimeBumpable;
 use risk::Saveable;
 use pricers::PricerFactory;
@@ -15,6 +16,7 @@ use data::fixings::FixingTable;
 use data::bumpspot::BumpSpot;
 use data::bumptime::BumpTime;
 use data::bumpvol::BumpVol;
+use data::bumpcorrelation::BumpCorrelation;
 use data::bumpdivs::BumpDivs;
 use data::bumpyield::BumpYield;
 use risk::marketdata::MarketData;
@@ -23,7 +25,7 @@ use risk::marketdata::MarketData;
 /// instrument to evaluate the instrument . It then exposes this
 /// interface as a Pricer, allowing bumping for risk calculation.
 pub struct SelfPricer {
-    instruments: Vec<(f64, Rc<Instrument>)>,
+    instruments: Vec<(f64, Arc<Instrument>)>,
     context: PricingContextPrefetch
 }
 
@@ -41,8 +43,8 @@ impl SelfPricerFactory {
 }
 
 impl PricerFactory for SelfPricerFactory {
-    fn new(&self, instrument: Rc<Instrument>, fixing_table: Rc<FixingTable>, 
-        market_data: Rc<MarketData>) -> Result<Box<Pricer>, qm::Error> {
+    fn new(&self, instrument: Arc<Instrument>, fixing_table: Arc<FixingTable>, 
+        market_data: Arc<MarketData>) -> Result<Box<Pricer>, qm::Error> {
 
         // Apply the fixings to the instrument. (This is the last time we need
         // the fixings.)
@@ -65,7 +67,7 @@ impl PricerFactory for SelfPricerFactory {
 
         // Create a cached pricing context, prefetching the data to price them
         let context = PricingContextPrefetch::new(&*market_data,
-            Rc::new(dependencies))?;
+            Arc::new(dependencies))?;
 
         Ok(Box::new(SelfPricer { instruments: instruments, context: context }))
     }
@@ -76,22 +78,42 @@ impl Pricer for SelfPricer {
     fn as_mut_bumpable(&mut self) -> &mut Bumpable { self }
     fn as_mut_time_bumpable(&mut self) -> &mut TimeBumpable { self }
 
-    fn price(&self) -> Result<f64, qm::Error> {
-        // Return a weighted sum of the individual prices. (TODO consider
-        // returning some data structure that shows the components as well as
-        // the weighted sum.)
+    fn instruments(&self) -> &[(f64, Arc<Instrument>)] {
+        &self.instruments
+    }
 
-        // Note that we have already verified that all components are priceable
-        // so here we simply skip any that are not.
+    fn as_pricing_context(&self) -> &PricingContext {
+        &self.context
+    }
 
-        let mut total = 0.0;
-        for &(weight, ref instrument) in self.instruments.iter() {
-            if let Some(priceable) = instrument.as_priceable() {
-                total += weight * priceable.price(&self.context)?;
-            }
+    fn clone_box(&self) -> Box<Pricer> {
+        Box::new(SelfPricer { instruments: self.instruments.clone(),
+            context: self.context.clone() })
+    }
+
+    fn price(&self) -> Result<f64, qm::Error> {
+        self_price(&self.instruments, &self.context)
+    }
+}
+
+/// Returns a weighted sum of the prices of the given instruments, priced
+/// by themselves in the given context. This is also used by other pricers
+/// that need analytic prices, such as for Monte-Carlo control variates.
+/// (TODO consider returning some data structure that shows the components
+/// as well as the weighted sum.)
+pub fn self_price(instruments: &[(f64, Arc<Instrument>)],
+    context: &PricingContext) -> Result<f64, qm::Error> {
+
+    // Note that we have already verified that all components are priceable
+    // so here we simply skip any that are not.
+
+    let mut total = 0.0;
+    for &(weight, ref instrument) in instruments.iter() {
+        if let Some(priceable) = instrument.as_priceable() {
+            total += weight * priceable.price(context)?;
         }
-        Ok(total)
     }
+    Ok(total)
 }
 
 /// There is a lot of discussion on the Rust language forum of ways to avoid
@@ -122,6 +144,12 @@ impl Bumpable for SelfPricer {
         self.context.bump_vol(id, bump, save)
     }
 
+    fn bump_correlation(&mut self, first: &str, second: &str,
+        bump: &BumpCorrelation, save: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        self.context.bump_correlation(first, second, bump, save)
+    }
+
     fn bump_discount_date(&mut self, replacement: Date, save: &mut Saveable)
         -> Result<bool, qm::Error> {
         self.context.bump_discount_date(replacement, save)
@@ -142,20 +170,40 @@ impl Bumpable for SelfPricer {
 }
 
 impl TimeBumpable for SelfPricer {
-    fn bump_time(&mut self, _bump: &BumpTime) -> Result<(), qm::Error> {
-        Err(qm::Error::new("Time bumps not yet supported"))
+    fn bump_time(&mut self, bump: &BumpTime) -> Result<(), qm::Error> {
+
+        // work on copies, so the pricer is unchanged if anything fails
+        let mut instruments = self.instruments.clone();
+        let mut context = self.context.clone();
+        if !context.bump_time(bump, &mut instruments)? {
+            return Ok(())
+        }
+
+        // fixing may have turned the instruments into something else, but
+        // they must still be priceable
+        for &(_, ref instr) in instruments.iter() {
+            if let None = instr.as_priceable() {
+                return Err(qm::Error::new(&format!("Instrument {} is not \
+                    priceable", instr.id())))
+            }
+        }
+
+        self.instruments = instruments;
+        self.context = context;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::rc::Rc;
+    use std::sync::Arc;
     use dates::datetime::DateTime;
     use dates::datetime::TimeOfDay;
     use math::numerics::approx_eq;
     use risk::marketdata::tests::sample_market_data;
     use risk::marketdata::tests::sample_european;
+    use data::bumptime::SpotDynamics;
 
     fn sample_fixings() -> FixingTable {
         let today = Date::from_ymd(2017, 01, 02);
@@ -167,9 +215,9 @@ mod tests {
     #[test]
     fn self_price_european_bumped_price() {
 
-        let market_data: Rc<MarketData> = Rc::new(sample_market_data());
-        let instrument: Rc<Instrument> = sample_european();
-        let fixings: Rc<FixingTable> = Rc::new(sample_fixings());
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let instrument: Arc<Instrument> = sample_european();
+        let fixings: Arc<FixingTable> = Arc::new(sample_fixings());
 
         let factory = SelfPricerFactory::new();
         let mut pricer = factory.new(instrument, fixings, market_data).unwrap();
@@ -253,6 +301,44 @@ mod tests {
         assert_approx(price, unbumped_price, 1e-12);
     }
 
+    #[test]
+    fn self_price_european_time_bump() {
+
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let instrument: Arc<Instrument> = sample_european();
+        let fixings: Arc<FixingTable> = Arc::new(sample_fixings());
+
+        let factory = SelfPricerFactory::new();
+        let mut pricer = factory.new(instrument, fixings, market_data).unwrap();
+        let unbumped_price = pricer.price().unwrap();
+
+        // a failed time bump leaves the pricer as it was
+        let today = Date::from_ymd(2017, 01, 02);
+        let bump = BumpTime::new(today - 1, SpotDynamics::StickySpot);
+        assert!(pricer.as_mut_time_bumpable().bump_time(&bump).is_err());
+        assert_approx(pricer.price().unwrap(), unbumped_price, 1e-12);
+
+        // move forward one day. A long call loses value overnight.
+        let bump = BumpTime::new(today + 1, SpotDynamics::StickySpot);
+        pricer.as_mut_time_bumpable().bump_time(&bump).unwrap();
+        assert_eq!(pricer.as_pricing_context().spot_date(), today + 1);
+        let theta = pricer.price().unwrap() - unbumped_price;
+        assert_approx(theta, -0.024972095834112196, 1e-12);
+
+        // we can calculate time-forward greeks on the bumped pricer. The
+        // effect of a spot bump is much the same as it was yesterday.
+        let theta_price = unbumped_price + theta;
+        let mut save = pricer.as_bumpable().new_saveable();
+        let bump = BumpSpot::new_relative(0.01);
+        let bumped = pricer.as_mut_bumpable().bump_spot(
+            "BP.L", &bump, &mut *save).unwrap();
+        assert!(bumped);
+        let bumped_price = pricer.price().unwrap();
+        assert_approx(bumped_price - theta_price, 0.633187905501792, 0.01);
+        pricer.as_mut_bumpable().restore(&*save).unwrap();
+        assert_approx(pricer.price().unwrap(), theta_price, 1e-12);
+    }
+
     fn assert_approx(value: f64, expected: f64, tolerance: f64) {
         assert!(approx_eq(value, expected, tolerance),
             "value={} expected={}", value, expected);
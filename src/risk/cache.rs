@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use std::collections::HashMap;
 use std::any::Any;
 use data::volsurface::VolSurface;
@@ -8,9 +8,12 @@ use data::bumpspot::BumpSpot;
 use data::bumpyield::BumpYield;
 use data::bumpdivs::BumpDivs;
 use data::bumpvol::BumpVol;
+use data::bumpcorrelation::BumpCorrelation;
+use data::bumptime::BumpTime;
 use dates::Date;
 use instruments::Instrument;
 use instruments::PricingContext;
+use instruments::DependencyContext;
 use risk::dependencies::DependencyCollector;
 use risk::marketdata::MarketData;
 use risk::marketdata::SavedData;
@@ -24,12 +27,12 @@ use core::qm;
 /// needed for calculations. Although the module is called cache, the behaviour
 /// is entirely deterministic. We prefetch the data, rather than lazily caching
 /// it.
-
+#[derive(Clone)]
 pub struct PricingContextPrefetch {
     context: MarketData,
-    dependencies: Rc<DependencyCollector>,
-    forward_curves: HashMap<String, Rc<Forward>>,
-    vol_surfaces: HashMap<String, Rc<VolSurface>>,
+    dependencies: Arc<DependencyCollector>,
+    forward_curves: HashMap<String, Arc<Forward>>,
+    vol_surfaces: HashMap<String, Arc<VolSurface>>,
 }
 
 impl PricingContextPrefetch {
@@ -40,7 +43,7 @@ impl PricingContextPrefetch {
     /// immutable.
     pub fn new(
         context: &MarketData,
-        dependencies: Rc<DependencyCollector>)
+        dependencies: Arc<DependencyCollector>)
         -> Result<PricingContextPrefetch, qm::Error> {
 
         // prefetch the forward curves and vol surfaces
@@ -126,19 +129,21 @@ impl PricingContextPrefetch {
             } else {
                 return Err(qm::Error::new("Cannot find instrument"))
             }
-        } else {
+        } else if self.dependencies.instrument_by_id(id).is_none() {
             return Err(qm::Error::new("Cannot find prefetched forward"))
         }
 
+        // An instrument with a dependency only on its spot, such as an
+        // equity held directly, has no forward or vol to refetch
         Ok(true)
     }
 }
 
 fn walk_dependencies(
     context: &MarketData,
-    dependencies: &Rc<DependencyCollector>,
-    forward_curves: &mut HashMap<String, Rc<Forward>>,
-    vol_surfaces: &mut HashMap<String, Rc<VolSurface>>)
+    dependencies: &Arc<DependencyCollector>,
+    forward_curves: &mut HashMap<String, Arc<Forward>>,
+    vol_surfaces: &mut HashMap<String, Arc<VolSurface>>)
     -> Result<(), qm::Error> {
 
     let forward_dependencies = dependencies.forward_curves();
@@ -184,34 +189,39 @@ impl PricingContext for PricingContextPrefetch {
     }
 
     fn yield_curve(&self, credit_id: &str, high_water_mark: Date)
-        -> Result<Rc<RateCurve>, qm::Error> {
+        -> Result<Arc<RateCurve>, qm::Error> {
         // Currently there is no work in fetching a yield curve, so we do
         // not cache this. If yield curves were to be cooked internally, this
         // would change.
         self.context.yield_curve(credit_id, high_water_mark)
     }
 
+    fn borrow_curve(&self, id: &str, high_water_mark: Date)
+        -> Result<Arc<RateCurve>, qm::Error> {
+        self.context.borrow_curve(id, high_water_mark)
+    }
+
     fn spot(&self, id: &str) -> Result<f64, qm::Error> {
         // no point caching this
         self.context.spot(id)
     }
 
     fn forward_curve(&self, instrument: &Instrument, _high_water_mark: Date)
-        -> Result<Rc<Forward>, qm::Error> {
+        -> Result<Arc<Forward>, qm::Error> {
         find_cached_data(instrument.id(), &self.forward_curves, "Forward")
     }
 
     /// Gets a Vol Surface, given any instrument, for example an equity.  Also
     /// specify a high water mark, beyond which we never directly ask for
     /// vols.
-    fn vol_surface(&self, instrument: &Instrument, _forward: Rc<Forward>,
-        _high_water_mark: Date) -> Result<Rc<VolSurface>, qm::Error> {
+    fn vol_surface(&self, instrument: &Instrument, _forward: Arc<Forward>,
+        _high_water_mark: Date) -> Result<Arc<VolSurface>, qm::Error> {
         find_cached_data(instrument.id(), &self.vol_surfaces, "Vol Surface")
     }
 
-    fn correlation(&self, first: &Instrument, second: &Instrument)
-        -> Result<f64, qm::Error> {
-        self.context.correlation(first, second)
+    fn correlation(&self, first: &Instrument, second: &Instrument,
+        date: Date) -> Result<f64, qm::Error> {
+        self.context.correlation(first, second, date)
     }
 }
 
@@ -274,6 +284,15 @@ impl Bumpable for PricingContextPrefetch {
         self.refetch(id, false, bumped, saved)
     }
 
+    fn bump_correlation(&mut self, first: &str, second: &str,
+        bump: &BumpCorrelation, any_saved: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        let saved = to_saved(any_saved)?;
+        self.context.bump_correlation(first, second, bump,
+            &mut saved.saved_data)
+        // correlations are not prefetched, so there is nothing to refetch
+    }
+
     fn bump_discount_date(&mut self, replacement: Date,
         any_saved: &mut Saveable) -> Result<bool, qm::Error> {
         let saved = to_saved(any_saved)?;
@@ -313,6 +332,31 @@ impl BumpablePricingContext for PricingContextPrefetch {
     fn as_bumpable(&self) -> &Bumpable { self }
     fn as_mut_bumpable(&mut self) -> &mut Bumpable { self }
     fn as_pricing_context(&self) -> &PricingContext { self }
+
+    /// Rolls the underlying market data, then finds the dependencies of the
+    /// fixed instruments, which may be quite different from before, and
+    /// refetches everything.
+    fn bump_time(&mut self, bump: &BumpTime,
+        instruments: &mut Vec<(f64, Arc<Instrument>)>)
+        -> Result<bool, qm::Error> {
+
+        if !self.context.bump_time(bump, instruments)? {
+            return Ok(false)
+        }
+
+        let mut dependencies = DependencyCollector::new(
+            self.context.spot_date());
+        for &(_, ref instrument) in instruments.iter() {
+            dependencies.spot(instrument);
+        }
+        self.dependencies = Arc::new(dependencies);
+        self.refetch_all()?;
+        Ok(true)
+    }
+
+    fn clone_box(&self) -> Box<BumpablePricingContext> {
+        Box::new(self.clone())
+    }
 }
 
 fn to_saved(any_saved: &mut Saveable) 
@@ -330,8 +374,8 @@ fn to_saved(any_saved: &mut Saveable)
 /// can be restored later on.
 pub struct SavedPrefetch {
     saved_data: SavedData,
-    forward_curves: HashMap<String, Rc<Forward>>,
-    vol_surfaces: HashMap<String, Rc<VolSurface>>
+    forward_curves: HashMap<String, Arc<Forward>>,
+    vol_surfaces: HashMap<String, Arc<VolSurface>>
 }
 
 impl SavedPrefetch {
@@ -363,19 +407,19 @@ impl Saveable for SavedPrefetch {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::rc::Rc;
+    use std::sync::Arc;
     use instruments::DependencyContext;
     use instruments::Priceable;
     use math::numerics::approx_eq;
     use risk::marketdata::tests::sample_market_data;
     use risk::marketdata::tests::sample_european;
 
-    fn create_dependencies(instrument: &Rc<Instrument>, spot_date: Date)
-        -> Rc<DependencyCollector> {
+    fn create_dependencies(instrument: &Arc<Instrument>, spot_date: Date)
+        -> Arc<DependencyCollector> {
 
         let mut collector = DependencyCollector::new(spot_date);
         collector.spot(instrument);
-        Rc::new(collector)
+        Arc::new(collector)
     }
 
     #[test]
@@ -389,7 +433,7 @@ mod tests {
         // so we can modify it and also create an
         // empty saved data to save state so we can restore it
         let spot_date = Date::from_ymd(2017, 01, 02);
-        let instrument: Rc<Instrument> = european.clone();
+        let instrument: Arc<Instrument> = european.clone();
         let dependencies = create_dependencies(&instrument, spot_date);
         let mut mut_data = PricingContextPrefetch::new(&market_data,
             dependencies).unwrap();
@@ -0,0 +1,437 @@
+use core::qm;
+use std::collections::BTreeMap;
+use instruments::Instrument;
+use instruments::RcInstrument;
+use risk::Pricer;
+use risk::Saveable;
+use risk::deltagamma::SpotBumpSize;
+use risk::reports::UnderlierSelector;
+use risk::reports::selected_spots;
+use risk::reports::cross_bumped_price;
+
+/// Restricts the pairs of underliers for which cross-gamma is calculated.
+/// The full matrix costs four reprices for each of the n(n-1)/2 pairs, so for
+/// large books it is normally restricted. Pairs are unordered, so (A, B)
+/// matches (B, A).
+#[derive(Clone, Debug)]
+pub enum PairFilter {
+    All,
+    ByPairs { pairs: Vec<(String, String)> },
+    Involving { ids: Vec<String> }
+}
+
+impl PairFilter {
+    pub fn new_all() -> PairFilter {
+        PairFilter::All
+    }
+
+    pub fn new_by_pairs(pairs: &[(&str, &str)]) -> PairFilter {
+        PairFilter::ByPairs { pairs: pairs.iter()
+            .map(|&(a, b)| (a.to_string(), b.to_string())).collect() }
+    }
+
+    /// Only pairs where at least one of the underliers is in the list
+    pub fn new_involving(ids: &[&str]) -> PairFilter {
+        PairFilter::Involving {
+            ids: ids.iter().map(|id| id.to_string()).collect() }
+    }
+
+    /// Returns true if the cross-gamma between these two underliers should
+    /// be calculated
+    pub fn matches(&self, first: &Instrument, second: &Instrument) -> bool {
+        let (a, b) = (first.id(), second.id());
+        match self {
+            &PairFilter::All => true,
+            &PairFilter::ByPairs { ref pairs } => pairs.iter().any(|pair|
+                (pair.0 == a && pair.1 == b) || (pair.0 == b && pair.1 == a)),
+            &PairFilter::Involving { ref ids }
+                => ids.iter().any(|id| id == a || id == b)
+        }
+    }
+}
+
+/// The results of a cross-gamma report. The cross-gammas are keyed by the
+/// pair of instrument ids, with the ids in sorted order. Only off-diagonal
+/// terms are included; use DeltaGammaReport for the diagonal.
+#[derive(Clone, Debug)]
+pub struct CrossGammaResult {
+    price: f64,
+    cross_gammas: BTreeMap<(String, String), f64>
+}
+
+impl CrossGammaResult {
+    pub fn price(&self) -> f64 { self.price }
+    pub fn cross_gammas(&self) -> &BTreeMap<(String, String), f64> {
+        &self.cross_gammas
+    }
+
+    /// Returns the cross-gamma for a pair of ids, in either order, or None
+    /// if it was not calculated.
+    pub fn get(&self, first: &str, second: &str) -> Option<f64> {
+        let key = if first < second {
+            (first.to_string(), second.to_string())
+        } else {
+            (second.to_string(), first.to_string())
+        };
+        self.cross_gammas.get(&key).cloned()
+    }
+}
+
+/// Report that calculates the mixed second derivative of the price with
+/// respect to pairs of spots. For each pair, both spots are bumped together,
+/// up and down, giving four reprices:
+///
+/// d2V/dSidSj = (V(+,+) - V(+,-) - V(-,+) + V(-,-)) / (4 hi hj)
+///
+/// The two bumps are applied with separate save areas and unwound in
+/// reverse order, so the pricer is always left as it was. Pricers that
+/// support it, such as Monte-Carlo with BlackDiffusion, only regenerate the
+/// paths for the bumped assets.
+pub struct CrossGammaReport {
+    bump_size: SpotBumpSize,
+    selector: UnderlierSelector,
+    filter: PairFilter
+}
+
+impl CrossGammaReport {
+    pub fn new(bump_size: SpotBumpSize, selector: UnderlierSelector,
+        filter: PairFilter) -> Result<CrossGammaReport, qm::Error> {
+        if !(bump_size.size() > 0.0) {
+            return Err(qm::Error::new("Cross-gamma bump size must be positive"))
+        }
+        Ok(CrossGammaReport { bump_size: bump_size, selector: selector,
+            filter: filter })
+    }
+
+    pub fn bump_size(&self) -> &SpotBumpSize { &self.bump_size }
+    pub fn selector(&self) -> &UnderlierSelector { &self.selector }
+    pub fn filter(&self) -> &PairFilter { &self.filter }
+
+    /// Runs the report against the given pricer. On return, the pricer is
+    /// in the same state as on entry, whether or not the report succeeded.
+    pub fn calculate(&self, pricer: &mut Pricer)
+        -> Result<CrossGammaResult, qm::Error> {
+
+        let price = pricer.price()?;
+        let mut outer_save = pricer.as_bumpable().new_saveable();
+        let mut inner_save = pricer.as_bumpable().new_saveable();
+        let mut cross_gammas = BTreeMap::new();
+
+        // selected_spots are sorted by id, so each pair is visited once,
+        // with the ids in order
+        let spots = selected_spots(pricer, &self.selector);
+        for (i, first) in spots.iter().enumerate() {
+            for second in spots[i + 1..].iter() {
+                if !self.filter.matches(first.instrument(),
+                    second.instrument()) {
+                    continue
+                }
+
+                let cross_gamma = self.cross_gamma(pricer, first, second,
+                    &mut *outer_save, &mut *inner_save)?;
+                cross_gammas.insert(
+                    (first.id().to_string(), second.id().to_string()),
+                    cross_gamma);
+            }
+        }
+
+        Ok(CrossGammaResult { price: price, cross_gammas: cross_gammas })
+    }
+
+    fn cross_gamma(&self, pricer: &mut Pricer, first: &RcInstrument,
+        second: &RcInstrument, outer_save: &mut Saveable,
+        inner_save: &mut Saveable) -> Result<f64, qm::Error> {
+
+        let (id_i, id_j) = (first.id(), second.id());
+        let spot_i = pricer.as_pricing_context().spot(id_i)?;
+        let spot_j = pricer.as_pricing_context().spot(id_j)?;
+        let (up_i, down_i, h_i) = self.bump_size.bumps(spot_i);
+        let (up_j, down_j, h_j) = self.bump_size.bumps(spot_j);
+        if !(h_i.abs() > 0.0 && h_j.abs() > 0.0) {
+            return Err(qm::Error::new(&format!(
+                "Cannot calculate cross-gamma for {} and {} with spot of zero",
+                id_i, id_j)))
+        }
+
+        let mut prices = [0.0; 4];
+        for (k, &(bump_i, bump_j)) in [(&up_i, &up_j), (&up_i, &down_j),
+            (&down_i, &up_j), (&down_i, &down_j)].iter().enumerate() {
+
+            match cross_bumped_price(pricer, outer_save, inner_save,
+                |b, s| b.bump_spot(id_i, bump_i, s),
+                |b, s| b.bump_spot(id_j, bump_j, s))? {
+                Some(price) => prices[k] = price,
+                // an underlier that could not be bumped has zero risk
+                None => return Ok(0.0)
+            }
+        }
+
+        Ok((prices[0] - prices[1] - prices[2] + prices[3]) / (4.0 * h_i * h_j))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use dates::Date;
+    use data::fixings::FixingTable;
+    use instruments::DependencyContext;
+    use instruments::SpotRequirement;
+    use instruments::Priceable;
+    use instruments::PricingContext;
+    use instruments::assets::Currency;
+    use instruments::assets::Equity;
+    use dates::rules::DateRule;
+    use math::numerics::approx_eq;
+    use pricers::PricerFactory;
+    use pricers::selfpricer::SelfPricerFactory;
+    use risk::marketdata::MarketData;
+    use risk::marketdata::tests::sample_market_data;
+    use risk::marketdata::tests::sample_currency;
+    use risk::marketdata::tests::sample_settlement;
+    use risk::marketdata::tests::sample_market_data_with_correlations;
+    use risk::marketdata::SavedData;
+    use risk::Bumpable;
+    use data::bumpspot::BumpSpot;
+    use data::correlation::LocalCorrelation;
+    use dates::datetime::DateDayFraction;
+    use instruments::MonteCarloPriceable;
+    use instruments::MonteCarloDependencies;
+    use instruments::MonteCarloContext;
+    use instruments::bonds::ZeroCoupon;
+    use pricers::montecarlo::MonteCarloPricerFactory;
+    use models::blackdiffusion::BlackDiffusionFactory;
+    use ndarray::Array2;
+
+    /// Test-only instrument worth the product of two spots divided by 100.
+    /// Its cross-gamma is exactly 0.01 and its gammas are zero.
+    struct SpotProduct {
+        currency: Currency,
+        settlement: Arc<DateRule>,
+        first: Arc<Instrument>,
+        second: Arc<Instrument>
+    }
+
+    impl Instrument for SpotProduct {
+        fn id(&self) -> &str { "SpotProduct" }
+        fn payoff_currency(&self) -> &Currency { &self.currency }
+        fn credit_id(&self) -> &str { "OPT" }
+        fn settlement(&self) -> &Arc<DateRule> { &self.settlement }
+        fn dependencies(&self, context: &mut DependencyContext)
+            -> SpotRequirement {
+            context.spot(&self.first);
+            context.spot(&self.second);
+            SpotRequirement::NotRequired
+        }
+        fn as_priceable(&self) -> Option<&Priceable> { Some(self) }
+    }
+
+    impl Priceable for SpotProduct {
+        fn as_instrument(&self) -> &Instrument { self }
+        fn price(&self, context: &PricingContext) -> Result<f64, qm::Error> {
+            Ok(context.spot(self.first.id())?
+                * context.spot(self.second.id())? / 100.0)
+        }
+    }
+
+    /// Test-only instrument paying the product of two spots at expiry,
+    /// divided by 100, which can only be priced by Monte-Carlo
+    struct ProductAtExpiry {
+        currency: Arc<Currency>,
+        settlement: Arc<DateRule>,
+        first: Arc<Instrument>,
+        second: Arc<Instrument>,
+        expiry: DateDayFraction,
+        pay_date: Date
+    }
+
+    impl Instrument for ProductAtExpiry {
+        fn id(&self) -> &str { "ProductAtExpiry" }
+        fn payoff_currency(&self) -> &Currency { &*self.currency }
+        fn credit_id(&self) -> &str { "OPT" }
+        fn settlement(&self) -> &Arc<DateRule> { &self.settlement }
+        fn dependencies(&self, context: &mut DependencyContext)
+            -> SpotRequirement {
+            context.yield_curve(self.credit_id(), self.pay_date);
+            for underlying in [&self.first, &self.second].iter() {
+                context.spot(underlying);
+                context.forward_curve(underlying, self.expiry.date());
+                context.vol_surface(underlying, self.expiry.date());
+            }
+            SpotRequirement::NotRequired
+        }
+        fn as_mc_priceable(&self) -> Option<&MonteCarloPriceable> {
+            Some(self)
+        }
+    }
+
+    impl MonteCarloPriceable for ProductAtExpiry {
+        fn as_instrument(&self) -> &Instrument { self }
+        fn mc_dependencies(&self, _dates: &[DateDayFraction],
+            output: &mut MonteCarloDependencies) -> Result<(), qm::Error> {
+            output.observation(&self.first, self.expiry);
+            output.observation(&self.second, self.expiry);
+            let payment: Arc<Instrument> = Arc::new(ZeroCoupon::new(
+                "ProductAtExpiry:Pay", "OPT", self.currency.clone(),
+                self.pay_date, self.settlement.clone()));
+            output.flow(&payment);
+            Ok(())
+        }
+        fn start_date(&self) -> Option<DateDayFraction> { None }
+        fn mc_price(&self, context: &MonteCarloContext)
+            -> Result<f64, qm::Error> {
+            let first = context.paths(&self.first)?;
+            let second = context.paths(&self.second)?;
+            let n_paths = first.shape()[0];
+            let mut quantities = Array2::zeros((n_paths, 1));
+            for (i, flow) in quantities.iter_mut().enumerate() {
+                *flow = first[(i, 0)] * second[(i, 0)] / 100.0;
+            }
+            context.evaluate_flows(quantities.view())
+        }
+    }
+
+    fn sample_correlated_market_data() -> MarketData {
+        let correlation = Arc::new(LocalCorrelation::new_constant(0.4)
+            .unwrap());
+        sample_market_data_with_correlations(&[
+            ("BP.L", "GSK.L", correlation)]).unwrap()
+    }
+
+    fn product_at_expiry() -> Arc<Instrument> {
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let bp: Arc<Instrument> = Arc::new(Equity::new("BP.L", "LSE",
+            currency.clone(), settlement.clone()));
+        let gsk: Arc<Instrument> = Arc::new(Equity::new("GSK.L", "LSE",
+            currency.clone(), settlement.clone()));
+        Arc::new(ProductAtExpiry { currency: currency,
+            settlement: settlement, first: bp, second: gsk,
+            expiry: DateDayFraction::new(Date::from_ymd(2018, 06, 01), 0.9),
+            pay_date: Date::from_ymd(2018, 06, 05) })
+    }
+
+    fn monte_carlo_pricer(market_data: MarketData) -> Box<Pricer> {
+        let today = Date::from_ymd(2017, 01, 02);
+        let fixings = Arc::new(FixingTable::new(today, &[]).unwrap());
+        let factory = MonteCarloPricerFactory::new(Box::new(
+            BlackDiffusionFactory::new(20, 0.01, 10000)));
+        factory.new(product_at_expiry(), fixings, Arc::new(market_data))
+            .unwrap()
+    }
+
+    fn sample_pricer() -> Box<Pricer> {
+        let currency = Arc::new(sample_currency(2));
+        let settlement = sample_settlement(2);
+        let bp: Arc<Instrument> = Arc::new(Equity::new("BP.L", "LSE",
+            currency.clone(), settlement.clone()));
+        let gsk: Arc<Instrument> = Arc::new(Equity::new("GSK.L", "LSE",
+            currency.clone(), settlement.clone()));
+        let instrument: Arc<Instrument> = Arc::new(SpotProduct {
+            currency: (*currency).clone(), settlement: settlement,
+            first: bp, second: gsk });
+
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let today = Date::from_ymd(2017, 01, 02);
+        let fixings = Arc::new(FixingTable::new(today, &[]).unwrap());
+        let factory = SelfPricerFactory::new();
+        factory.new(instrument, fixings, market_data).unwrap()
+    }
+
+    #[test]
+    fn cross_gamma_of_spot_product() {
+        let mut pricer = sample_pricer();
+        let unbumped = pricer.price().unwrap();
+        assert_approx(unbumped, 200.0, 1e-12);
+
+        let report = CrossGammaReport::new(SpotBumpSize::new_relative(0.01),
+            UnderlierSelector::new_all(), PairFilter::new_all()).unwrap();
+        let result = report.calculate(&mut *pricer).unwrap();
+        assert_approx(result.price(), unbumped, 1e-12);
+        assert_eq!(result.cross_gammas().len(), 1);
+        assert_approx(result.get("BP.L", "GSK.L").unwrap(), 0.01, 1e-12);
+        assert_approx(result.get("GSK.L", "BP.L").unwrap(), 0.01, 1e-12);
+
+        // the nested bumps must all have been unwound
+        assert_approx(pricer.price().unwrap(), unbumped, 1e-12);
+    }
+
+    #[test]
+    fn cross_gamma_pair_filter() {
+        let mut pricer = sample_pricer();
+        let size = SpotBumpSize::new_absolute(1.0);
+
+        for filter in [
+            PairFilter::new_by_pairs(&[("GSK.L", "BP.L")]),
+            PairFilter::new_involving(&["GSK.L"])].iter() {
+            let report = CrossGammaReport::new(size.clone(),
+                UnderlierSelector::new_all(), filter.clone()).unwrap();
+            let result = report.calculate(&mut *pricer).unwrap();
+            assert_approx(result.get("BP.L", "GSK.L").unwrap(), 0.01, 1e-12);
+        }
+
+        for filter in [
+            PairFilter::new_by_pairs(&[("BP.L", "VOD.L")]),
+            PairFilter::new_involving(&["VOD.L"])].iter() {
+            let report = CrossGammaReport::new(size.clone(),
+                UnderlierSelector::new_all(), filter.clone()).unwrap();
+            let result = report.calculate(&mut *pricer).unwrap();
+            assert!(result.cross_gammas().is_empty(), "{:?}", filter);
+        }
+
+        // restricting the underliers also restricts the pairs
+        let report = CrossGammaReport::new(size.clone(),
+            UnderlierSelector::new_by_id(&["BP.L"]), PairFilter::new_all())
+            .unwrap();
+        let result = report.calculate(&mut *pricer).unwrap();
+        assert!(result.cross_gammas().is_empty());
+    }
+
+    #[test]
+    fn monte_carlo_cross_gamma_matches_finite_differences() {
+        let mut pricer = monte_carlo_pricer(sample_correlated_market_data());
+        let unbumped = pricer.price().unwrap();
+
+        let report = CrossGammaReport::new(SpotBumpSize::new_relative(0.01),
+            UnderlierSelector::new_all(), PairFilter::new_all()).unwrap();
+        let result = report.calculate(&mut *pricer).unwrap();
+        assert_approx(result.price(), unbumped, 1e-12);
+        assert_eq!(result.cross_gammas().len(), 1);
+        let cross_gamma = result.get("BP.L", "GSK.L").unwrap();
+
+        // the nested bumps must all have been unwound exactly
+        assert_approx(pricer.price().unwrap(), unbumped, 1e-12);
+
+        // Reference by finite differences, pricing from scratch on market
+        // data with both spots bumped. The paths use the same random numbers
+        // whatever the spots, so this agrees to rounding, not just within
+        // the Monte-Carlo error.
+        let bumped_price = |up_bp: bool, up_gsk: bool| {
+            let mut market_data = sample_correlated_market_data();
+            let mut save = SavedData::new();
+            let bump = |up| BumpSpot::new_relative(if up { 0.01 } else {
+                -0.01 });
+            market_data.bump_spot("BP.L", &bump(up_bp), &mut save).unwrap();
+            market_data.bump_spot("GSK.L", &bump(up_gsk), &mut save)
+                .unwrap();
+            monte_carlo_pricer(market_data).price().unwrap()
+        };
+        let reference = (bumped_price(true, true) - bumped_price(true, false)
+            - bumped_price(false, true) + bumped_price(false, false))
+            / (4.0 * 1.0 * 2.0);
+        assert_approx(cross_gamma, reference, 1e-9);
+
+        // The payoff is exactly bilinear in the spots, so the cross-gamma is
+        // the discounted product of the forward sensitivities, scaled by
+        // exp(rho sigma1 sigma2 t), roughly 1.05. It is positive and of the
+        // same order as the spot-product instrument above.
+        assert!(cross_gamma > 0.009 && cross_gamma < 0.012,
+            "cross_gamma={}", cross_gamma);
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+}
@@ -0,0 +1,388 @@
+use core::qm;
+use std::collections::BTreeMap;
+use data::bumpspot::BumpSpot;
+use risk::Pricer;
+use risk::reports::UnderlierSelector;
+use risk::reports::selected_spots;
+use risk::reports::bumped_price;
+use risk::reports::ScenarioScheduler;
+
+/// Size of the spot bump used for delta and gamma. A relative bump is a
+/// fraction of the current spot, so 0.01 means one percent. An absolute bump
+/// is in the units of the spot itself.
+#[derive(Clone, Debug)]
+pub enum SpotBumpSize {
+    Relative { size: f64 },
+    Absolute { size: f64 }
+}
+
+impl SpotBumpSize {
+    pub fn new_relative(size: f64) -> SpotBumpSize {
+        SpotBumpSize::Relative { size: size }
+    }
+
+    pub fn new_absolute(size: f64) -> SpotBumpSize {
+        SpotBumpSize::Absolute { size: size }
+    }
+
+    pub fn size(&self) -> f64 {
+        match self {
+            &SpotBumpSize::Relative { size } => size,
+            &SpotBumpSize::Absolute { size } => size
+        }
+    }
+
+    /// Returns the up and down bumps, plus the absolute change in spot that
+    /// each represents.
+    pub fn bumps(&self, spot: f64) -> (BumpSpot, BumpSpot, f64) {
+        match self {
+            &SpotBumpSize::Relative { size } => (
+                BumpSpot::new_relative(size),
+                BumpSpot::new_relative(-size),
+                spot * size),
+            &SpotBumpSize::Absolute { size } => (
+                BumpSpot::new_absolute(size),
+                BumpSpot::new_absolute(-size),
+                size)
+        }
+    }
+}
+
+/// Delta and gamma to a single underlier, calculated by central differences.
+/// Both are with respect to an absolute change in spot, whatever the form of
+/// the bump.
+#[derive(Clone, Debug)]
+pub struct DeltaGamma {
+    spot: f64,
+    delta: f64,
+    gamma: f64
+}
+
+impl DeltaGamma {
+    pub fn spot(&self) -> f64 { self.spot }
+    pub fn delta(&self) -> f64 { self.delta }
+    pub fn gamma(&self) -> f64 { self.gamma }
+}
+
+/// The results of a delta-gamma report. Contains the unbumped price, and
+/// the delta and gamma to each selected underlier, keyed by its id.
+#[derive(Clone, Debug)]
+pub struct DeltaGammaResult {
+    price: f64,
+    greeks: BTreeMap<String, DeltaGamma>
+}
+
+impl DeltaGammaResult {
+    pub fn price(&self) -> f64 { self.price }
+    pub fn greeks(&self) -> &BTreeMap<String, DeltaGamma> { &self.greeks }
+    pub fn get(&self, id: &str) -> Option<&DeltaGamma> {
+        self.greeks.get(id)
+    }
+}
+
+/// Report that calculates delta and gamma for every spot that the pricer
+/// depends on and which matches a selector. Each underlier is bumped up and
+/// down in turn, and the pricer is restored after every bump, including when
+/// the pricer fails part way through.
+pub struct DeltaGammaReport {
+    bump_size: SpotBumpSize,
+    selector: UnderlierSelector
+}
+
+impl DeltaGammaReport {
+    pub fn new(bump_size: SpotBumpSize, selector: UnderlierSelector)
+        -> Result<DeltaGammaReport, qm::Error> {
+        if !(bump_size.size() > 0.0) {
+            return Err(qm::Error::new("Delta bump size must be positive"))
+        }
+        Ok(DeltaGammaReport { bump_size: bump_size, selector: selector })
+    }
+
+    pub fn bump_size(&self) -> &SpotBumpSize { &self.bump_size }
+    pub fn selector(&self) -> &UnderlierSelector { &self.selector }
+
+    /// Runs the report against the given pricer. On return, the pricer is
+    /// in the same state as on entry, whether or not the report succeeded.
+    pub fn calculate(&self, pricer: &mut Pricer)
+        -> Result<DeltaGammaResult, qm::Error> {
+        self.calculate_with(pricer, &ScenarioScheduler::sequential())
+    }
+
+    /// Runs the report as calculate, with the bumps of each underlier as a
+    /// separate scenario for the scheduler.
+    pub fn calculate_with(&self, pricer: &mut Pricer,
+        scheduler: &ScenarioScheduler) -> Result<DeltaGammaResult, qm::Error> {
+
+        let price = pricer.price()?;
+
+        let mut scenarios = Vec::new();
+        for underlier in selected_spots(pricer, &self.selector).iter() {
+            let id = underlier.id();
+            let spot = pricer.as_pricing_context().spot(id)?;
+            let (up, down, change) = self.bump_size.bumps(spot);
+            if !(change.abs() > 0.0) {
+                return Err(qm::Error::new(&format!(
+                    "Cannot calculate delta for {} with spot of zero", id)))
+            }
+            scenarios.push((id.to_string(), spot, up, down, change));
+        }
+
+        let greeks = scheduler.run(pricer, scenarios,
+            |pricer, save, (id, spot, up, down, change)| {
+
+            let up_price = bumped_price(pricer, save,
+                |b, s| b.bump_spot(&id, &up, s))?;
+            let down_price = bumped_price(pricer, save,
+                |b, s| b.bump_spot(&id, &down, s))?;
+
+            // an underlier that could not be bumped has zero risk
+            let (delta, gamma) = match (up_price, down_price) {
+                (Some(up), Some(down)) => (
+                    (up - down) / (2.0 * change),
+                    (up - 2.0 * price + down) / (change * change)),
+                _ => (0.0, 0.0)
+            };
+
+            Ok((id, DeltaGamma { spot: spot, delta: delta, gamma: gamma }))
+        })?;
+
+        Ok(DeltaGammaResult { price: price,
+            greeks: greeks.into_iter().collect() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use dates::Date;
+    use dates::datetime::DateTime;
+    use dates::datetime::TimeOfDay;
+    use data::fixings::FixingTable;
+    use data::bumptime::BumpTime;
+    use data::bumpvol::BumpVol;
+    use data::bumpcorrelation::BumpCorrelation;
+    use data::bumpdivs::BumpDivs;
+    use data::bumpyield::BumpYield;
+    use instruments::Instrument;
+    use instruments::PricingContext;
+    use math::numerics::approx_eq;
+    use pricers::PricerFactory;
+    use pricers::selfpricer::SelfPricerFactory;
+    use risk::Bumpable;
+    use risk::TimeBumpable;
+    use risk::Saveable;
+    use risk::marketdata::MarketData;
+    use risk::marketdata::tests::sample_market_data;
+    use risk::marketdata::tests::sample_european;
+    use risk::marketdata::tests::sample_currency;
+    use risk::marketdata::tests::sample_equity;
+
+    fn sample_fixings() -> FixingTable {
+        let today = Date::from_ymd(2017, 01, 02);
+        FixingTable::new(today, &[
+            ("BP.L", &[
+            (DateTime::new(today - 7, TimeOfDay::Close), 102.0)])]).unwrap()
+    }
+
+    fn sample_pricer() -> Box<Pricer> {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let instrument: Arc<Instrument> = sample_european();
+        let fixings: Arc<FixingTable> = Arc::new(sample_fixings());
+        let factory = SelfPricerFactory::new();
+        factory.new(instrument, fixings, market_data).unwrap()
+    }
+
+    #[test]
+    fn european_delta_gamma_relative_and_absolute() {
+        let mut pricer = sample_pricer();
+        let unbumped = pricer.price().unwrap();
+
+        let report = DeltaGammaReport::new(SpotBumpSize::new_relative(0.01),
+            UnderlierSelector::new_all()).unwrap();
+        let result = report.calculate(&mut *pricer).unwrap();
+        assert_approx(result.price(), unbumped, 1e-12);
+        assert_eq!(result.greeks().len(), 1);
+        let relative = result.get("BP.L").unwrap();
+        assert_approx(relative.spot(), 100.0, 1e-12);
+
+        // Compare with the analytic greeks, which are with respect to the
+        // forward. The forward is linear in spot, so we can convert them
+        // using its slope. The central differences have errors of order
+        // the bump squared times the next derivatives up, which for a bump
+        // of one is about 4e-5 in the delta and 2e-7 in the gamma.
+        let market_data = sample_market_data();
+        let analytic = sample_european().price_with_greeks(&market_data)
+            .unwrap();
+        let equity = sample_equity(Arc::new(sample_currency(2)), 2);
+        let expiry = Date::from_ymd(2018, 06, 01);
+        let forward = pricer.as_pricing_context().forward_curve(&equity,
+            expiry).unwrap().forward(expiry).unwrap();
+        let mut save = pricer.as_bumpable().new_saveable();
+        assert!(pricer.as_mut_bumpable().bump_spot("BP.L",
+            &BumpSpot::new_absolute(1.0), &mut *save).unwrap());
+        let bumped_forward = pricer.as_pricing_context().forward_curve(
+            &equity, expiry).unwrap().forward(expiry).unwrap();
+        pricer.as_mut_bumpable().restore(&*save).unwrap();
+        let slope = bumped_forward - forward;
+        assert_approx(relative.delta(), analytic.delta() * slope, 1e-4);
+        assert_approx(relative.gamma(), analytic.gamma() * slope * slope,
+            1e-6);
+
+        // an absolute bump of one unit on a spot of 100 is the same bump
+        let report = DeltaGammaReport::new(SpotBumpSize::new_absolute(1.0),
+            UnderlierSelector::new_all()).unwrap();
+        let result = report.calculate(&mut *pricer).unwrap();
+        let absolute = result.get("BP.L").unwrap();
+        assert_approx(absolute.delta(), relative.delta(), 1e-12);
+        assert_approx(absolute.gamma(), relative.gamma(), 1e-12);
+
+        // a scheduler with more threads than underliers gives the same
+        let scheduled = report.calculate_with(&mut *pricer,
+            &ScenarioScheduler::new(4).unwrap()).unwrap();
+        assert_eq!(scheduled.get("BP.L").unwrap().delta(), absolute.delta());
+
+        // the pricer must be left unbumped
+        assert_approx(pricer.price().unwrap(), unbumped, 1e-12);
+    }
+
+    #[test]
+    fn underliers_selected_by_id_currency_and_credit_id() {
+        let mut pricer = sample_pricer();
+        let size = SpotBumpSize::new_relative(0.01);
+
+        for selector in [
+            UnderlierSelector::new_by_id(&["BP.L", "GSK.L"]),
+            UnderlierSelector::new_by_currency("GBP"),
+            UnderlierSelector::new_by_credit_id("LSE")].iter() {
+            let report = DeltaGammaReport::new(size.clone(), selector.clone())
+                .unwrap();
+            let result = report.calculate(&mut *pricer).unwrap();
+            assert_eq!(result.greeks().len(), 1, "{:?}", selector);
+            assert!(result.get("BP.L").is_some());
+        }
+
+        for selector in [
+            UnderlierSelector::new_by_id(&["GSK.L"]),
+            UnderlierSelector::new_by_currency("USD"),
+            UnderlierSelector::new_by_credit_id("OPT")].iter() {
+            let report = DeltaGammaReport::new(size.clone(), selector.clone())
+                .unwrap();
+            let result = report.calculate(&mut *pricer).unwrap();
+            assert!(result.greeks().is_empty(), "{:?}", selector);
+        }
+    }
+
+    #[test]
+    fn bump_size_must_be_positive() {
+        assert!(DeltaGammaReport::new(SpotBumpSize::new_relative(0.0),
+            UnderlierSelector::new_all()).is_err());
+        assert!(DeltaGammaReport::new(SpotBumpSize::new_absolute(-1.0),
+            UnderlierSelector::new_all()).is_err());
+    }
+
+    #[test]
+    fn failing_pricer_is_restored() {
+        let inner = sample_pricer();
+        let unbumped = inner.price().unwrap();
+
+        // fail on the second call to price, which is the up-bumped price
+        let mut pricer = FailingPricer {
+            inner: inner, calls: AtomicUsize::new(0), fail_on: 2 };
+        let report = DeltaGammaReport::new(SpotBumpSize::new_relative(0.01),
+            UnderlierSelector::new_all()).unwrap();
+        assert!(report.calculate(&mut pricer).is_err());
+
+        // the spot bump must have been unwound
+        assert_approx(pricer.inner.price().unwrap(), unbumped, 1e-12);
+        assert_approx(pricer.as_pricing_context().spot("BP.L").unwrap(),
+            100.0, 1e-12);
+    }
+
+    /// Pricer that delegates to another, but fails on the nth call to price
+    struct FailingPricer {
+        inner: Box<Pricer>,
+        calls: AtomicUsize,
+        fail_on: usize
+    }
+
+    impl Pricer for FailingPricer {
+        fn as_bumpable(&self) -> &Bumpable { self }
+        fn as_mut_bumpable(&mut self) -> &mut Bumpable { self }
+        fn as_mut_time_bumpable(&mut self) -> &mut TimeBumpable { self }
+        fn instruments(&self) -> &[(f64, Arc<Instrument>)] {
+            self.inner.instruments()
+        }
+        fn as_pricing_context(&self) -> &PricingContext {
+            self.inner.as_pricing_context()
+        }
+        fn clone_box(&self) -> Box<Pricer> {
+            Box::new(FailingPricer { inner: self.inner.clone_box(),
+                calls: AtomicUsize::new(self.calls.load(Ordering::SeqCst)),
+                fail_on: self.fail_on })
+        }
+        fn price(&self) -> Result<f64, qm::Error> {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if calls == self.fail_on {
+                Err(qm::Error::new("Deliberate failure"))
+            } else {
+                self.inner.price()
+            }
+        }
+    }
+
+    impl Bumpable for FailingPricer {
+        fn bump_spot(&mut self, id: &str, bump: &BumpSpot,
+            save: &mut Saveable) -> Result<bool, qm::Error> {
+            self.inner.bump_spot(id, bump, save)
+        }
+        fn bump_yield(&mut self, credit_id: &str, bump: &BumpYield,
+            save: &mut Saveable) -> Result<bool, qm::Error> {
+            self.inner.bump_yield(credit_id, bump, save)
+        }
+        fn bump_borrow(&mut self, id: &str, bump: &BumpYield,
+            save: &mut Saveable) -> Result<bool, qm::Error> {
+            self.inner.bump_borrow(id, bump, save)
+        }
+        fn bump_divs(&mut self, id: &str, bump: &BumpDivs,
+            save: &mut Saveable) -> Result<bool, qm::Error> {
+            self.inner.bump_divs(id, bump, save)
+        }
+        fn bump_vol(&mut self, id: &str, bump: &BumpVol,
+            save: &mut Saveable) -> Result<bool, qm::Error> {
+            self.inner.bump_vol(id, bump, save)
+        }
+        fn bump_correlation(&mut self, first: &str, second: &str,
+            bump: &BumpCorrelation, save: &mut Saveable)
+            -> Result<bool, qm::Error> {
+            self.inner.bump_correlation(first, second, bump, save)
+        }
+        fn bump_discount_date(&mut self, replacement: Date,
+            save: &mut Saveable) -> Result<bool, qm::Error> {
+            self.inner.bump_discount_date(replacement, save)
+        }
+        fn forward_id_by_credit_id(&self, credit_id: &str)
+            -> Result<&[String], qm::Error> {
+            self.inner.forward_id_by_credit_id(credit_id)
+        }
+        fn new_saveable(&self) -> Box<Saveable> {
+            self.inner.new_saveable()
+        }
+        fn restore(&mut self, saved: &Saveable) -> Result<(), qm::Error> {
+            self.inner.restore(saved)
+        }
+    }
+
+    impl TimeBumpable for FailingPricer {
+        fn bump_time(&mut self, bump: &BumpTime) -> Result<(), qm::Error> {
+            self.inner.as_mut_time_bumpable().bump_time(bump)
+        }
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+}
@@ -5,7 +5,7 @@ use instruments::RcInstrument;
 use instruments::SpotRequirement;
 use std::collections::HashSet;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// Collect the dependencies of an instrument
 pub struct DependencyCollector {
@@ -14,7 +14,7 @@ pub struct DependencyCollector {
     yield_curves: HashMap<String, Date>,
     forward_curves: HashMap<RcInstrument, Date>,
     vol_surfaces: HashMap<RcInstrument, Date>,
-    instruments: HashMap<String, Rc<Instrument>>,
+    instruments: HashMap<String, Arc<Instrument>>,
     forward_id_from_credit_id: HashMap<String, Vec<String>>,
     empty: Vec<String>
 }
@@ -33,25 +33,33 @@ impl DependencyCollector {
         }
     }
 
-    pub fn has_spot(&self, instrument: &Rc<Instrument>) -> bool {
+    pub fn has_spot(&self, instrument: &Arc<Instrument>) -> bool {
         let key = RcInstrument::new(instrument.clone());
         self.spots.contains(&key)
     }
 
+    pub fn spots(&self) -> &HashSet<RcInstrument> {
+        &self.spots
+    }
+
     pub fn yield_curve_hwm(&self, credit_id: &str) -> Option<Date> {
         get_hwm_by_str(&self.yield_curves, credit_id)
     }
 
-    pub fn forward_curve_hwm(&self, instrument: &Rc<Instrument>)
+    pub fn forward_curve_hwm(&self, instrument: &Arc<Instrument>)
         -> Option<Date> {
         get_hwm(&self.forward_curves, instrument)
     }
 
-    pub fn vol_surface_hwm(&self, instrument: &Rc<Instrument>)
+    pub fn vol_surface_hwm(&self, instrument: &Arc<Instrument>)
         -> Option<Date> {
         get_hwm(&self.vol_surfaces, instrument)
     }
 
+    pub fn yield_curves(&self) -> &HashMap<String, Date> {
+        &self.yield_curves
+    }
+
     pub fn forward_curves(&self) -> &HashMap<RcInstrument, Date> {
         &self.forward_curves
     }
@@ -60,7 +68,7 @@ impl DependencyCollector {
         &self.vol_surfaces
     }
 
-    pub fn instrument_by_id(&self, id: &str) ->Option<&Rc<Instrument>> {
+    pub fn instrument_by_id(&self, id: &str) ->Option<&Arc<Instrument>> {
         self.instruments.get(&id.to_string())
     }
 
@@ -73,7 +81,7 @@ impl DependencyCollector {
         }       
     }
 
-    fn add_instrument(&mut self, instrument: &Rc<Instrument>) {
+    fn add_instrument(&mut self, instrument: &Arc<Instrument>) {
         self.instruments.insert(
             instrument.id().to_string(), instrument.clone());
     }
@@ -86,7 +94,7 @@ fn get_hwm_by_str(map: &HashMap<String, Date>, id: &str) -> Option<Date> {
     }
 }
 
-fn get_hwm(map: &HashMap<RcInstrument, Date>, instrument: &Rc<Instrument>)
+fn get_hwm(map: &HashMap<RcInstrument, Date>, instrument: &Arc<Instrument>)
     -> Option<Date> {
     let key = RcInstrument::new(instrument.clone());
     match map.get(&key) {
@@ -104,7 +112,7 @@ impl DependencyContext for DependencyCollector {
         set_hwm_by_str(credit_id, high_water_mark, &mut self.yield_curves);
     }
 
-    fn spot(&mut self, instrument: &Rc<Instrument>) {
+    fn spot(&mut self, instrument: &Arc<Instrument>) {
 
         // recurse into this instrument
         let spot_requirement = instrument.dependencies(self);
@@ -117,7 +125,7 @@ impl DependencyContext for DependencyCollector {
         }
     }
 
-    fn forward_curve(&mut self, instrument: &Rc<Instrument>,
+    fn forward_curve(&mut self, instrument: &Arc<Instrument>,
         high_water_mark: Date) {
 
         set_hwm(instrument, high_water_mark, &mut self.forward_curves);
@@ -136,7 +144,7 @@ impl DependencyContext for DependencyCollector {
         self.spot(instrument);
     }
 
-    fn vol_surface(&mut self, instrument: &Rc<Instrument>,
+    fn vol_surface(&mut self, instrument: &Arc<Instrument>,
         high_water_mark: Date) {
         set_hwm(instrument, high_water_mark, &mut self.vol_surfaces);
         self.add_instrument(instrument);
@@ -154,7 +162,7 @@ pub fn set_hwm_by_str(id: &str, high_water_mark: Date,
     }
 }
 
-pub fn set_hwm(instrument: &Rc<Instrument>, high_water_mark: Date,
+pub fn set_hwm(instrument: &Arc<Instrument>, high_water_mark: Date,
     map: &mut HashMap<RcInstrument, Date>) {
 
     let key = RcInstrument::new(instrument.clone());
@@ -178,20 +186,20 @@ mod tests {
     use instruments::options::PutOrCall;
     use instruments::options::OptionSettlement;
     use dates::rules::DateRule;
-    use std::rc::Rc;
+    use std::sync::Arc;
 
     fn sample_currency(step: u32) -> Currency {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        let settlement = Rc::new(BusinessDays::new_step(calendar, step));
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let settlement = Arc::new(BusinessDays::new_step(calendar, step));
         Currency::new("GBP", settlement)
     }
 
-    fn sample_settlement(step: u32) -> Rc<DateRule> {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        Rc::new(BusinessDays::new_step(calendar, step))
+    fn sample_settlement(step: u32) -> Arc<DateRule> {
+        let calendar = Arc::new(WeekdayCalendar::new());
+        Arc::new(BusinessDays::new_step(calendar, step))
     }
 
-    fn sample_equity(currency: Rc<Currency>, step: u32) -> Equity {
+    fn sample_equity(currency: Arc<Currency>, step: u32) -> Equity {
         let settlement = sample_settlement(step);
         Equity::new("BP.L", "LSE", currency, settlement)
     }
@@ -204,14 +212,14 @@ mod tests {
         let short_expiry = DateTime::new(d+70, TimeOfDay::Close);
         let long_expiry = DateTime::new(d+210, TimeOfDay::Close);
 
-        let currency = Rc::new(sample_currency(2));
+        let currency = Arc::new(sample_currency(2));
         let settlement = sample_settlement(2);
-        let equity: Rc<Instrument> = Rc::new(sample_equity(currency, 2));
-        let short_european: Rc<Instrument> = Rc::new(SpotStartingEuropean::new(
-            "ShortDatedEquity",
+        let equity: Arc<Instrument> = Arc::new(sample_equity(currency, 2));
+        let short_european: Arc<Instrument> = Arc::new(
+            SpotStartingEuropean::new("ShortDatedEquity",
             "OPT", equity.clone(), settlement.clone(), short_expiry,
             strike, PutOrCall::Call, OptionSettlement::Cash).unwrap());
-        let long_european: Rc<Instrument> = Rc::new(SpotStartingEuropean::new(
+        let long_european: Arc<Instrument> = Arc::new(SpotStartingEuropean::new(
             "LongDatedEquity",
             "OPT", equity.clone(), settlement, long_expiry,
             strike, PutOrCall::Call, OptionSettlement::Cash).unwrap());
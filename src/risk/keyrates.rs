@@ -0,0 +1,351 @@
+use core::qm;
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use data::bumpyield::BumpYield;
+use data::curves::RateCurve;
+use data::curves::KeyRateShape;
+use dates::Date;
+use risk::Pricer;
+use risk::Saveable;
+use risk::reports::UnderlierSelector;
+use risk::reports::selected_forward_curves;
+use risk::reports::selected_yield_curves;
+use risk::reports::bumped_price;
+use risk::reports::ScenarioScheduler;
+
+/// Which sort of rate curve a key-rate report bumps. Yield curves are
+/// identified by credit id and give rho. Borrow curves are identified by
+/// the id of the instrument being borrowed and give repo risk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyRateCurve {
+    Yield,
+    Borrow
+}
+
+/// Sensitivity to a single pillar of a rate curve. The rho is per unit of
+/// continuously compounded rate, so multiply by 0.0001 for the change per
+/// basis point.
+#[derive(Clone, Debug)]
+pub struct KeyRateBucket {
+    pillar: Date,
+    rho: f64
+}
+
+impl KeyRateBucket {
+    pub fn pillar(&self) -> Date { self.pillar }
+    pub fn rho(&self) -> f64 { self.rho }
+}
+
+/// The results of a key-rate report. Contains the unbumped price and, for
+/// each curve, its buckets in ascending order of pillar date. Curves are
+/// keyed by credit id for yield curves, or instrument id for borrow curves.
+#[derive(Clone, Debug)]
+pub struct KeyRateResult {
+    price: f64,
+    curve: KeyRateCurve,
+    buckets: BTreeMap<String, Vec<KeyRateBucket>>
+}
+
+impl KeyRateResult {
+    pub fn price(&self) -> f64 { self.price }
+    pub fn curve(&self) -> KeyRateCurve { self.curve }
+    pub fn buckets(&self) -> &BTreeMap<String, Vec<KeyRateBucket>> {
+        &self.buckets
+    }
+    pub fn get(&self, id: &str) -> Option<&[KeyRateBucket]> {
+        self.buckets.get(id).map(|buckets| &buckets[..])
+    }
+
+    /// Sum of the bucketed rhos for one curve. Because the key-rate bumps
+    /// add up to a flat bump, this is close to the parallel rho.
+    pub fn total(&self, id: &str) -> Option<f64> {
+        self.get(id).map(|buckets|
+            buckets.iter().map(|bucket| bucket.rho).sum())
+    }
+}
+
+/// Report that calculates rho or repo risk bucketed by the pillars of each
+/// rate curve the pricer depends on. The pillars are taken from the curve
+/// itself, so it must be one that has them, such as RateCurveAct365. Each
+/// pillar is bumped up and down by a key-rate bump of the given shape and
+/// size in continuously compounded rate, and the sensitivity calculated by
+/// central differences.
+///
+/// Yield curves are selected by credit id, using UnderlierSelector::
+/// matches_credit_id. Borrow curves are selected like any other underlier.
+/// Pillars beyond the last date the pricer is sensitive to are skipped, as
+/// they cannot have any risk.
+pub struct KeyRateReport {
+    curve: KeyRateCurve,
+    size: f64,
+    shape: KeyRateShape,
+    selector: UnderlierSelector
+}
+
+impl KeyRateReport {
+    pub fn new(curve: KeyRateCurve, size: f64, shape: KeyRateShape,
+        selector: UnderlierSelector) -> Result<KeyRateReport, qm::Error> {
+        if !(size > 0.0) {
+            return Err(qm::Error::new("Key rate bump size must be positive"))
+        }
+        Ok(KeyRateReport { curve: curve, size: size, shape: shape,
+            selector: selector })
+    }
+
+    pub fn curve(&self) -> KeyRateCurve { self.curve }
+    pub fn size(&self) -> f64 { self.size }
+    pub fn shape(&self) -> KeyRateShape { self.shape }
+    pub fn selector(&self) -> &UnderlierSelector { &self.selector }
+
+    /// Runs the report against the given pricer. On return, the pricer is
+    /// in the same state as on entry, whether or not the report succeeded.
+    pub fn calculate(&self, pricer: &mut Pricer)
+        -> Result<KeyRateResult, qm::Error> {
+        self.calculate_with(pricer, &ScenarioScheduler::sequential())
+    }
+
+    /// Runs the report as calculate, with the bumps of each pillar as a
+    /// separate scenario for the scheduler.
+    pub fn calculate_with(&self, pricer: &mut Pricer,
+        scheduler: &ScenarioScheduler) -> Result<KeyRateResult, qm::Error> {
+
+        let price = pricer.price()?;
+        let mut buckets = BTreeMap::new();
+        let mut scenarios = Vec::new();
+
+        // find the curves to bump, and the pillars of each
+        let curves: Vec<(String, Date)> = match self.curve {
+            KeyRateCurve::Yield
+                => selected_yield_curves(pricer, &self.selector),
+            KeyRateCurve::Borrow
+                => selected_forward_curves(pricer, &self.selector).iter()
+                    .map(|&(ref instrument, hwm)|
+                        (instrument.id().to_string(), hwm))
+                    .collect()
+        };
+
+        for &(ref id, hwm) in curves.iter() {
+            let curve = {
+                let context = pricer.as_pricing_context();
+                match self.curve {
+                    KeyRateCurve::Yield => context.yield_curve(id, hwm)?,
+                    KeyRateCurve::Borrow => context.borrow_curve(id, hwm)?
+                }
+            };
+            buckets.insert(id.to_string(), Vec::new());
+            self.curve_scenarios(id, curve, hwm, &mut scenarios)?;
+        }
+
+        let rhos = scheduler.run(pricer, scenarios, |pricer, save, pillar|
+            self.rho(pricer, pillar, save))?;
+        for (id, rho) in rhos.into_iter() {
+            buckets.get_mut(&id).unwrap().push(rho);
+        }
+
+        Ok(KeyRateResult { price: price, curve: self.curve, buckets: buckets })
+    }
+
+    fn curve_scenarios(&self, id: &str, curve: Arc<RateCurve>, hwm: Date,
+        scenarios: &mut Vec<KeyRateScenario>) -> Result<(), qm::Error> {
+
+        let pillars = match curve.pillar_dates() {
+            Some(pillars) => pillars,
+            None => return Err(qm::Error::new(&format!(
+                "Rate curve for {} has no pillars for key-rate risk", id)))
+        };
+
+        for pillar in 0..pillars.len() {
+
+            // a pillar only affects dates up to the following pillar
+            if pillar > 0 && pillars[pillar - 1] >= hwm {
+                break
+            }
+
+            scenarios.push(KeyRateScenario { id: id.to_string(),
+                pillar: pillars[pillar],
+                up: BumpYield::new_key_rate(&pillars, pillar, self.size,
+                    self.shape)?,
+                down: BumpYield::new_key_rate(&pillars, pillar, -self.size,
+                    self.shape)? });
+        }
+
+        Ok(())
+    }
+
+    fn rho(&self, pricer: &mut Pricer, pillar: KeyRateScenario,
+        save: &mut Saveable) -> Result<(String, KeyRateBucket), qm::Error> {
+
+        let curve = self.curve;
+        let id = &pillar.id;
+        let bump = |bump: &BumpYield, pricer: &mut Pricer,
+            save: &mut Saveable| bumped_price(pricer, save, |b, s|
+                match curve {
+                    KeyRateCurve::Yield => b.bump_yield(id, bump, s),
+                    KeyRateCurve::Borrow => b.bump_borrow(id, bump, s)
+                });
+
+        let up_price = bump(&pillar.up, pricer, save)?;
+        let down_price = bump(&pillar.down, pricer, save)?;
+
+        // a curve that could not be bumped has zero risk
+        let rho = match (up_price, down_price) {
+            (Some(up), Some(down)) => (up - down) / (2.0 * self.size),
+            _ => 0.0
+        };
+        Ok((pillar.id.clone(), KeyRateBucket { pillar: pillar.pillar,
+            rho: rho }))
+    }
+}
+
+/// The up and down bumps for one pillar of one rate curve
+struct KeyRateScenario {
+    id: String,
+    pillar: Date,
+    up: BumpYield,
+    down: BumpYield
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::fixings::FixingTable;
+    use dates::datetime::DateTime;
+    use dates::datetime::TimeOfDay;
+    use instruments::Instrument;
+    use math::numerics::approx_eq;
+    use pricers::PricerFactory;
+    use pricers::selfpricer::SelfPricerFactory;
+    use pricers::montecarlo::MonteCarloPricerFactory;
+    use models::blackdiffusion::BlackDiffusionFactory;
+    use risk::marketdata::MarketData;
+    use risk::marketdata::tests::sample_market_data;
+    use risk::marketdata::tests::sample_european;
+
+    fn sample_pricer() -> Box<Pricer> {
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let instrument: Arc<Instrument> = sample_european();
+        let today = Date::from_ymd(2017, 01, 02);
+        let fixings = Arc::new(FixingTable::new(today, &[
+            ("BP.L", &[
+            (DateTime::new(today - 7, TimeOfDay::Close), 102.0)])]).unwrap());
+        let factory = SelfPricerFactory::new();
+        factory.new(instrument, fixings, market_data).unwrap()
+    }
+
+    fn parallel_rho(pricer: &mut Pricer, curve: KeyRateCurve, id: &str,
+        size: f64) -> f64 {
+        let mut save = pricer.as_bumpable().new_saveable();
+        let mut price = |size: f64| {
+            let bump = BumpYield::new_flat_continuously_compounded(size);
+            bumped_price(pricer, &mut *save, |b, s| match curve {
+                KeyRateCurve::Yield => b.bump_yield(id, &bump, s),
+                KeyRateCurve::Borrow => b.bump_borrow(id, &bump, s)
+            }).unwrap().unwrap()
+        };
+        let up = price(size);
+        let down = price(-size);
+        (up - down) / (2.0 * size)
+    }
+
+    #[test]
+    fn key_rate_rho_sums_to_parallel() {
+        let mut pricer = sample_pricer();
+        let unbumped = pricer.price().unwrap();
+
+        for shape in [KeyRateShape::Triangular, KeyRateShape::Pillar].iter() {
+            let report = KeyRateReport::new(KeyRateCurve::Yield, 0.0001,
+                *shape, UnderlierSelector::new_all()).unwrap();
+            let result = report.calculate(&mut *pricer).unwrap();
+            assert_approx(result.price(), unbumped, 1e-12);
+
+            // the option discounts on OPT, and its underlying funds on LSE
+            assert_eq!(result.buckets().len(), 2);
+            for id in ["OPT", "LSE"].iter() {
+                let parallel = parallel_rho(&mut *pricer, KeyRateCurve::Yield,
+                    id, 0.0001);
+                assert_approx(result.total(id).unwrap(), parallel,
+                    parallel.abs() * 1e-6);
+            }
+        }
+
+        // the pricer must be left unbumped
+        assert_approx(pricer.price().unwrap(), unbumped, 1e-12);
+    }
+
+    #[test]
+    fn scheduled_report_matches_sequential() {
+
+        // Forks of a Monte-Carlo pricer share its paths, so the bumped
+        // prices on other threads are exactly those of the pricer itself
+        let market_data: Arc<MarketData> = Arc::new(sample_market_data());
+        let today = Date::from_ymd(2017, 01, 02);
+        let fixings = Arc::new(FixingTable::new(today, &[]).unwrap());
+        let factory = MonteCarloPricerFactory::new(Box::new(
+            BlackDiffusionFactory::new(20, 0.01, 5000)));
+        let mut pricer = factory.new(sample_european(), fixings, market_data)
+            .unwrap();
+        let unbumped = pricer.price().unwrap();
+
+        let report = KeyRateReport::new(KeyRateCurve::Yield, 0.0001,
+            KeyRateShape::Triangular, UnderlierSelector::new_all()).unwrap();
+        let sequential = report.calculate(&mut *pricer).unwrap();
+        let scheduled = report.calculate_with(&mut *pricer,
+            &ScenarioScheduler::new(3).unwrap()).unwrap();
+        assert_eq!(scheduled.buckets().len(), 2);
+        for (id, buckets) in sequential.buckets().iter() {
+            let others = scheduled.get(id).unwrap();
+            assert_eq!(others.len(), buckets.len());
+            for (bucket, other) in buckets.iter().zip(others.iter()) {
+                assert_eq!(other.pillar(), bucket.pillar());
+                assert_eq!(other.rho(), bucket.rho());
+            }
+        }
+        assert_eq!(pricer.price().unwrap(), unbumped);
+        assert!(ScenarioScheduler::new(0).is_err());
+    }
+
+    #[test]
+    fn key_rate_rho_is_localised() {
+        let mut pricer = sample_pricer();
+
+        // the option is discounted from the pay date back to the settlement
+        // of spot. The pay date lies between the last two pillars, and the
+        // settlement date between the first two, so with triangular bumps
+        // the middle pillar has no rho.
+        let report = KeyRateReport::new(KeyRateCurve::Yield, 0.0001,
+            KeyRateShape::Triangular, UnderlierSelector::new_by_id(&["OPT"]))
+            .unwrap();
+        let result = report.calculate(&mut *pricer).unwrap();
+        assert_eq!(result.buckets().len(), 1);
+        let buckets = result.get("OPT").unwrap();
+        assert_eq!(buckets.len(), 5);
+        assert_approx(buckets[2].rho(), 0.0, 1e-12);
+        for bucket in buckets[3..5].iter() {
+            assert!(bucket.rho() < 0.0, "rho={}", bucket.rho());
+        }
+        assert!(buckets[4].rho().abs() > 10.0 * buckets[0].rho().abs());
+    }
+
+    #[test]
+    fn key_rate_repo_risk() {
+        let mut pricer = sample_pricer();
+
+        let report = KeyRateReport::new(KeyRateCurve::Borrow, 0.0001,
+            KeyRateShape::Triangular, UnderlierSelector::new_all()).unwrap();
+        let result = report.calculate(&mut *pricer).unwrap();
+        assert_eq!(result.buckets().len(), 1);
+        assert_eq!(result.get("BP.L").unwrap().len(), 4);
+
+        // borrow reduces the forward, so a call has negative repo risk
+        let parallel = parallel_rho(&mut *pricer, KeyRateCurve::Borrow,
+            "BP.L", 0.0001);
+        assert!(parallel < 0.0);
+        assert_approx(result.total("BP.L").unwrap(), parallel,
+            parallel.abs() * 1e-6);
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+}
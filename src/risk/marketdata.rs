@@ -1,20 +1,38 @@
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::collections::HashSet;
+use std::sync::Arc;
 use std::any::Any;
+use nalgebra::base::DMatrix;
 use core::qm;
 use dates::Date;
+use dates::datetime::DateTime;
+use dates::datetime::TimeOfDay;
 use data::curves::RateCurve;
 use data::divstream::DividendStream;
+use data::divstream::Dividend;
 use data::volsurface::VolSurface;
+use data::correlation::LocalCorrelation;
+use data::correlation::correlation_key;
 use data::forward::Forward;
 use data::forward::EquityForward;
+use data::forward::DriftlessForward;
+use data::volarbitrage::VolArbitrageChecker;
+use data::volarbitrage::VolArbitrageReport;
 use data::bump::Bump;
 use data::bumpspot::BumpSpot;
 use data::bumpyield::BumpYield;
 use data::bumpdivs::BumpDivs;
 use data::bumpvol::BumpVol;
+use data::bumpcorrelation::BumpCorrelation;
+use data::bumptime::BumpTime;
+use data::bumptime::SpotDynamics;
+use data::fixings::FixingTable;
+use math::correlation::min_eigenvalue;
 use instruments::Instrument;
+use instruments::RcInstrument;
 use instruments::PricingContext;
+use instruments::DependencyContext;
+use risk::dependencies::DependencyCollector;
 use risk::Bumpable;
 use risk::Saveable;
 use risk::BumpablePricingContext;
@@ -36,10 +54,11 @@ pub struct MarketData {
     spot_date: Date, 
     discount_date: Option<Date>, 
     spots: HashMap<String, f64>,
-    yield_curves: HashMap<String, Rc<RateCurve>>,
-    borrow_curves: HashMap<String, Rc<RateCurve>>,
-    dividends: HashMap<String, Rc<DividendStream>>,
-    vol_surfaces: HashMap<String, Rc<VolSurface>>
+    yield_curves: HashMap<String, Arc<RateCurve>>,
+    borrow_curves: HashMap<String, Arc<RateCurve>>,
+    dividends: HashMap<String, Arc<DividendStream>>,
+    vol_surfaces: HashMap<String, Arc<VolSurface>>,
+    correlations: HashMap<String, Arc<LocalCorrelation>>
 }
 
 impl MarketData {
@@ -71,10 +90,10 @@ impl MarketData {
         spot_date: Date, 
         discount_date: Option<Date>, 
         spots: HashMap<String, f64>,
-        yield_curves: HashMap<String, Rc<RateCurve>>,
-        borrow_curves: HashMap<String, Rc<RateCurve>>,
-        dividends: HashMap<String, Rc<DividendStream>>,
-        vol_surfaces: HashMap<String, Rc<VolSurface>>) -> MarketData {
+        yield_curves: HashMap<String, Arc<RateCurve>>,
+        borrow_curves: HashMap<String, Arc<RateCurve>>,
+        dividends: HashMap<String, Arc<DividendStream>>,
+        vol_surfaces: HashMap<String, Arc<VolSurface>>) -> MarketData {
 
         MarketData {
             spot_date: spot_date, 
@@ -83,10 +102,241 @@ impl MarketData {
             yield_curves: yield_curves,
             borrow_curves: borrow_curves,
             dividends: dividends,
-            vol_surfaces: vol_surfaces }
+            vol_surfaces: vol_surfaces,
+            correlations: HashMap::new() }
+    }
+
+    /// Creates a market data object with correlations between underliers.
+    /// The other parameters are the same as for new.
+    ///
+    /// * 'correlations'   - Local correlations, keyed by the pair of ids of
+    ///                      the instruments they correlate. Either order
+    ///                      of ids may be used, but each pair may only be
+    ///                      given once. Missing pairs are treated as
+    ///                      uncorrelated when validating the matrix, but
+    ///                      are an error if requested during pricing.
+    ///
+    /// The correlation matrix of all underliers must be positive
+    /// semi-definite on every date, otherwise it cannot be used to generate
+    /// correlated paths. This is checked on construction.
+    pub fn new_with_correlations(
+        spot_date: Date, 
+        discount_date: Option<Date>, 
+        spots: HashMap<String, f64>,
+        yield_curves: HashMap<String, Arc<RateCurve>>,
+        borrow_curves: HashMap<String, Arc<RateCurve>>,
+        dividends: HashMap<String, Arc<DividendStream>>,
+        vol_surfaces: HashMap<String, Arc<VolSurface>>,
+        correlations: HashMap<(String, String), Arc<LocalCorrelation>>)
+        -> Result<MarketData, qm::Error> {
+
+        let mut keyed = HashMap::new();
+        for (&(ref first, ref second), correlation) in correlations.iter() {
+            if first == second {
+                return Err(qm::Error::new(&format!(
+                    "Correlation of '{}' with itself must not be supplied",
+                    first)))
+            }
+            let key = correlation_key(first, second);
+            if keyed.insert(key, correlation.clone()).is_some() {
+                return Err(qm::Error::new(&format!("Correlation between \
+                    '{}' and '{}' supplied more than once", first, second)))
+            }
+        }
+        validate_correlations(spot_date, &correlations)?;
+
+        let mut market_data = MarketData::new(spot_date, discount_date, spots,
+            yield_curves, borrow_curves, dividends, vol_surfaces);
+        market_data.correlations = keyed;
+        Ok(market_data)
+    }
+
+    /// Creates market data as in `new`, but first runs the given arbitrage
+    /// checker over all the vol surfaces, and rejects the market data with
+    /// an error describing any arbitrage found. Use this when loading
+    /// market data from an external source.
+    pub fn new_checked(
+        spot_date: Date, 
+        discount_date: Option<Date>, 
+        spots: HashMap<String, f64>,
+        yield_curves: HashMap<String, Arc<RateCurve>>,
+        borrow_curves: HashMap<String, Arc<RateCurve>>,
+        dividends: HashMap<String, Arc<DividendStream>>,
+        vol_surfaces: HashMap<String, Arc<VolSurface>>,
+        checker: &VolArbitrageChecker) -> Result<MarketData, qm::Error> {
+
+        let market_data = MarketData::new(spot_date, discount_date, spots,
+            yield_curves, borrow_curves, dividends, vol_surfaces);
+        market_data.check_vol_arbitrage(checker)?;
+        Ok(market_data)
+    }
+
+    /// Runs the arbitrage checker over all the vol surfaces, returning a
+    /// report for each, keyed by the instrument id and sorted by it. If a
+    /// surface has no forward of its own, we use the spot as a driftless
+    /// forward, which is good enough for the purpose of checking.
+    pub fn vol_arbitrage_reports(&self, checker: &VolArbitrageChecker)
+        -> Result<Vec<(String, VolArbitrageReport)>, qm::Error> {
+
+        let mut ids: Vec<&String> = self.vol_surfaces.keys().collect();
+        ids.sort();
+        let mut reports = Vec::with_capacity(ids.len());
+        for id in ids {
+            let surface = &self.vol_surfaces[id];
+            let report = match surface.forward() {
+                Some(forward) => checker.check_with_forward(&**surface,
+                    forward)?,
+                None => {
+                    let spot = find_market_data(id, &self.spots,
+                        "Spot for vol arbitrage check")?;
+                    checker.check_with_forward(&**surface,
+                        &DriftlessForward::new(spot))?
+                }
+            };
+            reports.push((id.clone(), report));
+        }
+        Ok(reports)
+    }
+
+    /// Use this as a gate when loading market data. Runs the arbitrage
+    /// checker over all the vol surfaces, and returns an error describing
+    /// any arbitrage found.
+    pub fn check_vol_arbitrage(&self, checker: &VolArbitrageChecker)
+        -> Result<(), qm::Error> {
+
+        let failures: Vec<String> = self.vol_arbitrage_reports(checker)?
+            .iter().filter(|r| !r.1.is_clean())
+            .map(|r| format!("'{}': {}", r.0, r.1)).collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(qm::Error::new(&format!("Vol surface arbitrage found. {}",
+                failures.join(". "))))
+        }
     }
 }
 
+impl MarketData {
+    /// Rolls the spots of the given underliers forward to the new spot date,
+    /// according to the spot dynamics, and sets the spot date. Returns a
+    /// table of the fixings implied by the same dynamics, for every time of
+    /// day from the old spot date up to but excluding the new one.
+    ///
+    /// Spots of instruments that are not underliers are left unchanged, but
+    /// all dividend streams lose any dividends that have gone ex.
+    fn roll_spot_date(&mut self, spot_date: Date, dynamics: SpotDynamics,
+        underliers: &[RcInstrument]) -> Result<FixingTable, qm::Error> {
+
+        let old_spot_date = self.spot_date;
+        let days = spot_date - old_spot_date;
+
+        // work out the fixings and the new spots before we change anything
+        let mut fixings = Vec::with_capacity(underliers.len());
+        let mut spots = Vec::with_capacity(underliers.len());
+        for underlier in underliers.iter() {
+            let id = underlier.id();
+            let mut values = Vec::with_capacity(days as usize + 1);
+            match dynamics {
+                SpotDynamics::StickySpot => {
+                    let spot = find_market_data(id, &self.spots, "Spot")?;
+                    let divs = self.dividends.get(id).map(|divs|
+                        divs.dividends()).unwrap_or(&[]);
+                    for day in 0..(days + 1) {
+                        let date = old_spot_date + day;
+                        let paid: f64 = divs.iter()
+                            .filter(|div| div.ex_date() > old_spot_date
+                                && div.ex_date() <= date)
+                            .map(|div| div.cash() + div.relative() * spot)
+                            .sum();
+                        values.push(spot - paid);
+                    }
+                },
+                SpotDynamics::StickyForward => {
+                    let forward = self.forward_curve(underlier.instrument(),
+                        spot_date)?;
+                    for day in 0..(days + 1) {
+                        values.push(forward.forward(old_spot_date + day)?);
+                    }
+                }
+            }
+
+            // the last value is the new spot, which is not yet a fixing
+            spots.push((id.to_string(), values.pop().unwrap()));
+            let mut curve = Vec::with_capacity(values.len() * 3);
+            for (day, value) in values.iter().enumerate() {
+                let date = old_spot_date + day as i32;
+                for time_of_day in [TimeOfDay::Open, TimeOfDay::EDSP,
+                    TimeOfDay::Close].iter() {
+                    curve.push((DateTime::new(date, *time_of_day), *value));
+                }
+            }
+            fixings.push((id.to_string(), curve));
+        }
+
+        let fixing_refs: Vec<(&str, &[(DateTime, f64)])> = fixings.iter()
+            .map(|&(ref id, ref curve)| (&id[..], &curve[..])).collect();
+        let fixing_table = FixingTable::new(spot_date, &fixing_refs)?;
+
+        self.spot_date = spot_date;
+        for (id, spot) in spots.into_iter() {
+            self.spots.insert(id, spot);
+        }
+
+        // dividends that have gone ex are now reflected in the spot, so
+        // they must be removed from the dividend streams
+        for divs in self.dividends.values_mut() {
+            if divs.dividends().iter().any(|div| div.ex_date() <= spot_date) {
+                let remaining: Vec<Dividend> = divs.dividends().iter()
+                    .filter(|div| div.ex_date() > spot_date)
+                    .cloned().collect();
+                *divs = Arc::new(DividendStream::new(&remaining,
+                    divs.div_yield()));
+            }
+        }
+        Ok(fixing_table)
+    }
+}
+
+/// Finds the underliers of the given instruments whose spots must be rolled
+/// and fixed during a time bump. These are any whose spot or forward is
+/// needed for pricing. The result is sorted by id.
+fn time_bump_underliers(spot_date: Date,
+    instruments: &[(f64, Arc<Instrument>)]) -> Vec<RcInstrument> {
+
+    let mut collector = DependencyCollector::new(spot_date);
+    for &(_, ref instrument) in instruments.iter() {
+        collector.spot(instrument);
+    }
+
+    let mut underliers: HashSet<RcInstrument> = collector.spots().clone();
+    for instrument in collector.forward_curves().keys() {
+        underliers.insert(instrument.clone());
+    }
+    let mut underliers: Vec<RcInstrument> = underliers.into_iter().collect();
+    underliers.sort();
+    underliers
+}
+
+/// Applies the fixings to each of the instruments, replacing any that are
+/// affected by their fixed equivalents, scaled by the original weight.
+pub fn fix_instruments(instruments: &[(f64, Arc<Instrument>)],
+    fixing_table: &FixingTable)
+    -> Result<Vec<(f64, Arc<Instrument>)>, qm::Error> {
+
+    let mut fixed = Vec::with_capacity(instruments.len());
+    for &(weight, ref instrument) in instruments.iter() {
+        match instrument.fix(fixing_table)? {
+            Some(decomposition) => {
+                for (component_weight, component) in decomposition {
+                    fixed.push((weight * component_weight, component));
+                }
+            },
+            None => fixed.push((weight, instrument.clone()))
+        }
+    }
+    Ok(fixed)
+}
+
 impl PricingContext for MarketData {
 
     fn spot_date(&self) -> Date {
@@ -98,16 +348,21 @@ impl PricingContext for MarketData {
     }
 
     fn yield_curve(&self, credit_id: &str, _high_water_mark: Date)
-            -> Result<Rc<RateCurve>, qm::Error> {
+            -> Result<Arc<RateCurve>, qm::Error> {
         find_market_data(credit_id, &self.yield_curves, "Yield curve")
     }
 
+    fn borrow_curve(&self, id: &str, _high_water_mark: Date)
+            -> Result<Arc<RateCurve>, qm::Error> {
+        find_market_data(id, &self.borrow_curves, "Borrow curve")
+    }
+
     fn spot(&self, id: &str) -> Result<f64, qm::Error> {
         find_market_data(id, &self.spots, "Spot")
     }
 
     fn forward_curve(&self, instrument: &Instrument, high_water_mark: Date)
-        -> Result<Rc<Forward>, qm::Error> {
+        -> Result<Arc<Forward>, qm::Error> {
 
         // This assumes the instrument is an equity. Need handling for other
         // types of underlying that may not have dividends or borrow, or may
@@ -127,28 +382,99 @@ impl PricingContext for MarketData {
         let settlement = instrument.settlement().clone();
         let forward = EquityForward::new(self.spot_date, spot, settlement,
             yield_curve, borrow, &*divs, high_water_mark)?;
-        Ok(Rc::new(forward))
+        Ok(Arc::new(forward))
     }
 
     /// Gets a Vol Surface, given any instrument, for example an equity.  Also
     /// specify a high water mark, beyond which we never directly ask for
     /// vols.
-    fn vol_surface(&self, instrument: &Instrument, forward: Rc<Forward>,
-        _high_water_mark: Date) -> Result<Rc<VolSurface>, qm::Error> {
+    fn vol_surface(&self, instrument: &Instrument, forward: Arc<Forward>,
+        _high_water_mark: Date) -> Result<Arc<VolSurface>, qm::Error> {
 
         let id = instrument.id();
         let mut vol = find_market_data(id, &self.vol_surfaces, "Vol surface")?;
 
+        // the surface must be quoted the way the instrument expects, or we
+        // would silently misprice by using the wrong formula
+        let quoting = vol.vol_quoting();
+        if quoting != instrument.vol_quoting() {
+            return Err(qm::Error::new(&format!("Vol surface for '{}' is \
+                quoted as {:?} but the instrument expects {:?}", id,
+                quoting, instrument.vol_quoting())))
+        }
+
         // decorate or modify the surface to cope with any time or forward shift
         instrument.vol_time_dynamics().modify(&mut vol, self.spot_date)?; 
         instrument.vol_forward_dynamics().modify(&mut vol, forward)?;
         Ok(vol)
     }
 
-    fn correlation(&self, _first: &Instrument, _second: &Instrument)
-        -> Result<f64, qm::Error> {
-        Err(qm::Error::new("Correlation not implemented"))
+    fn correlation(&self, first: &Instrument, second: &Instrument,
+        date: Date) -> Result<f64, qm::Error> {
+
+        if first.id() == second.id() {
+            return Ok(1.0)
+        }
+        let key = correlation_key(first.id(), second.id());
+        let correlation = find_market_data(&key, &self.correlations,
+            "Correlation")?;
+        Ok(correlation.correlation(date))
+    }
+}
+
+/// Checks that the correlation matrix is positive semi-definite on every
+/// date from the spot date onwards where it may change, which is any pillar
+/// date of any of the correlations and the day after the last of them.
+/// (Correlations are piecewise constant, applying up to and including their
+/// pillar dates.)
+fn validate_correlations(spot_date: Date,
+    correlations: &HashMap<(String, String), Arc<LocalCorrelation>>)
+    -> Result<(), qm::Error> {
+
+    if correlations.is_empty() {
+        return Ok(())
+    }
+
+    // index the underliers, sorted for a deterministic error message
+    let mut ids: Vec<&str> = Vec::new();
+    for &(ref first, ref second) in correlations.keys() {
+        ids.push(first);
+        ids.push(second);
+    }
+    ids.sort();
+    ids.dedup();
+    let index: HashMap<&str, usize> = ids.iter().enumerate()
+        .map(|(i, id)| (*id, i)).collect();
+
+    let mut dates: Vec<Date> = correlations.values()
+        .flat_map(|c| c.pillar_dates().iter().cloned())
+        .filter(|d| *d > spot_date).collect();
+    dates.sort();
+    dates.dedup();
+    if let Some(last) = dates.last().cloned() {
+        dates.push(last + 1);
+    }
+    dates.insert(0, spot_date);
+
+    let n = ids.len();
+    for date in dates.iter() {
+        let mut matrix = DMatrix::<f64>::identity(n, n);
+        for (&(ref first, ref second), correlation) in correlations.iter() {
+            let i = index[&first[..]];
+            let j = index[&second[..]];
+            let c = correlation.correlation(*date);
+            matrix[(i, j)] = c;
+            matrix[(j, i)] = c;
+        }
+
+        let min_eigenvalue = min_eigenvalue(&matrix);
+        if min_eigenvalue < -1e-12 {
+            return Err(qm::Error::new(&format!("Correlation matrix of {} \
+                is not positive semi-definite on {}: smallest eigenvalue {}",
+                ids.join(", "), date, min_eigenvalue)))
+        }
     }
+    Ok(())
 }
 
 fn find_market_data<T: Clone>(id: &str, collection: &HashMap<String, T>,
@@ -194,6 +520,14 @@ impl Bumpable for MarketData {
         apply_bump(id, bump, &mut self.vol_surfaces, &mut saved.vol_surfaces)
     }
 
+    fn bump_correlation(&mut self, first: &str, second: &str,
+        bump: &BumpCorrelation, save: &mut Saveable)
+        -> Result<bool, qm::Error> {
+        let saved = to_saved_data(save)?;
+        apply_bump(&correlation_key(first, second), bump,
+            &mut self.correlations, &mut saved.correlations)
+    }
+
     fn bump_discount_date(&mut self, replacement: Date, save: &mut Saveable)
         -> Result<bool, qm::Error> {
         let saved = to_saved_data(save)?;
@@ -225,6 +559,7 @@ impl Bumpable for MarketData {
             copy_from_saved(&mut self.borrow_curves, &saved.borrow_curves);
             copy_from_saved(&mut self.dividends, &saved.dividends);
             copy_from_saved(&mut self.vol_surfaces, &saved.vol_surfaces);
+            copy_from_saved(&mut self.correlations, &saved.correlations);
             Ok(())
 
         } else {
@@ -237,6 +572,35 @@ impl BumpablePricingContext for MarketData {
     fn as_bumpable(&self) -> &Bumpable { self }
     fn as_mut_bumpable(&mut self) -> &mut Bumpable { self }
     fn as_pricing_context(&self) -> &PricingContext { self }
+
+    /// Rolls the spot date forward. Yield curves, borrow curves and
+    /// dividends keep their base dates, so forwards are built from the new
+    /// spot date. Vol surfaces are evolved by the VolTimeDynamics of the
+    /// instrument when they are next fetched. The discount date is not
+    /// changed.
+    fn bump_time(&mut self, bump: &BumpTime,
+        instruments: &mut Vec<(f64, Arc<Instrument>)>)
+        -> Result<bool, qm::Error> {
+
+        let spot_date = bump.spot_date();
+        if spot_date == self.spot_date {
+            return Ok(false)
+        }
+        if spot_date < self.spot_date {
+            return Err(qm::Error::new(&format!("Cannot bump the spot date \
+                backwards from {} to {}", self.spot_date, spot_date)))
+        }
+
+        let underliers = time_bump_underliers(self.spot_date, instruments);
+        let fixing_table = self.roll_spot_date(spot_date,
+            bump.spot_dynamics(), &underliers)?;
+        *instruments = fix_instruments(instruments, &fixing_table)?;
+        Ok(true)
+    }
+
+    fn clone_box(&self) -> Box<BumpablePricingContext> {
+        Box::new(self.clone())
+    }
 }
 
 fn to_saved_data(save: &mut Saveable) -> Result<&mut SavedData, qm::Error> {
@@ -281,10 +645,11 @@ pub struct SavedData {
     discount_date: Option<Date>,
     replaced_discount_date: bool,
     spots: HashMap<String, f64>,
-    yield_curves: HashMap<String, Rc<RateCurve>>,
-    borrow_curves: HashMap<String, Rc<RateCurve>>,
-    dividends: HashMap<String, Rc<DividendStream>>,
-    vol_surfaces: HashMap<String, Rc<VolSurface>>
+    yield_curves: HashMap<String, Arc<RateCurve>>,
+    borrow_curves: HashMap<String, Arc<RateCurve>>,
+    dividends: HashMap<String, Arc<DividendStream>>,
+    vol_surfaces: HashMap<String, Arc<VolSurface>>,
+    correlations: HashMap<String, Arc<LocalCorrelation>>
 }
 
 impl SavedData {
@@ -299,7 +664,8 @@ impl SavedData {
             yield_curves: HashMap::new(),
             borrow_curves: HashMap::new(),
             dividends: HashMap::new(),
-            vol_surfaces: HashMap::new() }
+            vol_surfaces: HashMap::new(),
+            correlations: HashMap::new() }
     }
 }
 
@@ -315,6 +681,7 @@ impl Saveable for SavedData {
         self.borrow_curves.clear();
         self.dividends.clear();
         self.vol_surfaces.clear();
+        self.correlations.clear();
     }
 }
 
@@ -322,7 +689,7 @@ impl Saveable for SavedData {
 pub mod tests {
     use super::*;
     use instruments::assets::Currency;
-    use std::rc::Rc;
+    use std::sync::Arc;
     use dates::datetime::DateTime;
     use dates::datetime::TimeOfDay;
     use dates::datetime::DateDayFraction;
@@ -339,42 +706,47 @@ pub mod tests {
     use data::curves::RateCurveAct365;
     use data::volsurface::VolSurface;
     use data::volsurface::FlatVolSurface;
+    use data::volsurface::VolByProbability;
+    use data::volsurface::DivAssumptions;
+    use data::volsmile::CubicSplineSmile;
+    use data::volsmile::SviSmile;
     use dates::calendar::WeekdayCalendar;
+    use dates::calendar::Calendar;
     use math::numerics::approx_eq;
     use math::interpolation::Extrap;
 
     pub fn sample_currency(step: u32) -> Currency {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        let settlement = Rc::new(BusinessDays::new_step(calendar, step));
+        let calendar = Arc::new(WeekdayCalendar::new());
+        let settlement = Arc::new(BusinessDays::new_step(calendar, step));
         Currency::new("GBP", settlement)
     }
 
-    pub fn sample_settlement(step: u32) -> Rc<DateRule> {
-        let calendar = Rc::new(WeekdayCalendar::new());
-        Rc::new(BusinessDays::new_step(calendar, step))
+    pub fn sample_settlement(step: u32) -> Arc<DateRule> {
+        let calendar = Arc::new(WeekdayCalendar::new());
+        Arc::new(BusinessDays::new_step(calendar, step))
     }
 
-    pub fn sample_equity(currency: Rc<Currency>, step: u32) -> Equity {
+    pub fn sample_equity(currency: Arc<Currency>, step: u32) -> Equity {
         let settlement = sample_settlement(step);
         Equity::new("BP.L", "LSE", currency, settlement)
     }
 
-    pub fn sample_european() -> Rc<SpotStartingEuropean> {
+    pub fn sample_european() -> Arc<SpotStartingEuropean> {
 
         let strike = 100.0;
         let put_or_call = PutOrCall::Call;
         let expiry = DateTime::new(
             Date::from_ymd(2018, 06, 01), TimeOfDay::Close);
-        let currency = Rc::new(sample_currency(2));
+        let currency = Arc::new(sample_currency(2));
         let settlement = sample_settlement(2);
-        let equity = Rc::new(sample_equity(currency, 2));
+        let equity = Arc::new(sample_equity(currency, 2));
         let european = SpotStartingEuropean::new("SampleEquity", "OPT",
             equity.clone(), settlement, expiry,
             strike, put_or_call, OptionSettlement::Cash).unwrap();
-        Rc::new(european)
+        Arc::new(european)
     }
 
-    pub fn create_sample_divstream() -> Rc<DividendStream> {
+    pub fn create_sample_divstream() -> Arc<DividendStream> {
 
         // Early divs are purely cash. Later ones are mixed cash/relative
         let d = Date::from_ymd(2017, 01, 02);
@@ -390,35 +762,63 @@ pub mod tests {
             (d + 365 * 5, 0.01), (d + 365 * 10, 0.015)];
         let curve = RateCurveAct365::new(d + 365 * 2, &points,
             Extrap::Zero, Extrap::Flat).unwrap();
-        let div_yield = Rc::new(curve);
+        let div_yield = Arc::new(curve);
 
-        Rc::new(DividendStream::new(&divs, div_yield))
+        Arc::new(DividendStream::new(&divs, div_yield))
     }
 
-    pub fn create_sample_rate() -> Rc<RateCurve> {
+    pub fn create_sample_rate() -> Arc<RateCurve> {
         let d = Date::from_ymd(2016, 12, 30);
         let rate_points = [(d, 0.05), (d + 14, 0.08), (d + 182, 0.09),
             (d + 364, 0.085), (d + 728, 0.082)];
-        Rc::new(RateCurveAct365::new(d, &rate_points,
+        Arc::new(RateCurveAct365::new(d, &rate_points,
             Extrap::Flat, Extrap::Flat).unwrap())
     }
 
-    pub fn create_sample_borrow() -> Rc<RateCurve> {
+    pub fn create_sample_borrow() -> Arc<RateCurve> {
         let d = Date::from_ymd(2016, 12, 30);
         let borrow_points = [(d, 0.01), (d + 196, 0.012),
             (d + 364, 0.0125), (d + 728, 0.012)];
-        Rc::new(RateCurveAct365::new(d, &borrow_points,
+        Arc::new(RateCurveAct365::new(d, &borrow_points,
             Extrap::Flat, Extrap::Flat).unwrap())
     }
 
-    pub fn create_sample_flat_vol() -> Rc<VolSurface> {
+    pub fn create_sample_flat_vol() -> Arc<VolSurface> {
+        let calendar = Box::new(WeekdayCalendar());
+        let base_date = Date::from_ymd(2016, 12, 30);
+        let base = DateDayFraction::new(base_date, 0.2);
+        Arc::new(FlatVolSurface::new(0.3, calendar, base))
+    }
+
+    /// A vol surface with a pronounced downside skew, built from SVI smiles
+    /// whose total variance grows linearly in time, so it is free of
+    /// calendar arbitrage.
+    pub fn create_sample_skewed_vol() -> Arc<VolSurface> {
         let calendar = Box::new(WeekdayCalendar());
         let base_date = Date::from_ymd(2016, 12, 30);
         let base = DateDayFraction::new(base_date, 0.2);
-        Rc::new(FlatVolSurface::new(0.3, calendar, base))
+        let forward = 100.0;
+
+        let mut smiles = Vec::new();
+        for days in [182, 730].iter() {
+            let pillar = DateDayFraction::new(base_date + *days, 0.7);
+            let t = calendar.year_fraction(base, pillar);
+            smiles.push((pillar, SviSmile::new_raw(forward, t, 0.06 * t,
+                0.2 * t, -0.6, 0.0, 0.2).unwrap()));
+        }
+
+        Arc::new(VolByProbability::new(&smiles, calendar, base,
+            Box::new(DriftlessForward::new(forward)),
+            DivAssumptions::NoCashDivs).unwrap())
     }
 
     pub fn sample_market_data() -> MarketData {
+        sample_market_data_with_vol(create_sample_flat_vol())
+    }
+
+    /// The same as sample_market_data, but with the given vol surface for
+    /// BP.L
+    pub fn sample_market_data_with_vol(vol: Arc<VolSurface>) -> MarketData {
     
         let spot_date = Date::from_ymd(2017, 01, 02);
         let mut spots = HashMap::new();
@@ -438,13 +838,180 @@ pub mod tests {
         borrow_curves.insert("GSK.L".to_string(), create_sample_borrow());
 
         let mut vol_surfaces = HashMap::new();
-        vol_surfaces.insert("BP.L".to_string(), create_sample_flat_vol());
+        vol_surfaces.insert("BP.L".to_string(), vol);
         vol_surfaces.insert("GSK.L".to_string(), create_sample_flat_vol());
 
         MarketData::new(spot_date, None, spots, yield_curves,
             borrow_curves, dividends, vol_surfaces)
     }
 
+    /// The same as sample_market_data, but with correlations between BP.L,
+    /// GSK.L and a third underlier, which need not be in the market data.
+    pub fn sample_market_data_with_correlations(
+        correlations: &[(&str, &str, Arc<LocalCorrelation>)])
+        -> Result<MarketData, qm::Error> {
+        sample_market_data_with_vol_and_correlations(
+            create_sample_flat_vol(), correlations)
+    }
+
+    /// The same as sample_market_data_with_correlations, but with the given
+    /// vol surface for BP.L
+    pub fn sample_market_data_with_vol_and_correlations(vol: Arc<VolSurface>,
+        correlations: &[(&str, &str, Arc<LocalCorrelation>)])
+        -> Result<MarketData, qm::Error> {
+
+        let market_data = sample_market_data_with_vol(vol);
+        let correlations = correlations.iter().map(|c|
+            ((c.0.to_string(), c.1.to_string()), c.2.clone())).collect();
+        MarketData::new_with_correlations(market_data.spot_date,
+            market_data.discount_date, market_data.spots,
+            market_data.yield_curves, market_data.borrow_curves,
+            market_data.dividends, market_data.vol_surfaces, correlations)
+    }
+
+    #[test]
+    fn correlations_validated_and_bumped() {
+        let d = Date::from_ymd(2017, 01, 02);
+        let constant = |c| Arc::new(LocalCorrelation::new_constant(c).unwrap());
+        let currency = Arc::new(sample_currency(2));
+        let bp = Equity::new("BP.L", "LSE", currency.clone(),
+            sample_settlement(2));
+        let gsk = Equity::new("GSK.L", "LSE", currency, sample_settlement(2));
+
+        // a consistent set of correlations, one of which is term-structured
+        let term = Arc::new(LocalCorrelation::new_term_structure(&[
+            (d + 90, 0.5), (d + 365, 0.3)]).unwrap());
+        let mut market_data = sample_market_data_with_correlations(&[
+            ("GSK.L", "BP.L", term.clone()),
+            ("BP.L", "RIO.L", constant(0.4)),
+            ("GSK.L", "RIO.L", constant(0.2))]).unwrap();
+        assert_eq!(market_data.correlation(&bp, &gsk, d).unwrap(), 0.5);
+        assert_eq!(market_data.correlation(&gsk, &bp, d + 100).unwrap(), 0.3);
+        assert_eq!(market_data.correlation(&bp, &bp, d).unwrap(), 1.0);
+
+        // bump and restore, which must work whatever order the ids are in
+        let mut save = SavedData::new();
+        let bump = BumpCorrelation::new_flat_additive(0.1);
+        assert!(market_data.bump_correlation("GSK.L", "BP.L", &bump,
+            &mut save).unwrap());
+        assert_approx(market_data.correlation(&bp, &gsk, d).unwrap(),
+            0.6, 1e-12);
+        assert_approx(market_data.correlation(&bp, &gsk, d + 100).unwrap(),
+            0.4, 1e-12);
+        assert!(!market_data.bump_correlation("GSK.L", "VOD.L", &bump,
+            &mut save).unwrap());
+        market_data.restore(&save).unwrap();
+        assert_eq!(market_data.correlation(&bp, &gsk, d).unwrap(), 0.5);
+
+        // missing correlations are an error during pricing
+        let vod = Equity::new("VOD.L", "LSE", Arc::new(sample_currency(2)),
+            sample_settlement(2));
+        assert!(market_data.correlation(&bp, &vod, d).is_err());
+
+        // strong positive correlations each way with a strong negative one
+        // make the matrix invalid, but only after the first pillar
+        match sample_market_data_with_correlations(&[
+            ("BP.L", "GSK.L", Arc::new(LocalCorrelation::new_term_structure(&[
+                (d + 90, 0.5), (d + 365, -0.9)]).unwrap())),
+            ("BP.L", "RIO.L", constant(0.7)),
+            ("GSK.L", "RIO.L", constant(0.7))]) {
+            Ok(_) => panic!("Invalid correlation matrix accepted"),
+            Err(err) => assert!(err.to_string().contains(
+                "not positive semi-definite"), "{}", err)
+        }
+
+        // the same pair cannot be supplied twice, nor a self-correlation
+        assert!(sample_market_data_with_correlations(&[
+            ("BP.L", "GSK.L", constant(0.5)),
+            ("GSK.L", "BP.L", constant(0.5))]).is_err());
+        assert!(sample_market_data_with_correlations(&[
+            ("BP.L", "BP.L", constant(1.0))]).is_err());
+    }
+
+    #[test]
+    fn vol_arbitrage_gate() {
+
+        let checker = VolArbitrageChecker::new(&[7, 30, 91, 365],
+            &[-2.0, -1.0, -0.5, 0.0, 0.5, 1.0, 2.0], 1e-10).unwrap();
+        let market_data = sample_market_data();
+        market_data.check_vol_arbitrage(&checker).unwrap();
+
+        // replace one of the surfaces with one with a spike in the smile
+        let base_date = Date::from_ymd(2016, 12, 30);
+        let base = DateDayFraction::new(base_date, 0.2);
+        let points = [(50.0, 0.3), (190.0, 0.3), (195.0, 0.9), (200.0, 0.3),
+            (300.0, 0.3)];
+        let smiles = vec![(DateDayFraction::new(base_date + 91, 0.2),
+            CubicSplineSmile::new(&points).unwrap())];
+        let spiky = VolByProbability::new(&smiles, Box::new(WeekdayCalendar()),
+            base, Box::new(DriftlessForward::new(200.0)),
+            DivAssumptions::NoCashDivs).unwrap();
+        let mut vol_surfaces = market_data.vol_surfaces.clone();
+        vol_surfaces.insert("GSK.L".to_string(), Arc::new(spiky));
+        let bad_data = MarketData { vol_surfaces: vol_surfaces,
+            .. market_data };
+
+        let reports = bad_data.vol_arbitrage_reports(&checker).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].0, "BP.L");
+        assert!(reports[0].1.is_clean());
+        assert_eq!(reports[1].0, "GSK.L");
+        assert!(!reports[1].1.is_clean());
+
+        let err = bad_data.check_vol_arbitrage(&checker).unwrap_err();
+        assert!(err.to_string().contains("GSK.L"), "{}", err);
+    }
+
+    #[test]
+    fn vol_arbitrage_rejected_on_load() {
+
+        let checker = VolArbitrageChecker::new(&[7, 30, 91, 365],
+            &[-2.0, -1.0, 0.0, 1.0, 2.0], 1e-10).unwrap();
+        let market_data = sample_market_data();
+
+        // the sample data loads cleanly
+        MarketData::new_checked(market_data.spot_date,
+            market_data.discount_date, market_data.spots.clone(),
+            market_data.yield_curves.clone(),
+            market_data.borrow_curves.clone(),
+            market_data.dividends.clone(),
+            market_data.vol_surfaces.clone(), &checker).unwrap();
+
+        // a surface whose steep skew at three months flattens out by one
+        // year has less total variance at low strikes at one year than at
+        // three months, even though the at-the-money variance increases
+        let base_date = Date::from_ymd(2016, 12, 30);
+        let base = DateDayFraction::new(base_date, 0.2);
+        let points = [(100.0, 1.2), (150.0, 0.9), (200.0, 0.3),
+            (300.0, 0.3)];
+        let collapsed = [(100.0, 0.3), (200.0, 0.3), (300.0, 0.3)];
+        let smiles = vec![
+            (DateDayFraction::new(base_date + 91, 0.2),
+                CubicSplineSmile::new(&points).unwrap()),
+            (DateDayFraction::new(base_date + 365, 0.2),
+                CubicSplineSmile::new(&collapsed).unwrap())];
+        let calendar = VolByProbability::new(&smiles,
+            Box::new(WeekdayCalendar()), base,
+            Box::new(DriftlessForward::new(200.0)),
+            DivAssumptions::NoCashDivs).unwrap();
+        let mut vol_surfaces = market_data.vol_surfaces.clone();
+        vol_surfaces.insert("GSK.L".to_string(), Arc::new(calendar));
+
+        let result = MarketData::new_checked(market_data.spot_date,
+            market_data.discount_date, market_data.spots.clone(),
+            market_data.yield_curves.clone(),
+            market_data.borrow_curves.clone(),
+            market_data.dividends.clone(), vol_surfaces, &checker);
+        match result {
+            Ok(_) => panic!("calendar arbitrage was not rejected"),
+            Err(err) => {
+                let message = err.to_string();
+                assert!(message.contains("GSK.L"), "{}", message);
+                assert!(message.contains("calendar spread"), "{}", message);
+            }
+        }
+    }
+
     #[test]
     fn european_unbumped_price() {
 
@@ -538,6 +1105,71 @@ pub mod tests {
         assert_approx(price, unbumped_price, 1e-12);
     }
 
+    #[test]
+    fn time_bump_rolls_spot() {
+        let market_data = sample_market_data();
+        let european: Arc<Instrument> = sample_european();
+        let equity: Arc<Instrument> = Arc::new(sample_equity(
+            Arc::new(sample_currency(2)), 2));
+        let today = market_data.spot_date();
+        let spot = market_data.spot("BP.L").unwrap();
+
+        // bump past the ex date of the first dividend, which is pure cash
+        let theta_date = today + 35;
+        let later = today + 100;
+        let forward = market_data.forward_curve(&*equity, later).unwrap();
+
+        // with sticky spot, spot drops by the dividend
+        let mut rolled = market_data.clone();
+        let mut instruments = vec!((1.0, european.clone()));
+        let bump = BumpTime::new(theta_date, SpotDynamics::StickySpot);
+        assert!(rolled.bump_time(&bump, &mut instruments).unwrap());
+        assert_eq!(rolled.spot_date(), theta_date);
+        assert_approx(rolled.spot("BP.L").unwrap(), spot - 1.2, 1e-12);
+        assert_eq!(instruments.len(), 1);
+        assert_eq!(instruments[0].1.id(), "SampleEquity");
+
+        // with sticky forward, spot moves up the forward, and later forwards
+        // are almost unchanged
+        let mut rolled = market_data.clone();
+        let mut instruments = vec!((1.0, european.clone()));
+        let bump = BumpTime::new(theta_date, SpotDynamics::StickyForward);
+        assert!(rolled.bump_time(&bump, &mut instruments).unwrap());
+        assert_approx(rolled.spot("BP.L").unwrap(),
+            forward.forward(theta_date).unwrap(), 1e-12);
+        let rolled_forward = rolled.forward_curve(&*equity, later).unwrap();
+        assert_approx(rolled_forward.forward(later).unwrap(),
+            forward.forward(later).unwrap(), 1e-6);
+
+        // bumping to the same date does nothing, and backwards is an error
+        let bump = BumpTime::new(theta_date, SpotDynamics::StickySpot);
+        assert!(!rolled.bump_time(&bump, &mut instruments).unwrap());
+        let bump = BumpTime::new(today, SpotDynamics::StickySpot);
+        assert!(rolled.bump_time(&bump, &mut instruments).is_err());
+    }
+
+    #[test]
+    fn time_bump_past_expiry_fixes_instrument() {
+        let market_data = sample_market_data();
+        let european: Arc<Instrument> = sample_european();
+        let equity: Arc<Instrument> = Arc::new(sample_equity(
+            Arc::new(sample_currency(2)), 2));
+        let expiry = Date::from_ymd(2018, 06, 01);
+        let forward = market_data.forward_curve(&*equity, expiry).unwrap()
+            .forward(expiry).unwrap();
+        assert!(forward > 100.0);
+
+        // the option fixes at the forward, so it turns into a payment of the
+        // intrinsic value
+        let mut rolled = market_data.clone();
+        let mut instruments = vec!((1.0, european.clone()));
+        let bump = BumpTime::new(expiry + 3, SpotDynamics::StickyForward);
+        assert!(rolled.bump_time(&bump, &mut instruments).unwrap());
+        assert_eq!(instruments.len(), 1);
+        assert_eq!(instruments[0].1.id(), "SampleEquity:payment");
+        assert_approx(instruments[0].0, forward - 100.0, 1e-12);
+    }
+
     fn assert_approx(value: f64, expected: f64, tolerance: f64) {
         assert!(approx_eq(value, expected, tolerance),
             "value={} expected={}", value, expected);
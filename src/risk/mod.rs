@@ -1,6 +1,11 @@
 pub mod marketdata;
 pub mod dependencies;
 pub mod cache;
+pub mod reports;
+pub mod deltagamma;
+pub mod crossgamma;
+pub mod vegabuckets;
+pub mod keyrates;
 
 use core::qm;
 use data::bumpvol::BumpVol;
@@ -8,9 +13,12 @@ use data::bumpdivs::BumpDivs;
 use data::bumpyield::BumpYield;
 use data::bumpspot::BumpSpot;
 use data::bumptime::BumpTime;
+use data::bumpcorrelation::BumpCorrelation;
 use instruments::PricingContext;
+use instruments::Instrument;
 use dates::Date;
 use std::any::Any;
+use std::sync::Arc;
 
 /// Interface that defines all bumps of simple underlying market data. This
 /// defines most risks that the analytics outputs. Most methods take a save
@@ -51,6 +59,15 @@ pub trait Bumpable {
     fn bump_vol(&mut self, id: &str, bump: &BumpVol,
         save: &mut Saveable) -> Result<bool, qm::Error>;
 
+    /// Bumps the correlation between two underliers and returns true if it
+    /// was bumped. The order of the ids does not matter. Bumped
+    /// correlations are clamped to the range -1 to 1, but the resulting
+    /// correlation matrix is not checked, so large bumps may leave it no
+    /// longer positive semi-definite.
+    fn bump_correlation(&mut self, first: &str, second: &str,
+        bump: &BumpCorrelation, save: &mut Saveable)
+        -> Result<bool, qm::Error>;
+
     /// Bumps the discount date. This is the only sort of time bump that can
     /// be performed on the pricing context alone. A bump to the spot date
     /// generally also involves a change to the instrument.
@@ -74,12 +91,29 @@ pub trait BumpablePricingContext: Bumpable + PricingContext {
     fn as_bumpable(&self) -> &Bumpable;
     fn as_mut_bumpable(&mut self) -> &mut Bumpable;
     fn as_pricing_context(&self) -> &PricingContext;
+
+    /// Moves the spot date forward to that of the bump, rolling the market
+    /// data and fixing the instruments with the fixings implied by the spot
+    /// dynamics of the bump. The instruments are replaced by their fixed
+    /// equivalents. Returns false if the spot date was unchanged. Unlike
+    /// other bumps, this cannot be restored.
+    fn bump_time(&mut self, bump: &BumpTime,
+        instruments: &mut Vec<(f64, Arc<Instrument>)>)
+        -> Result<bool, qm::Error>;
+
+    /// Creates an independent copy of this context. The market data itself
+    /// is shared, so this is cheap.
+    fn clone_box(&self) -> Box<BumpablePricingContext>;
 }
 
 /// Time bumping is done to calculate theta or time-forward greeks, such as
 /// the delta as of the next market open. It is more complicated than other
 /// greeks, because it may involve changes to the instrument, which may have
 /// fixings before the theta date.
+///
+/// A time bump cannot be restored, so to calculate theta, price before and
+/// after the bump, or bump a second pricer constructed for the purpose. If
+/// the bump fails, the pricer is left unchanged.
 pub trait TimeBumpable {
     fn bump_time(&mut self, bump: &BumpTime) -> Result<(), qm::Error>;
 }
@@ -87,14 +121,29 @@ pub trait TimeBumpable {
 /// The basic pricing interface for qm. Returns a price from a pricer or a
 /// priceable instrument. The point of this interface is that it is bumpable,
 /// so it can be used to calculate risks and scenarios.
-pub trait Pricer : Bumpable + TimeBumpable {
+pub trait Pricer : Bumpable + TimeBumpable + Send + Sync {
     fn as_bumpable(&self) -> &Bumpable;
     fn as_mut_bumpable(&mut self) -> &mut Bumpable;
     fn as_mut_time_bumpable(&mut self) -> &mut TimeBumpable;
+
+    /// Returns the instruments being priced, with their weights. These are
+    /// the instruments after any fixings have been applied, so they may
+    /// differ from the instrument the pricer was constructed with.
+    fn instruments(&self) -> &[(f64, Arc<Instrument>)];
+
+    /// Gives access to the market data used for pricing, including any
+    /// bumps currently applied.
+    fn as_pricing_context(&self) -> &PricingContext;
     
     /// Returns the present value, discounted to the discount date expressed
     /// in the pricing context.
     fn price(&self) -> Result<f64, qm::Error>;
+
+    /// Forks an independent copy of this pricer, including any bumps
+    /// currently applied. The market data itself is shared, so the copies
+    /// can be bumped separately, for example on different threads. Copies
+    /// of Monte-Carlo pricers share the same paths.
+    fn clone_box(&self) -> Box<Pricer>;
 }
 
 /// Interface that defines how market data or derived data can save itself
@@ -0,0 +1,266 @@
+//! Building blocks shared by the risk reports. A risk report drives any
+//! Pricer through its Bumpable interface, so the same report works whether
+//! the underlying pricer is closed-form, Monte-Carlo or PDE.
+
+use core::qm;
+use instruments::Instrument;
+use instruments::RcInstrument;
+use instruments::DependencyContext;
+use risk::Pricer;
+use risk::Bumpable;
+use risk::Saveable;
+use risk::dependencies::DependencyCollector;
+use dates::Date;
+use core::parallel::map_parallel;
+
+
+/// Selects the underliers that a risk report should bump. Underliers may be
+/// chosen by instrument id, by the currency they pay in, or by their credit
+/// id (for equities, this is the exchange or issuer they are funded by).
+#[derive(Clone, Debug)]
+pub enum UnderlierSelector {
+    All,
+    ById { ids: Vec<String> },
+    ByCurrency { currency: String },
+    ByCreditId { credit_id: String }
+}
+
+impl UnderlierSelector {
+    pub fn new_all() -> UnderlierSelector {
+        UnderlierSelector::All
+    }
+
+    pub fn new_by_id(ids: &[&str]) -> UnderlierSelector {
+        UnderlierSelector::ById {
+            ids: ids.iter().map(|id| id.to_string()).collect() }
+    }
+
+    pub fn new_by_currency(currency: &str) -> UnderlierSelector {
+        UnderlierSelector::ByCurrency { currency: currency.to_string() }
+    }
+
+    pub fn new_by_credit_id(credit_id: &str) -> UnderlierSelector {
+        UnderlierSelector::ByCreditId { credit_id: credit_id.to_string() }
+    }
+
+    /// Returns true if the given underlier should be included in the report
+    pub fn matches(&self, instrument: &Instrument) -> bool {
+        match self {
+            &UnderlierSelector::All => true,
+            &UnderlierSelector::ById { ref ids }
+                => ids.iter().any(|id| id == instrument.id()),
+            &UnderlierSelector::ByCurrency { ref currency }
+                => instrument.payoff_currency().id() == currency,
+            &UnderlierSelector::ByCreditId { ref credit_id }
+                => instrument.credit_id() == credit_id
+        }
+    }
+
+    /// Returns true if the yield curve for the given credit id should be
+    /// included in the report. A yield curve is identified by its credit
+    /// id, so selecting by id or by credit id both match it. Yield curves
+    /// are not associated with a currency, so never match by currency.
+    pub fn matches_credit_id(&self, curve_credit_id: &str) -> bool {
+        match self {
+            &UnderlierSelector::All => true,
+            &UnderlierSelector::ById { ref ids }
+                => ids.iter().any(|id| id == curve_credit_id),
+            &UnderlierSelector::ByCurrency { .. } => false,
+            &UnderlierSelector::ByCreditId { ref credit_id }
+                => credit_id == curve_credit_id
+        }
+    }
+}
+
+/// Finds all the instruments whose spot the pricer depends on and which
+/// match the selector. The result is sorted by instrument id, so reports
+/// are produced in a deterministic order.
+pub fn selected_spots(pricer: &Pricer, selector: &UnderlierSelector)
+    -> Vec<RcInstrument> {
+
+    let collector = collect_dependencies(pricer);
+    let mut spots: Vec<RcInstrument> = collector.spots().iter()
+        .filter(|spot| selector.matches(spot.instrument()))
+        .cloned().collect();
+    spots.sort();
+    spots
+}
+
+/// Finds all the instruments whose vol surface the pricer depends on and
+/// which match the selector, together with the high water mark of the
+/// dependency. The result is sorted by instrument id.
+pub fn selected_vol_surfaces(pricer: &Pricer, selector: &UnderlierSelector)
+    -> Vec<(RcInstrument, Date)> {
+
+    let collector = collect_dependencies(pricer);
+    let mut surfaces: Vec<(RcInstrument, Date)> = collector.vol_surfaces()
+        .iter()
+        .filter(|&(instrument, _)| selector.matches(instrument.instrument()))
+        .map(|(instrument, hwm)| (instrument.clone(), *hwm))
+        .collect();
+    surfaces.sort_by(|a, b| a.0.cmp(&b.0));
+    surfaces
+}
+
+/// Finds all the instruments whose forward curve the pricer depends on and
+/// which match the selector, together with the high water mark of the
+/// dependency. The result is sorted by instrument id.
+pub fn selected_forward_curves(pricer: &Pricer, selector: &UnderlierSelector)
+    -> Vec<(RcInstrument, Date)> {
+
+    let collector = collect_dependencies(pricer);
+    let mut forwards: Vec<(RcInstrument, Date)> = collector.forward_curves()
+        .iter()
+        .filter(|&(instrument, _)| selector.matches(instrument.instrument()))
+        .map(|(instrument, hwm)| (instrument.clone(), *hwm))
+        .collect();
+    forwards.sort_by(|a, b| a.0.cmp(&b.0));
+    forwards
+}
+
+/// Finds the credit ids of all the yield curves the pricer depends on and
+/// which match the selector, together with the high water mark of the
+/// dependency. The result is sorted by credit id.
+pub fn selected_yield_curves(pricer: &Pricer, selector: &UnderlierSelector)
+    -> Vec<(String, Date)> {
+
+    let collector = collect_dependencies(pricer);
+    let mut curves: Vec<(String, Date)> = collector.yield_curves().iter()
+        .filter(|&(credit_id, _)| selector.matches_credit_id(credit_id))
+        .map(|(credit_id, hwm)| (credit_id.clone(), *hwm))
+        .collect();
+    curves.sort_by(|a, b| a.0.cmp(&b.0));
+    curves
+}
+
+fn collect_dependencies(pricer: &Pricer) -> DependencyCollector {
+    let spot_date = pricer.as_pricing_context().spot_date();
+    let mut collector = DependencyCollector::new(spot_date);
+    for &(_, ref instrument) in pricer.instruments().iter() {
+        collector.spot(instrument);
+    }
+    collector
+}
+
+/// Applies a bump to the pricer, prices it, then restores it to its state
+/// before the bump. The restore happens whether or not the bump or the
+/// pricing succeeded, so the pricer is never left bumped. Returns None if
+/// the bump had no effect on the pricer.
+///
+/// The save area is cleared after the restore, so it can be reused for the
+/// next bump.
+pub fn bumped_price<F>(pricer: &mut Pricer, save: &mut Saveable, bump: F)
+    -> Result<Option<f64>, qm::Error>
+    where F: FnOnce(&mut Bumpable, &mut Saveable) -> Result<bool, qm::Error> {
+
+    let price = match bump(pricer.as_mut_bumpable(), save) {
+        Ok(true) => pricer.price().map(|p| Some(p)),
+        Ok(false) => Ok(None),
+        Err(e) => Err(e)
+    };
+    unwind(pricer, save, price)
+}
+
+/// Applies two bumps on top of each other, prices, then unwinds them in
+/// reverse order. Each bump has its own save area, so the bumps may touch
+/// the same underlying data without the inner restore losing the state
+/// saved by the outer one. Returns None if either bump had no effect.
+pub fn cross_bumped_price<F, G>(pricer: &mut Pricer,
+    outer_save: &mut Saveable, inner_save: &mut Saveable,
+    outer_bump: F, inner_bump: G) -> Result<Option<f64>, qm::Error>
+    where F: FnOnce(&mut Bumpable, &mut Saveable) -> Result<bool, qm::Error>,
+        G: FnOnce(&mut Bumpable, &mut Saveable) -> Result<bool, qm::Error> {
+
+    let price = match outer_bump(pricer.as_mut_bumpable(), outer_save) {
+        Ok(true) => bumped_price(pricer, inner_save, inner_bump),
+        Ok(false) => Ok(None),
+        Err(e) => Err(e)
+    };
+    unwind(pricer, outer_save, price)
+}
+
+/// Restores the pricer from the save area and clears it. This is always
+/// done, even if the bump failed part way through, as the save area
+/// contains whatever was modified before the failure. An error in the
+/// result takes precedence over an error in the restore.
+fn unwind<T>(pricer: &mut Pricer, save: &mut Saveable,
+    result: Result<T, qm::Error>) -> Result<T, qm::Error> {
+
+    let restored = pricer.as_mut_bumpable().restore(save);
+    save.clear();
+
+    match (result, restored) {
+        (Err(e), _) => Err(e),
+        (_, Err(e)) => Err(e),
+        (Ok(value), Ok(())) => Ok(value)
+    }
+}
+
+/// Decides how the bump-and-reprice scenarios of a risk report are run.
+/// Sequentially, every scenario bumps and restores the pricer itself. With
+/// more than one thread, the scenarios are divided into contiguous runs,
+/// one per thread, and each run works on its own fork of the pricer, made
+/// by Pricer::clone_box. The results are returned in the order of the
+/// scenarios, so a report gives the same results however it is run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScenarioScheduler {
+    n_threads: usize
+}
+
+impl ScenarioScheduler {
+    /// Runs the scenarios on up to the given number of threads
+    pub fn new(n_threads: usize) -> Result<ScenarioScheduler, qm::Error> {
+        if n_threads == 0 {
+            return Err(qm::Error::new(
+                "Scenario scheduler needs at least one thread"))
+        }
+        Ok(ScenarioScheduler { n_threads: n_threads })
+    }
+
+    /// Runs the scenarios one at a time on the pricer itself
+    pub fn sequential() -> ScenarioScheduler {
+        ScenarioScheduler { n_threads: 1 }
+    }
+
+    pub fn n_threads(&self) -> usize { self.n_threads }
+
+    /// Runs each scenario against the pricer, or a fork of it, and returns
+    /// the results in the same order as the scenarios. Each scenario is
+    /// given a save area, which it must leave clear, for example by using
+    /// bumped_price. If any scenario fails, the error is that of the first
+    /// failing scenario. Forks are discarded afterwards, so the pricer is
+    /// left in the same state as on entry.
+    pub fn run<S, R, F>(&self, pricer: &mut Pricer, scenarios: Vec<S>,
+        scenario: F) -> Result<Vec<R>, qm::Error>
+        where S: Send, R: Send,
+            F: Fn(&mut Pricer, &mut Saveable, S) -> Result<R, qm::Error>
+                + Sync {
+
+        let n_threads = self.n_threads.min(scenarios.len());
+        if n_threads <= 1 {
+            let mut save = pricer.as_bumpable().new_saveable();
+            return scenarios.into_iter()
+                .map(|s| scenario(pricer, &mut *save, s)).collect()
+        }
+
+        let run_size = (scenarios.len() + n_threads - 1) / n_threads;
+        let mut runs = Vec::with_capacity(n_threads);
+        let mut remaining = scenarios.into_iter();
+        loop {
+            let run: Vec<S> = remaining.by_ref().take(run_size).collect();
+            if run.is_empty() {
+                break
+            }
+            runs.push(run);
+        }
+
+        let pricer: &Pricer = pricer;
+        let results = map_parallel(n_threads, runs, |run| {
+            let mut fork = pricer.clone_box();
+            let mut save = fork.as_bumpable().new_saveable();
+            run.into_iter().map(|s| scenario(&mut *fork, &mut *save, s))
+                .collect::<Result<Vec<R>, qm::Error>>()
+        })?;
+        Ok(results.into_iter().flat_map(|run| run).collect())
+    }
+}
@@ -0,0 +1,360 @@
+use core::qm;
+use std::collections::BTreeMap;
+use data::bumpvol::BumpVol;
+use dates::Date;
+use dates::datetime::DateDayFraction;
+use instruments::RcInstrument;
+use risk::Pricer;
+use risk::Saveable;
+use risk::reports::UnderlierSelector;
+use risk::reports::selected_vol_surfaces;
+use risk::reports::bumped_price;
+use risk::reports::ScenarioScheduler;
+
+/// Vega to a single bucket of a vol surface: either an expiry pillar, or a
+/// region around an expiry pillar and a strike. The vega is per unit of
+/// volatility, so multiply by 0.01 for the change per vol point.
+#[derive(Clone, Debug)]
+pub struct VegaBucket {
+    expiry: DateDayFraction,
+    strike: Option<f64>,
+    vega: f64
+}
+
+impl VegaBucket {
+    pub fn expiry(&self) -> DateDayFraction { self.expiry }
+    pub fn strike(&self) -> Option<f64> { self.strike }
+    pub fn vega(&self) -> f64 { self.vega }
+}
+
+/// The results of a vega-bucket report. Contains the unbumped price and,
+/// for each selected underlier, its vega buckets in ascending order of
+/// expiry then strike.
+#[derive(Clone, Debug)]
+pub struct VegaBucketResult {
+    price: f64,
+    buckets: BTreeMap<String, Vec<VegaBucket>>
+}
+
+impl VegaBucketResult {
+    pub fn price(&self) -> f64 { self.price }
+    pub fn buckets(&self) -> &BTreeMap<String, Vec<VegaBucket>> {
+        &self.buckets
+    }
+    pub fn get(&self, id: &str) -> Option<&[VegaBucket]> {
+        self.buckets.get(id).map(|buckets| &buckets[..])
+    }
+
+    /// Sum of the bucketed vegas for one underlier. Because the bucket
+    /// weights sum to one, this is close to the parallel vega.
+    pub fn total(&self, id: &str) -> Option<f64> {
+        self.get(id).map(|buckets|
+            buckets.iter().map(|bucket| bucket.vega).sum())
+    }
+}
+
+/// Report that calculates vega bucketed by the expiry pillars of each vol
+/// surface the pricer depends on, optionally also bucketed by strike. The
+/// pillars are taken from the surface itself, so it must be one that has
+/// them, such as VolByProbability. Each bucket is bumped up and down by the
+/// given size in vol, and the vega calculated by central differences.
+///
+/// Strike buckets are specified relative to the current spot, so the same
+/// report can be used across underliers. Pillars beyond the last date the
+/// pricer is sensitive to are skipped, as they cannot have any vega.
+pub struct VegaBucketReport {
+    size: f64,
+    selector: UnderlierSelector,
+    relative_strikes: Option<Vec<f64>>
+}
+
+impl VegaBucketReport {
+    /// Creates a report bucketed by expiry pillar only
+    pub fn new(size: f64, selector: UnderlierSelector)
+        -> Result<VegaBucketReport, qm::Error> {
+        if !(size > 0.0) {
+            return Err(qm::Error::new("Vega bump size must be positive"))
+        }
+        Ok(VegaBucketReport { size: size, selector: selector,
+            relative_strikes: None })
+    }
+
+    /// Creates a report bucketed by expiry pillar and by strike, where the
+    /// strikes are fractions of the spot, in strictly ascending order.
+    pub fn new_with_strikes(size: f64, selector: UnderlierSelector,
+        relative_strikes: &[f64]) -> Result<VegaBucketReport, qm::Error> {
+        let mut report = VegaBucketReport::new(size, selector)?;
+        if relative_strikes.is_empty() {
+            return Err(qm::Error::new("No strikes for vega buckets"))
+        }
+        report.relative_strikes = Some(relative_strikes.to_vec());
+        Ok(report)
+    }
+
+    pub fn size(&self) -> f64 { self.size }
+    pub fn selector(&self) -> &UnderlierSelector { &self.selector }
+    pub fn relative_strikes(&self) -> Option<&[f64]> {
+        self.relative_strikes.as_ref().map(|strikes| &strikes[..])
+    }
+
+    /// Runs the report against the given pricer. On return, the pricer is
+    /// in the same state as on entry, whether or not the report succeeded.
+    pub fn calculate(&self, pricer: &mut Pricer)
+        -> Result<VegaBucketResult, qm::Error> {
+        self.calculate_with(pricer, &ScenarioScheduler::sequential())
+    }
+
+    /// Runs the report as calculate, with the bumps of each bucket as a
+    /// separate scenario for the scheduler.
+    pub fn calculate_with(&self, pricer: &mut Pricer,
+        scheduler: &ScenarioScheduler) -> Result<VegaBucketResult, qm::Error> {
+
+        let price = pricer.price()?;
+        let mut buckets = BTreeMap::new();
+        let mut scenarios = Vec::new();
+
+        for &(ref underlier, hwm) in selected_vol_surfaces(
+            pricer, &self.selector).iter() {
+            buckets.insert(underlier.id().to_string(), Vec::new());
+            self.underlier_scenarios(pricer, underlier, hwm,
+                &mut scenarios)?;
+        }
+
+        let vegas = scheduler.run(pricer, scenarios, |pricer, save, bucket|
+            self.vega(pricer, bucket, save))?;
+        for (id, vega) in vegas.into_iter() {
+            buckets.get_mut(&id).unwrap().push(vega);
+        }
+
+        Ok(VegaBucketResult { price: price, buckets: buckets })
+    }
+
+    fn underlier_scenarios(&self, pricer: &Pricer, underlier: &RcInstrument,
+        hwm: Date, scenarios: &mut Vec<VegaScenario>)
+        -> Result<(), qm::Error> {
+
+        let id = underlier.id();
+        let pillars = {
+            let context = pricer.as_pricing_context();
+            let forward = context.forward_curve(underlier.instrument(), hwm)?;
+            let surface = context.vol_surface(underlier.instrument(),
+                forward, hwm)?;
+            match surface.pillar_dates() {
+                Some(pillars) => pillars,
+                None => return Err(qm::Error::new(&format!(
+                    "Vol surface for {} has no pillars to bucket vega", id)))
+            }
+        };
+
+        let strikes = match self.relative_strikes {
+            Some(ref relative) => {
+                let spot = pricer.as_pricing_context().spot(id)?;
+                Some(relative.iter().map(|k| k * spot).collect::<Vec<f64>>())
+            },
+            None => None
+        };
+
+        for pillar in 0..pillars.len() {
+
+            // a pillar only affects times up to the following pillar
+            if pillar > 0 && pillars[pillar - 1].date() >= hwm {
+                break
+            }
+
+            match strikes {
+                None => scenarios.push(VegaScenario {
+                    id: id.to_string(), expiry: pillars[pillar], strike: None,
+                    up: BumpVol::new_expiry_pillar(
+                        &pillars, pillar, self.size)?,
+                    down: BumpVol::new_expiry_pillar(
+                        &pillars, pillar, -self.size)? }),
+                Some(ref strikes) => for strike in 0..strikes.len() {
+                    scenarios.push(VegaScenario {
+                        id: id.to_string(), expiry: pillars[pillar],
+                        strike: Some(strikes[strike]),
+                        up: BumpVol::new_region(&pillars, pillar,
+                            strikes, strike, self.size)?,
+                        down: BumpVol::new_region(&pillars, pillar,
+                            strikes, strike, -self.size)? });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn vega(&self, pricer: &mut Pricer, bucket: VegaScenario,
+        save: &mut Saveable) -> Result<(String, VegaBucket), qm::Error> {
+
+        let id = &bucket.id;
+        let up_price = bumped_price(pricer, save,
+            |b, s| b.bump_vol(id, &bucket.up, s))?;
+        let down_price = bumped_price(pricer, save,
+            |b, s| b.bump_vol(id, &bucket.down, s))?;
+
+        // a surface that could not be bumped has zero vega
+        let vega = match (up_price, down_price) {
+            (Some(up), Some(down)) => (up - down) / (2.0 * self.size),
+            _ => 0.0
+        };
+        Ok((bucket.id.clone(), VegaBucket { expiry: bucket.expiry,
+            strike: bucket.strike, vega: vega }))
+    }
+}
+
+/// The up and down bumps for one vega bucket of one underlier
+struct VegaScenario {
+    id: String,
+    expiry: DateDayFraction,
+    strike: Option<f64>,
+    up: BumpVol,
+    down: BumpVol
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use data::fixings::FixingTable;
+    use data::volsmile::SviSmile;
+    use data::volsurface::VolSurface;
+    use data::volsurface::VolByProbability;
+    use data::volsurface::DivAssumptions;
+    use data::forward::DriftlessForward;
+    use dates::calendar::WeekdayCalendar;
+    use dates::calendar::Calendar;
+    use dates::datetime::DateTime;
+    use dates::datetime::TimeOfDay;
+    use instruments::Instrument;
+    use math::numerics::approx_eq;
+    use pricers::PricerFactory;
+    use pricers::selfpricer::SelfPricerFactory;
+    use risk::marketdata::MarketData;
+    use risk::marketdata::tests::sample_market_data_with_vol;
+    use risk::marketdata::tests::sample_european;
+
+    /// A smile surface with pillars at three months, six months, one year
+    /// and two years. The sample European expires after about 17 months.
+    fn sample_pillared_vol() -> Arc<VolSurface> {
+        let calendar = Box::new(WeekdayCalendar());
+        let base_date = Date::from_ymd(2016, 12, 30);
+        let base = DateDayFraction::new(base_date, 0.2);
+        let forward = 100.0;
+
+        let mut smiles = Vec::new();
+        for days in [91, 182, 364, 728].iter() {
+            let pillar = DateDayFraction::new(base_date + *days, 0.7);
+            let t = calendar.year_fraction(base, pillar);
+            smiles.push((pillar, SviSmile::new_raw(forward, t, 0.06 * t,
+                0.2 * t, -0.6, 0.0, 0.2).unwrap()));
+        }
+
+        Arc::new(VolByProbability::new(&smiles, calendar, base,
+            Box::new(DriftlessForward::new(forward)),
+            DivAssumptions::NoCashDivs).unwrap())
+    }
+
+    fn sample_pricer() -> Box<Pricer> {
+        let market_data: Arc<MarketData> = Arc::new(
+            sample_market_data_with_vol(sample_pillared_vol()));
+        let instrument: Arc<Instrument> = sample_european();
+        let today = Date::from_ymd(2017, 01, 02);
+        let fixings = Arc::new(FixingTable::new(today, &[
+            ("BP.L", &[
+            (DateTime::new(today - 7, TimeOfDay::Close), 102.0)])]).unwrap());
+        let factory = SelfPricerFactory::new();
+        factory.new(instrument, fixings, market_data).unwrap()
+    }
+
+    fn parallel_vega(pricer: &mut Pricer, size: f64) -> f64 {
+        let mut save = pricer.as_bumpable().new_saveable();
+        let up = bumped_price(pricer, &mut *save, |b, s| b.bump_vol("BP.L",
+            &BumpVol::new_flat_additive(size), s)).unwrap().unwrap();
+        let down = bumped_price(pricer, &mut *save, |b, s| b.bump_vol("BP.L",
+            &BumpVol::new_flat_additive(-size), s)).unwrap().unwrap();
+        (up - down) / (2.0 * size)
+    }
+
+    #[test]
+    fn vega_buckets_by_expiry_pillar() {
+        let mut pricer = sample_pricer();
+        let unbumped = pricer.price().unwrap();
+
+        let report = VegaBucketReport::new(0.01,
+            UnderlierSelector::new_all()).unwrap();
+        let result = report.calculate(&mut *pricer).unwrap();
+        assert_approx(result.price(), unbumped, 1e-12);
+
+        // all four pillars are reported, but only the two either side of
+        // the expiry have any vega
+        let buckets = result.get("BP.L").unwrap();
+        assert_eq!(buckets.len(), 4);
+        assert_approx(buckets[0].vega(), 0.0, 1e-12);
+        assert_approx(buckets[1].vega(), 0.0, 1e-12);
+        assert!(buckets[2].vega() > 1.0, "vega={}", buckets[2].vega());
+        assert!(buckets[3].vega() > 1.0, "vega={}", buckets[3].vega());
+
+        let parallel = parallel_vega(&mut *pricer, 0.01);
+        assert_approx(result.total("BP.L").unwrap(), parallel,
+            parallel * 1e-4);
+
+        // the pricer must be left unbumped
+        assert_approx(pricer.price().unwrap(), unbumped, 1e-12);
+    }
+
+    #[test]
+    fn vega_buckets_by_expiry_and_strike() {
+        let mut pricer = sample_pricer();
+
+        let report = VegaBucketReport::new_with_strikes(0.01,
+            UnderlierSelector::new_by_id(&["BP.L"]), &[0.8, 1.0, 1.2])
+            .unwrap();
+        let result = report.calculate(&mut *pricer).unwrap();
+        let buckets = result.get("BP.L").unwrap();
+        assert_eq!(buckets.len(), 12);
+        assert_approx(buckets[6].strike().unwrap(), 80.0, 1e-12);
+
+        // the option is struck at spot, so only the at-the-money strike
+        // buckets have vega
+        for bucket in buckets.iter() {
+            if bucket.strike() != Some(100.0) {
+                assert_approx(bucket.vega(), 0.0, 1e-12);
+            }
+        }
+
+        let parallel = parallel_vega(&mut *pricer, 0.01);
+        assert_approx(result.total("BP.L").unwrap(), parallel,
+            parallel * 1e-4);
+
+        // spreading the buckets across threads gives the same results
+        let scheduled = report.calculate_with(&mut *pricer,
+            &ScenarioScheduler::new(4).unwrap()).unwrap();
+        let others = scheduled.get("BP.L").unwrap();
+        assert_eq!(others.len(), buckets.len());
+        for (bucket, other) in buckets.iter().zip(others.iter()) {
+            assert_eq!(other.expiry(), bucket.expiry());
+            assert_eq!(other.strike(), bucket.strike());
+            assert_eq!(other.vega(), bucket.vega());
+        }
+    }
+
+    #[test]
+    fn vega_buckets_need_pillars() {
+        let market_data: Arc<MarketData> = Arc::new(
+            ::risk::marketdata::tests::sample_market_data());
+        let instrument: Arc<Instrument> = sample_european();
+        let today = Date::from_ymd(2017, 01, 02);
+        let fixings = Arc::new(FixingTable::new(today, &[]).unwrap());
+        let mut pricer = SelfPricerFactory::new().new(instrument, fixings,
+            market_data).unwrap();
+
+        let report = VegaBucketReport::new(0.01,
+            UnderlierSelector::new_all()).unwrap();
+        assert!(report.calculate(&mut *pricer).is_err());
+    }
+
+    fn assert_approx(value: f64, expected: f64, tolerance: f64) {
+        assert!(approx_eq(value, expected, tolerance),
+            "value={} expected={}", value, expected);
+    }
+}